@@ -0,0 +1,695 @@
+//! AI 用量与响应缓存模块
+//! AI usage and response cache module
+//!
+//! 包装对 `LlmProvider` 的调用：相同的提供方、模型、提示词与数据直接返回缓存的回复，订阅数据变更
+//! (`BROADCAST`) 时清空缓存；每次请求按提供方报告的 token 用量记录到 `ai_usage` 表，并按价格表估算费用。
+//! 配置了月度预算时，本月费用达到预算后 AI 功能自动降级为 Mock 模式。
+//! Wraps calls to the `LlmProvider`: the same provider, model, prompt and data return the cached
+//! reply, and the cache is cleared whenever subscription data changes (`BROADCAST`). Every
+//! request records the token usage reported by the provider in the `ai_usage` table, with the
+//! cost estimated from a price table. With a monthly budget configured, AI features fall back to
+//! mock mode once this month's cost reaches it.
+//!
+//! 请求完成前被取消 (客户端断开、流式接收方关闭) 时没有提供方报告的用量，此时按提示词与已收到的
+//! 增量估算 token 数并照常记录，中途断开的请求不会绕过预算。
+//! Requests cancelled before they complete (the client disconnects or the streaming receiver goes
+//! away) have no usage reported by the provider, so the tokens are estimated from the prompt and
+//! the deltas received so far and recorded as usual; aborted requests cannot bypass the budget.
+
+use crate::auth::{AuthUser, Role, Scope};
+use crate::dates;
+use crate::db::DbPool;
+use crate::events;
+use crate::llm::{self, ChatRequest, Completion, LlmError, LlmProvider, OnDelta, Usage};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+/// 缓存的回复条数上限，超出时淘汰最早写入的条目
+/// Maximum number of cached replies; the oldest entries are evicted beyond it
+const CACHE_CAPACITY: usize = 256;
+
+/// 价格表的计价货币
+/// Currency of the price table
+const PRICE_CURRENCY: &str = "USD";
+
+/// 模型单价 (每百万 token)
+/// Model prices (per million tokens)
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct Price {
+    input: f64,
+    output: f64,
+}
+
+/// 内置价格表 (仅供估算，可通过 `LLM_PRICES` 覆盖或补充)
+/// Built-in price table (estimates only; override or extend it with `LLM_PRICES`)
+const DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("deepseek-chat", 0.27, 1.1),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+];
+
+/// 价格表：内置价格加上 `LLM_PRICES` 中的 JSON 对象，例如 `{"gpt-4o-mini": {"input": 0.15, "output": 0.6}}`
+/// Price table: the built-in prices plus the JSON object in `LLM_PRICES`, for example
+/// `{"gpt-4o-mini": {"input": 0.15, "output": 0.6}}`
+static PRICES: Lazy<HashMap<String, Price>> = Lazy::new(|| {
+    let mut prices: HashMap<String, Price> = DEFAULT_PRICES
+        .iter()
+        .map(|(model, input, output)| (model.to_string(), Price { input: *input, output: *output }))
+        .collect();
+    if let Ok(raw) = std::env::var("LLM_PRICES") {
+        match serde_json::from_str::<HashMap<String, Price>>(&raw) {
+            Ok(custom) => prices.extend(custom),
+            Err(e) => warn!("Invalid LLM_PRICES ignored: {}", e),
+        }
+    }
+    prices
+});
+
+/// 月度预算 (价格表货币)，未设置时不限制
+/// Monthly budget (in the price table currency); unlimited when unset
+static BUDGET: Lazy<Option<f64>> = Lazy::new(|| {
+    std::env::var("LLM_MONTHLY_BUDGET").ok().and_then(|v| v.trim().parse().ok()).filter(|b: &f64| *b > 0.0)
+});
+
+/// 回复缓存 (键为请求哈希)
+/// Reply cache (keyed by request hash)
+///
+/// 每次清空时 `generation` 加一；请求期间缓存被清空时不写入其结果，避免缓存基于旧数据的回复。
+/// `generation` is bumped on every clear; a reply whose request spanned a clear is not stored,
+/// so replies built from stale data are never cached.
+#[derive(Default)]
+struct ResponseCache {
+    entries: HashMap<String, String>,
+    order: VecDeque<String>,
+    generation: u64,
+}
+
+static CACHE: Lazy<Mutex<ResponseCache>> = Lazy::new(|| Mutex::new(ResponseCache::default()));
+
+/// 启动缓存失效任务：订阅数据变更时清空缓存
+/// Start the cache invalidation task: the cache is cleared whenever subscription data changes
+///
+/// 提示词包含订阅列表与分类，无法精确判断哪些条目受影响，因此整体清空；
+/// 事件积压 (`Lagged`) 时同样清空。
+/// Prompts embed the subscription list and categories, so it is not possible to tell which
+/// entries are affected and the whole cache is cleared; a lagged receiver clears it too.
+pub fn start() {
+    Lazy::force(&PRICES);
+    if let Some(budget) = *BUDGET {
+        info!("AI monthly budget: {:.2} {}", budget, PRICE_CURRENCY);
+    }
+    let mut rx = events::receiver();
+    tokio::spawn(async move {
+        while let Ok(_) | Err(RecvError::Lagged(_)) = rx.recv().await {
+            clear_cache();
+        }
+    });
+}
+
+fn clear_cache() {
+    let mut cache = CACHE.lock();
+    cache.entries.clear();
+    cache.order.clear();
+    cache.generation += 1;
+}
+
+/// 获取可用的提供方：未配置或本月预算已用完时返回 `None`，调用方应降级为 Mock 模式
+/// Get the usable provider: `None` when unconfigured or when this month's budget is used up, in
+/// which case callers fall back to mock mode
+pub async fn provider(pool: &DbPool) -> Option<&'static dyn LlmProvider> {
+    let provider = llm::provider()?;
+    if let Some(budget) = *BUDGET {
+        match month_cost(pool, &current_month()).await {
+            Ok(spent) if spent >= budget => return None,
+            Ok(_) => {}
+            Err(e) => warn!("Failed to check AI budget: {}", e),
+        }
+    }
+    Some(provider)
+}
+
+/// 发送对话，命中缓存时直接返回缓存的回复
+/// Send a chat, returning the cached reply on a cache hit
+///
+/// `feature` 标明调用来源 (如 `smart_parse`、`analyze`)，用于用量统计。
+/// `feature` names the caller (such as `smart_parse` or `analyze`) for usage reporting.
+pub async fn chat(
+    pool: &DbPool,
+    user_id: i64,
+    feature: &str,
+    provider: &dyn LlmProvider,
+    request: &ChatRequest,
+) -> Result<String, LlmError> {
+    let key = cache_key(provider, request);
+    let (hit, generation) = lookup(&key);
+    if let Some(content) = hit {
+        record(pool, user_id, feature, provider, None, true).await;
+        return Ok(content);
+    }
+    let pending = Pending::new(pool, user_id, feature, provider, request);
+    let result = provider.chat(request).await;
+    pending.disarm();
+    finish(pool, user_id, feature, provider, (key, generation), result?).await
+}
+
+/// 流式对话；命中缓存时把缓存的回复作为单个增量发送
+/// Streaming chat; on a cache hit the cached reply is sent as a single delta
+pub async fn chat_stream(
+    pool: &DbPool,
+    user_id: i64,
+    feature: &str,
+    provider: &dyn LlmProvider,
+    request: &ChatRequest,
+    on_delta: &mut OnDelta<'_>,
+) -> Result<String, LlmError> {
+    let key = cache_key(provider, request);
+    let (hit, generation) = lookup(&key);
+    if let Some(content) = hit {
+        if !on_delta(&content) {
+            return Err(LlmError::Cancelled);
+        }
+        record(pool, user_id, feature, provider, None, true).await;
+        return Ok(content);
+    }
+    let pending = Pending::new(pool, user_id, feature, provider, request);
+    let received = pending.completion_tokens.clone();
+    let mut counting = |delta: &str| {
+        received.fetch_add(estimate_tokens(delta), Ordering::Relaxed);
+        on_delta(delta)
+    };
+    match provider.chat_stream(request, &mut counting).await {
+        Ok(completion) => {
+            pending.disarm();
+            finish(pool, user_id, feature, provider, (key, generation), completion).await
+        }
+        Err(LlmError::Cancelled) => {
+            pending.record().await;
+            Err(LlmError::Cancelled)
+        }
+        Err(e) => {
+            pending.disarm();
+            Err(e)
+        }
+    }
+}
+
+/// 粗略估算文本的 token 数：ASCII 约 4 个字符一个 token，其他字符 (如中文) 约每字一个
+/// Roughly estimate the tokens of a text: about 4 ASCII characters per token, and about one per
+/// other character (such as Chinese)
+fn estimate_tokens(text: &str) -> i64 {
+    let ascii = text.chars().filter(char::is_ascii).count();
+    let other = text.chars().count() - ascii;
+    (ascii.div_ceil(4) + other) as i64
+}
+
+/// 进行中的请求：在拿到提供方报告的用量之前被取消或丢弃 (例如客户端断开导致处理任务被中止) 时，
+/// 按提示词与已收到的增量估算用量并记录
+/// A request in flight: when it is cancelled or dropped before the provider reports its usage
+/// (for example because the client disconnected and the handler was aborted), the usage is
+/// estimated from the prompt and the deltas received so far and recorded
+struct Pending {
+    armed: bool,
+    pool: DbPool,
+    user_id: i64,
+    feature: String,
+    provider: &'static str,
+    model: String,
+    prompt_tokens: i64,
+    completion_tokens: Arc<AtomicI64>,
+}
+
+impl Pending {
+    fn new(pool: &DbPool, user_id: i64, feature: &str, provider: &dyn LlmProvider, request: &ChatRequest) -> Self {
+        Pending {
+            armed: true,
+            pool: pool.clone(),
+            user_id,
+            feature: feature.to_string(),
+            provider: provider.name(),
+            model: provider.model().to_string(),
+            prompt_tokens: request.messages.iter().map(|m| estimate_tokens(&m.content)).sum(),
+            completion_tokens: Arc::new(AtomicI64::new(0)),
+        }
+    }
+
+    fn usage(&self) -> Usage {
+        Usage { prompt_tokens: self.prompt_tokens, completion_tokens: self.completion_tokens.load(Ordering::Relaxed) }
+    }
+
+    /// 请求已结束，用量由调用方记录 (或没有产生费用)
+    /// The request has ended and its usage is recorded by the caller (or nothing was billed)
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+
+    /// 立即记录估算的用量
+    /// Record the estimated usage right away
+    async fn record(mut self) {
+        self.armed = false;
+        info!("AI {} request cancelled, recording estimated usage", self.feature);
+        record_usage(&self.pool, self.user_id, &self.feature, self.provider, &self.model, Some(self.usage()), false).await;
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!("AI {} request dropped outside the runtime, usage not recorded", self.feature);
+            return;
+        };
+        let pending = Pending {
+            armed: false,
+            pool: self.pool.clone(),
+            feature: std::mem::take(&mut self.feature),
+            model: std::mem::take(&mut self.model),
+            completion_tokens: self.completion_tokens.clone(),
+            ..*self
+        };
+        runtime.spawn(pending.record());
+    }
+}
+
+/// 记录用量并写入缓存
+/// Record the usage and store the reply in the cache
+async fn finish(
+    pool: &DbPool,
+    user_id: i64,
+    feature: &str,
+    provider: &dyn LlmProvider,
+    (key, generation): (String, u64),
+    completion: Completion,
+) -> Result<String, LlmError> {
+    record(pool, user_id, feature, provider, completion.usage, false).await;
+    let mut cache = CACHE.lock();
+    if cache.generation == generation && cache.entries.insert(key.clone(), completion.content.clone()).is_none() {
+        cache.order.push_back(key);
+        while cache.order.len() > CACHE_CAPACITY {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.entries.remove(&oldest);
+            }
+        }
+    }
+    Ok(completion.content)
+}
+
+/// 查找缓存的回复，同时返回当前的缓存代数
+/// Look up a cached reply, also returning the current cache generation
+fn lookup(key: &str) -> (Option<String>, u64) {
+    let cache = CACHE.lock();
+    (cache.entries.get(key).cloned(), cache.generation)
+}
+
+/// 缓存键：提供方、模型、温度、消息 (包含提示词与数据) 与输出模式的哈希
+/// Cache key: hash of the provider, model, temperature, messages (prompt and data) and output schema
+fn cache_key(provider: &dyn LlmProvider, request: &ChatRequest) -> String {
+    let material = serde_json::json!({
+        "provider": provider.name(),
+        "model": provider.model(),
+        "temperature": request.temperature,
+        "messages": request.messages,
+        "schema": request.schema.as_ref().map(|s| (s.name, &s.schema)),
+    });
+    hex::encode(Sha256::digest(material.to_string().as_bytes()))
+}
+
+/// 当前月份 (按配置的时区)，格式 YYYY-MM
+/// Current month (in the configured timezone) as YYYY-MM
+fn current_month() -> String {
+    dates::today().format("%Y-%m").to_string()
+}
+
+/// 查找模型单价：先精确匹配，再取最长的前缀匹配 (如 `gpt-4o-mini-2024-07-18`)
+/// Look up a model's price: exact match first, then the longest prefix match (such as
+/// `gpt-4o-mini-2024-07-18`)
+fn price_of(model: &str) -> Option<Price> {
+    PRICES.get(model).copied().or_else(|| {
+        PRICES
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| *price)
+    })
+}
+
+/// 估算费用；价格未知时为 0
+/// Estimate the cost; zero when the price is unknown
+fn estimate_cost(model: &str, usage: Usage) -> f64 {
+    price_of(model).map_or(0.0, |price| {
+        (usage.prompt_tokens as f64 * price.input + usage.completion_tokens as f64 * price.output) / 1_000_000.0
+    })
+}
+
+async fn month_cost(pool: &DbPool, month: &str) -> Result<f64, sqlx::Error> {
+    let (cost,): (f64,) = sqlx::query_as("SELECT COALESCE(SUM(cost), 0.0) FROM ai_usage WHERE month = ?")
+        .bind(month)
+        .fetch_one(pool)
+        .await?;
+    Ok(cost)
+}
+
+/// 写入一条用量记录；失败只记录日志，不影响调用方
+/// Write one usage row; failures are only logged and do not affect the caller
+async fn record(
+    pool: &DbPool,
+    user_id: i64,
+    feature: &str,
+    provider: &dyn LlmProvider,
+    usage: Option<Usage>,
+    cached: bool,
+) {
+    record_usage(pool, user_id, feature, provider.name(), provider.model(), usage, cached).await;
+}
+
+async fn record_usage(
+    pool: &DbPool,
+    user_id: i64,
+    feature: &str,
+    provider: &str,
+    model: &str,
+    usage: Option<Usage>,
+    cached: bool,
+) {
+    let usage = usage.unwrap_or_default();
+    let cost = estimate_cost(model, usage);
+    let month = current_month();
+    let result = sqlx::query(
+        "INSERT INTO ai_usage (user_id, feature, provider, model, prompt_tokens, completion_tokens, cost, cached, month) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(feature)
+    .bind(provider)
+    .bind(model)
+    .bind(usage.prompt_tokens)
+    .bind(usage.completion_tokens)
+    .bind(cost)
+    .bind(cached)
+    .bind(&month)
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        warn!("Failed to record AI usage: {}", e);
+        return;
+    }
+
+    if let (Some(budget), true) = (*BUDGET, cost > 0.0) {
+        if let Ok(spent) = month_cost(pool, &month).await {
+            if spent >= budget && spent - cost < budget {
+                warn!(
+                    "AI monthly budget reached ({:.4} / {:.2} {}), AI features now run in mock mode",
+                    spent, budget, PRICE_CURRENCY
+                );
+            }
+        }
+    }
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    /// 统计月份 YYYY-MM，默认当前月份
+    /// Month to report as YYYY-MM, defaults to the current month
+    pub month: Option<String>,
+}
+
+/// 一组用量的汇总
+/// Totals of a group of usage rows
+#[derive(Debug, Default, Serialize, FromRow)]
+pub struct UsageTotals {
+    pub key: String,
+    pub requests: i64,
+    pub cached_requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub cost: f64,
+}
+
+/// 月度 AI 用量报告
+/// Monthly AI usage report
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub month: String,
+    pub currency: &'static str,
+    /// 月度预算与全站本月已用费用
+    /// Monthly budget and the whole site's spend this month
+    pub budget: Option<f64>,
+    pub spent: f64,
+    pub budget_exhausted: bool,
+    /// 报告范围内的合计 (管理员为全站，其他用户为本人)
+    /// Totals within the report scope (the whole site for admins, otherwise the user's own)
+    pub totals: UsageTotals,
+    pub by_model: Vec<UsageTotals>,
+    pub by_feature: Vec<UsageTotals>,
+}
+
+/// 月度 AI 用量 (GET /api/ai/usage?month=YYYY-MM)
+/// Monthly AI usage
+///
+/// 管理员看到全站用量，其他用户只看到自己的请求；预算与已用费用始终是全站数据。
+/// Admins see the whole site's usage and other users only their own requests; the budget and
+/// spend are always site-wide.
+pub async fn get_usage(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsageReport>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+    let month = match query.month {
+        Some(month) if chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").is_ok() => month,
+        Some(_) => return Err((StatusCode::BAD_REQUEST, "month must be YYYY-MM".to_string())),
+        None => current_month(),
+    };
+    let all_users = user.has_scope(Scope::Admin);
+
+    let group = |key: &'static str| {
+        let sql = format!(
+            "SELECT {} AS key, COUNT(*) AS requests, COALESCE(SUM(cached), 0) AS cached_requests, \
+             COALESCE(SUM(prompt_tokens), 0) AS prompt_tokens, COALESCE(SUM(completion_tokens), 0) AS completion_tokens, \
+             COALESCE(SUM(cost), 0.0) AS cost \
+             FROM ai_usage WHERE month = ? AND (? OR user_id = ?) GROUP BY 1 ORDER BY cost DESC, requests DESC",
+            key
+        );
+        let pool = pool.clone();
+        let month = month.clone();
+        async move {
+            sqlx::query_as::<_, UsageTotals>(&sql)
+                .bind(month)
+                .bind(all_users)
+                .bind(user.user_id)
+                .fetch_all(&pool)
+                .await
+        }
+    };
+    let by_model = group("provider || '/' || model").await.map_err(db_error)?;
+    let by_feature = group("feature").await.map_err(db_error)?;
+
+    let mut totals = UsageTotals { key: "total".to_string(), ..Default::default() };
+    for row in &by_model {
+        totals.requests += row.requests;
+        totals.cached_requests += row.cached_requests;
+        totals.prompt_tokens += row.prompt_tokens;
+        totals.completion_tokens += row.completion_tokens;
+        totals.cost += row.cost;
+    }
+
+    let spent = month_cost(&pool, &month).await.map_err(db_error)?;
+    Ok(Json(UsageReport {
+        month,
+        currency: PRICE_CURRENCY,
+        budget: *BUDGET,
+        spent,
+        budget_exhausted: BUDGET.is_some_and(|b| spent >= b),
+        totals,
+        by_model,
+        by_feature,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::llm::ChatMessage;
+    use async_trait::async_trait;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    /// 测试用提供方：流式回复按 `deltas` 逐段发送，`hang` 时发送完后一直等待
+    /// Test provider: streams `deltas` one by one and, with `hang`, then waits forever
+    #[derive(Default)]
+    struct Fake {
+        calls: AtomicUsize,
+        deltas: Vec<&'static str>,
+        hang: bool,
+        clear_cache: bool,
+    }
+
+    #[async_trait]
+    impl LlmProvider for Fake {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn model(&self) -> &str {
+            "gpt-4o-mini"
+        }
+
+        fn supports_schema(&self) -> bool {
+            false
+        }
+
+        async fn chat(&self, _request: &ChatRequest) -> Result<Completion, LlmError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if self.clear_cache {
+                clear_cache();
+            }
+            Ok(Completion { content: format!("reply {}", call), usage: Some(Usage { prompt_tokens: 100, completion_tokens: 20 }) })
+        }
+
+        async fn chat_stream(&self, _request: &ChatRequest, on_delta: &mut OnDelta<'_>) -> Result<Completion, LlmError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            for delta in &self.deltas {
+                if !on_delta(delta) {
+                    return Err(LlmError::Cancelled);
+                }
+            }
+            if self.hang {
+                std::future::pending::<()>().await;
+            }
+            Ok(Completion { content: self.deltas.concat(), usage: None })
+        }
+    }
+
+    fn request(text: &str, temperature: f32) -> ChatRequest {
+        ChatRequest { messages: vec![ChatMessage::system("You are helpful."), ChatMessage::user(text)], temperature, schema: None }
+    }
+
+    async fn usage_rows(pool: &DbPool) -> Vec<(String, i64, i64, bool)> {
+        sqlx::query_as("SELECT feature, prompt_tokens, completion_tokens, cached FROM ai_usage ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn looks_up_prices() {
+        let input = |model: &str| price_of(model).map(|p| p.input);
+        assert_eq!(input("gpt-4o"), Some(2.5));
+        assert_eq!(input("gpt-4o-mini"), Some(0.15));
+        // 最长前缀优先 / The longest prefix wins
+        assert_eq!(input("gpt-4o-mini-2024-07-18"), Some(0.15));
+        assert_eq!(input("gpt-4o-2024-08-06"), Some(2.5));
+        assert_eq!(input("claude-3-5-sonnet-20241022"), Some(3.0));
+        assert_eq!(input("llama3"), None);
+        assert_eq!(input("my-gpt-4o"), None);
+    }
+
+    #[test]
+    fn estimates_costs() {
+        let usage = |prompt_tokens, completion_tokens| Usage { prompt_tokens, completion_tokens };
+        assert!((estimate_cost("gpt-4o", usage(1_000_000, 1_000_000)) - 12.5).abs() < 1e-9);
+        assert!((estimate_cost("gpt-4o-mini-2024-07-18", usage(2_000, 500)) - 0.0006).abs() < 1e-12);
+        assert_eq!(estimate_cost("gpt-4o", usage(0, 0)), 0.0);
+        assert_eq!(estimate_cost("llama3", usage(1_000_000, 1_000_000)), 0.0);
+        assert_eq!((estimate_tokens("hello world"), estimate_tokens("你好"), estimate_tokens("")), (3, 2, 0));
+    }
+
+    /// 相同请求命中缓存；参数不同、缓存被清空或请求期间缓存被清空时不命中
+    /// Identical requests hit the cache; different parameters, a cleared cache or a clear during
+    /// the request miss it
+    #[tokio::test]
+    async fn caches_replies() {
+        let pool = db::test_pool().await;
+        let fake = Fake::default();
+        let first = request("cache test: how much do I spend?", 0.2);
+
+        assert_eq!(chat(&pool, 1, "analyze", &fake, &first).await.unwrap(), "reply 1");
+        assert_eq!(chat(&pool, 1, "analyze", &fake, &first).await.unwrap(), "reply 1");
+        assert_eq!(chat(&pool, 1, "analyze", &fake, &request("cache test: how much do I spend?", 0.7)).await.unwrap(), "reply 2");
+        let mut deltas = Vec::new();
+        let streamed = chat_stream(&pool, 1, "analyze", &fake, &first, &mut |d: &str| {
+            deltas.push(d.to_string());
+            true
+        })
+        .await
+        .unwrap();
+        assert_eq!((streamed.as_str(), deltas), ("reply 1", vec!["reply 1".to_string()]));
+        assert_eq!(fake.calls.load(Ordering::SeqCst), 2);
+
+        clear_cache();
+        assert_eq!(chat(&pool, 1, "analyze", &fake, &first).await.unwrap(), "reply 3");
+
+        // 请求期间缓存被清空，回复基于旧数据，不写入缓存
+        // The cache was cleared during the request, so the reply is based on stale data and not stored
+        let clearing = Fake { clear_cache: true, ..Fake::default() };
+        let stale = request("cache test: clear while running", 0.2);
+        assert_eq!(chat(&pool, 1, "analyze", &clearing, &stale).await.unwrap(), "reply 1");
+        assert_eq!(chat(&pool, 1, "analyze", &clearing, &stale).await.unwrap(), "reply 2");
+
+        let rows = usage_rows(&pool).await;
+        assert_eq!(rows.iter().filter(|r| r.3).count(), 2);
+        assert_eq!(rows[0], ("analyze".to_string(), 100, 20, false));
+    }
+
+    /// 接收方断开后，按提示词与已收到的增量记录估算用量
+    /// After the receiver goes away, the usage estimated from the prompt and received deltas is
+    /// recorded
+    #[tokio::test]
+    async fn records_cancelled_streams() {
+        let pool = db::test_pool().await;
+        let fake = Fake { deltas: vec!["Spend ", "less on ", "video"], ..Fake::default() };
+        let mut received = 0;
+        let result = chat_stream(&pool, 1, "analyze", &fake, &request("cancel test", 0.2), &mut |_: &str| {
+            received += 1;
+            received < 2
+        })
+        .await;
+        assert!(matches!(result, Err(LlmError::Cancelled)));
+        assert_eq!(usage_rows(&pool).await, vec![("analyze".to_string(), 7, 4, false)]);
+    }
+
+    /// 请求被中止 (future 被丢弃) 时同样记录估算用量
+    /// An aborted request (its future dropped) records the estimated usage too
+    #[tokio::test]
+    async fn records_dropped_streams() {
+        let pool = db::test_pool().await;
+        let fake = Fake { deltas: vec!["Spend less ", "on video"], hang: true, ..Fake::default() };
+        let (request, mut on_delta) = (request("drop test", 0.2), |_: &str| true);
+        let stream = chat_stream(&pool, 1, "analyze", &fake, &request, &mut on_delta);
+        assert!(tokio::time::timeout(Duration::from_millis(50), stream).await.is_err());
+
+        let mut rows = Vec::new();
+        for _ in 0..100 {
+            rows = usage_rows(&pool).await;
+            if !rows.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(rows, vec![("analyze".to_string(), 7, 5, false)]);
+    }
+}
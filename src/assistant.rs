@@ -0,0 +1,977 @@
+//! 对话助手模块
+//! Conversational assistant module
+//!
+//! 用户可以用自然语言询问订阅数据 ("我每年在视频上花多少钱？") 或要求修改 ("把 Netflix 暂停到三月")。
+//! 模型每一步返回一个 JSON 对象：要么是给用户的回复，要么是一次工具调用。只读工具 (列出订阅、汇总支出)
+//! 在服务端直接执行并把结果交回模型；写工具 (创建、修改、暂停、恢复) 只生成待确认操作，用户确认后才会
+//! 通过与 REST 接口相同的处理函数执行。对话记录按用户保存在数据库中。
+//! Users can ask about their subscriptions in natural language ("what do I pay for video per
+//! year?") or ask for changes ("pause Netflix until March"). At every step the model returns one
+//! JSON object: either a reply to the user or a tool call. Read-only tools (listing subscriptions,
+//! summarising spending) run on the server and their results go back to the model; write tools
+//! (create, update, pause, resume) only produce a pending action, which runs through the same
+//! handlers as the REST API once the user confirms it. Conversations are stored per user.
+
+use crate::ai_usage;
+use crate::auth::{AuthUser, Role};
+use crate::dates;
+use crate::db::DbPool;
+use crate::handlers::{self, PauseRequest};
+use crate::households::VISIBLE_TO_USER;
+use crate::insights;
+use crate::llm::{ChatMessage, ChatRequest, LlmProvider, OutputSchema};
+use crate::models::{ChatAction, ChatConversation, ChatMessageRecord, CreateSubscription, Subscription};
+use crate::prompts::{self, get_prompts, PromptContext};
+use crate::smart_parse::{extract_json, normalize_date};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/// 每条用户消息最多的模型调用次数 (工具调用后需要再次调用模型)
+/// Maximum model calls per user message (each tool call needs another model call)
+const MAX_STEPS: usize = 6;
+
+/// 发送给模型的历史消息条数上限
+/// Maximum number of history messages sent to the model
+const HISTORY_LIMIT: i64 = 40;
+
+/// 用户消息长度上限 (字符)
+/// Maximum user message length (characters)
+const MAX_MESSAGE_CHARS: usize = 4000;
+
+/// 只读工具，直接执行
+/// Read-only tools, run immediately
+const READ_TOOLS: [&str; 2] = ["list_subscriptions", "summarize_spending"];
+
+/// 写工具，需要用户确认
+/// Write tools, which need the user's confirmation
+const WRITE_TOOLS: [&str; 4] = ["create_subscription", "update_subscription", "pause_subscription", "resume_subscription"];
+
+/// 修改订阅时允许更改的字段
+/// Fields that may be changed when updating a subscription
+const EDITABLE_FIELDS: [&str; 8] = ["name", "price", "currency", "next_payment", "frequency", "url", "start_date", "category"];
+
+/// 回复格式与工具说明，追加在可配置的系统提示词之后
+/// Reply format and tool descriptions, appended to the configurable system prompt
+const TOOL_CONTRACT: &str = r#"Reply with ONLY a JSON object {"message": string, "tool": string or null, "arguments": object or null}.
+Set "tool" to call one of the tools below; the result comes back in the next message, and "message" may briefly say what you are doing. Set "tool" to null to answer the user in "message".
+Tools:
+- list_subscriptions {"query"?: name substring, "category"?: string, "include_paused"?: boolean (default true)}: subscriptions visible to the user, with ids.
+- summarize_spending {"category"?: string}: monthly and yearly totals of active recurring subscriptions, per currency and per category.
+- create_subscription {"name", "price", "currency", "frequency" (-1 daily, 1 monthly, 3 quarterly, 12 yearly, 0 lifetime), "start_date"?, "next_payment"?, "url"?, "category"?}
+- update_subscription {"id", plus only the fields to change among name, price, currency, frequency, start_date, next_payment, url, category}
+- pause_subscription {"id", "until"?: "YYYY-MM-DD" (omit to pause indefinitely)}
+- resume_subscription {"id"}
+create, update, pause and resume are not applied directly: the user is asked to confirm them, so do not claim they are done."#;
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// 结构化输出模式：每一步的回复
+/// Structured output schema: the reply of each step
+fn step_schema() -> OutputSchema {
+    let tools: Vec<Value> = READ_TOOLS.iter().chain(WRITE_TOOLS.iter()).map(|t| json!(t)).chain([Value::Null]).collect();
+    OutputSchema {
+        name: "assistant_step",
+        schema: json!({
+            "type": "object",
+            "properties": {
+                "message": { "type": "string" },
+                "tool": { "type": ["string", "null"], "enum": tools },
+                "arguments": { "type": ["object", "null"] },
+            },
+            "required": ["message", "tool", "arguments"],
+        }),
+    }
+}
+
+/// 模型的一步回复
+/// One step of the model's reply
+#[derive(Deserialize)]
+struct Step {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    tool: Option<String>,
+    #[serde(default)]
+    arguments: Option<Value>,
+}
+
+impl Step {
+    /// 解析模型回复；不是 JSON 时把整段文本当作给用户的回复
+    /// Parse the model reply; text that is not JSON is taken as the reply to the user
+    fn parse(content: &str) -> Step {
+        serde_json::from_str(extract_json(content)).unwrap_or_else(|_| Step {
+            message: content.trim().to_string(),
+            tool: None,
+            arguments: None,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChatInput {
+    /// 继续的对话 ID，为空时新建对话
+    /// Conversation to continue; a new one is started when empty
+    pub conversation_id: Option<i64>,
+    pub message: String,
+}
+
+/// 助手回复：本轮新增的消息与 (待确认或刚处理的) 操作
+/// Assistant reply: the messages added in this turn and the action (pending or just handled)
+#[derive(Serialize)]
+pub struct ChatReply {
+    pub conversation_id: i64,
+    pub messages: Vec<ChatMessageRecord>,
+    pub action: Option<ChatAction>,
+}
+
+/// 对话详情
+/// Conversation details
+#[derive(Serialize)]
+pub struct ConversationDetail {
+    pub conversation: ChatConversation,
+    pub messages: Vec<ChatMessageRecord>,
+    pub actions: Vec<ChatAction>,
+}
+
+/// 发送消息给助手 (POST /api/chat)
+/// Send a message to the assistant
+///
+/// 与智能解析相同，调用 LLM 需要 `editor` 角色；未配置 LLM (或预算已用完) 时返回 503。
+/// 对话中仍待确认的操作会在用户发送新消息时自动取消。
+/// As with smart parse, calling the LLM requires the `editor` role; without an LLM (or with the
+/// budget used up) this returns 503. Actions still awaiting confirmation are cancelled when the
+/// user sends a new message.
+pub async fn chat(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(input): Json<ChatInput>,
+) -> Result<Json<ChatReply>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    let message = input.message.trim();
+    if message.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Message is required".to_string()));
+    }
+    if message.chars().count() > MAX_MESSAGE_CHARS {
+        return Err((StatusCode::BAD_REQUEST, format!("Message is longer than {} characters", MAX_MESSAGE_CHARS)));
+    }
+    let Some(provider) = ai_usage::provider(&pool).await else {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "The assistant needs an LLM provider".to_string()));
+    };
+    converse(&pool, &user, provider, input.conversation_id, message).await.map(Json)
+}
+
+/// 处理一条用户消息：依次调用模型并执行只读工具，直到模型回复用户或提出写操作
+/// Handle one user message: call the model and run read-only tools until the model replies to the
+/// user or proposes a write action
+async fn converse(
+    pool: &DbPool,
+    user: &AuthUser,
+    provider: &dyn LlmProvider,
+    conversation_id: Option<i64>,
+    message: &str,
+) -> Result<ChatReply, (StatusCode, String)> {
+    let conversation_id = match conversation_id {
+        Some(id) => owned_conversation(pool, user.user_id, id).await?.id,
+        None => {
+            let title: String = message.chars().take(60).collect();
+            sqlx::query("INSERT INTO chat_conversations (user_id, title) VALUES (?, ?)")
+                .bind(user.user_id)
+                .bind(title)
+                .execute(pool)
+                .await
+                .map_err(db_error)?
+                .last_insert_rowid()
+        }
+    };
+    let first_id = append(pool, conversation_id, "user", message, None, &Value::Null).await.map_err(db_error)?;
+    cancel_pending(pool, conversation_id).await.map_err(db_error)?;
+
+    let context = PromptContext::load(pool, user.user_id).await.map_err(db_error)?;
+    let system = format!("{}\n\n{}", context.render(&get_prompts().chat_system, &[]), TOOL_CONTRACT);
+    let schema = provider.supports_schema().then(step_schema);
+
+    let mut action = None;
+    let mut finished = false;
+    for _ in 0..MAX_STEPS {
+        let mut messages = vec![ChatMessage::system(system.clone())];
+        messages.extend(history(pool, conversation_id).await.map_err(db_error)?);
+        let request = ChatRequest { messages, temperature: 0.2, schema: schema.clone() };
+        let content = match ai_usage::chat(pool, user.user_id, "chat", provider, &request).await {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("LLM chat call failed: {}", e);
+                append(pool, conversation_id, "assistant", "助手暂时无法回复，请稍后再试。", None, &Value::Null)
+                    .await
+                    .map_err(db_error)?;
+                finished = true;
+                break;
+            }
+        };
+
+        let step = Step::parse(&content);
+        let Some(tool) = step.tool.filter(|t| !t.is_empty()) else {
+            append(pool, conversation_id, "assistant", &step.message, None, &Value::Null).await.map_err(db_error)?;
+            finished = true;
+            break;
+        };
+        let arguments = step.arguments.unwrap_or_else(|| json!({}));
+        append(pool, conversation_id, "assistant", &step.message, Some(&tool), &arguments).await.map_err(db_error)?;
+
+        if WRITE_TOOLS.contains(&tool.as_str()) {
+            match prepare(pool, user, &tool, &arguments).await? {
+                Ok((arguments, summary)) => {
+                    action = Some(propose(pool, conversation_id, user.user_id, &tool, &arguments, &summary).await?);
+                    finished = true;
+                    break;
+                }
+                Err(error) => {
+                    append(pool, conversation_id, "tool", &error, Some(&tool), &json!({ "error": error }))
+                        .await
+                        .map_err(db_error)?;
+                }
+            }
+        } else {
+            let (content, result) = match run_read_tool(pool, user, &tool, &arguments).await? {
+                Ok(result) => (tool.clone(), result),
+                Err(error) => (error.clone(), json!({ "error": error })),
+            };
+            append(pool, conversation_id, "tool", &content, Some(&tool), &result).await.map_err(db_error)?;
+        }
+    }
+    if !finished {
+        append(pool, conversation_id, "assistant", "这个问题需要的步骤太多，请换个更具体的问法。", None, &Value::Null)
+            .await
+            .map_err(db_error)?;
+    }
+
+    sqlx::query("UPDATE chat_conversations SET updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+        .bind(conversation_id)
+        .execute(pool)
+        .await
+        .map_err(db_error)?;
+    let messages = sqlx::query_as::<_, ChatMessageRecord>(
+        "SELECT id, role, content, tool, payload, created_at FROM chat_messages WHERE conversation_id = ? AND id >= ? ORDER BY id",
+    )
+    .bind(conversation_id)
+    .bind(first_id)
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)?;
+    Ok(ChatReply { conversation_id, messages, action })
+}
+
+/// 确认并执行待确认操作 (POST /api/chat/actions/:id/confirm)
+/// Confirm and run a pending action
+///
+/// 通过与 REST 接口相同的处理函数执行，因此角色、所有权与校验规则完全一致。
+/// Runs through the same handlers as the REST API, so roles, ownership and validation are
+/// exactly the same.
+pub async fn confirm_action(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<ChatReply>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    let action = claim(&pool, user.user_id, id, "confirmed").await?;
+
+    let (status, result) = match execute(&pool, &user, &action.tool, &action.arguments).await {
+        Ok(sub) => ("confirmed", serde_json::to_value(sub).unwrap_or_default()),
+        Err((_, error)) => ("failed", json!({ "error": error })),
+    };
+    finish(&pool, action, status, result).await
+}
+
+/// 取消待确认操作 (POST /api/chat/actions/:id/cancel)
+/// Cancel a pending action
+pub async fn cancel_action(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<ChatReply>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    let action = claim(&pool, user.user_id, id, "cancelled").await?;
+    finish(&pool, action, "cancelled", Value::Null).await
+}
+
+/// 列出当前用户的对话 (GET /api/chat/conversations)
+/// List the current user's conversations
+pub async fn list_conversations(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<Vec<ChatConversation>>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+    let conversations = sqlx::query_as::<_, ChatConversation>(
+        "SELECT id, title, created_at, updated_at FROM chat_conversations WHERE user_id = ? ORDER BY updated_at DESC, id DESC",
+    )
+    .bind(user.user_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+    Ok(Json(conversations))
+}
+
+/// 查看对话的全部消息与操作 (GET /api/chat/conversations/:id)
+/// Get all messages and actions of a conversation
+pub async fn get_conversation(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<ConversationDetail>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+    let conversation = owned_conversation(&pool, user.user_id, id).await?;
+    let messages = sqlx::query_as::<_, ChatMessageRecord>(
+        "SELECT id, role, content, tool, payload, created_at FROM chat_messages WHERE conversation_id = ? ORDER BY id",
+    )
+    .bind(id)
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+    let actions = sqlx::query_as::<_, ChatAction>(
+        "SELECT id, conversation_id, tool, arguments, summary, status, result, created_at FROM chat_actions \
+         WHERE conversation_id = ? ORDER BY id",
+    )
+    .bind(id)
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+    Ok(Json(ConversationDetail { conversation, messages, actions }))
+}
+
+/// 删除对话 (DELETE /api/chat/conversations/:id)
+/// Delete a conversation
+pub async fn delete_conversation(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+    let result = sqlx::query("DELETE FROM chat_conversations WHERE id = ? AND user_id = ?")
+        .bind(id)
+        .bind(user.user_id)
+        .execute(&pool)
+        .await
+        .map_err(db_error)?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Conversation not found".to_string()));
+    }
+    Ok(Json(json!({ "status": "deleted" })))
+}
+
+async fn owned_conversation(pool: &DbPool, user_id: i64, id: i64) -> Result<ChatConversation, (StatusCode, String)> {
+    sqlx::query_as::<_, ChatConversation>(
+        "SELECT id, title, created_at, updated_at FROM chat_conversations WHERE id = ? AND user_id = ?",
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(db_error)?
+    .ok_or((StatusCode::NOT_FOUND, "Conversation not found".to_string()))
+}
+
+/// 追加一条消息，返回其 ID
+/// Append a message, returning its ID
+async fn append(
+    pool: &DbPool,
+    conversation_id: i64,
+    role: &str,
+    content: &str,
+    tool: Option<&str>,
+    payload: &Value,
+) -> Result<i64, sqlx::Error> {
+    Ok(sqlx::query("INSERT INTO chat_messages (conversation_id, role, content, tool, payload) VALUES (?, ?, ?, ?, ?)")
+        .bind(conversation_id)
+        .bind(role)
+        .bind(content)
+        .bind(tool)
+        .bind(payload.to_string())
+        .execute(pool)
+        .await?
+        .last_insert_rowid())
+}
+
+/// 读取最近的历史消息并转换为模型消息
+/// Load the recent history and convert it to model messages
+///
+/// 助手消息按模型的 JSON 回复格式还原，工具结果作为用户消息交给模型。
+/// Assistant messages are rebuilt in the model's JSON reply format, and tool results are passed
+/// to the model as user messages.
+async fn history(pool: &DbPool, conversation_id: i64) -> Result<Vec<ChatMessage>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, ChatMessageRecord>(
+        "SELECT * FROM (SELECT id, role, content, tool, payload, created_at FROM chat_messages \
+         WHERE conversation_id = ? ORDER BY id DESC LIMIT ?) ORDER BY id",
+    )
+    .bind(conversation_id)
+    .bind(HISTORY_LIMIT)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| match row.role.as_str() {
+            "assistant" => {
+                let arguments = if row.tool.is_some() { row.payload } else { Value::Null };
+                ChatMessage::assistant(json!({ "message": row.content, "tool": row.tool, "arguments": arguments }).to_string())
+            }
+            "tool" => ChatMessage::user(format!(
+                "Tool result ({}): {}",
+                row.tool.unwrap_or_default(),
+                if row.payload.is_null() { json!({ "status": row.content }) } else { row.payload }
+            )),
+            _ => ChatMessage::user(row.content),
+        })
+        .collect())
+}
+
+/// 用户发送新消息时取消仍待确认的操作，并在对话中记录
+/// Cancel actions still awaiting confirmation when the user sends a new message, noting it in the
+/// conversation
+async fn cancel_pending(pool: &DbPool, conversation_id: i64) -> Result<(), sqlx::Error> {
+    let cancelled: Vec<(String, String)> = sqlx::query_as(
+        "UPDATE chat_actions SET status = 'cancelled' WHERE conversation_id = ? AND status = 'pending' RETURNING tool, summary",
+    )
+    .bind(conversation_id)
+    .fetch_all(pool)
+    .await?;
+    for (tool, summary) in cancelled {
+        let content = format!("{} (cancelled)", summary);
+        append(pool, conversation_id, "tool", &content, Some(&tool), &json!({ "status": "cancelled" })).await?;
+    }
+    Ok(())
+}
+
+/// 记录待确认操作，并在对话中提示用户确认
+/// Record a pending action and ask the user to confirm it in the conversation
+async fn propose(
+    pool: &DbPool,
+    conversation_id: i64,
+    user_id: i64,
+    tool: &str,
+    arguments: &Value,
+    summary: &str,
+) -> Result<ChatAction, (StatusCode, String)> {
+    let action = sqlx::query_as::<_, ChatAction>(
+        "INSERT INTO chat_actions (conversation_id, user_id, tool, arguments, summary) VALUES (?, ?, ?, ?, ?) \
+         RETURNING id, conversation_id, tool, arguments, summary, status, result, created_at",
+    )
+    .bind(conversation_id)
+    .bind(user_id)
+    .bind(tool)
+    .bind(arguments.to_string())
+    .bind(summary)
+    .fetch_one(pool)
+    .await
+    .map_err(db_error)?;
+    let content = format!("{} (awaiting confirmation)", summary);
+    append(pool, conversation_id, "tool", &content, Some(tool), &json!({ "status": "pending", "action_id": action.id }))
+        .await
+        .map_err(db_error)?;
+    Ok(action)
+}
+
+/// 将待确认操作标记为新状态；已处理的操作返回 409，防止重复执行
+/// Move a pending action to a new status; already handled actions return 409, preventing double
+/// execution
+async fn claim(pool: &DbPool, user_id: i64, id: i64, status: &str) -> Result<ChatAction, (StatusCode, String)> {
+    let claimed = sqlx::query_as::<_, ChatAction>(
+        "UPDATE chat_actions SET status = ? WHERE id = ? AND user_id = ? AND status = 'pending' \
+         RETURNING id, conversation_id, tool, arguments, summary, status, result, created_at",
+    )
+    .bind(status)
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(db_error)?;
+    if let Some(action) = claimed {
+        return Ok(action);
+    }
+    let current: Option<(String,)> = sqlx::query_as("SELECT status FROM chat_actions WHERE id = ? AND user_id = ?")
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(db_error)?;
+    match current {
+        Some((status,)) => Err((StatusCode::CONFLICT, format!("Action is already {}", status))),
+        None => Err((StatusCode::NOT_FOUND, "Action not found".to_string())),
+    }
+}
+
+/// 保存操作结果并在对话中记录
+/// Store the action result and note it in the conversation
+async fn finish(
+    pool: &DbPool,
+    mut action: ChatAction,
+    status: &str,
+    result: Value,
+) -> Result<Json<ChatReply>, (StatusCode, String)> {
+    sqlx::query("UPDATE chat_actions SET status = ?, result = ? WHERE id = ?")
+        .bind(status)
+        .bind(result.to_string())
+        .bind(action.id)
+        .execute(pool)
+        .await
+        .map_err(db_error)?;
+    let content = match result["error"].as_str() {
+        Some(error) => format!("{} (failed: {})", action.summary, error),
+        None => format!("{} ({})", action.summary, status),
+    };
+    let payload = if result.is_null() { json!({ "status": status }) } else { result.clone() };
+    let message_id = append(pool, action.conversation_id, "tool", &content, Some(&action.tool), &payload)
+        .await
+        .map_err(db_error)?;
+    sqlx::query("UPDATE chat_conversations SET updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+        .bind(action.conversation_id)
+        .execute(pool)
+        .await
+        .map_err(db_error)?;
+
+    let messages = sqlx::query_as::<_, ChatMessageRecord>(
+        "SELECT id, role, content, tool, payload, created_at FROM chat_messages WHERE id = ?",
+    )
+    .bind(message_id)
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)?;
+    action.status = status.to_string();
+    action.result = result;
+    Ok(Json(ChatReply { conversation_id: action.conversation_id, messages, action: Some(action) }))
+}
+
+fn frequency_label(frequency: i64) -> &'static str {
+    match frequency {
+        -1 => "daily",
+        0 => "lifetime",
+        1 => "monthly",
+        3 => "quarterly",
+        12 => "yearly",
+        _ => "unknown",
+    }
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn category_of(sub: &Subscription) -> Option<String> {
+    sub.category.clone().or_else(|| insights::infer_category(&sub.name).map(str::to_string))
+}
+
+async fn visible_subscriptions(pool: &DbPool, user_id: i64) -> Result<Vec<Subscription>, (StatusCode, String)> {
+    sqlx::query_as::<_, Subscription>(&format!("SELECT * FROM subscriptions WHERE {} ORDER BY name", VISIBLE_TO_USER))
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .map_err(db_error)
+}
+
+/// 执行只读工具；内层错误交回模型，外层错误为数据库错误
+/// Run a read-only tool; inner errors go back to the model, outer errors are database errors
+async fn run_read_tool(
+    pool: &DbPool,
+    user: &AuthUser,
+    tool: &str,
+    arguments: &Value,
+) -> Result<Result<Value, String>, (StatusCode, String)> {
+    let text = |key: &str| arguments[key].as_str().map(|s| s.trim().to_lowercase()).filter(|s| !s.is_empty());
+    let category = text("category");
+    let subs = visible_subscriptions(pool, user.user_id).await?;
+    let in_category = |sub: &Subscription| category.is_none() || category_of(sub) == category;
+
+    match tool {
+        "list_subscriptions" => {
+            let query = text("query");
+            let include_paused = arguments["include_paused"].as_bool().unwrap_or(true);
+            let items: Vec<Value> = subs
+                .iter()
+                .filter(|sub| query.as_ref().is_none_or(|q| sub.name.to_lowercase().contains(q)))
+                .filter(|sub| in_category(sub) && (include_paused || sub.active))
+                .map(|sub| {
+                    json!({
+                        "id": sub.id,
+                        "name": sub.name,
+                        "price": sub.price,
+                        "currency": sub.currency,
+                        "frequency": frequency_label(sub.frequency),
+                        "next_payment": sub.next_payment,
+                        "category": category_of(sub),
+                        "active": sub.active,
+                        "paused_until": sub.paused_until,
+                        "owned": sub.owner_id == user.user_id,
+                    })
+                })
+                .collect();
+            Ok(Ok(json!({ "subscriptions": items })))
+        }
+        "summarize_spending" => {
+            let mut by_currency: BTreeMap<String, (i64, f64)> = BTreeMap::new();
+            let mut by_category: BTreeMap<(String, String), (i64, f64)> = BTreeMap::new();
+            for sub in subs.iter().filter(|sub| sub.active && sub.frequency != 0 && in_category(sub)) {
+                let monthly = sub.monthly_cost();
+                let total = by_currency.entry(sub.currency.clone()).or_default();
+                total.0 += 1;
+                total.1 += monthly;
+                let key = (category_of(sub).unwrap_or_else(|| "other".to_string()), sub.currency.clone());
+                let total = by_category.entry(key).or_default();
+                total.0 += 1;
+                total.1 += monthly;
+            }
+            let totals = |count: i64, monthly: f64| {
+                json!({ "count": count, "monthly": round2(monthly), "yearly": round2(monthly * 12.0) })
+            };
+            let by_currency: Vec<Value> = by_currency
+                .into_iter()
+                .map(|(currency, (count, monthly))| {
+                    let mut value = totals(count, monthly);
+                    value["currency"] = json!(currency);
+                    value
+                })
+                .collect();
+            let by_category: Vec<Value> = by_category
+                .into_iter()
+                .map(|((category, currency), (count, monthly))| {
+                    let mut value = totals(count, monthly);
+                    value["category"] = json!(category);
+                    value["currency"] = json!(currency);
+                    value
+                })
+                .collect();
+            Ok(Ok(json!({
+                "note": "Totals are per currency; no exchange rates are applied. Paused and lifetime subscriptions are excluded.",
+                "by_currency": by_currency,
+                "by_category": by_category,
+            })))
+        }
+        other => Ok(Err(format!("Unknown tool '{}'", other))),
+    }
+}
+
+/// 读取参数中当前用户拥有的订阅
+/// Load the subscription owned by the current user named in the arguments
+async fn owned_subscription(
+    pool: &DbPool,
+    user_id: i64,
+    arguments: &Value,
+) -> Result<Result<Subscription, String>, (StatusCode, String)> {
+    let Some(id) = arguments["id"].as_i64() else {
+        return Ok(Err("id is required; look it up with list_subscriptions".to_string()));
+    };
+    let sub = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ? AND owner_id = ?")
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(db_error)?;
+    Ok(sub.ok_or_else(|| format!("Subscription {} not found or not owned by the user", id)))
+}
+
+/// 规范化参数中的日期字段
+/// Normalise the date fields of the arguments
+fn normalize_dates(arguments: &mut Value, keys: &[&str]) -> Result<(), String> {
+    for key in keys {
+        match arguments.get(*key) {
+            Some(Value::String(raw)) if raw.trim().is_empty() => arguments[*key] = Value::Null,
+            Some(Value::String(raw)) => {
+                let date = normalize_date(raw).ok_or_else(|| format!("{} '{}' is not a date in YYYY-MM-DD format", key, raw))?;
+                arguments[*key] = json!(date);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// 校验写工具的参数，返回执行时使用的完整参数与供确认的说明；内层错误交回模型
+/// Validate the arguments of a write tool, returning the full arguments used on execution and a
+/// description for confirmation; inner errors go back to the model
+async fn prepare(
+    pool: &DbPool,
+    user: &AuthUser,
+    tool: &str,
+    arguments: &Value,
+) -> Result<Result<(Value, String), String>, (StatusCode, String)> {
+    let mut arguments = if arguments.is_object() { arguments.clone() } else { json!({}) };
+    match tool {
+        "create_subscription" => {
+            if let Err(e) = normalize_dates(&mut arguments, &["start_date", "next_payment"]) {
+                return Ok(Err(e));
+            }
+            if arguments["currency"].as_str().is_none_or(|c| c.trim().is_empty()) {
+                arguments["currency"] = json!(prompts::base_currency());
+            }
+            let frequency = arguments["frequency"].as_i64().unwrap_or(1);
+            arguments["frequency"] = json!(frequency);
+            if arguments["next_payment"].is_null() && frequency != 0 {
+                let start = arguments["start_date"]
+                    .as_str()
+                    .and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+                    .unwrap_or_else(dates::today);
+                arguments["start_date"] = json!(start.format("%Y-%m-%d").to_string());
+                arguments["next_payment"] =
+                    json!(dates::next_payment(start, frequency).map(|d| d.format("%Y-%m-%d").to_string()));
+            }
+            let payload: CreateSubscription = match serde_json::from_value(arguments.clone()) {
+                Ok(payload) => payload,
+                Err(e) => return Ok(Err(format!("Invalid arguments: {}", e))),
+            };
+            let (price, next_payment) = match payload.validate() {
+                Ok(validated) => validated,
+                Err(e) => return Ok(Err(e)),
+            };
+            let summary = format!(
+                "Create {}: {} {} {}, next payment {}",
+                payload.name,
+                price,
+                payload.currency,
+                frequency_label(payload.frequency),
+                next_payment.as_deref().unwrap_or("-")
+            );
+            Ok(Ok((arguments, summary)))
+        }
+        "update_subscription" => {
+            let sub = match owned_subscription(pool, user.user_id, &arguments).await? {
+                Ok(sub) => sub,
+                Err(e) => return Ok(Err(e)),
+            };
+            if let Err(e) = normalize_dates(&mut arguments, &["start_date", "next_payment"]) {
+                return Ok(Err(e));
+            }
+            let current = serde_json::to_value(&sub).unwrap_or_default();
+            let mut merged = current.clone();
+            let mut changes = Vec::new();
+            for field in EDITABLE_FIELDS {
+                if let Some(value) = arguments.get(field).filter(|v| **v != current[field]) {
+                    changes.push(format!("{} {} → {}", field, current[field], value));
+                    merged[field] = value.clone();
+                }
+            }
+            if changes.is_empty() {
+                return Ok(Err("No fields would change".to_string()));
+            }
+            let payload: CreateSubscription = match serde_json::from_value(merged.clone()) {
+                Ok(payload) => payload,
+                Err(e) => return Ok(Err(format!("Invalid arguments: {}", e))),
+            };
+            if let Err(e) = payload.validate() {
+                return Ok(Err(e));
+            }
+            Ok(Ok((merged, format!("Update {} (#{}): {}", sub.name, sub.id, changes.join(", ")))))
+        }
+        "pause_subscription" => {
+            let sub = match owned_subscription(pool, user.user_id, &arguments).await? {
+                Ok(sub) => sub,
+                Err(e) => return Ok(Err(e)),
+            };
+            if let Err(e) = normalize_dates(&mut arguments, &["until"]) {
+                return Ok(Err(e));
+            }
+            let until = arguments["until"].as_str().map(str::to_string);
+            if until.as_deref().is_some_and(|u| u <= dates::today().format("%Y-%m-%d").to_string().as_str()) {
+                return Ok(Err("until must be in the future".to_string()));
+            }
+            let summary = match &until {
+                Some(until) => format!("Pause {} (#{}) until {}", sub.name, sub.id, until),
+                None => format!("Pause {} (#{}) indefinitely", sub.name, sub.id),
+            };
+            Ok(Ok((json!({ "id": sub.id, "until": until }), summary)))
+        }
+        "resume_subscription" => {
+            let sub = match owned_subscription(pool, user.user_id, &arguments).await? {
+                Ok(sub) => sub,
+                Err(e) => return Ok(Err(e)),
+            };
+            if sub.active {
+                return Ok(Err(format!("{} is not paused", sub.name)));
+            }
+            Ok(Ok((json!({ "id": sub.id }), format!("Resume {} (#{})", sub.name, sub.id))))
+        }
+        other => Ok(Err(format!("Unknown tool '{}'", other))),
+    }
+}
+
+/// 通过 REST 接口的处理函数执行已确认的写操作
+/// Run a confirmed write action through the REST handlers
+async fn execute(
+    pool: &DbPool,
+    user: &AuthUser,
+    tool: &str,
+    arguments: &Value,
+) -> Result<Subscription, (StatusCode, String)> {
+    let id = arguments["id"].as_i64().unwrap_or_default();
+    let payload = || {
+        serde_json::from_value::<CreateSubscription>(arguments.clone()).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+    };
+    let state = State(pool.clone());
+    let user = Extension(user.clone());
+    let Json(sub) = match tool {
+        "create_subscription" => handlers::create_subscription(state, user, Json(payload()?)).await?,
+        "update_subscription" => handlers::update_subscription(state, user, Path(id), Json(payload()?)).await?,
+        "pause_subscription" => {
+            let until = arguments["until"].as_str().map(str::to_string);
+            handlers::pause_subscription(state, user, Path(id), Json(PauseRequest { until })).await?
+        }
+        "resume_subscription" => handlers::resume_subscription(state, user, Path(id)).await?,
+        other => return Err((StatusCode::BAD_REQUEST, format!("Unknown tool '{}'", other))),
+    };
+    Ok(sub)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::db;
+    use crate::llm::{Completion, LlmError, OnDelta};
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+    use std::collections::VecDeque;
+
+    /// 测试用提供方：按顺序返回预设回复，并记录收到的请求
+    /// Test provider: returns scripted replies in order and records the requests it receives
+    #[derive(Default)]
+    struct Scripted {
+        replies: Mutex<VecDeque<String>>,
+        requests: Mutex<Vec<Vec<ChatMessage>>>,
+    }
+
+    impl Scripted {
+        fn new(replies: &[Value]) -> Self {
+            let replies = replies.iter().map(|r| r.as_str().map_or_else(|| r.to_string(), str::to_string)).collect();
+            Scripted { replies: Mutex::new(replies), ..Scripted::default() }
+        }
+    }
+
+    #[async_trait]
+    impl LlmProvider for Scripted {
+        fn name(&self) -> &'static str {
+            "scripted"
+        }
+
+        fn model(&self) -> &str {
+            "scripted"
+        }
+
+        fn supports_schema(&self) -> bool {
+            false
+        }
+
+        async fn chat(&self, request: &ChatRequest) -> Result<Completion, LlmError> {
+            self.requests.lock().push(request.messages.clone());
+            let content = self.replies.lock().pop_front().expect("no scripted reply left");
+            Ok(Completion { content, usage: None })
+        }
+
+        async fn chat_stream(&self, request: &ChatRequest, _on_delta: &mut OnDelta<'_>) -> Result<Completion, LlmError> {
+            self.chat(request).await
+        }
+    }
+
+    fn call(tool: &str, arguments: Value) -> Value {
+        json!({ "message": "", "tool": tool, "arguments": arguments })
+    }
+
+    fn admin() -> AuthUser {
+        AuthUser { user_id: 1, role: Role::Admin, scopes: vec![Scope::Admin] }
+    }
+
+    /// 只读工具直接执行并把结果交回模型；写工具只生成待确认操作，确认一次后不能再次确认
+    /// Read-only tools run immediately and their result goes back to the model; write tools only
+    /// produce a pending action, which cannot be confirmed a second time
+    #[tokio::test]
+    async fn confirms_write_actions_once() {
+        let pool = db::test_pool().await;
+        let payload: CreateSubscription = serde_json::from_value(json!({
+            "name": "Assistant Stream", "price": 12, "currency": "USD", "frequency": 1, "next_payment": "2026-11-01"
+        }))
+        .unwrap();
+        let state = State(pool.clone());
+        let Json(sub) = handlers::create_subscription(state, Extension(admin()), Json(payload)).await.unwrap();
+
+        let provider = Scripted::new(&[
+            call("list_subscriptions", json!({ "query": "assistant stream" })),
+            call("pause_subscription", json!({ "id": sub.id, "until": "2099-03-01" })),
+        ]);
+        let message = "Pause Assistant Stream until March 2099";
+        let reply = converse(&pool, &admin(), &provider, None, message).await.unwrap();
+        let roles: Vec<&str> = reply.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "tool", "assistant", "tool"]);
+        let listed = &reply.messages[2].payload["subscriptions"];
+        assert_eq!(listed.as_array().map(Vec::len), Some(1));
+        assert_eq!(listed[0]["id"], json!(sub.id));
+        // 第二次调用模型时带上了工具结果 / The second model call carries the tool result
+        let requests = provider.requests.lock().clone();
+        assert!(requests[1].last().unwrap().content.starts_with("Tool result (list_subscriptions)"));
+
+        let action = reply.action.unwrap();
+        assert_eq!((action.tool.as_str(), action.status.as_str()), ("pause_subscription", "pending"));
+        assert_eq!(action.arguments, json!({ "id": sub.id, "until": "2099-03-01" }));
+        let active: bool = sqlx::query_scalar("SELECT active FROM subscriptions WHERE id = ?")
+            .bind(sub.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(active, "a write tool must not run before confirmation");
+
+        let Json(confirmed) = confirm_action(State(pool.clone()), Extension(admin()), Path(action.id)).await.unwrap();
+        let confirmed = confirmed.action.unwrap();
+        assert_eq!(confirmed.status, "confirmed");
+        assert_eq!(confirmed.result["active"], json!(false));
+        assert_eq!(confirmed.result["paused_until"], json!("2099-03-01"));
+
+        let again = confirm_action(State(pool.clone()), Extension(admin()), Path(action.id)).await.err().unwrap();
+        assert_eq!(again.0, StatusCode::CONFLICT);
+        let cancel = cancel_action(State(pool.clone()), Extension(admin()), Path(action.id)).await.err().unwrap();
+        assert_eq!(cancel.0, StatusCode::CONFLICT);
+        let other = AuthUser { user_id: 2, ..admin() };
+        let missing = confirm_action(State(pool.clone()), Extension(other), Path(action.id)).await.err().unwrap();
+        assert_eq!(missing.0, StatusCode::NOT_FOUND);
+    }
+
+    /// 未知工具与无效的写参数作为错误交回模型；非 JSON 回复直接作为给用户的回复；
+    /// 新消息取消待确认操作
+    /// Unknown tools and invalid write arguments go back to the model as errors; a reply that is
+    /// not JSON is the answer to the user; a new message cancels the pending action
+    #[tokio::test]
+    async fn dispatches_tools() {
+        let pool = db::test_pool().await;
+        let provider = Scripted::new(&[
+            call("drop_tables", json!({})),
+            call("update_subscription", json!({ "price": 5 })),
+            json!("I could not find that subscription."),
+            call("create_subscription", json!({ "name": "Dispatch Music", "price": 5, "frequency": 1 })),
+            json!({ "message": "Nothing else to do.", "tool": null, "arguments": null }),
+        ]);
+        let reply = converse(&pool, &admin(), &provider, None, "Make my music cheaper").await.unwrap();
+        assert!(reply.action.is_none());
+        let errors: Vec<&str> = reply.messages.iter().filter_map(|m| m.payload["error"].as_str()).collect();
+        assert_eq!(errors, vec!["Unknown tool 'drop_tables'", "id is required; look it up with list_subscriptions"]);
+        assert_eq!(reply.messages.last().unwrap().content, "I could not find that subscription.");
+
+        let conversation_id = Some(reply.conversation_id);
+        let reply = converse(&pool, &admin(), &provider, conversation_id, "Then add Dispatch Music").await.unwrap();
+        let action = reply.action.unwrap();
+        assert_eq!(action.arguments["currency"], json!(prompts::base_currency()));
+        assert!(action.arguments["next_payment"].is_string());
+
+        let reply = converse(&pool, &admin(), &provider, conversation_id, "Never mind").await.unwrap();
+        assert!(reply.action.is_none());
+        assert_eq!(reply.messages[1].payload, json!({ "status": "cancelled" }));
+        let status: String = sqlx::query_scalar("SELECT status FROM chat_actions WHERE id = ?")
+            .bind(action.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "cancelled");
+        assert!(provider.replies.lock().is_empty());
+    }
+}
@@ -0,0 +1,715 @@
+//! 认证与个人访问令牌模块
+//! Authentication and personal access token module
+//!
+//! 负责解析请求携带的凭据 (`Authorization: Bearer <token>` 或浏览器会话 Cookie)，校验权限范围，
+//! 并提供令牌的创建、列出与吊销接口。
+//! Resolves the credentials carried by a request (`Authorization: Bearer <token>` or a browser
+//! session cookie), checks scopes, and provides endpoints to create, list and revoke tokens.
+
+use crate::db::DbPool;
+use crate::models::{ApiToken, CreateApiToken, User};
+use axum::{
+    extract::{Path, Request, State},
+    http::{header::{AUTHORIZATION, COOKIE, SET_COOKIE}, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+/// `api_tokens` 表中可公开的列 (不含 `token_hash`)
+/// Public columns of the `api_tokens` table (without `token_hash`)
+const TOKEN_COLUMNS: &str =
+    "id, user_id, name, token_prefix, scopes, expires_at, last_used_at, created_at, revoked_at";
+
+/// 明文令牌前缀，便于在日志或代码仓库中识别泄露的令牌
+/// Plaintext token prefix, makes leaked tokens easy to recognise in logs or repositories
+const TOKEN_PREFIX: &str = "wos_";
+
+/// 浏览器会话 Cookie 名称
+/// Browser session cookie name
+pub const SESSION_COOKIE: &str = "wallet_session";
+
+/// 浏览器会话有效期 (天)
+/// Browser session lifetime (days)
+const SESSION_DAYS: i64 = 30;
+
+/// 令牌有效天数上限 / Maximum validity of a token (days)
+const MAX_TOKEN_DAYS: i64 = 3650;
+
+/// 默认本地用户 ID (见 `db::init_db`)
+/// Default local user ID (see `db::init_db`)
+pub const LOCAL_USER_ID: i64 = 1;
+
+/// 令牌权限范围
+/// Token scope
+///
+/// `admin` 包含 `write`，`write` 包含 `read`。
+/// `admin` implies `write`, and `write` implies `read`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Scope {
+    pub fn parse(s: &str) -> Option<Scope> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+/// 用户角色
+/// User role
+///
+/// - `viewer`: 只读，可查看订阅列表与汇总
+/// - `editor`: 额外可以创建、修改订阅并使用 AI 功能
+/// - `admin`: 额外可以管理用户与系统设置
+/// - `viewer`: read-only, can view the subscription list and summaries
+/// - `editor`: can also create and update subscriptions and use the AI features
+/// - `admin`: can also manage users and system settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Editor,
+    Admin,
+}
+
+impl Role {
+    pub fn parse(s: &str) -> Option<Role> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// 该角色可以使用的最高权限范围
+    /// Highest scope this role may exercise
+    fn max_scope(&self) -> Scope {
+        match self {
+            Role::Viewer => Scope::Read,
+            Role::Editor => Scope::Write,
+            Role::Admin => Scope::Admin,
+        }
+    }
+}
+
+/// 当前请求的认证主体
+/// Authenticated principal of the current request
+///
+/// 由 `authenticate` 中间件写入请求扩展，处理函数可通过 `Extension<AuthUser>` 获取。
+/// Inserted into request extensions by the `authenticate` middleware; handlers obtain it
+/// through `Extension<AuthUser>`.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    /// 用户 ID
+    /// User ID
+    pub user_id: i64,
+
+    /// 用户角色
+    /// User role
+    pub role: Role,
+
+    /// 本次请求拥有的权限范围 (实际生效的权限同时受角色限制)
+    /// Scopes granted to this request (effective permissions are also capped by the role)
+    pub scopes: Vec<Scope>,
+}
+
+impl AuthUser {
+    /// 判断是否拥有指定权限 (考虑包含关系与角色上限)
+    /// Check whether the given scope is granted (taking implication and the role cap into account)
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.role.max_scope() >= scope && self.scopes.iter().any(|s| *s >= scope)
+    }
+
+    /// 要求至少为指定角色，否则返回 403
+    /// Require at least the given role, otherwise return 403
+    ///
+    /// 通过令牌访问时，令牌本身也必须拥有对应的权限范围。
+    /// When accessed through a token, the token itself must also hold the matching scope.
+    pub fn require_role(&self, role: Role) -> Result<(), (StatusCode, String)> {
+        if self.has_scope(role.max_scope()) {
+            Ok(())
+        } else {
+            Err((StatusCode::FORBIDDEN, format!("Requires role: {}", role.as_str())))
+        }
+    }
+
+    /// 要求指定权限，否则返回 403
+    /// Require the given scope, otherwise return 403
+    pub fn require(&self, scope: Scope) -> Result<(), (StatusCode, String)> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err((StatusCode::FORBIDDEN, format!("Missing scope: {}", scope.as_str())))
+        }
+    }
+}
+
+/// 是否强制要求认证 (环境变量 `AUTH_REQUIRED=true`)
+/// Whether authentication is mandatory (env var `AUTH_REQUIRED=true`)
+///
+/// 未开启时，不带凭据的请求按默认本地用户处理，以兼容单用户部署。
+/// When disabled, requests without credentials act as the default local user, keeping
+/// single-user deployments working.
+fn auth_required() -> bool {
+    std::env::var("AUTH_REQUIRED")
+        .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// 计算令牌哈希 (SHA-256, 十六进制)
+/// Compute token hash (SHA-256, hex)
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// 生成 32 字节随机密钥 (十六进制)
+/// Generate a 32-byte random secret (hex)
+pub fn random_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// 生成新的随机明文令牌
+/// Generate a new random plaintext token
+fn generate_token() -> String {
+    format!("{}{}", TOKEN_PREFIX, random_secret())
+}
+
+/// 当前 UTC 时间，格式与 SQLite `CURRENT_TIMESTAMP` 一致
+/// Current UTC time, formatted like SQLite `CURRENT_TIMESTAMP`
+fn now_utc() -> String {
+    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// 从 `Authorization` 头中提取 Bearer 令牌
+/// Extract a Bearer token from the `Authorization` header
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// 从 `Cookie` 头中提取指定名称的非空 Cookie
+/// Extract the non-empty cookie of the given name from the `Cookie` header
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, value)| *key == name && !value.is_empty())
+        .map(|(_, value)| value)
+}
+
+/// 从 `Cookie` 头中提取会话令牌
+/// Extract the session token from the `Cookie` header
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    cookie(headers, SESSION_COOKIE)
+}
+
+/// 查询用户角色 (未知角色按 viewer 处理)
+/// Look up a user's role (unknown roles are treated as viewer)
+async fn user_role(pool: &DbPool, user_id: i64) -> Result<Role, sqlx::Error> {
+    let row: Option<(String,)> = sqlx::query_as("SELECT role FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.and_then(|(r,)| Role::parse(&r)).unwrap_or(Role::Viewer))
+}
+
+/// 通过令牌解析认证主体，并记录最近使用时间
+/// Resolve the principal from a token and record the last-used time
+async fn resolve_token(pool: &DbPool, token: &str) -> Result<Option<AuthUser>, sqlx::Error> {
+    let row = sqlx::query_as::<_, ApiToken>(&format!(
+        "SELECT {} FROM api_tokens WHERE token_hash = ? AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > ?)",
+        TOKEN_COLUMNS
+    ))
+    .bind(hash_token(token))
+    .bind(now_utc())
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(row) = row else { return Ok(None) };
+
+    sqlx::query("UPDATE api_tokens SET last_used_at = ? WHERE id = ?")
+        .bind(now_utc())
+        .bind(row.id)
+        .execute(pool)
+        .await?;
+
+    Ok(Some(AuthUser {
+        user_id: row.user_id,
+        role: user_role(pool, row.user_id).await?,
+        scopes: row.scopes.split(',').filter_map(Scope::parse).collect(),
+    }))
+}
+
+/// 通过会话 Cookie 解析认证主体
+/// Resolve the principal from a session cookie
+///
+/// 会话拥有全部权限范围，实际权限由用户角色决定。
+/// Sessions carry every scope; effective permissions are decided by the user's role.
+async fn resolve_session(pool: &DbPool, token: &str) -> Result<Option<AuthUser>, sqlx::Error> {
+    let row: Option<(i64, String)> = sqlx::query_as(
+        r#"
+        SELECT users.id, users.role FROM sessions
+        JOIN users ON users.id = sessions.user_id
+        WHERE sessions.token_hash = ? AND sessions.expires_at > ?
+        "#,
+    )
+    .bind(hash_token(token))
+    .bind(now_utc())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(user_id, role)| AuthUser {
+        user_id,
+        role: Role::parse(&role).unwrap_or(Role::Viewer),
+        scopes: vec![Scope::Read, Scope::Write, Scope::Admin],
+    }))
+}
+
+/// 为用户创建浏览器会话，返回可直接写入响应的 `Set-Cookie` 头
+/// Create a browser session for a user and return a `Set-Cookie` header ready for the response
+pub async fn create_session(pool: &DbPool, user_id: i64, secure: bool) -> Result<HeaderValue, sqlx::Error> {
+    let token = random_secret();
+    let expires_at = (chrono::Utc::now() + chrono::Duration::days(SESSION_DAYS))
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+
+    sqlx::query("INSERT INTO sessions (user_id, token_hash, expires_at) VALUES (?, ?, ?)")
+        .bind(user_id)
+        .bind(hash_token(&token))
+        .bind(&expires_at)
+        .execute(pool)
+        .await?;
+
+    // 顺便清理过期会话
+    // Clean up expired sessions along the way
+    sqlx::query("DELETE FROM sessions WHERE expires_at <= ?")
+        .bind(now_utc())
+        .execute(pool)
+        .await?;
+
+    let cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        SESSION_COOKIE,
+        token,
+        SESSION_DAYS * 24 * 3600,
+        if secure { "; Secure" } else { "" }
+    );
+    Ok(HeaderValue::from_str(&cookie).expect("cookie is ASCII"))
+}
+
+/// 解析请求的认证主体
+/// Resolve the principal of a request
+///
+/// 返回 `Err` 时为可直接返回给客户端的错误响应。
+/// On `Err`, the value is an error response ready to be returned to the client.
+pub async fn resolve(pool: &DbPool, headers: &HeaderMap) -> Result<AuthUser, (StatusCode, String)> {
+    if let Some(token) = bearer_token(headers) {
+        return match resolve_token(pool, token).await {
+            Ok(Some(user)) => Ok(user),
+            Ok(None) => {
+                warn!("Rejected invalid, expired or revoked API token");
+                Err((StatusCode::UNAUTHORIZED, "Invalid or expired token".to_string()))
+            }
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        };
+    }
+
+    if let Some(token) = session_cookie(headers) {
+        match resolve_session(pool, token).await {
+            Ok(Some(user)) => return Ok(user),
+            Ok(None) => debug!("Ignoring unknown or expired session cookie"),
+            Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        }
+    }
+
+    if auth_required() {
+        return Err((StatusCode::UNAUTHORIZED, "Authentication required".to_string()));
+    }
+
+    // 未携带凭据：作为默认本地用户，拥有全部权限范围
+    // No credentials: act as the default local user with every scope
+    let role = user_role(pool, LOCAL_USER_ID)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(AuthUser {
+        user_id: LOCAL_USER_ID,
+        role,
+        scopes: vec![Scope::Read, Scope::Write, Scope::Admin],
+    })
+}
+
+/// 认证中间件
+/// Authentication middleware
+///
+/// 解析认证主体并写入请求扩展；`GET`/`HEAD` 请求至少需要 `viewer` 角色，其余方法至少需要 `editor`。
+/// 更细的权限 (例如管理员接口) 由各处理函数自行校验。
+/// Resolves the principal and stores it in request extensions; `GET`/`HEAD` requests need at
+/// least the `viewer` role, every other method needs `editor`. Finer-grained permissions (such
+/// as admin endpoints) are checked by the handlers themselves.
+pub async fn authenticate(
+    State(pool): State<DbPool>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let user = match resolve(&pool, req.headers()).await {
+        Ok(u) => u,
+        Err(e) => return e.into_response(),
+    };
+
+    let needed = if req.method() == Method::GET || req.method() == Method::HEAD {
+        Role::Viewer
+    } else {
+        Role::Editor
+    };
+    if let Err(e) = user.require_role(needed) {
+        return e.into_response();
+    }
+
+    req.extensions_mut().insert(user);
+    next.run(req).await
+}
+
+/// 计算令牌的过期时间；天数必须在 1 到 `MAX_TOKEN_DAYS` 之间
+/// Compute a token's expiry time; the number of days must be between 1 and `MAX_TOKEN_DAYS`
+fn expiry_after_days(now: chrono::DateTime<chrono::Utc>, days: i64) -> Result<String, (StatusCode, String)> {
+    if !(1..=MAX_TOKEN_DAYS).contains(&days) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("expires_in_days must be between 1 and {}", MAX_TOKEN_DAYS),
+        ));
+    }
+    Ok((now + chrono::Duration::days(days)).format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// 创建令牌 (POST /api/tokens)
+/// Create a token
+///
+/// 明文令牌只在此响应中返回一次。新令牌的权限不能超过调用者自身的权限。
+/// The plaintext token is returned only once, in this response. A new token cannot be granted
+/// more scopes than the caller holds.
+pub async fn create_token(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<CreateApiToken>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    if payload.name.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Name is required".to_string()));
+    }
+
+    let mut scopes = Vec::new();
+    for s in &payload.scopes {
+        match Scope::parse(s) {
+            Some(scope) => scopes.push(scope),
+            None => return Err((StatusCode::BAD_REQUEST, format!("Invalid scope: {}", s))),
+        }
+    }
+    if scopes.is_empty() {
+        scopes.push(Scope::Read);
+    }
+    scopes.sort();
+    scopes.dedup();
+    for scope in &scopes {
+        user.require(*scope)?;
+    }
+
+    let expires_at = match payload.expires_in_days {
+        Some(days) => Some(expiry_after_days(chrono::Utc::now(), days)?),
+        None => None,
+    };
+
+    let token = generate_token();
+    let prefix: String = token.chars().take(TOKEN_PREFIX.len() + 6).collect();
+    let scopes_str = scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(",");
+
+    let id = sqlx::query(
+        r#"
+        INSERT INTO api_tokens (user_id, name, token_hash, token_prefix, scopes, expires_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(user.user_id)
+    .bind(payload.name.trim())
+    .bind(hash_token(&token))
+    .bind(&prefix)
+    .bind(&scopes_str)
+    .bind(&expires_at)
+    .execute(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .last_insert_rowid();
+
+    info!("Created API token {} ({}) for user {}", id, prefix, user.user_id);
+    Ok(Json(serde_json::json!({
+        "id": id,
+        "name": payload.name.trim(),
+        "token": token,
+        "token_prefix": prefix,
+        "scopes": scopes_str,
+        "expires_at": expires_at,
+    })))
+}
+
+/// 列出当前用户的令牌 (GET /api/tokens)
+/// List the current user's tokens
+pub async fn list_tokens(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<Vec<ApiToken>>, (StatusCode, String)> {
+    let tokens = sqlx::query_as::<_, ApiToken>(&format!(
+        "SELECT {} FROM api_tokens WHERE user_id = ? ORDER BY created_at DESC",
+        TOKEN_COLUMNS
+    ))
+    .bind(user.user_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(tokens))
+}
+
+/// 吊销令牌 (DELETE /api/tokens/:id)
+/// Revoke a token
+///
+/// 仅标记吊销时间，保留记录以便审计。
+/// Only stamps the revocation time; the row is kept for auditing.
+pub async fn revoke_token(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let result = sqlx::query(
+        "UPDATE api_tokens SET revoked_at = ? WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+    )
+    .bind(now_utc())
+    .bind(id)
+    .bind(user.user_id)
+    .execute(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Token not found".to_string()));
+    }
+
+    info!("Revoked API token {} for user {}", id, user.user_id);
+    Ok(Json(serde_json::json!({ "status": "revoked" })))
+}
+
+/// 获取当前用户信息 (GET /api/me)
+/// Get the current user
+pub async fn me(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let row = sqlx::query_as::<_, User>(
+        "SELECT id, username, email, display_name, role, created_at FROM users WHERE id = ?",
+    )
+    .bind(user.user_id)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+
+    let scopes: Vec<&str> = user.scopes.iter().filter(|s| user.has_scope(**s)).map(|s| s.as_str()).collect();
+    Ok(Json(serde_json::json!({ "user": row, "scopes": scopes })))
+}
+
+/// 退出登录 (POST /auth/logout)
+/// Log out
+///
+/// 删除当前会话并清除浏览器 Cookie。
+/// Deletes the current session and clears the browser cookie.
+pub async fn logout(State(pool): State<DbPool>, headers: HeaderMap) -> Response {
+    if let Some(token) = session_cookie(&headers) {
+        if let Err(e) = sqlx::query("DELETE FROM sessions WHERE token_hash = ?")
+            .bind(hash_token(token))
+            .execute(&pool)
+            .await
+        {
+            return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    }
+
+    let mut resp = Json(serde_json::json!({ "status": "logged_out" })).into_response();
+    let cookie = format!("{}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0", SESSION_COOKIE);
+    resp.headers_mut().insert(SET_COOKIE, HeaderValue::from_str(&cookie).expect("cookie is ASCII"));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use axum::{middleware, routing::get, Router};
+    use chrono::TimeZone;
+
+    fn admin() -> AuthUser {
+        AuthUser { user_id: LOCAL_USER_ID, role: Role::Admin, scopes: vec![Scope::Admin] }
+    }
+
+    /// 创建令牌，返回其 ID 与明文 / Create a token, returning its ID and plaintext
+    async fn new_token(pool: &DbPool, user: AuthUser, scopes: &[&str]) -> Result<(i64, String), (StatusCode, String)> {
+        let payload: CreateApiToken =
+            serde_json::from_value(serde_json::json!({ "name": "test", "scopes": scopes })).unwrap();
+        let Json(created) = create_token(State(pool.clone()), Extension(user), Json(payload)).await?;
+        Ok((created["id"].as_i64().unwrap(), created["token"].as_str().unwrap().to_string()))
+    }
+
+    /// 只保存令牌的 SHA-256 哈希并按哈希查找，解析时记录最近使用时间
+    /// Only the SHA-256 hash of a token is stored and looked up, and resolving records the last use
+    #[tokio::test]
+    async fn resolves_tokens_by_hash() {
+        let pool = db::test_pool().await;
+        let (id, token) = new_token(&pool, admin(), &["write"]).await.unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+
+        let (stored, prefix, last_used): (String, String, Option<String>) =
+            sqlx::query_as("SELECT token_hash, token_prefix, last_used_at FROM api_tokens WHERE id = ?")
+                .bind(id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(stored, hex::encode(Sha256::digest(token.as_bytes())));
+        assert!(token.starts_with(&prefix) && prefix.len() < token.len());
+        assert_eq!(last_used, None);
+
+        let user = resolve_token(&pool, &token).await.unwrap().unwrap();
+        assert_eq!((user.user_id, user.role, user.scopes), (LOCAL_USER_ID, Role::Admin, vec![Scope::Write]));
+        // 数据库中的哈希本身不能当作令牌使用 / The stored hash cannot be used as a token
+        assert!(resolve_token(&pool, &stored).await.unwrap().is_none());
+        let last_used: Option<String> = sqlx::query_scalar("SELECT last_used_at FROM api_tokens WHERE id = ?")
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(last_used.is_some());
+    }
+
+    #[tokio::test]
+    async fn rejects_revoked_and_expired_tokens() {
+        let pool = db::test_pool().await;
+        let (revoked, revoked_token) = new_token(&pool, admin(), &["read"]).await.unwrap();
+        let (expired, expired_token) = new_token(&pool, admin(), &["read"]).await.unwrap();
+        assert!(resolve_token(&pool, &revoked_token).await.unwrap().is_some());
+
+        let Json(result) = revoke_token(State(pool.clone()), Extension(admin()), Path(revoked)).await.unwrap();
+        assert_eq!(result["status"], "revoked");
+        assert!(resolve_token(&pool, &revoked_token).await.unwrap().is_none());
+        let again = revoke_token(State(pool.clone()), Extension(admin()), Path(revoked)).await.unwrap_err();
+        assert_eq!(again.0, StatusCode::NOT_FOUND);
+
+        sqlx::query("UPDATE api_tokens SET expires_at = ? WHERE id = ?")
+            .bind((chrono::Utc::now() - chrono::Duration::minutes(1)).format("%Y-%m-%d %H:%M:%S").to_string())
+            .bind(expired)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(resolve_token(&pool, &expired_token).await.unwrap().is_none());
+    }
+
+    /// 令牌的权限不能超过创建者，且实际生效的权限受用户当前角色限制
+    /// A token cannot exceed its creator's permissions, and its effective scopes are capped by the
+    /// user's current role
+    #[tokio::test]
+    async fn caps_scopes_by_role() {
+        let pool = db::test_pool().await;
+        let viewer_id = sqlx::query("INSERT INTO users (username, role) VALUES ('viewer', 'viewer')")
+            .execute(&pool)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+        // 会话拥有全部权限范围，实际权限由角色决定 / Sessions hold every scope; the role decides
+        let viewer = AuthUser { user_id: viewer_id, role: Role::Viewer, scopes: vec![Scope::Admin] };
+        for scopes in [&["write"][..], &["admin"], &["read", "write"]] {
+            let error = new_token(&pool, viewer.clone(), scopes).await.unwrap_err();
+            assert_eq!(error.0, StatusCode::FORBIDDEN, "{:?}", scopes);
+        }
+        let (_, read_token) = new_token(&pool, viewer.clone(), &["read"]).await.unwrap();
+        assert!(resolve_token(&pool, &read_token).await.unwrap().unwrap().has_scope(Scope::Read));
+
+        // 创建后被降级的用户：令牌中的 write/admin 不再生效
+        // A user demoted after creating the token: write/admin in the token no longer apply
+        let (_, token) = new_token(&pool, AuthUser { user_id: viewer_id, ..admin() }, &["admin"]).await.unwrap();
+        let user = resolve_token(&pool, &token).await.unwrap().unwrap();
+        assert_eq!(user.role, Role::Viewer);
+        assert!(user.has_scope(Scope::Read));
+        assert!(!user.has_scope(Scope::Write) && !user.has_scope(Scope::Admin));
+        assert_eq!(user.require_role(Role::Editor).unwrap_err().0, StatusCode::FORBIDDEN);
+    }
+
+    /// 中间件按请求方法检查权限：只读令牌可以 GET，不能 POST
+    /// The middleware checks permissions by method: a read-only token may GET but not POST
+    #[tokio::test]
+    async fn authenticate_checks_scopes_per_method() {
+        let pool = db::test_pool().await;
+        let (_, read_token) = new_token(&pool, admin(), &["read"]).await.unwrap();
+        let (_, write_token) = new_token(&pool, admin(), &["write"]).await.unwrap();
+
+        let app = Router::new()
+            .route("/api/test", get(|| async { "read" }).post(|| async { "written" }))
+            .route_layer(middleware::from_fn_with_state(pool.clone(), authenticate))
+            .with_state(pool.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/test", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let status = |request: reqwest::RequestBuilder, token: &str| {
+            let request = request.bearer_auth(token);
+            async move { request.send().await.unwrap().status().as_u16() }
+        };
+        assert_eq!(status(client.get(&url), &read_token).await, 200);
+        assert_eq!(status(client.post(&url), &read_token).await, 403);
+        assert_eq!(status(client.post(&url), &write_token).await, 200);
+        assert_eq!(status(client.post(&url), "wos_unknown").await, 401);
+    }
+
+    /// 有效天数超出范围返回 400 而不是溢出
+    /// Out-of-range validity returns 400 instead of overflowing
+    #[test]
+    fn token_expiry_is_bounded() {
+        let now = chrono::Utc.with_ymd_and_hms(2026, 1, 31, 12, 0, 0).unwrap();
+        assert_eq!(expiry_after_days(now, 1).unwrap(), "2026-02-01 12:00:00");
+        assert!(expiry_after_days(now, MAX_TOKEN_DAYS).is_ok());
+        for days in [0, -1, MAX_TOKEN_DAYS + 1, 9_999_999_999_999, i64::MAX, i64::MIN] {
+            assert_eq!(expiry_after_days(now, days).unwrap_err().0, StatusCode::BAD_REQUEST, "{}", days);
+        }
+    }
+}
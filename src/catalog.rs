@@ -0,0 +1,257 @@
+//! 内置服务目录模块
+//! Built-in service catalog module
+//!
+//! 随程序一起编译的常见订阅服务目录 (`data/catalog.json`)：多语言别名、官网域名、分类、各地区的
+//! 典型套餐价格以及退订页面。域名搜索、智能填单与创建订阅都会先查询目录，常见服务无需联网即可识别。
+//! Catalog of common subscription services compiled into the binary (`data/catalog.json`):
+//! aliases in several languages, the official domain, a category, typical plan prices per region
+//! and the cancellation page. Domain search, smart parse and subscription creation all consult
+//! the catalog first, so common services are recognised without network access.
+
+use crate::auth::{AuthUser, Role};
+use crate::models::{CatalogSuggestion, SmartParseResult};
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    Extension, Json,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// 目录文件 (编译时嵌入)
+/// Catalog file (embedded at compile time)
+const CATALOG_JSON: &str = include_str!("../data/catalog.json");
+
+#[derive(Deserialize)]
+struct CatalogFile {
+    updated: String,
+    services: Vec<Service>,
+}
+
+/// 目录中的服务
+/// A service in the catalog
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Service {
+    /// 稳定的标识，例如 `netflix`
+    /// Stable identifier, such as `netflix`
+    pub id: String,
+    /// 规范名称 / Canonical name
+    pub name: String,
+    /// 小写别名 (中文、英文、日文等) / Lowercase aliases (Chinese, English, Japanese, ...)
+    pub aliases: Vec<String>,
+    /// 官网域名 / Official domain
+    pub domain: String,
+    /// 官网地址 / Official website
+    pub url: String,
+    pub category: String,
+    /// 退订或管理订阅的页面
+    /// Page for cancelling or managing the subscription
+    pub cancel_url: String,
+    pub plans: Vec<Plan>,
+}
+
+/// 典型套餐价格 (仅供参考，以服务商实际价格为准)
+/// Typical plan price (for reference only; the provider's actual price prevails)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    pub name: String,
+    /// 地区代码 (ISO 3166，例如 CN、US) / Region code (ISO 3166, such as CN or US)
+    pub region: String,
+    pub currency: String,
+    pub price: f64,
+    /// 付款频率，与订阅相同 / Payment frequency, as for subscriptions
+    pub frequency: i64,
+}
+
+static CATALOG: Lazy<CatalogFile> =
+    Lazy::new(|| serde_json::from_str(CATALOG_JSON).expect("embedded service catalog is invalid"));
+
+/// 目录匹配结果
+/// Catalog match
+pub struct Match {
+    pub service: &'static Service,
+    /// 名称与别名 (或规范名称) 完全一致
+    /// The name equals an alias (or the canonical name)
+    pub exact: bool,
+}
+
+impl Service {
+    /// 图标地址 (经由图标接口获取)
+    /// Logo URL (served through the icon endpoint)
+    pub fn logo(&self) -> String {
+        format!("/api/icon?domain={}&sz=64", self.domain)
+    }
+
+    /// 作为近似匹配的建议返回 / Returned as the suggestion for an approximate match
+    pub fn suggestion(&self) -> CatalogSuggestion {
+        CatalogSuggestion {
+            service_id: self.id.clone(),
+            name: self.name.clone(),
+            url: self.url.clone(),
+            logo: self.logo(),
+            category: self.category.clone(),
+        }
+    }
+
+    /// 按货币与付款频率查找典型价格
+    /// Find a typical price by currency and payment frequency
+    pub fn typical_plan(&self, currency: &str, frequency: i64) -> Option<&Plan> {
+        self.plans.iter().find(|p| p.frequency == frequency && p.currency.eq_ignore_ascii_case(currency))
+    }
+}
+
+/// 在小写文本中查找服务，包含的别名最长者优先
+/// Find a service in lowercase text; the longest contained alias wins
+pub fn find(lower: &str) -> Option<&'static Service> {
+    CATALOG
+        .services
+        .iter()
+        .flat_map(|s| s.aliases.iter().map(move |a| (s, a)))
+        .filter(|(_, alias)| lower.contains(alias.as_str()))
+        .max_by_key(|(_, alias)| alias.len())
+        .map(|(s, _)| s)
+}
+
+/// 按订阅名称查找服务 (忽略大小写与多余空白)
+/// Look up a service by subscription name (ignoring case and extra whitespace)
+pub fn lookup(name: &str) -> Option<Match> {
+    let lower = name.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    if lower.is_empty() {
+        return None;
+    }
+    let service = find(&lower)?;
+    let exact = service.aliases.contains(&lower) || service.name.to_lowercase() == lower;
+    Some(Match { service, exact })
+}
+
+/// 用目录补全智能填单结果：规范名称、官网与分类；没有识别出价格时按货币与周期填入典型价格
+/// Complete a smart parse result from the catalog: canonical name, website and category; when
+/// no price was recognised, fill in the typical price for the currency and period
+pub fn enrich(result: &mut SmartParseResult) {
+    let Some(Match { service, exact }) = lookup(&result.name) else {
+        return;
+    };
+    if exact {
+        result.name = service.name.clone();
+    }
+    result.confidence.name = result.confidence.name.max(if exact { 0.95 } else { 0.8 });
+    if result.url.is_none() {
+        result.url = Some(service.url.clone());
+    }
+    if result.category.is_none() {
+        result.category = Some(service.category.clone());
+    }
+    // 置信度为 0 表示没有识别出价格
+    // A confidence of 0 means no price was recognised
+    if result.confidence.price == 0.0 {
+        if let Some(plan) = service.typical_plan(&result.currency, result.frequency) {
+            result.price = Some(plan.price);
+            result.confidence.price = 0.4;
+        }
+    }
+}
+
+/// 接口返回的目录条目 (附带图标地址)
+/// Catalog entry returned by the API (with the logo URL)
+#[derive(Serialize)]
+pub struct CatalogEntry {
+    #[serde(flatten)]
+    pub service: Service,
+    pub logo: String,
+}
+
+/// 目录列表
+/// Catalog listing
+#[derive(Serialize)]
+pub struct CatalogList {
+    /// 价格数据的更新月份 / Month the price data was last updated
+    pub updated: &'static str,
+    pub services: Vec<CatalogEntry>,
+}
+
+#[derive(Deserialize)]
+pub struct CatalogQuery {
+    /// 按名称或别名筛选 / Filter by name or alias
+    pub q: Option<String>,
+    pub category: Option<String>,
+    /// 只返回该地区的套餐 / Only return plans for this region
+    pub region: Option<String>,
+}
+
+/// 复制服务并按地区筛选套餐
+/// Copy a service, keeping only the plans of a region
+fn entry(service: &Service, region: Option<&str>) -> CatalogEntry {
+    let mut service = service.clone();
+    service.plans.retain(|p| region.is_none_or(|r| p.region.eq_ignore_ascii_case(r)));
+    CatalogEntry { logo: service.logo(), service }
+}
+
+/// 查询服务目录 (GET /api/catalog?q=&category=&region=)
+/// Query the service catalog
+pub async fn list_catalog(
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<CatalogQuery>,
+) -> Result<Json<CatalogList>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+    let needle = query.q.as_deref().map(|q| q.trim().to_lowercase()).filter(|q| !q.is_empty());
+    let services = CATALOG
+        .services
+        .iter()
+        .filter(|s| query.category.as_deref().is_none_or(|c| s.category.eq_ignore_ascii_case(c)))
+        .filter(|s| {
+            needle.as_deref().is_none_or(|n| {
+                s.name.to_lowercase().contains(n) || s.aliases.iter().any(|a| a.contains(n) || n.contains(a.as_str()))
+            })
+        })
+        .map(|s| entry(s, query.region.as_deref()))
+        .collect();
+    Ok(Json(CatalogList { updated: &CATALOG.updated, services }))
+}
+
+/// 获取单个服务 (GET /api/catalog/:id?region=)
+/// Get a single service
+pub async fn get_service(
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    Query(query): Query<CatalogQuery>,
+) -> Result<Json<CatalogEntry>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+    CATALOG
+        .services
+        .iter()
+        .find(|s| s.id == id)
+        .map(|s| Json(entry(s, query.region.as_deref())))
+        .ok_or((StatusCode::NOT_FOUND, "Service not found".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// 目录文件可以解析，标识与别名唯一，别名为小写，域名与官网一致
+    /// The catalog parses, ids and aliases are unique, aliases are lowercase and domains match
+    /// the websites
+    #[test]
+    fn catalog_is_consistent() {
+        let mut ids = HashSet::new();
+        let mut aliases = HashSet::new();
+        for service in &CATALOG.services {
+            assert!(ids.insert(&service.id), "duplicate id {}", service.id);
+            assert!(!service.aliases.is_empty(), "{} has no aliases", service.id);
+            for alias in &service.aliases {
+                assert_eq!(alias, &alias.to_lowercase(), "alias {} is not lowercase", alias);
+                assert!(aliases.insert(alias), "duplicate alias {}", alias);
+            }
+            let host = url::Url::parse(&service.url).unwrap().host_str().unwrap().to_string();
+            assert_eq!(host, service.domain, "{} domain does not match its url", service.id);
+            assert!(url::Url::parse(&service.cancel_url).is_ok(), "{} has an invalid cancel_url", service.id);
+            for plan in &service.plans {
+                assert!([-1, 0, 1, 3, 12].contains(&plan.frequency), "{} has an invalid frequency", service.id);
+                assert!(plan.price > 0.0 && plan.currency.len() == 3, "{} has an invalid plan", service.id);
+            }
+        }
+        assert_eq!(lookup("爱奇艺").map(|m| (m.service.domain.as_str(), m.exact)), Some(("www.iqiyi.com", true)));
+        assert_eq!(lookup("Netflix  Premium").map(|m| (m.service.id.as_str(), m.exact)), Some(("netflix", false)));
+    }
+}
@@ -0,0 +1,350 @@
+//! 日期工具模块
+//! Date utilities module
+//!
+//! 提供服务器本地日期与时区，以及中英文相对日期短语的解析 ("today"、"next Friday"、
+//! "in 30 days"、"明天"、"下周五"、"3个月后" 等)，供提示词模板与离线解析使用。
+//! Provides the server's local date and time zone, and parses relative-date phrases in English
+//! and Chinese ("today", "next Friday", "in 30 days", "明天", "下周五", "3个月后", ...) for prompt
+//! templates and offline parsing.
+
+use chrono::{Datelike, Days, Local, Months, NaiveDate, Weekday};
+use once_cell::sync::Lazy;
+
+/// 服务器时区名称，例如 "Asia/Shanghai (UTC+08:00)"
+/// Server time zone name, e.g. "Asia/Shanghai (UTC+08:00)"
+///
+/// 优先使用 `TZ` 环境变量，其次读取系统时区设置。
+/// Uses the `TZ` environment variable first, then the system time zone setting.
+static TIMEZONE: Lazy<String> = Lazy::new(|| {
+    let name = std::env::var("TZ")
+        .ok()
+        .filter(|tz| !tz.trim().is_empty())
+        .or_else(|| iana_time_zone::get_timezone().ok())
+        .unwrap_or_else(|| "UTC".to_string());
+    format!("{} (UTC{})", name, Local::now().format("%:z"))
+});
+
+/// 服务器本地的今天
+/// Today in the server's local time zone
+pub fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+/// 服务器时区描述
+/// Description of the server time zone
+pub fn timezone() -> &'static str {
+    &TIMEZONE
+}
+
+/// 时间单位
+/// Time unit
+#[derive(Debug, Clone, Copy)]
+enum Unit {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+/// 在日期上增加若干个单位 (月末按目标月份最后一天处理)；超出范围时返回 `None`
+/// Add a number of units to a date (month ends clamp to the last day of the target month);
+/// `None` when out of range
+fn shift(date: NaiveDate, n: i64, unit: Unit) -> Option<NaiveDate> {
+    match unit {
+        Unit::Day if n >= 0 => date.checked_add_days(Days::new(n as u64)),
+        Unit::Day => date.checked_sub_days(Days::new(n.unsigned_abs())),
+        Unit::Week => shift(date, n.checked_mul(7)?, Unit::Day),
+        Unit::Month => {
+            let months = Months::new(u32::try_from(n.unsigned_abs()).ok()?);
+            if n >= 0 {
+                date.checked_add_months(months)
+            } else {
+                date.checked_sub_months(months)
+            }
+        }
+        Unit::Year => shift(date, n.checked_mul(12)?, Unit::Month),
+    }
+}
+
+/// 根据付款频率计算下一次付款日期 (永久订阅返回 `None`)
+/// Compute the next payment date from a payment frequency (`None` for lifetime)
+pub fn next_payment(start: NaiveDate, frequency: i64) -> Option<NaiveDate> {
+    match frequency {
+        -1 => shift(start, 1, Unit::Day),
+        n if n > 0 => shift(start, n, Unit::Month),
+        _ => None,
+    }
+}
+
+/// 严格晚于 `from` 的下一个星期几
+/// The next given weekday strictly after `from`
+fn next_weekday(from: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let ahead = (7 + weekday.num_days_from_monday() as i64 - from.weekday().num_days_from_monday() as i64) % 7;
+    from + chrono::Duration::days(if ahead == 0 { 7 } else { ahead })
+}
+
+/// `from` 所在周 (周一开始) 的某一天
+/// A given day of the week (starting Monday) containing `from`
+fn weekday_of_week(from: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let monday = from - chrono::Duration::days(from.weekday().num_days_from_monday() as i64);
+    monday + chrono::Duration::days(weekday.num_days_from_monday() as i64)
+}
+
+fn english_weekday(word: &str) -> Option<Weekday> {
+    match word {
+        "monday" | "mon" => Some(Weekday::Mon),
+        "tuesday" | "tue" | "tues" => Some(Weekday::Tue),
+        "wednesday" | "wed" => Some(Weekday::Wed),
+        "thursday" | "thu" | "thurs" => Some(Weekday::Thu),
+        "friday" | "fri" => Some(Weekday::Fri),
+        "saturday" | "sat" => Some(Weekday::Sat),
+        "sunday" | "sun" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn english_unit(word: &str) -> Option<Unit> {
+    match word.trim_end_matches('s') {
+        "day" => Some(Unit::Day),
+        "week" => Some(Unit::Week),
+        "month" => Some(Unit::Month),
+        "year" => Some(Unit::Year),
+        _ => None,
+    }
+}
+
+fn english_number(word: &str) -> Option<i64> {
+    match word {
+        "a" | "an" | "one" => Some(1),
+        "two" => Some(2),
+        "three" => Some(3),
+        "four" => Some(4),
+        "five" => Some(5),
+        "six" => Some(6),
+        "seven" => Some(7),
+        "ten" => Some(10),
+        "twelve" => Some(12),
+        _ => word.parse().ok(),
+    }
+}
+
+fn chinese_weekday(c: char) -> Option<Weekday> {
+    match c {
+        '一' => Some(Weekday::Mon),
+        '二' => Some(Weekday::Tue),
+        '三' => Some(Weekday::Wed),
+        '四' => Some(Weekday::Thu),
+        '五' => Some(Weekday::Fri),
+        '六' => Some(Weekday::Sat),
+        '日' | '天' => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// 解析中文或阿拉伯数字 (支持到 "九十九")
+/// Parse Chinese or Arabic numerals (up to "九十九")
+fn chinese_number(s: &str) -> Option<i64> {
+    if let Ok(n) = s.parse() {
+        return Some(n);
+    }
+    let digit = |c: char| "零一二三四五六七八九".chars().position(|d| d == c).map(|p| p as i64).or((c == '两').then_some(2));
+    let chars: Vec<char> = s.chars().collect();
+    match chars.iter().position(|c| *c == '十') {
+        Some(pos) => {
+            let tens = if pos == 0 { 1 } else { digit(chars[0])? };
+            let ones = if pos + 1 < chars.len() { digit(chars[pos + 1])? } else { 0 };
+            Some(tens * 10 + ones)
+        }
+        None if chars.len() == 1 => digit(chars[0]),
+        None => None,
+    }
+}
+
+/// 解析英文相对日期
+/// Resolve an English relative date
+fn resolve_english(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let lower = text.to_lowercase();
+    let words: Vec<&str> = lower.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+    let word = |i: usize| words.get(i).copied().unwrap_or_default();
+
+    for i in 0..words.len() {
+        // "in 30 days", "in a month"
+        if word(i) == "in" {
+            if let (Some(n), Some(unit)) = (english_number(word(i + 1)), english_unit(word(i + 2))) {
+                return shift(today, n, unit);
+            }
+        }
+        // "30 days from now", "2 weeks later"
+        if let (Some(n), Some(unit)) = (english_number(word(i)), english_unit(word(i + 1))) {
+            if (word(i + 2) == "from" && word(i + 3) == "now") || word(i + 2) == "later" {
+                return shift(today, n, unit);
+            }
+        }
+        // "next friday", "next month"
+        if word(i) == "next" {
+            if let Some(weekday) = english_weekday(word(i + 1)) {
+                return Some(next_weekday(today, weekday));
+            }
+            if let Some(unit) = english_unit(word(i + 1)) {
+                return shift(today, 1, unit);
+            }
+        }
+        // "this friday", "on friday", "coming friday"
+        if matches!(word(i), "this" | "on" | "coming") {
+            if let Some(weekday) = english_weekday(word(i + 1)) {
+                return Some(if today.weekday() == weekday { today } else { next_weekday(today, weekday) });
+            }
+        }
+    }
+
+    if lower.contains("day after tomorrow") {
+        return shift(today, 2, Unit::Day);
+    }
+    if words.contains(&"tomorrow") {
+        return shift(today, 1, Unit::Day);
+    }
+    if words.contains(&"yesterday") {
+        return shift(today, -1, Unit::Day);
+    }
+    if words.contains(&"today") || words.contains(&"now") {
+        return Some(today);
+    }
+    None
+}
+
+/// 解析中文相对日期
+/// Resolve a Chinese relative date
+fn resolve_chinese(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    // "3天后"、"两个月后"、"一年之后"
+    // "in 3 days", "in two months", "in a year"
+    const SUFFIXES: [(&str, Unit); 6] = [
+        ("天", Unit::Day),
+        ("日", Unit::Day),
+        ("周", Unit::Week),
+        ("星期", Unit::Week),
+        ("个月", Unit::Month),
+        ("年", Unit::Year),
+    ];
+    for (suffix, unit) in SUFFIXES {
+        for marker in ["后", "之后", "以后"] {
+            let pattern = format!("{}{}", suffix, marker);
+            let Some(byte_pos) = text.find(&pattern) else {
+                continue;
+            };
+            let prefix: Vec<char> = text[..byte_pos].chars().collect();
+            let start = prefix
+                .iter()
+                .rposition(|c| !(c.is_ascii_digit() || "零一二三四五六七八九十两".contains(*c)))
+                .map_or(0, |p| p + 1);
+            let number: String = prefix[start..].iter().collect();
+            if let Some(n) = chinese_number(&number) {
+                return shift(today, n, unit);
+            }
+        }
+    }
+
+    // "下周五"、"下星期一"、"下周"
+    // "Friday next week", "Monday next week", "next week"
+    for marker in ["下周", "下星期", "下礼拜"] {
+        if let Some(byte_pos) = text.find(marker) {
+            let next_char = text[byte_pos + marker.len()..].chars().next();
+            let next_week = shift(today, 1, Unit::Week)?;
+            return Some(match next_char.and_then(chinese_weekday) {
+                Some(weekday) => weekday_of_week(next_week, weekday),
+                None => next_week,
+            });
+        }
+    }
+
+    // "本周五"、"周五"、"星期五"
+    // "this Friday", "Friday"
+    for marker in ["星期", "礼拜", "周"] {
+        let mut search = 0;
+        while let Some(found) = text[search..].find(marker) {
+            let byte_pos = search + found;
+            search = byte_pos + marker.len();
+            if let Some(weekday) = text[search..].chars().next().and_then(chinese_weekday) {
+                return Some(weekday_of_week(today, weekday));
+            }
+        }
+    }
+
+    const KEYWORDS: [(&str, i64, Unit); 11] = [
+        ("大后天", 3, Unit::Day),
+        ("后天", 2, Unit::Day),
+        ("明天", 1, Unit::Day),
+        ("明日", 1, Unit::Day),
+        ("前天", -2, Unit::Day),
+        ("昨天", -1, Unit::Day),
+        ("下个月", 1, Unit::Month),
+        ("下月", 1, Unit::Month),
+        ("明年", 1, Unit::Year),
+        ("今天", 0, Unit::Day),
+        ("今日", 0, Unit::Day),
+    ];
+    if let Some((_, n, unit)) = KEYWORDS.iter().find(|(k, _, _)| text.contains(k)) {
+        return shift(today, *n, *unit);
+    }
+    None
+}
+
+/// 解析文本中的相对日期短语，返回对应日期
+/// Resolve a relative-date phrase in the text to a date
+pub fn resolve_relative(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    resolve_chinese(text, today).or_else(|| resolve_english(text, today))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn shifts_dates() {
+        let cases = [
+            ("2026-01-31", 1, Unit::Month, Some("2026-02-28")),
+            ("2024-01-31", 1, Unit::Month, Some("2024-02-29")),
+            ("2026-03-31", -1, Unit::Month, Some("2026-02-28")),
+            ("2026-10-18", 2, Unit::Week, Some("2026-11-01")),
+            ("2026-10-18", -1, Unit::Week, Some("2026-10-11")),
+            ("2024-02-29", 1, Unit::Year, Some("2025-02-28")),
+            ("2026-10-18", -3, Unit::Day, Some("2026-10-15")),
+            ("2026-10-18", i64::MAX, Unit::Week, None),
+            ("2026-10-18", i64::MIN, Unit::Year, None),
+            ("2026-10-18", i64::MAX, Unit::Month, None),
+            ("2026-10-18", i64::MIN, Unit::Day, None),
+        ];
+        for (from, n, unit, expected) in cases {
+            assert_eq!(shift(date(from), n, unit), expected.map(date), "{} {} {:?}", from, n, unit);
+        }
+    }
+
+    /// 今天是 2026-10-18 (星期日)
+    /// Today is 2026-10-18 (a Sunday)
+    #[test]
+    fn resolves_relative_phrases() {
+        let today = date("2026-10-18");
+        let cases = [
+            ("renews in 30 days", Some("2026-11-17")),
+            ("2 weeks from now", Some("2026-11-01")),
+            ("next friday", Some("2026-10-23")),
+            ("next year", Some("2027-10-18")),
+            ("day after tomorrow", Some("2026-10-20")),
+            ("3个月后", Some("2027-01-18")),
+            ("两周之后", Some("2026-11-01")),
+            ("下周五", Some("2026-10-23")),
+            ("本周三", Some("2026-10-14")),
+            ("明年", Some("2027-10-18")),
+            ("in 99999999999999999 weeks", None),
+            ("99999999999999999 years later", None),
+            ("whenever", None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(resolve_relative(text, today), expected.map(date), "{}", text);
+        }
+    }
+}
@@ -1,9 +1,9 @@
-/// 数据库连接管理模块
-/// Database connection management module
-///
-/// 负责初始化数据库连接池、创建数据库文件（如果不存在）以及执行数据库迁移（创建表结构）。
-/// Handles database connection pool initialization, database file creation (if missing),
-/// and database migrations (table schema creation).
+//! 数据库连接管理模块
+//! Database connection management module
+//!
+//! 负责初始化数据库连接池、创建数据库文件（如果不存在）以及执行数据库迁移（创建表结构）。
+//! Handles database connection pool initialization, database file creation (if missing),
+//! and database migrations (table schema creation).
 
 use sqlx::sqlite::SqlitePoolOptions;
 use sqlx::{Pool, Sqlite};
@@ -36,12 +36,17 @@ pub async fn init_db() -> Result<DbPool, sqlx::Error> {
     //    默认为 "sqlite:wallet-os.db"
     //    Defaults to "sqlite:wallet-os.db"
     let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:wallet-os.db".to_string());
-    
+    open(&database_url).await
+}
+
+/// 打开指定的数据库并执行迁移 (步骤 2–4)
+/// Open the given database and run the migrations (steps 2–4)
+pub async fn open(database_url: &str) -> Result<DbPool, sqlx::Error> {
     // 2. 处理数据库文件路径
     //    Handle database file path
     //    去除 "sqlite:" 前缀以获取文件系统路径
     //    Strip "sqlite:" prefix to get the filesystem path
-    let db_path = database_url.strip_prefix("sqlite:").unwrap_or(&database_url);
+    let db_path = database_url.strip_prefix("sqlite:").unwrap_or(database_url);
     
     // 检查并创建数据库文件
     // Check and create database file
@@ -70,7 +75,7 @@ pub async fn init_db() -> Result<DbPool, sqlx::Error> {
     
     // 解析数据库连接字符串
     // Parse database connection string
-    let connect_options = SqliteConnectOptions::from_str(&database_url)?
+    let connect_options = SqliteConnectOptions::from_str(database_url)?
         .journal_mode(SqliteJournalMode::Wal) // 开启 WAL 模式以提高并发性能 / Enable WAL mode for better concurrency
         .create_if_missing(true)
         .log_statements(log::LevelFilter::Info); // 链式调用 log_statements
@@ -112,5 +117,415 @@ pub async fn init_db() -> Result<DbPool, sqlx::Error> {
         .execute(&pool)
         .await;
 
+    // 5. 用户与个人访问令牌 (API Token)
+    //    Users and personal access tokens
+    //    令牌只保存 SHA-256 哈希，明文仅在创建时返回一次。
+    //    Only the SHA-256 hash of a token is stored; the plaintext is returned once on creation.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL UNIQUE,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS api_tokens (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            token_prefix TEXT NOT NULL,
+            scopes TEXT NOT NULL, -- 逗号分隔 Comma separated: read,write,admin
+            expires_at TEXT,
+            last_used_at TEXT,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            revoked_at TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_api_tokens_user ON api_tokens(user_id);
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 为 OIDC 单点登录补充用户字段 (旧数据库迁移，列已存在时忽略错误)
+    // Add user columns for OIDC single sign-on (migration for existing db; errors ignored if present)
+    for column in [
+        "email TEXT",
+        "display_name TEXT",
+        "oidc_issuer TEXT",
+        "oidc_subject TEXT",
+        "is_admin BOOLEAN NOT NULL DEFAULT 0",
+    ] {
+        let _ = sqlx::query(&format!("ALTER TABLE users ADD COLUMN {}", column))
+            .execute(&pool)
+            .await;
+    }
+
+    // 浏览器会话 (OIDC 登录后签发)
+    // Browser sessions (issued after OIDC login)
+    sqlx::query(
+        r#"
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_users_oidc ON users(oidc_issuer, oidc_subject);
+        CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            token_hash TEXT NOT NULL UNIQUE,
+            expires_at TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 单用户部署的默认本地用户 (id = 1)，未携带凭据的请求归属于该用户
+    // Default local user (id = 1) for single-user deployments; requests without credentials belong to it
+    sqlx::query("INSERT OR IGNORE INTO users (id, username, is_admin) VALUES (1, 'admin', 1)")
+        .execute(&pool)
+        .await?;
+
+    // 用户角色 (viewer / editor / admin)；首次添加该列时，由旧的 is_admin 标记迁移
+    // User roles (viewer / editor / admin); migrated from the old is_admin flag when the column is first added
+    if sqlx::query("ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'editor'")
+        .execute(&pool)
+        .await
+        .is_ok()
+    {
+        sqlx::query("UPDATE users SET role = 'admin' WHERE is_admin = 1")
+            .execute(&pool)
+            .await?;
+    }
+
+    // 6. 家庭组与订阅归属
+    //    Households and subscription ownership
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS households (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            owner_id INTEGER NOT NULL REFERENCES users(id),
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS household_members (
+            household_id INTEGER NOT NULL REFERENCES households(id) ON DELETE CASCADE,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            joined_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (household_id, user_id)
+        );
+        CREATE INDEX IF NOT EXISTS idx_household_members_user ON household_members(user_id);
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 为订阅添加拥有者与共享家庭组列，已有数据归属默认本地用户
+    // Add owner and shared-household columns to subscriptions; existing rows belong to the default local user
+    let _ = sqlx::query("ALTER TABLE subscriptions ADD COLUMN owner_id INTEGER REFERENCES users(id)")
+        .execute(&pool)
+        .await;
+    let _ = sqlx::query("ALTER TABLE subscriptions ADD COLUMN household_id INTEGER REFERENCES households(id)")
+        .execute(&pool)
+        .await;
+    sqlx::query(
+        r#"
+        UPDATE subscriptions SET owner_id = 1 WHERE owner_id IS NULL;
+        CREATE INDEX IF NOT EXISTS idx_subscriptions_owner ON subscriptions(owner_id);
+        CREATE INDEX IF NOT EXISTS idx_subscriptions_household ON subscriptions(household_id);
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 7. 费用分摊与结清记录
+    //    Cost splits and settlements
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS subscription_splits (
+            subscription_id INTEGER PRIMARY KEY REFERENCES subscriptions(id) ON DELETE CASCADE,
+            method TEXT NOT NULL, -- equal, percentage, fixed
+            updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS subscription_split_members (
+            subscription_id INTEGER NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            share REAL,
+            PRIMARY KEY (subscription_id, user_id)
+        );
+        CREATE TABLE IF NOT EXISTS split_settlements (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            subscription_id INTEGER NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            period TEXT NOT NULL, -- YYYY-MM
+            amount REAL NOT NULL,
+            settled_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (subscription_id, user_id, period)
+        );
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 8. 订阅分类与价格变更记录 (用于消费洞察)
+    //    Subscription categories and price change history (used by spending insights)
+    let _ = sqlx::query("ALTER TABLE subscriptions ADD COLUMN category TEXT")
+        .execute(&pool)
+        .await;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS price_changes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            subscription_id INTEGER NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+            old_price REAL NOT NULL,
+            new_price REAL NOT NULL,
+            currency TEXT NOT NULL,
+            changed_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX IF NOT EXISTS idx_price_changes_subscription ON price_changes(subscription_id);
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 9. 邮件导入：已处理的邮件 UID 与待审核队列
+    //    Email ingestion: processed message UIDs and the review queue
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS mail_processed (
+            folder TEXT NOT NULL,
+            uid_validity INTEGER NOT NULL,
+            uid INTEGER NOT NULL,
+            processed_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (folder, uid_validity, uid)
+        );
+        CREATE TABLE IF NOT EXISTS review_queue (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            source TEXT NOT NULL,
+            subject TEXT,
+            sender TEXT,
+            received_at TEXT,
+            excerpt TEXT NOT NULL,
+            parsed TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            subscription_id INTEGER REFERENCES subscriptions(id) ON DELETE SET NULL,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX IF NOT EXISTS idx_review_queue_user ON review_queue(user_id, status);
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 10. 提示词版本 (最新一条为当前版本)
+    //     Prompt versions (the latest row is the current version)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS prompt_versions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            content TEXT NOT NULL,
+            source TEXT NOT NULL,
+            file_hash TEXT,
+            created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 11. AI 用量记录 (缓存命中也记录一行，token 与费用为 0)
+    //     AI usage records (cache hits are recorded too, with zero tokens and cost)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS ai_usage (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+            feature TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            model TEXT NOT NULL,
+            prompt_tokens INTEGER NOT NULL DEFAULT 0,
+            completion_tokens INTEGER NOT NULL DEFAULT 0,
+            cost REAL NOT NULL DEFAULT 0,
+            cached BOOLEAN NOT NULL DEFAULT 0,
+            month TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX IF NOT EXISTS idx_ai_usage_month ON ai_usage(month);
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 12. 订阅暂停 (暂停期间 active 为 0，到期自动恢复) 与助手对话记录
+    //     Subscription pausing (active is 0 while paused, resumed automatically when due) and
+    //     assistant conversations
+    let _ = sqlx::query("ALTER TABLE subscriptions ADD COLUMN paused_until TEXT")
+        .execute(&pool)
+        .await;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS chat_conversations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            title TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX IF NOT EXISTS idx_chat_conversations_user ON chat_conversations(user_id);
+        CREATE TABLE IF NOT EXISTS chat_messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            conversation_id INTEGER NOT NULL REFERENCES chat_conversations(id) ON DELETE CASCADE,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            tool TEXT,
+            payload TEXT NOT NULL DEFAULT 'null',
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX IF NOT EXISTS idx_chat_messages_conversation ON chat_messages(conversation_id);
+        CREATE TABLE IF NOT EXISTS chat_actions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            conversation_id INTEGER NOT NULL REFERENCES chat_conversations(id) ON DELETE CASCADE,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            tool TEXT NOT NULL,
+            arguments TEXT NOT NULL,
+            summary TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            result TEXT NOT NULL DEFAULT 'null',
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 13. 域名覆盖表 (用户手动指定的名称与官网域名，优先于其他解析来源)
+    //     Domain overrides (names mapped to official domains by users, ahead of other resolvers)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS domain_overrides (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            query TEXT NOT NULL,
+            domain TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (user_id, query)
+        );
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 14. 域名搜索缓存 (domain 为空表示缓存的“未找到”；pinned 条目不过期也不被淘汰)
+    //     Domain search cache (an empty domain is a cached "not found"; pinned entries never
+    //     expire and are never evicted)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS search_cache (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            key TEXT NOT NULL UNIQUE,
+            query TEXT NOT NULL,
+            domain TEXT,
+            source TEXT,
+            confidence REAL,
+            pinned BOOLEAN NOT NULL DEFAULT 0,
+            hits INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            last_used_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            expires_at TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_search_cache_last_used ON search_cache(last_used_at);
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 15. 图标缓存索引 (文件保存在 ICON_CACHE_DIR；checked_at 为最近一次获取或检查的时间)
+    //     Icon cache index (the files live in ICON_CACHE_DIR; checked_at is the last fetch or check)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS icon_cache (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            domain TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            source TEXT NOT NULL,
+            etag TEXT NOT NULL,
+            bytes INTEGER NOT NULL,
+            hits INTEGER NOT NULL DEFAULT 0,
+            fetched_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            checked_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            last_used_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (domain, size)
+        );
+        CREATE INDEX IF NOT EXISTS idx_icon_cache_checked ON icon_cache(checked_at);
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 16. 上传的自定义 Logo (按内容哈希保存在 LOGO_DIR，订阅的 logo 字段引用 /api/logos/<hash>)
+    //     Uploaded custom logos (stored by content hash in LOGO_DIR, referenced from a
+    //     subscription's logo field as /api/logos/<hash>)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS logo_assets (
+            hash TEXT PRIMARY KEY,
+            format TEXT NOT NULL,
+            original_bytes INTEGER NOT NULL,
+            uploaded_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+            uploaded_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 17. 图标与自定义 Logo 的主色与强调色 (#rrggbb)
+    //     Dominant and accent colours (#rrggbb) of icons and custom logos
+    for table in ["icon_cache", "logo_assets"] {
+        for column in ["dominant_color", "accent_color"] {
+            let _ = sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} TEXT", table, column))
+                .execute(&pool)
+                .await;
+        }
+    }
+
+    // 18. 已推送的续费提醒 (每个订阅每天至多一次，服务重启后不会重复推送)
+    //     Renewal reminders already pushed (at most once per subscription and day, so a restart
+    //     does not push them again)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS reminders_sent (
+            subscription_id INTEGER NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+            sent_on TEXT NOT NULL,
+            PRIMARY KEY (subscription_id, sent_on)
+        );
+        "#
+    )
+    .execute(&pool)
+    .await?;
+
+    // 19. 审核条目被认领 (开始通过) 的时间，超时未完成的条目可以重新审核
+    //     When a review item was claimed for approval, so items stuck past the timeout can be
+    //     reviewed again
+    let _ = sqlx::query("ALTER TABLE review_queue ADD COLUMN claimed_at TEXT")
+        .execute(&pool)
+        .await;
+
     Ok(pool)
 }
+
+/// 测试用的独立数据库 (临时目录中的新文件，已执行迁移)
+/// A separate database for tests (a new file in the temp directory, with migrations applied)
+#[cfg(test)]
+pub async fn test_pool() -> DbPool {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "wallet-os-test-{}-{}.db",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    open(&format!("sqlite:{}", path.display())).await.expect("test database")
+}
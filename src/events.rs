@@ -0,0 +1,277 @@
+//! 事件总线模块
+//! Event bus module
+//!
+//! 订阅变更、到期提醒等事件带有类型 (`subscription.created` 等)、变更后的实体 (JSON) 与单调递增
+//! 的 id。最近的事件保存在有界日志中，断线重连的客户端携带 `Last-Event-ID` 即可补收错过的事件；
+//! 错过的事件已不在日志中或接收端积压时，客户端会收到 `resync` 事件并应重新拉取完整数据。
+//! 每个事件记录了能看到它的用户 (拥有者与共享家庭组成员)，推送前逐个检查。
+//! Events such as subscription changes and due reminders carry a type (`subscription.created`
+//! and so on), the changed entity as JSON and a monotonically increasing id. Recent events are
+//! kept in a bounded log, so a reconnecting client that sends `Last-Event-ID` receives the events
+//! it missed; when they are no longer in the log or the receiver lags behind, the client gets a
+//! `resync` event and should fetch the full data again. Each event records who may see it (the
+//! owner and members of the shared household), checked before it is pushed.
+
+use crate::db::DbPool;
+use crate::households;
+use crate::insights;
+use crate::models::Subscription;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Serialize, Serializer};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::info;
+
+/// 广播通道容量 / Capacity of the broadcast channel
+const CHANNEL_CAPACITY: usize = 100;
+
+/// 要求客户端重新拉取完整数据的事件类型
+/// Event type asking the client to fetch the full data again
+pub const RESYNC: &str = "resync";
+
+/// 事件类型 (序列化为 `name()` 返回的名称)
+/// Event type (serialized as the name returned by `name()`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    SubscriptionCreated,
+    SubscriptionUpdated,
+    SubscriptionDeleted,
+    SubscriptionPaused,
+    SubscriptionResumed,
+    /// 订阅不再共享给某个家庭组，该组成员应将其移除
+    /// A subscription is no longer shared with a household, whose members should drop it
+    SubscriptionUnshared,
+    ReminderDue,
+}
+
+impl EventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::SubscriptionCreated => "subscription.created",
+            EventKind::SubscriptionUpdated => "subscription.updated",
+            EventKind::SubscriptionDeleted => "subscription.deleted",
+            EventKind::SubscriptionPaused => "subscription.paused",
+            EventKind::SubscriptionResumed => "subscription.resumed",
+            EventKind::SubscriptionUnshared => "subscription.unshared",
+            EventKind::ReminderDue => "reminder.due",
+        }
+    }
+}
+
+impl Serialize for EventKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// 能看到事件的用户
+/// Users who may see an event
+#[derive(Debug, Clone, Copy)]
+pub enum Audience {
+    /// 订阅的拥有者与共享家庭组成员 / The subscription's owner and members of its shared household
+    Subscription { owner_id: i64, household_id: Option<i64> },
+    /// 家庭组成员，不含指定用户 (通常是仍能看到该订阅的拥有者)
+    /// Members of a household except one user (usually the owner, who can still see the subscription)
+    Household { household_id: i64, except: i64 },
+}
+
+impl Audience {
+    pub fn of(sub: &Subscription) -> Self {
+        Audience::Subscription { owner_id: sub.owner_id, household_id: sub.household_id }
+    }
+}
+
+/// 事件涉及的订阅，用于按订阅或分类过滤
+/// The subscription an event is about, used to filter by subscription or category
+#[derive(Debug, Clone, Default)]
+pub struct Subject {
+    pub subscription_id: i64,
+    /// 订阅当前的分类 / The subscription's current category
+    pub categories: Vec<String>,
+    /// 本次修改前的分类 (分类未变时为空)；按这些分类过滤的客户端会收到订阅离开过滤范围的通知
+    /// Categories before this change (empty when unchanged); clients filtering on them are told
+    /// that the subscription left their filter
+    pub left_categories: Vec<String>,
+}
+
+impl Subject {
+    pub fn of(sub: &Subscription) -> Self {
+        Subject {
+            subscription_id: sub.id,
+            categories: insights::category_of(sub).into_iter().collect(),
+            left_categories: Vec::new(),
+        }
+    }
+}
+
+/// 事件
+/// Event
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub id: u64,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    /// 事件时间 (RFC 3339) / Event time (RFC 3339)
+    pub at: String,
+    pub data: serde_json::Value,
+    #[serde(skip)]
+    pub audience: Audience,
+    #[serde(skip)]
+    pub subject: Option<Subject>,
+}
+
+impl Event {
+    /// 用户能否看到该事件；家庭组成员关系可能在连接期间变化，因此每次都重新查询
+    /// Whether a user may see the event; household membership may change while connected, so it
+    /// is checked every time
+    pub async fn visible_to(&self, pool: &DbPool, user_id: i64) -> bool {
+        let (household_id, except) = match self.audience {
+            Audience::Subscription { owner_id, .. } if owner_id == user_id => return true,
+            Audience::Subscription { household_id: Some(hid), .. } => (hid, None),
+            Audience::Subscription { household_id: None, .. } => return false,
+            Audience::Household { household_id, except } => (household_id, Some(except)),
+        };
+        except != Some(user_id) && households::is_member(pool, household_id, user_id).await.unwrap_or(false)
+    }
+}
+
+/// 重新同步的原因
+/// Reason for a resync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResyncReason {
+    /// 错过的事件已不在日志中 (或服务已重启)
+    /// Missed events are no longer in the log (or the server restarted)
+    Expired,
+    /// 接收端积压，部分事件被丢弃 / The receiver lagged behind and some events were dropped
+    Lagged,
+}
+
+struct Log {
+    next_id: u64,
+    events: VecDeque<Arc<Event>>,
+}
+
+struct Bus {
+    sender: broadcast::Sender<Arc<Event>>,
+    log: Mutex<Log>,
+    capacity: usize,
+}
+
+static BUS: Lazy<Bus> = Lazy::new(|| {
+    let capacity = std::env::var("EVENT_LOG_SIZE")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(1000);
+    let (sender, _rx) = broadcast::channel(CHANNEL_CAPACITY);
+    // id 从启动时刻 (微秒) 开始计数，服务重启后仍然递增，旧进程的 id 不会被误认为仍可补发
+    // Ids start at the startup time (in microseconds), so they keep increasing across restarts and
+    // ids from a previous process are never mistaken for replayable ones
+    let next_id = Utc::now().timestamp_micros().max(1) as u64;
+    Bus { sender, log: Mutex::new(Log { next_id, events: VecDeque::with_capacity(capacity) }), capacity }
+});
+
+/// 发布事件，返回分配的 id
+/// Publish an event, returning the id it was assigned
+pub fn publish(kind: EventKind, audience: Audience, subject: Option<Subject>, data: serde_json::Value) -> u64 {
+    let mut log = BUS.log.lock();
+    let event = Arc::new(Event { id: log.next_id, kind, at: Utc::now().to_rfc3339(), data, audience, subject });
+    log.next_id += 1;
+    if log.events.len() == BUS.capacity {
+        log.events.pop_front();
+    }
+    log.events.push_back(event.clone());
+    // 持锁发送，保证订阅时的日志快照与通道中的事件不重不漏
+    // Send while holding the lock, so a subscriber's log snapshot and the channel neither overlap
+    // nor leave a gap
+    let _ = BUS.sender.send(event.clone());
+    event.id
+}
+
+/// 发布订阅相关事件，数据为订阅本身
+/// Publish a subscription event whose data is the subscription itself
+pub fn publish_subscription(kind: EventKind, sub: &Subscription) -> u64 {
+    publish(kind, Audience::of(sub), Some(Subject::of(sub)), serde_json::to_value(sub).unwrap_or_default())
+}
+
+/// 事件订阅：先补发的事件，再接收后续事件
+/// An event feed: replayed events first, then live ones
+pub struct Feed {
+    /// 客户端错过、仍在日志中的事件 / Missed events that are still in the log
+    pub replay: Vec<Arc<Event>>,
+    /// 错过的事件无法完整补发时为 `Some` / `Some` when the missed events cannot all be replayed
+    pub resync: Option<ResyncReason>,
+    pub receiver: broadcast::Receiver<Arc<Event>>,
+}
+
+/// 订阅事件；`last_id` 为客户端最后收到的事件 id
+/// Subscribe to events; `last_id` is the id of the last event the client received
+pub fn subscribe(last_id: Option<u64>) -> Feed {
+    let log = BUS.log.lock();
+    let receiver = BUS.sender.subscribe();
+    let Some(last_id) = last_id else {
+        return Feed { replay: Vec::new(), resync: None, receiver };
+    };
+    let oldest = log.events.front().map_or(log.next_id, |e| e.id);
+    // 早于日志中最旧事件 (含服务重启前) 或从未分配过的 id 都无法补发
+    // Ids older than the oldest logged event (including those from before a restart) or never
+    // assigned cannot be replayed
+    if last_id >= log.next_id || last_id + 1 < oldest {
+        return Feed { replay: Vec::new(), resync: Some(ResyncReason::Expired), receiver };
+    }
+    let replay = log.events.iter().filter(|e| e.id > last_id).cloned().collect();
+    Feed { replay, resync: None, receiver }
+}
+
+/// 只接收实时事件 (用于失效依赖订阅数据的缓存)
+/// Receive live events only (used to invalidate caches derived from subscription data)
+pub fn receiver() -> broadcast::Receiver<Arc<Event>> {
+    BUS.sender.subscribe()
+}
+
+/// 解析 `Last-Event-ID`
+/// Parse `Last-Event-ID`
+pub fn parse_last_id(value: &str) -> Option<u64> {
+    value.trim().parse().ok()
+}
+
+pub fn init() {
+    info!("Event log keeps the last {} events", BUS.capacity);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 补发最后收到的 id 之后的事件；未分配或已移出日志的 id 要求重新同步
+    /// Events after the last received id are replayed; ids never assigned or already dropped from
+    /// the log require a resync
+    #[test]
+    fn replays_events_after_last_id() {
+        let audience = Audience::Subscription { owner_id: 1, household_id: None };
+        let first = publish(EventKind::SubscriptionCreated, audience, None, serde_json::json!({ "id": 1 }));
+        let second = publish(EventKind::SubscriptionDeleted, audience, None, serde_json::json!({ "id": 1 }));
+
+        let feed = subscribe(Some(first));
+        assert_eq!(feed.replay.iter().map(|e| e.id).collect::<Vec<_>>(), vec![second]);
+        assert_eq!(feed.resync, None);
+        assert!(subscribe(Some(second)).replay.is_empty());
+        assert_eq!(subscribe(Some(second + 1)).resync, Some(ResyncReason::Expired));
+        // 其他测试可能先发布了事件，以日志中最旧的事件为界
+        // Other tests may have published events first, so the bound is the oldest logged event
+        let oldest = BUS.log.lock().events.front().map_or(first, |e| e.id);
+        assert_eq!(subscribe(Some(oldest - 1)).resync, None);
+        assert_eq!(subscribe(Some(oldest - 2)).resync, Some(ResyncReason::Expired));
+        assert_eq!(subscribe(None).resync, None);
+    }
+
+    #[test]
+    fn serializes_kind_as_name() {
+        let event = serde_json::to_value(EventKind::SubscriptionUnshared).unwrap();
+        assert_eq!(event, serde_json::json!("subscription.unshared"));
+    }
+}
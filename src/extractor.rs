@@ -0,0 +1,585 @@
+//! 离线解析模块
+//! Offline extraction module
+//!
+//! 未配置 LLM 或调用失败时使用的规则解析器，面向收据、账单邮件与扣款短信：识别货币符号与代码、
+//! 带千分位的金额、中英文计费周期短语 ("monthly"、"每月"、"年付"、"季度")、常见日期格式与相对
+//! 日期，并将商户名称与内置服务目录匹配。
+//! Rule-based parser used when no LLM is configured or the call fails, aimed at receipts, billing
+//! emails and payment SMS: recognises currency symbols and codes, amounts with thousand
+//! separators, billing-period phrases in English and Chinese ("monthly", "每月", "年付", "季度"),
+//! common date formats and relative dates, and matches merchant names against a built-in service
+//! catalog.
+
+use crate::catalog;
+use crate::dates;
+use crate::insights;
+use crate::models::{FieldConfidence, SmartParseResult};
+use crate::prompts;
+use chrono::{Datelike, NaiveDate};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// 货币符号与名称 (长的在前，避免 "US$" 被识别为 "$")
+/// Currency symbols and names (longer first, so that "US$" is not read as "$")
+const CURRENCY_SYMBOLS: &[(&str, &str)] = &[
+    ("us$", "USD"),
+    ("hk$", "HKD"),
+    ("nt$", "TWD"),
+    ("a$", "AUD"),
+    ("c$", "CAD"),
+    ("s$", "SGD"),
+    ("jp¥", "JPY"),
+    ("cn¥", "CNY"),
+    ("人民币", "CNY"),
+    ("rmb", "CNY"),
+    ("美元", "USD"),
+    ("美金", "USD"),
+    ("港币", "HKD"),
+    ("欧元", "EUR"),
+    ("英镑", "GBP"),
+    ("日元", "JPY"),
+    ("元", "CNY"),
+    ("块", "CNY"),
+    ("円", "JPY"),
+    ("$", "USD"),
+    ("¥", "CNY"),
+    ("€", "EUR"),
+    ("£", "GBP"),
+    ("₩", "KRW"),
+    ("₹", "INR"),
+];
+
+/// 识别的 ISO 4217 货币代码
+/// Recognised ISO 4217 currency codes
+const CURRENCY_CODES: &[&str] = &["CNY", "USD", "EUR", "GBP", "JPY", "HKD", "TWD", "AUD", "CAD", "SGD", "KRW", "INR", "CHF"];
+
+/// 计费周期短语 (匹配小写文本，取最先出现的一个)
+/// Billing-period phrases (matched against lowercase text; the earliest one wins)
+const FREQUENCY_PHRASES: &[(&str, i64)] = &[
+    ("lifetime", 0),
+    ("one-time", 0),
+    ("one time", 0),
+    ("永久", 0),
+    ("终身", 0),
+    ("买断", 0),
+    ("一次性", 0),
+    ("quarterly", 3),
+    ("per quarter", 3),
+    ("every 3 months", 3),
+    ("/quarter", 3),
+    ("季度", 3),
+    ("季付", 3),
+    ("包季", 3),
+    ("季卡", 3),
+    ("yearly", 12),
+    ("annual", 12),
+    ("per year", 12),
+    ("every year", 12),
+    ("/year", 12),
+    ("/yr", 12),
+    ("每年", 12),
+    ("年付", 12),
+    ("包年", 12),
+    ("年度", 12),
+    ("年费", 12),
+    ("年卡", 12),
+    ("/年", 12),
+    ("monthly", 1),
+    ("per month", 1),
+    ("every month", 1),
+    ("each month", 1),
+    ("/month", 1),
+    ("/mo", 1),
+    ("每月", 1),
+    ("每个月", 1),
+    ("月付", 1),
+    ("包月", 1),
+    ("月度", 1),
+    ("月费", 1),
+    ("月卡", 1),
+    ("/月", 1),
+    ("daily", -1),
+    ("per day", -1),
+    ("every day", -1),
+    ("/day", -1),
+    ("每天", -1),
+    ("每日", -1),
+    ("/天", -1),
+];
+
+/// 表示价格的关键词
+/// Keywords that introduce a price
+const PRICE_KEYWORDS: &[&str] = &[
+    "price", "amount", "total", "charged", "charge", "paid", "payment of", "billed", "cost",
+    "金额", "价格", "扣款", "扣费", "支付", "付款", "实付", "消费", "费用", "收费", "续费",
+];
+
+/// 表示下次付款或到期的关键词
+/// Keywords that introduce the next payment or expiry date
+const NEXT_PAYMENT_KEYWORDS: &[&str] = &[
+    "next", "renew", "due", "expire", "until", "valid through", "through",
+    "下次", "续费", "续订", "到期", "截止", "有效期至", "至",
+];
+
+/// 续费或到期关键词：出现在文本中时相对日期视为续费日期，紧跟在日期后时该日期为下次付款日期
+/// Renewal or expiry keywords: anywhere in the text they make a relative date the renewal date;
+/// directly after a date they make it the next payment date
+const RENEWAL_KEYWORDS: &[&str] = &["renew", "due", "expire", "续费", "续订", "到期", "下次扣款", "下次付款"];
+
+/// 紧跟在数字后表示非金额的单位
+/// Units directly after a number that mark it as not being an amount
+const NON_AMOUNT_UNITS: &[&str] = &[
+    "day", "week", "month", "year", "gb", "tb", "%", "user", "device", "screen", "seat", "member", "person", "people",
+    "天", "日", "周", "个月", "月", "年", "次", "人", "位", "台", "个", "张", "期",
+];
+
+/// 前面出现时表示非金额的词 (卡号、订单号等)
+/// Words before a number that mark it as not being an amount (card numbers, order numbers, ...)
+const NON_AMOUNT_PREFIXES: &[&str] = &["ending in", "ending", "card", "order", "invoice", "#", "no.", "尾号", "订单", "单号", "卡号"];
+
+/// 短信签名中的支付平台或银行，不作为商户名称
+/// Payment platforms or banks in SMS signatures, not used as merchant names
+const PAYMENT_PLATFORMS: &[&str] = &["支付宝", "微信", "财付通", "银联", "云闪付", "银行", "apple", "google play", "paypal"];
+
+/// 金额：整数部分可带千分位 (逗号或点)，小数部分最多两位
+/// Amount: the integer part may have thousand separators (comma or dot), at most two decimals
+static AMOUNT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(\d{1,3}(?:[,.]\d{3})+|\d+)(?:[.,](\d{1,2}))?").unwrap());
+
+/// 数字日期：2026-01-05、2026/1/5、2026.01.05、2026年1月5日
+/// Numeric dates: 2026-01-05, 2026/1/5, 2026.01.05, 2026年1月5日
+static ISO_DATE_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(\d{4})\s*[-/.年]\s*(\d{1,2})\s*[-/.月]\s*(\d{1,2})\s*[日号]?").unwrap());
+
+/// 美式日期 01/05/2026 (首位大于 12 时按日/月/年处理)
+/// US dates 01/05/2026 (read as day/month/year when the first part is above 12)
+static SLASH_DATE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d{1,2})/(\d{1,2})/(\d{4})\b").unwrap());
+
+/// 英文月份日期：Jan 5, 2026 / January 5th 2026
+/// English month dates: Jan 5, 2026 / January 5th 2026
+static MONTH_FIRST_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.?\s+(\d{1,2})(?:st|nd|rd|th)?,?\s+(\d{4})\b").unwrap()
+});
+
+/// 英文日期：5 January 2026 / 5th Jan, 2026
+/// English dates: 5 January 2026 / 5th Jan, 2026
+static DAY_FIRST_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\b(\d{1,2})(?:st|nd|rd|th)?\s+(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.?,?\s+(\d{4})\b").unwrap()
+});
+
+/// 无年份的中文日期：1月5日 (按今年处理)
+/// Chinese dates without a year: 1月5日 (taken as this year)
+static MONTH_DAY_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d{1,2})\s*月\s*(\d{1,2})\s*[日号]").unwrap());
+
+/// 短信签名：【爱奇艺】
+/// SMS signature: 【爱奇艺】
+static SIGNATURE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"【([^】]{1,20})】").unwrap());
+
+/// 英文账单中的服务名称："Your Acme Pro subscription"
+/// Service name in English bills: "Your Acme Pro subscription"
+static YOUR_PLAN_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)\byour\s+([A-Za-z0-9][A-Za-z0-9+ .&-]{0,40}?)\s+(?:subscription|membership|plan)\b").unwrap()
+});
+
+/// 书名号中的标题
+/// Titles in book-title marks
+static TITLE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"《[^》]*》").unwrap());
+
+static URL_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"https?://[^\s<>"'，。）)]+"#).unwrap());
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// 文本中识别到的日期
+/// A date found in the text
+struct FoundDate {
+    start: usize,
+    end: usize,
+    date: NaiveDate,
+}
+
+/// 将全角符号与数字转换为半角，便于统一匹配
+/// Convert full-width symbols and digits to half-width for uniform matching
+fn normalize(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32).unwrap_or(c),
+            '￥' => '¥',
+            '＄' => '$',
+            '．' => '.',
+            '，' => ',',
+            '：' => ':',
+            '／' => '/',
+            '\u{00a0}' | '\u{3000}' => ' ',
+            _ => c,
+        })
+        .collect()
+}
+
+fn month_number(name: &str) -> Option<u32> {
+    let prefix = name.get(..3)?.to_lowercase();
+    MONTHS.iter().position(|m| *m == prefix).map(|p| p as u32 + 1)
+}
+
+/// 找出文本中的所有日期，按出现顺序排列
+/// Find all dates in the text, in order of appearance
+fn find_dates(text: &str, today: NaiveDate) -> Vec<FoundDate> {
+    let mut found: Vec<FoundDate> = Vec::new();
+    let mut push = |start: usize, end: usize, date: Option<NaiveDate>| {
+        if let Some(date) = date {
+            if !found.iter().any(|f| start < f.end && f.start < end) {
+                found.push(FoundDate { start, end, date });
+            }
+        }
+    };
+    let number = |s: &str| s.parse::<u32>().ok();
+
+    for c in ISO_DATE_RE.captures_iter(text) {
+        let m = c.get(0).unwrap();
+        let date = number(&c[1]).and_then(|y| NaiveDate::from_ymd_opt(y as i32, number(&c[2])?, number(&c[3])?));
+        push(m.start(), m.end(), date);
+    }
+    for c in SLASH_DATE_RE.captures_iter(text) {
+        let m = c.get(0).unwrap();
+        let (a, b, year) = (number(&c[1]), number(&c[2]), number(&c[3]));
+        let date = match (a, b, year) {
+            (Some(a), Some(b), Some(y)) if a > 12 => NaiveDate::from_ymd_opt(y as i32, b, a),
+            (Some(a), Some(b), Some(y)) => NaiveDate::from_ymd_opt(y as i32, a, b),
+            _ => None,
+        };
+        push(m.start(), m.end(), date);
+    }
+    for c in MONTH_FIRST_RE.captures_iter(text) {
+        let m = c.get(0).unwrap();
+        let date = number(&c[3]).and_then(|y| NaiveDate::from_ymd_opt(y as i32, month_number(&c[1])?, number(&c[2])?));
+        push(m.start(), m.end(), date);
+    }
+    for c in DAY_FIRST_RE.captures_iter(text) {
+        let m = c.get(0).unwrap();
+        let date = number(&c[3]).and_then(|y| NaiveDate::from_ymd_opt(y as i32, month_number(&c[2])?, number(&c[1])?));
+        push(m.start(), m.end(), date);
+    }
+    for c in MONTH_DAY_RE.captures_iter(text) {
+        let m = c.get(0).unwrap();
+        let date = NaiveDate::from_ymd_opt(today.year(), number(&c[1]).unwrap_or(0), number(&c[2]).unwrap_or(0));
+        push(m.start(), m.end(), date);
+    }
+
+    found.sort_by_key(|f| f.start);
+    found
+}
+
+/// `pos` 之前最多 `chars` 个字符的文本 (小写)
+/// Up to `chars` characters of text before `pos` (lowercase)
+fn before(text: &str, pos: usize, chars: usize) -> String {
+    let head = &text[..pos];
+    let skip = head.chars().count().saturating_sub(chars);
+    head.chars().skip(skip).collect::<String>().to_lowercase()
+}
+
+/// 日期前是否出现下次付款或到期的关键词，或日期后紧跟续费/到期关键词 ("2026-02-05 到期")
+/// Whether a next-payment or expiry keyword appears before the date, or a renewal/expiry keyword
+/// directly follows it ("2026-02-05 到期")
+fn is_next_payment(text: &str, date: &FoundDate) -> bool {
+    let context = before(text, date.start, 24);
+    let after: String = text[date.end..].chars().take(8).collect::<String>().to_lowercase();
+    NEXT_PAYMENT_KEYWORDS.iter().any(|k| context.contains(k)) || RENEWAL_KEYWORDS.iter().any(|k| after.contains(k))
+}
+
+/// 紧邻金额的货币 (前面或后面)
+/// Currency directly next to an amount (before or after it)
+fn adjacent_currency(text: &str, start: usize, end: usize) -> Option<&'static str> {
+    let head = before(text, start, 6);
+    let head = head.trim_end();
+    let tail: String = text[end..].trim_start().chars().take(6).collect::<String>().to_lowercase();
+    CURRENCY_SYMBOLS
+        .iter()
+        .find(|(symbol, _)| head.ends_with(symbol) || tail.starts_with(symbol))
+        .map(|(_, code)| *code)
+        .or_else(|| {
+            CURRENCY_CODES
+                .iter()
+                .find(|code| {
+                    let code = code.to_lowercase();
+                    head.ends_with(&code) || tail.starts_with(&code)
+                })
+                .copied()
+        })
+}
+
+/// 文本中任意位置出现的货币
+/// A currency appearing anywhere in the text
+fn any_currency(text: &str) -> Option<&'static str> {
+    let lower = text.to_lowercase();
+    CURRENCY_CODES
+        .iter()
+        .find(|code| {
+            lower.match_indices(&code.to_lowercase()).any(|(i, m)| {
+                let boundary = |c: Option<char>| !c.is_some_and(|c| c.is_ascii_alphabetic());
+                boundary(lower[..i].chars().next_back()) && boundary(lower[i + m.len()..].chars().next())
+            })
+        })
+        .copied()
+        .or_else(|| CURRENCY_SYMBOLS.iter().find(|(symbol, _)| lower.contains(symbol)).map(|(_, code)| *code))
+}
+
+/// 解析金额字符串：最后一个分隔符后为 1–2 位数字时视为小数点，其余分隔符视为千分位
+/// Parse an amount: a separator followed by 1–2 trailing digits is the decimal point, other
+/// separators are thousand separators
+fn parse_amount(integer: &str, fraction: Option<&str>) -> Option<f64> {
+    let digits: String = integer.chars().filter(char::is_ascii_digit).collect();
+    match fraction {
+        Some(f) => format!("{}.{}", digits, f).parse().ok(),
+        None => digits.parse().ok(),
+    }
+}
+
+/// 解析只包含一个金额的字符串 (可带货币符号或代码)，小数点与千分位的判断与文本提取相同；
+/// 没有或有多个数字时返回 `None`，不处理正负号
+/// Parse a string holding a single amount (optionally with a currency symbol or code), telling
+/// decimal points from thousand separators the same way as text extraction; returns `None` when
+/// there is no number or more than one, and ignores signs
+pub fn parse_single_amount(text: &str) -> Option<f64> {
+    let mut found = AMOUNT_RE.captures_iter(text);
+    let c = found.next()?;
+    if found.next().is_some() {
+        return None;
+    }
+    parse_amount(&c[1], c.get(2).map(|f| f.as_str()))
+}
+
+/// 金额候选
+/// Amount candidate
+struct Amount {
+    value: f64,
+    score: i32,
+    currency: Option<&'static str>,
+}
+
+/// 找出最可能是价格的金额 (日期所在位置已被遮盖)
+/// Find the amount most likely to be the price (date positions have been masked)
+fn best_amount(masked: &str) -> Option<Amount> {
+    let mut best: Option<Amount> = None;
+    for c in AMOUNT_RE.captures_iter(masked) {
+        let m = c.get(0).unwrap();
+        let Some(value) = parse_amount(&c[1], c.get(2).map(|f| f.as_str())) else {
+            continue;
+        };
+        // 跳过单词或编号中的数字 ("USD9.99" 除外)
+        // Skip numbers inside words or identifiers (except "USD9.99")
+        let currency = adjacent_currency(masked, m.start(), m.end());
+        let next = masked[m.end()..].chars().next();
+        let prev = masked[..m.start()].chars().next_back();
+        if next.is_some_and(|c| c.is_ascii_digit()) || (currency.is_none() && prev.is_some_and(|c| c.is_ascii_alphabetic())) {
+            continue;
+        }
+        let context = before(masked, m.start(), 16);
+        let prefix = context.trim_end();
+        let tail = masked[m.end()..].trim_start().to_lowercase();
+        let mut score = 0;
+        if currency.is_some() {
+            score += 3;
+        }
+        if PRICE_KEYWORDS.iter().any(|k| context.contains(k)) {
+            score += 2;
+        }
+        if currency.is_none() && NON_AMOUNT_UNITS.iter().any(|u| tail.starts_with(u)) {
+            score -= 5;
+        }
+        if NON_AMOUNT_PREFIXES.iter().any(|p| prefix.ends_with(p)) {
+            score -= 5;
+        }
+        if currency.is_none() && c.get(2).is_none() && c[1].len() >= 5 {
+            score -= 3;
+        }
+        if score < 0 {
+            continue;
+        }
+        if best.as_ref().is_none_or(|b| score > b.score) {
+            best = Some(Amount { value, score, currency });
+        }
+    }
+    best
+}
+
+/// 遮盖日期，避免年份等数字被当作金额
+/// Mask dates so that years and other date numbers are not taken as amounts
+fn mask(text: &str, dates: &[FoundDate]) -> String {
+    let mut masked = text.to_string();
+    for d in dates {
+        masked.replace_range(d.start..d.end, &" ".repeat(d.end - d.start));
+    }
+    masked
+}
+
+/// 目录未命中时，从短信签名或 "Your X subscription" 中取名称
+/// Without a catalog match, take the name from an SMS signature or "Your X subscription"
+fn guess_name(text: &str) -> Option<String> {
+    SIGNATURE_RE
+        .captures_iter(text)
+        .map(|c| c[1].trim().to_string())
+        .find(|name| {
+            let lower = name.to_lowercase();
+            !PAYMENT_PLATFORMS.iter().any(|p| lower.contains(p))
+        })
+        .or_else(|| YOUR_PLAN_RE.captures(text).map(|c| c[1].trim().to_string()))
+        .filter(|name| !name.is_empty())
+}
+
+fn format_date(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+/// 规则解析订阅文本，相对日期以 `today` 为基准
+/// Rule-based parsing of subscription text, with relative dates resolved against `today`
+pub fn extract(text: &str, today: NaiveDate) -> SmartParseResult {
+    let text = normalize(text);
+    let lower = text.to_lowercase();
+    let mut confidence = FieldConfidence::default();
+
+    // 名称与分类：服务目录优先
+    // Name and category: the service catalog first
+    let merchant = catalog::find(&lower);
+    let name = match (merchant, guess_name(&text)) {
+        (Some(m), _) => {
+            confidence.name = 0.9;
+            m.name.clone()
+        }
+        (None, Some(name)) => {
+            confidence.name = 0.5;
+            name
+        }
+        (None, None) => "Unknown Subscription".to_string(),
+    };
+    let category = merchant
+        .map(|m| m.category.clone())
+        .or_else(|| insights::infer_category(&name).map(str::to_string));
+    let url = merchant
+        .map(|m| m.url.clone())
+        .or_else(|| URL_RE.find(&text).map(|m| m.as_str().to_string()));
+
+    // 金额与货币：紧邻金额的货币优先，其次文本中出现的货币，最后为默认货币
+    // Amount and currency: a currency next to the amount first, then any currency in the text,
+    // then the default currency
+    let found = find_dates(&text, today);
+    let amount = best_amount(&mask(&text, &found));
+    let price = amount.as_ref().map(|a| a.value);
+    confidence.price = match amount.as_ref().map(|a| a.score) {
+        Some(s) if s >= 5 => 0.9,
+        Some(s) if s >= 3 => 0.8,
+        Some(s) if s >= 2 => 0.6,
+        Some(_) => 0.3,
+        None => 0.0,
+    };
+    let currency = match amount.as_ref().and_then(|a| a.currency) {
+        Some(code) => {
+            confidence.currency = 0.9;
+            code.to_string()
+        }
+        None => match any_currency(&text) {
+            Some(code) => {
+                confidence.currency = 0.6;
+                code.to_string()
+            }
+            None => prompts::base_currency().to_string(),
+        },
+    };
+
+    // 计费周期：最先出现的短语 (忽略书名号中的标题，如《每天听本书》)
+    // Billing period: the earliest phrase (ignoring titles in book-title marks, e.g. 《每天听本书》)
+    let period_text = TITLE_RE.replace_all(&lower, "").replace("/ ", "/").replace(" /", "/");
+    let frequency = FREQUENCY_PHRASES
+        .iter()
+        .filter_map(|(phrase, freq)| period_text.find(phrase).map(|pos| (pos, *freq)))
+        .min_by_key(|(pos, _)| *pos)
+        .map(|(_, freq)| freq);
+    confidence.frequency = if frequency.is_some() { 0.8 } else { 0.2 };
+    let frequency = frequency.unwrap_or(1);
+
+    // 日期：带到期/续费关键词的为下次付款日期，其余第一个为开始日期；
+    // 没有明确日期时使用相对日期短语，最后以今天为开始日期
+    // Dates: one introduced by an expiry/renewal keyword is the next payment date, the first
+    // other one is the start date; without explicit dates a relative-date phrase is used, and
+    // finally today is the start date
+    let (labelled, unlabelled): (Vec<&FoundDate>, Vec<&FoundDate>) =
+        found.iter().partition(|d| is_next_payment(&text, d));
+    let mut start = unlabelled.first().map(|d| (d.date, 0.8));
+    let mut next = labelled.first().map(|d| (d.date, 0.8));
+    if next.is_none() {
+        next = unlabelled.get(1).filter(|d| start.is_some_and(|(s, _)| d.date > s)).map(|d| (d.date, 0.6));
+    }
+    if let Some(relative) = dates::resolve_relative(&text, today) {
+        let renewal = RENEWAL_KEYWORDS.iter().any(|k| lower.contains(k));
+        if renewal && next.is_none() {
+            next = Some((relative, 0.6));
+        } else if start.is_none() {
+            start = Some((relative, 0.6));
+        }
+    }
+    let (start, start_confidence) = start.unwrap_or((today, 0.1));
+    let next = next.or_else(|| dates::next_payment(start, frequency).map(|d| (d, start_confidence / 2.0)));
+    confidence.start_date = start_confidence;
+    confidence.next_payment = next.map_or(0.0, |(_, c)| c);
+
+    SmartParseResult {
+        name,
+        price: Some(price.unwrap_or(0.0)),
+        currency,
+        frequency,
+        start_date: Some(format_date(start)),
+        next_payment: next.filter(|_| frequency != 0).map(|(d, _)| format_date(d)),
+        url,
+        category,
+        confidence,
+        source: "rules",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    /// 测试语料：`tests/fixtures/smart_parse.json`，每条给出文本、基准日期与期望字段
+    /// Test corpus: `tests/fixtures/smart_parse.json`, each case gives the text, the reference date
+    /// and the expected fields
+    #[derive(Deserialize)]
+    struct Case {
+        text: String,
+        today: NaiveDate,
+        expect: Expect,
+    }
+
+    #[derive(Deserialize)]
+    struct Expect {
+        name: Option<String>,
+        price: Option<f64>,
+        currency: Option<String>,
+        frequency: Option<i64>,
+        start_date: Option<String>,
+        next_payment: Option<String>,
+        category: Option<String>,
+    }
+
+    #[test]
+    fn fixture_corpus() {
+        let cases: Vec<Case> = serde_json::from_str(include_str!("../tests/fixtures/smart_parse.json")).unwrap();
+        let mut failures = Vec::new();
+        for case in &cases {
+            let result = extract(&case.text, case.today);
+            let mut check = |field: &str, expected: Option<String>, actual: Option<String>| {
+                if expected.is_some() && expected != actual {
+                    failures.push(format!("{:?}: {} expected {:?}, got {:?}", case.text, field, expected, actual));
+                }
+            };
+            let e = &case.expect;
+            check("name", e.name.clone(), Some(result.name.clone()));
+            check("price", e.price.map(|p| p.to_string()), result.price.map(|p| p.to_string()));
+            check("currency", e.currency.clone(), Some(result.currency.clone()));
+            check("frequency", e.frequency.map(|f| f.to_string()), Some(result.frequency.to_string()));
+            check("start_date", e.start_date.clone(), result.start_date.clone());
+            check("next_payment", e.next_payment.clone(), result.next_payment.clone());
+            check("category", e.category.clone(), result.category.clone());
+        }
+        assert!(failures.is_empty(), "{} fixture mismatches:\n{}", failures.len(), failures.join("\n"));
+    }
+}
@@ -1,34 +1,44 @@
-/// HTTP 请求处理模块
-/// HTTP Request Handlers Module
-///
-/// 包含所有 API 接口的具体实现逻辑。
-/// Contains implementation logic for all API endpoints.
-
+//! HTTP 请求处理模块
+//! HTTP Request Handlers Module
+//!
+//! 包含所有 API 接口的具体实现逻辑。
+//! Contains implementation logic for all API endpoints.
+
+use crate::ai_usage;
+use crate::auth::{AuthUser, Role};
+use crate::catalog;
+use crate::dates;
 use crate::db::DbPool;
-use crate::models::{CreateSubscription, Subscription};
+use crate::events::{self, Audience, EventKind, ResyncReason, Subject};
+use crate::households::{self, VISIBLE_TO_USER};
+use crate::icon_cache;
+use crate::icons;
+use crate::llm::{ChatMessage, ChatRequest, LlmError, LlmProvider};
+use crate::logos;
+use crate::insights;
+use crate::models::{CreateSubscription, Finding, Subscription};
+use crate::prompts::{get_prompts, PromptContext};
+use crate::resolver;
+use crate::search_cache;
+use crate::smart_parse;
 use axum::{
     extract::{Path, State, Query},
-    Json,
+    Extension, Json,
     http::StatusCode,
     response::IntoResponse,
 };
 use axum::response::Response;
-use axum::http::header::{CONTENT_TYPE, CACHE_CONTROL};
-use axum::http::HeaderValue;
-use tokio::fs;
-use tokio::io::AsyncReadExt;
+use axum::http::header::{CONTENT_TYPE, CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
-use tracing::{info, warn, debug};
+use tracing::{info, warn};
 use std::time::Duration;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use parking_lot::RwLock;
-use tokio::sync::broadcast;
+use tokio::sync::mpsc;
 use axum::response::sse::{Sse, Event, KeepAlive};
-use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, UnboundedReceiverStream};
 use tokio_stream::StreamExt;
 use std::convert::Infallible;
-use chrono::Datelike;
 
 #[derive(Deserialize)]
 pub struct SmartParseRequest {
@@ -37,116 +47,87 @@ pub struct SmartParseRequest {
 
 /// 智能解析订阅信息 (POST /api/smart-parse)
 /// Smart parse subscription info
+///
+/// 调用 LLM 会产生费用，因此至少需要 `editor` 角色。
+/// Calling the LLM costs money, so at least the `editor` role is required.
 #[axum::debug_handler]
 pub async fn smart_parse(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
     Json(payload): Json<SmartParseRequest>,
 ) -> impl IntoResponse {
+    if let Err(e) = user.require_role(Role::Editor) {
+        return e.into_response();
+    }
     let text = payload.text;
     info!("Smart parse request: {}", text);
 
-    // 1. 尝试从环境变量获取 API Key
-    // 1. Try to get API Key from env vars
-    let api_key = std::env::var("OPENAI_API_KEY").ok();
-    let api_base = std::env::var("OPENAI_API_BASE").unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
-
-    if let Some(key) = api_key {
-        // 调用 LLM API
-        // Call LLM API
-        let client = reqwest::Client::new();
-        let model = std::env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-3.5-turbo".to_string());
-        
-        let prompts = get_prompts();
-        let template = prompts.smart_parse_user_template.unwrap_or_else(|| default_prompts().smart_parse_user_template.unwrap());
-        let prompt = template.replace("{text}", &text);
-        debug!("smart_parse prompt loaded from prompts.json");
-
-        let body = serde_json::json!({
-            "model": model,
-            "messages": [
-                {"role": "system", "content": prompts.smart_parse_system.unwrap_or_else(|| default_prompts().smart_parse_system.unwrap())},
-                {"role": "user", "content": prompt}
-            ],
-            "temperature": 0.1
-        });
-
-        match client.post(format!("{}/chat/completions", api_base))
-            .header("Authorization", format!("Bearer {}", key))
-            .json(&body)
-            .send()
-            .await 
-        {
-            Ok(res) => {
-                if let Ok(json) = res.json::<serde_json::Value>().await {
-                    if let Some(content) = json["choices"][0]["message"]["content"].as_str() {
-                        // 尝试清理 markdown 代码块标记
-                        // Try to clean markdown code block markers
-                        let clean_content = content.trim().trim_start_matches("```json").trim_start_matches("```").trim_end_matches("```");
-                        if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(clean_content) {
-                            return Json(parsed).into_response();
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("LLM API call failed: {}", e);
-            }
-        }
-    }
-
-    // 2. 降级方案 / 演示模式 (Mock Mode)
-    // 2. Fallback / Mock Mode
-    // 简单的关键词匹配用于演示
-    let mut mock_data = serde_json::json!({
-        "name": "Unknown Subscription",
-        "price": 0,
-        "currency": "CNY",
-        "frequency": 1,
-        "start_date": "2025-12-23",
-        "next_payment": "2026-01-23"
-    });
-
-    let lower_text = text.to_lowercase();
-    if lower_text.contains("netflix") {
-        mock_data["name"] = "Netflix".into();
-        mock_data["price"] = 15.99.into();
-        mock_data["currency"] = "USD".into();
-    } else if lower_text.contains("spotify") {
-        mock_data["name"] = "Spotify".into();
-        mock_data["price"] = 10.99.into();
-        mock_data["currency"] = "USD".into();
-    } else if lower_text.contains("chatgpt") {
-        mock_data["name"] = "ChatGPT Plus".into();
-        mock_data["price"] = 20.00.into();
-        mock_data["currency"] = "USD".into();
+    match smart_parse::parse(&pool, user.user_id, &text).await {
+        Ok(result) => Json(result).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
-
-    // 尝试提取数字作为价格
-    // Try to extract number as price
-    if let Some(price_match) = text.split_whitespace().find(|s| s.parse::<f64>().is_ok()) {
-        if let Ok(p) = price_match.parse::<f64>() {
-            mock_data["price"] = p.into();
-        }
-    }
-
-    Json(mock_data).into_response()
 }
 
 /// 财务分析 (POST /api/analyze)
 /// Financial Analysis
+///
+/// 与智能解析相同，调用 LLM 需要 `editor` 角色。
+/// As with smart parse, calling the LLM requires the `editor` role.
 #[axum::debug_handler]
 pub async fn analyze_spending(
     State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
 ) -> impl IntoResponse {
-    // 1. 获取所有活跃订阅
-    // 1. Get all active subscriptions
-    let subs = match sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions")
-        .fetch_all(&pool)
-        .await 
-    {
-        Ok(s) => s,
+    if let Err(e) = user.require_role(Role::Editor) {
+        return e.into_response();
+    }
+
+    // 1. 根据当前用户可见的订阅构造 Prompt
+    // 1. Build the prompt from the subscriptions visible to the current user
+    let (request, findings) = match analysis_request(&pool, user.user_id).await {
+        Ok(r) => r,
         Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "DB Error").into_response(),
     };
 
+    // 2. 调用 LLM (或 Mock) 获取建议文本（不让其改动金额，只做建议描述）
+    // 2. Call LLM (or Mock) to get advisory text (do not change computed amounts)
+    let advisory_text = if let Some(provider) = ai_usage::provider(&pool).await {
+        match ai_usage::chat(&pool, user.user_id, "analyze", provider, &request).await {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("LLM API call failed: {}", e);
+                "建议生成失败".to_string()
+            }
+        }
+    } else {
+        // Mock 模式直接使用规则引擎的发现作为建议
+        // In mock mode the rule engine's findings are the advice
+        insights::to_markdown(&findings)
+    };
+
+    let final_md = format!("{}{}", ANALYSIS_HEADING, advisory_text);
+    Json(serde_json::json!({ "analysis": final_md, "findings": findings })).into_response()
+}
+
+/// 财务分析报告标题
+/// Heading of the financial analysis report
+const ANALYSIS_HEADING: &str = "### 订阅优化建议\n\n";
+
+/// 构造财务分析的 LLM 请求，同时返回规则引擎的发现
+/// Build the LLM request for the financial analysis, together with the rule engine's findings
+///
+/// 发现通过 `{facts}` 占位符写入提示词；自定义模板缺少该占位符时追加在末尾。
+/// Findings go into the prompt through the `{facts}` placeholder, or are appended when a custom
+/// template lacks it.
+async fn analysis_request(pool: &DbPool, user_id: i64) -> Result<(ChatRequest, Vec<Finding>), sqlx::Error> {
+    // 1. 获取当前用户可见的所有订阅
+    // 1. Get all subscriptions visible to the current user
+    let subs = sqlx::query_as::<_, Subscription>(&format!("SELECT * FROM subscriptions WHERE {}", VISIBLE_TO_USER))
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
     // 2. 构造 Prompt 数据
     // 2. Construct Prompt Data
     let mut data_str = String::new();
@@ -164,92 +145,99 @@ pub async fn analyze_spending(
         data_str.push_str(&format!("- {} | {} | price={} {} | start={} | end={}\n", sub.name, freq_str, sub.price, sub.currency, start, end));
     }
 
-    
-
-    // 3. 调用 LLM (或 Mock) 获取建议文本（不让其改动金额，只做建议描述）
-    // 3. Call LLM (or Mock) to get advisory text (do not change computed amounts)
-    let api_key = std::env::var("OPENAI_API_KEY").ok();
-    let api_base = std::env::var("OPENAI_API_BASE").unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
-
-    let advisory_text = if let Some(key) = api_key {
-        let client = reqwest::Client::new();
-        let model = std::env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-3.5-turbo".to_string());
-
-        let prompts = get_prompts();
-        let template = prompts.analyze_user_template.unwrap_or_else(|| default_prompts().analyze_user_template.unwrap());
-        let prompt = template.replace("{list}", &data_str);
-        debug!("analyze prompt loaded from prompts.json");
-
-        let body = serde_json::json!({
-            "model": model,
-            "messages": [
-                {"role": "system", "content": prompts.analyze_system.unwrap_or_else(|| default_prompts().analyze_system.unwrap())},
-                {"role": "user", "content": prompt}
-            ],
-            "temperature": 0.3
-        });
-
-        match client.post(format!("{}/chat/completions", api_base))
-            .header("Authorization", format!("Bearer {}", key))
-            .json(&body)
-            .send()
-            .await 
-        {
-            Ok(res) => {
-                if let Ok(json) = res.json::<serde_json::Value>().await {
-                    json["choices"][0]["message"]["content"].as_str().unwrap_or("建议生成失败").to_string()
-                } else {
-                    "建议生成失败".to_string()
-                }
-            }
-            Err(_) => "建议生成失败".to_string()
-        }
-    } else {
-        // Mock 建议
-        "- 检查是否存在功能重叠的订阅，避免重复付费。\n- 长期使用的工具优先考虑年度方案或一次性买断。\n- 对低使用频率的订阅进行降级或暂停。".to_string()
-    };
+    let prompts = get_prompts();
+    let template = &prompts.analyze_user_template;
+    let context = PromptContext::load(pool, user_id).await?;
+    let findings = insights::compute(pool, user_id, context.today).await?;
+    let facts = insights::to_markdown(&findings);
+    let mut prompt = context.render(template, &[("list", &data_str), ("facts", &facts)]);
+    if !template.contains("{facts}") {
+        prompt.push_str(&format!("\n\n已确认的事实（由系统根据数据计算）：\n{}", facts));
+    }
 
-    let final_md = format!("### 订阅优化建议\n\n{}", advisory_text);
-    Json(serde_json::json!({ "analysis": final_md })).into_response()
+    let request = ChatRequest {
+        messages: vec![
+            ChatMessage::system(context.render(&prompts.analyze_system, &[])),
+            ChatMessage::user(prompt),
+        ],
+        temperature: 0.3,
+        schema: None,
+    };
+    Ok((request, findings))
 }
 
-/// 域名搜索结果内存缓存
-/// In-memory cache for domain search results
+/// 流式财务分析 (GET /api/analyze/stream)
+/// Streaming financial analysis
 ///
-/// 目的：避免对同一查询名称的重复网络请求，提升响应速度并降低外部 API 负载。
-/// Purpose: Prevent duplicate network requests for the same query name, improving
-/// response time and reducing external API load.
-static SEARCH_CACHE: Lazy<RwLock<HashMap<String, String>>> =
-    Lazy::new(|| RwLock::new(HashMap::new()));
-
-static BROADCAST: Lazy<broadcast::Sender<()>> = Lazy::new(|| {
-    let (tx, _rx) = broadcast::channel(100);
-    tx
-});
-
-#[derive(Clone, Deserialize)]
-struct Prompts {
-    smart_parse_system: Option<String>,
-    smart_parse_user_template: Option<String>,
-    analyze_system: Option<String>,
-    analyze_user_template: Option<String>,
-}
-
-fn default_prompts() -> Prompts {
-    Prompts {
-        smart_parse_system: Some("You are a helpful assistant that extracts JSON.".to_string()),
-        smart_parse_user_template: Some("You are a subscription data extractor. Extract details from this text: '{text}'. Return ONLY a valid JSON object with these fields: name (string), price (number), currency (string, e.g. CNY, USD), start_date (string YYYY-MM-DD, assume today is 2025-12-23 if 'today'), next_payment (string YYYY-MM-DD, synonymous with end_date), frequency (number: -1=daily, 1=monthly, 3=quarterly, 12=yearly, 0=lifetime). If missing, guess or leave null.".to_string()),
-        analyze_system: Some("You are a financial advisor.".to_string()),
-        analyze_user_template: Some("作为订阅优化顾问，请仅依据下面的订阅信息给出 3–5 条中文建议（Markdown 列表）。不要进行任何金额计算或估算。关注冗余订阅、升级/降级机会、取消指引、以及临近到期的提醒。\n\n列表：\n{list}".to_string()),
+/// 以 SSE 推送模型的增量输出：`delta` 事件携带 `{"text"}` 片段，最后的 `done` 事件携带完整的
+/// Markdown `{"analysis"}` 与规则引擎的 `{"findings"}`，失败时发送 `error` 事件。客户端断开后停止读取模型输出并中止请求。
+/// Streams the model output over SSE: `delta` events carry `{"text"}` fragments and the final
+/// `done` event carries the complete Markdown as `{"analysis"}` plus the rule engine's
+/// `{"findings"}`; failures send an `error` event.
+/// When the client disconnects, reading the model output stops and the request is aborted.
+pub async fn analyze_spending_stream(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Response {
+    if let Err(e) = user.require_role(Role::Editor) {
+        return e.into_response();
     }
+    let (request, findings) = match analysis_request(&pool, user.user_id).await {
+        Ok(r) => r,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "DB Error").into_response(),
+    };
+
+    let provider = ai_usage::provider(&pool).await;
+    let rx = stream_analysis(pool, user.user_id, provider, request, findings);
+    let stream = UnboundedReceiverStream::new(rx).map(Ok::<_, Infallible>);
+    Sse::new(stream).keep_alive(KeepAlive::new()).into_response()
 }
 
-fn get_prompts() -> Prompts {
-    let loaded = std::fs::read_to_string("static/prompts.json")
-        .ok()
-        .and_then(|s| serde_json::from_str::<Prompts>(&s).ok())
-        .unwrap_or_else(|| default_prompts());
-    loaded
+/// 在后台任务中生成分析并把 SSE 事件写入返回的通道；通道接收方被丢弃时中止模型请求。
+/// 未配置提供方 (`None`) 时直接发送规则引擎的建议。
+/// Generate the analysis in a background task and write SSE events into the returned channel; the
+/// model request is aborted once the receiver is dropped. Without a provider (`None`) the rule
+/// engine's advice is sent directly.
+fn stream_analysis(
+    pool: DbPool,
+    user_id: i64,
+    provider: Option<&'static dyn LlmProvider>,
+    request: ChatRequest,
+    findings: Vec<Finding>,
+) -> mpsc::UnboundedReceiver<Event> {
+    let (tx, rx) = mpsc::unbounded_channel::<Event>();
+    tokio::spawn(async move {
+        let json_event = |name: &str, data: serde_json::Value| Event::default().event(name).data(data.to_string());
+        let mut on_delta = |delta: &str| tx.send(json_event("delta", serde_json::json!({ "text": delta }))).is_ok();
+
+        let result = match provider {
+            // 等待首个增量期间客户端也可能断开，因此同时监听通道关闭
+            // The client may also disconnect while waiting for the first delta, so watch for the channel closing too
+            Some(provider) => tokio::select! {
+                r = ai_usage::chat_stream(&pool, user_id, "analyze", provider, &request, &mut on_delta) => r,
+                _ = tx.closed() => Err(LlmError::Cancelled),
+            },
+            None => {
+                let advice = insights::to_markdown(&findings);
+                on_delta(&advice);
+                Ok(advice)
+            }
+        };
+
+        match result {
+            Ok(text) => {
+                let final_md = format!("{}{}", ANALYSIS_HEADING, text);
+                let _ = tx.send(json_event("done", serde_json::json!({ "analysis": final_md, "findings": findings })));
+            }
+            Err(LlmError::Cancelled) => info!("Streaming analysis cancelled by client"),
+            Err(e) => {
+                warn!("LLM streaming call failed: {}", e);
+                let _ = tx.send(json_event("error", serde_json::json!({ "message": "建议生成失败" })));
+            }
+        }
+    });
+
+    rx
 }
 
 #[derive(Deserialize)]
@@ -260,6 +248,12 @@ pub struct SearchQuery {
 #[derive(Serialize)]
 pub struct SearchResult {
     domain: String,
+    /// 采用结果的解析来源 / Resolver that produced the domain
+    source: String,
+    confidence: f32,
+    /// 是否来自搜索缓存 (此时 attempts 为空) / Whether it came from the search cache (attempts is then empty)
+    cached: bool,
+    attempts: Vec<resolver::Attempt>,
 }
 
 #[derive(Deserialize)]
@@ -272,110 +266,149 @@ pub struct IconQuery {
 /// Fetch website icon
 ///
 /// 行为：
-/// 1. 尝试从本地缓存目录 `static/icons` 读取，如果存在则直接返回并设置缓存头。
-/// 2. 若不存在，调用 Google Favicon 服务下载 PNG，并写入本地以供后续命中。
-/// 3. 所有成功响应附带 `Cache-Control`，允许前端浏览器进行缓存。
+/// 1. 先查图标缓存 (`icon_cache` 模块)；命中且 `If-None-Match` 与 ETag 一致时返回 304。
+/// 2. 未命中时按 `icons` 模块配置的来源顺序获取图标并转换为 PNG，写入缓存以供后续命中。
+/// 3. 所有来源都没有有效图标时返回 404，由前端回退到文字头像。
+/// 4. 所有成功响应附带 `ETag` 与 `Cache-Control`，浏览器缓存过期后可以低成本地重新验证。
+///
 /// Behavior:
-/// 1. Try reading from local cache dir `static/icons`; return if present with cache headers.
-/// 2. If missing, fetch PNG via Google Favicon, then persist for future hits.
-/// 3. Successful responses include `Cache-Control` for browser caching.
-#[axum::debug_handler]
+/// 1. Check the icon cache (the `icon_cache` module) first; on a hit whose ETag matches
+///    `If-None-Match`, return 304.
+/// 2. On a miss, fetch the icon from the sources configured in the `icons` module, convert it
+///    to PNG and store it in the cache for future hits.
+/// 3. Return 404 when no source has a valid icon, so the frontend falls back to a text avatar.
+/// 4. Successful responses include `ETag` and `Cache-Control`, so browsers can cheaply
+///    revalidate once their copy expires.
 pub async fn get_icon(
+    State(pool): State<DbPool>,
+    headers: HeaderMap,
     Query(params): Query<IconQuery>,
 ) -> Response {
-    let mut domain = params.domain.to_lowercase();
-    domain.retain(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
-    if domain.is_empty() {
+    let Some(domain) = icon_cache::sanitize_domain(&params.domain) else {
         return (StatusCode::BAD_REQUEST, "invalid domain").into_response();
-    }
-    let sz = params.sz.unwrap_or(64);
-    let file_name = format!("{}_{}.png", domain, sz);
-    let dir = "static/icons";
-    let path = format!("{}/{}", dir, file_name);
-
-    if let Ok(mut f) = fs::File::open(&path).await {
-        let mut buf = Vec::new();
-        if f.read_to_end(&mut buf).await.is_ok() {
-            let mut resp = Response::new(buf.into());
-            resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
-            resp.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("public, max-age=604800"));
-            return resp;
-        }
+    };
+    let sz = params.sz.unwrap_or(64).clamp(icons::MIN_SIZE, icons::MAX_SIZE);
+
+    match icon_cache::get(&pool, &domain, sz).await {
+        Ok(Some(cached)) => return png_response(&headers, cached.png, &cached.etag, &cached.source),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to read icon cache for {}: {}", domain, e),
     }
 
-    let _ = fs::create_dir_all(dir).await;
-    let url = format!("https://www.google.com/s2/favicons?domain={}&sz={}", domain, sz);
-    let client = match reqwest::Client::builder()
-        .user_agent("Mozilla/5.0")
-        .timeout(Duration::from_secs(8))
-        .build() {
-        Ok(c) => c,
-        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    let Some(icon) = icons::fetch(&domain, sz).await else {
+        info!("No icon found for {}", domain);
+        return (StatusCode::NOT_FOUND, "No icon found").into_response();
     };
+    info!("Icon for {} from {}", domain, icon.source.name());
+    let etag = icon_cache::store(&pool, &domain, sz, &icon).await;
+    png_response(&headers, icon.png, &etag, icon.source.name())
+}
 
-    match client.get(&url).send().await {
-        Ok(resp) => {
-            match resp.bytes().await {
-                Ok(bytes) => {
-                    let _ = fs::write(&path, &bytes).await;
-                    let mut resp = Response::new(bytes.into());
-                    resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
-                    resp.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("public, max-age=604800"));
-                    return resp;
-                },
-                Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
-            }
-        },
-        Err(e) => (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+/// 构造 PNG 图标响应；客户端持有相同版本时返回 304
+/// Build a PNG icon response; returns 304 when the client already holds this version
+fn png_response(headers: &HeaderMap, png: Vec<u8>, etag: &str, source: &str) -> Response {
+    let not_modified = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').map(|t| t.trim().trim_start_matches("W/")).any(|t| t == etag || t == "*"));
+    let mut resp = if not_modified {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mut resp = Response::new(png.into());
+        resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        resp
+    };
+    // 缓存一天后向服务器重新验证，刷新后的图标最迟一天内生效
+    // Revalidate with the server after a day, so refreshed icons show up within a day
+    resp.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("public, max-age=86400"));
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        resp.headers_mut().insert(ETAG, value);
     }
+    if let Ok(value) = HeaderValue::from_str(source) {
+        resp.headers_mut().insert("x-icon-source", value);
+    }
+    resp
+}
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    /// 同 `Last-Event-ID`，供无法设置请求头的客户端使用
+    /// Same as `Last-Event-ID`, for clients that cannot set the header
+    last_event_id: Option<u64>,
 }
 
 /// 实时更新流 (GET `/api/stream`)
 /// Server-Sent Events stream
 ///
-/// 当订阅数据发生变化时，向前端推送一个轻量事件 `"update"`，
-/// 前端接收到事件后会主动调用列表刷新接口以获取最新数据。
-/// Pushes a lightweight `"update"` event when subscription data changes.
-/// The frontend listens to this SSE and refreshes the list on message.
+/// 每个事件的 `event` 为事件类型 (`subscription.created`、`subscription.updated`、
+/// `subscription.deleted`、`reminder.due` 等)，`id` 为单调递增的事件 id，`data` 为包含变更实体的
+/// JSON。只有能看到该订阅的用户 (拥有者或共享家庭组成员) 才会收到事件。
+/// 重连时携带 `Last-Event-ID` (或 `?last_event_id=`) 可补收错过的事件；无法补发或接收端积压时推送
+/// `resync` 事件，客户端应重新拉取列表。
+/// Each event's `event` is its type (`subscription.created`, `subscription.updated`,
+/// `subscription.deleted`, `reminder.due` and so on), its `id` the monotonically increasing
+/// event id and its `data` JSON carrying the changed entity. Only users who can see the
+/// subscription (its owner or members of the shared household) receive the event.
+/// Reconnecting with `Last-Event-ID` (or `?last_event_id=`) replays the missed events; when they
+/// cannot be replayed or the receiver lags behind, a `resync` event is sent and the client should
+/// fetch the list again.
 #[axum::debug_handler]
-pub async fn stream_updates() -> Sse<impl tokio_stream::Stream<Item = Result<Event, Infallible>>> {
-    let rx = BROADCAST.subscribe();
-    let stream = BroadcastStream::new(rx).filter_map(|msg| match msg {
-        Ok(_) => Some(Ok(Event::default().data("update"))),
-        Err(_) => None,
-    });
-    Sse::new(stream).keep_alive(KeepAlive::new())
-}
-
-/// 搜索域名 API (GET /api/search?q=name)
-/// Search Domain API
-///
-/// 使用 DuckDuckGo HTML 搜索，解析结果获取官网域名。
-/// Uses DuckDuckGo HTML search, parses results to get official website domain.
-#[derive(serde::Deserialize)]
-struct DdgResponse {
-    #[serde(rename = "OfficialWebsite")]
-    official_website: Option<String>,
-    #[serde(rename = "Results")]
-    results: Option<Vec<DdgResult>>,
-    #[serde(rename = "AbstractURL")]
-    abstract_url: Option<String>,
+pub async fn stream_updates(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    headers: HeaderMap,
+    Query(params): Query<StreamQuery>,
+) -> Sse<impl tokio_stream::Stream<Item = Result<Event, Infallible>>> {
+    let last_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(events::parse_last_id)
+        .or(params.last_event_id);
+    let feed = events::subscribe(last_id);
+    let resync = tokio_stream::iter(feed.resync.map(|reason| Ok(resync_event(reason))));
+    let updates = tokio_stream::iter(feed.replay.into_iter().map(Ok))
+        .chain(BroadcastStream::new(feed.receiver))
+        .then(move |msg| {
+            let pool = pool.clone();
+            async move {
+                match msg {
+                    Ok(event) => {
+                        if !event.visible_to(&pool, user.user_id).await {
+                            return None;
+                        }
+                        Event::default().id(event.id.to_string()).event(event.kind.name()).json_data(&*event).ok()
+                    }
+                    Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                        warn!("Event stream for user {} lagged, skipped {} events", user.user_id, skipped);
+                        Some(resync_event(ResyncReason::Lagged))
+                    }
+                }
+            }
+        })
+        .filter_map(|event| event.map(Ok));
+    Sse::new(resync.chain(updates)).keep_alive(KeepAlive::new())
 }
 
-#[derive(serde::Deserialize)]
-struct DdgResult {
-    #[serde(rename = "FirstURL")]
-    first_url: Option<String>,
+/// `resync` 事件不带 id，客户端重连时仍从最后收到的事件继续
+/// `resync` events carry no id, so a reconnecting client still continues from the last event it
+/// received
+fn resync_event(reason: ResyncReason) -> Event {
+    Event::default().event(events::RESYNC).data(serde_json::json!({ "reason": reason }).to_string())
 }
 
+/// 搜索域名 API (GET /api/search?q=name)
 /// 搜索域名 API (GET /api/search?q=name)
 /// Search Domain API
 ///
-/// 使用 DuckDuckGo API 和 HTML 搜索，解析结果获取官网域名。
-/// Uses DuckDuckGo API and HTML search, parses results to get official website domain.
-#[axum::debug_handler]
+/// 依次尝试配置的域名解析来源 (见 `resolver` 模块)，返回采用的域名、来源、置信度与每个来源的尝试记录。
+/// 本地来源 (用户覆盖与服务目录) 先于搜索缓存查询，联网来源的结果写入缓存。
+/// Tries the configured domain resolvers in turn (see the `resolver` module) and returns the
+/// chosen domain, its source, its confidence and the attempt of every source. Local sources
+/// (user overrides and the service catalog) are queried before the search cache; results of the
+/// online sources are cached.
 pub async fn search_domain(
-    State(_pool): State<DbPool>,
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
     Query(params): Query<SearchQuery>,
 ) -> axum::response::Response {
     let query = params.q.trim();
@@ -385,173 +418,103 @@ pub async fn search_domain(
 
     info!("Searching for: {}", query);
 
-    if let Some(cached) = { SEARCH_CACHE.read().get(query).cloned() } {
-        info!("Cache hit: {}", cached);
-        return Json(SearchResult { domain: cached }).into_response();
+    let lookup = resolver::Lookup { pool: &pool, user_id: user.user_id, query };
+    if let Some(resolution) = resolver::resolve_local(&lookup).await {
+        return found(resolution);
     }
 
-    // 1. 尝试 DuckDuckGo API (JSON)
-    // Try DuckDuckGo API (JSON) first
-    let api_url = format!("https://api.duckduckgo.com/?q={}&format=json", urlencoding::encode(query));
-    
-    let client = match reqwest::Client::builder()
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
-        .timeout(Duration::from_secs(8))
-        .build() {
-        Ok(c) => c,
-        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
-    };
-
-    match client.get(&api_url).send().await {
-        Ok(resp) => {
-            if let Ok(text) = resp.text().await {
-                // println!("API Response: {}", text); // Debug log
-                if let Ok(ddg_resp) = serde_json::from_str::<DdgResponse>(&text) {
-                    // 优先级 1: OfficialWebsite
-                    if let Some(url) = ddg_resp.official_website {
-                        if !url.is_empty() {
-                            if let Ok(parsed) = url::Url::parse(&url) {
-                                if let Some(domain) = parsed.host_str() {
-                                    info!("Found via API (OfficialWebsite): {}", domain);
-                                    { SEARCH_CACHE.write().insert(query.to_string(), domain.to_string()); }
-                                    return Json(SearchResult { domain: domain.to_string() }).into_response();
-                                }
-                            }
-                        }
-                    }
-
-                    // 优先级 2: AbstractURL
-                    if let Some(url) = ddg_resp.abstract_url {
-                         if !url.is_empty() {
-                            if let Ok(parsed) = url::Url::parse(&url) {
-                                if let Some(domain) = parsed.host_str() {
-                                    if !domain.contains("wikipedia.org") {
-                                        info!("Found via API (AbstractURL): {}", domain);
-                                        { SEARCH_CACHE.write().insert(query.to_string(), domain.to_string()); }
-                                        return Json(SearchResult { domain: domain.to_string() }).into_response();
-                                    }
-                                }
-                            }
-                         }
-                    }
-
-                    // 优先级 3: Results 中的 FirstURL
-                    if let Some(results) = ddg_resp.results {
-                        for result in results {
-                            if let Some(url) = result.first_url {
-                                if !url.is_empty() {
-                                    if let Ok(parsed) = url::Url::parse(&url) {
-                                        if let Some(domain) = parsed.host_str() {
-                                            info!("Found via API (Results): {}", domain);
-                                            { SEARCH_CACHE.write().insert(query.to_string(), domain.to_string()); }
-                                            return Json(SearchResult { domain: domain.to_string() }).into_response();
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        },
-        Err(e) => warn!("API request failed: {}", e),
-    }
-
-    info!("API failed to find domain, falling back to HTML search...");
-
-    // 2. 回退到 DuckDuckGo HTML 搜索
-    // Fallback to DuckDuckGo HTML search
-    let url = format!("https://html.duckduckgo.com/html/?q={}", urlencoding::encode(query));
-    debug!("Searching: {}", url);
-
-    // 发送请求
-    let resp = match client.get(&url)
-        .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
-        .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8")
-        .header("Accept-Language", "zh-CN,zh;q=0.9,en;q=0.8")
-        .header("Referer", "https://html.duckduckgo.com/")
-        .send()
-        .await {
-        Ok(r) => r,
-        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
-    };
-    let resp = match resp.text().await {
-        Ok(t) => t,
-        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
-    };
-    
-    debug!("Response length: {}", resp.len());
-    // if resp.len() < 1000 {
-    //      println!("Response body: {}", resp);
-    // }
-
-    // 3. 解析 HTML 提取第一个非广告链接
-    let document = scraper::Html::parse_document(&resp);
-    // 尝试更广泛的选择器
-    let selector = scraper::Selector::parse(".result__a, .result__url, .links_main a").unwrap();
-
-    for element in document.select(&selector) {
-        if let Some(href) = element.value().attr("href") {
-            debug!("Found candidate link: {}", href);
-            
-            let actual_url = if href.starts_with("/l/") {
-                if let Some(start) = href.find("uddg=") {
-                    let encoded = &href[start + 5..];
-                    let end = encoded.find('&').unwrap_or(encoded.len());
-                    if let Ok(decoded) = urlencoding::decode(&encoded[..end]) {
-                         decoded.into_owned()
-                    } else {
-                        continue;
-                    }
-                } else {
-                    continue; 
-                }
-            } else {
-                href.to_string()
+    match search_cache::get(&pool, query).await {
+        Ok(Some(cached)) => {
+            info!("Cache hit for '{}': {:?}", query, cached.domain);
+            return match cached.domain {
+                Some(domain) => Json(SearchResult {
+                    domain,
+                    source: cached.source.unwrap_or_default(),
+                    confidence: cached.confidence.unwrap_or_default(),
+                    cached: true,
+                    attempts: Vec::new(),
+                })
+                .into_response(),
+                None => (StatusCode::NOT_FOUND, "No domain found".to_string()).into_response(),
             };
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Search cache lookup failed: {}", e),
+    }
 
-            // 提取域名
-            if let Ok(parsed_url) = url::Url::parse(&actual_url) {
-                if let Some(domain) = parsed_url.host_str() {
-                    if domain.contains("duckduckgo.com") || parsed_url.scheme() != "http" && parsed_url.scheme() != "https" {
-                        continue;
-                    }
-                    
-                    info!("Found domain: {}", domain);
-                    { SEARCH_CACHE.write().insert(query.to_string(), domain.to_string()); }
-                    return Json(SearchResult { domain: domain.to_string() }).into_response();
-                }
-            }
+    let resolution = resolver::resolve_remote(&lookup).await;
+    match (&resolution.candidate, resolution.source) {
+        (Some(candidate), source) => {
+            search_cache::store(&pool, query, Some(&candidate.domain), source, Some(candidate.confidence)).await
+        }
+        // 只有所有来源都正常答复“未找到”时才缓存否定结果，避免网络故障被缓存
+        // Only cache a negative result when every source answered normally, so that network
+        // failures are not cached
+        (None, _) if resolution.attempts.iter().all(|a| a.outcome != "error") => {
+            search_cache::store(&pool, query, None, None, None).await
         }
+        (None, _) => {}
     }
+    found(resolution)
+}
 
-    (StatusCode::NOT_FOUND, "No domain found".to_string()).into_response()
+/// 把解析链的结果转换为响应
+/// Turn a resolver chain result into a response
+fn found(resolution: resolver::Resolution) -> axum::response::Response {
+    let (Some(candidate), Some(source)) = (resolution.candidate, resolution.source) else {
+        return (StatusCode::NOT_FOUND, "No domain found".to_string()).into_response();
+    };
+    info!("Found domain via {}: {} ({:.2})", source, candidate.domain, candidate.confidence);
+    Json(SearchResult {
+        domain: candidate.domain,
+        source: source.to_string(),
+        confidence: candidate.confidence,
+        cached: false,
+        attempts: resolution.attempts,
+    })
+    .into_response()
 }
 
 /// 获取所有订阅列表 (GET /api/subscriptions)
 /// Get list of all subscriptions
 ///
-/// 从数据库中查询所有订阅记录，并按“下次付款日期”升序排列。
-/// Queries all subscription records from the database, ordered by "next payment date" ascending.
+/// 查询当前用户拥有或共享到其家庭组的订阅记录，并按“下次付款日期”升序排列。
+/// Queries the subscriptions owned by the current user or shared with their households,
+/// ordered by "next payment date" ascending.
 pub async fn list_subscriptions(
     // 从应用状态中提取数据库连接池
     // Extract database connection pool from application state
     State(pool): State<DbPool>,
-) -> Result<Json<Vec<Subscription>>, String> {
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<Vec<Subscription>>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+
     // 执行 SQL 查询
     // Execute SQL query
     // query_as 将查询结果映射为 Subscription 结构体
     // query_as maps query results to Subscription struct
-    let subs = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions ORDER BY next_payment ASC")
+    let mut subs = sqlx::query_as::<_, Subscription>(&format!(
+        "SELECT * FROM subscriptions WHERE {} ORDER BY next_payment ASC",
+        VISIBLE_TO_USER
+    ))
+        .bind(user.user_id)
+        .bind(user.user_id)
         .fetch_all(&pool)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    icon_cache::attach_colors(&pool, &mut subs).await;
 
     // 返回 JSON 格式的数据
     // Return data in JSON format
     Ok(Json(subs))
 }
 
+/// 规范化分类：去除空白并转为小写，空字符串视为未设置
+/// Normalise a category: trimmed and lowercased, with an empty string meaning unset
+fn normalize_category(category: Option<&str>) -> Option<String> {
+    category.map(str::trim).filter(|c| !c.is_empty()).map(str::to_lowercase)
+}
+
 /// 创建新订阅 (POST /api/subscriptions)
 /// Create a new subscription
 ///
@@ -559,38 +522,50 @@ pub async fn list_subscriptions(
 /// Receives subscription data in JSON format, validates required fields, and saves to database.
 pub async fn create_subscription(
     State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
     // 解析请求体中的 JSON 数据
     // Parse JSON data from request body
-    Json(payload): Json<CreateSubscription>,
-) -> Result<Json<Subscription>, String> {
+    Json(mut payload): Json<CreateSubscription>,
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+
     // 1. 数据验证
     //    Data Validation
-    //    确保订阅名称不为空
-    //    Ensure subscription name is not empty
-    if payload.name.trim().is_empty() {
-        return Err("Name is required".to_string());
+    //    名称、频率、价格与日期规则见 `CreateSubscription::validate`
+    //    Name, frequency, price and date rules live in `CreateSubscription::validate`
+    let (price, next_payment) = payload.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    // 名称与服务目录中的名称或别名完全一致时，补全未填写的官网、图标与分类；
+    // 近似匹配 (例如名称中包含别名) 只在响应中返回建议，避免误填
+    // When the name equals a name or alias in the service catalog, fill in a missing website,
+    // logo and category; an approximate match (such as an alias inside the name) is only
+    // returned as a suggestion in the response, so nothing is filled in wrongly
+    let mut catalog_suggestion = None;
+    if let Some(found) = catalog::lookup(&payload.name) {
+        if found.exact {
+            let blank = |value: &Option<String>| value.as_deref().is_none_or(|v| v.trim().is_empty());
+            if blank(&payload.url) {
+                payload.url = Some(found.service.url.clone());
+            }
+            if blank(&payload.logo) {
+                payload.logo = Some(found.service.logo());
+            }
+            if blank(&payload.category) {
+                payload.category = Some(found.service.category.clone());
+            }
+        } else {
+            catalog_suggestion = Some(found.service.suggestion());
+        }
     }
+    let category = normalize_category(payload.category.as_deref());
 
-    // 处理价格和日期逻辑
-    // Handle price and date logic
-    if ![-1,0,1,3,12].contains(&payload.frequency) {
-        return Err("Invalid frequency".to_string());
-    }
-    let (price, next_payment) = if payload.frequency == 0 {
-        // 永久订阅：价格可选 (默认为 0)，无需下次付款日期
-        // Lifetime: Price optional (default 0), no next payment date
-        (payload.price.unwrap_or(0.0), None)
-    } else {
-        // 普通订阅：价格和日期必填
-        // Normal: Price and Date required
-        if payload.price.is_none() {
-            return Err("Price is required for non-lifetime subscriptions".to_string());
-        }
-        if payload.next_payment.is_none() {
-            return Err("Next payment date is required for non-lifetime subscriptions".to_string());
+    // 只能共享到自己所在的家庭组
+    // Can only share with a household the user belongs to
+    if let Some(household_id) = payload.household_id {
+        if !households::is_member(&pool, household_id, user.user_id).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))? {
+            return Err((StatusCode::FORBIDDEN, "Not a member of this household".to_string()));
         }
-        (payload.price.unwrap(), payload.next_payment)
-    };
+    }
 
     // 2. 插入数据库
     //    Insert into database
@@ -598,8 +573,8 @@ pub async fn create_subscription(
     //    Execute INSERT statement and get the newly generated ID
     let id = sqlx::query(
         r#"
-        INSERT INTO subscriptions (name, price, currency, next_payment, frequency, url, logo, start_date)
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        INSERT INTO subscriptions (name, price, currency, next_payment, frequency, url, logo, start_date, owner_id, household_id, category)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         "#
     )
     .bind(&payload.name)
@@ -610,16 +585,19 @@ pub async fn create_subscription(
     .bind(&payload.url)
     .bind(&payload.logo)
     .bind(&payload.start_date)
+    .bind(user.user_id)
+    .bind(payload.household_id)
+    .bind(&category)
     .execute(&pool)
     .await
-    .map_err(|e| e.to_string())?
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
     .last_insert_rowid();
 
     // 3. 构建返回对象
     //    Construct response object
     //    将插入的数据和新 ID 组合成完整的 Subscription 对象返回
     //    Combine inserted data and new ID into a full Subscription object to return
-    let sub = Subscription {
+    let mut sub = Subscription {
         id,
         name: payload.name,
         price,
@@ -630,71 +608,100 @@ pub async fn create_subscription(
         logo: payload.logo,
         start_date: payload.start_date,
         active: true, // 默认为激活状态 Default to active
+        owner_id: user.user_id,
+        household_id: payload.household_id,
+        category,
+        paused_until: None,
+        dominant_color: None,
+        accent_color: None,
+        catalog_suggestion,
     };
+    icon_cache::attach_colors(&pool, std::slice::from_mut(&mut sub)).await;
 
-    let _ = BROADCAST.send(());
+    events::publish_subscription(EventKind::SubscriptionCreated, &sub);
     Ok(Json(sub))
 }
 
 /// 删除指定订阅 (DELETE /api/subscriptions/:id)
 /// Delete specific subscription
 ///
-/// 根据路径参数中的 ID 删除对应的订阅记录。
-/// Deletes the subscription record corresponding to the ID in the path parameter.
+/// 根据路径参数中的 ID 删除对应的订阅记录，只能删除自己拥有的订阅。
+/// Deletes the subscription record corresponding to the ID in the path parameter; only the
+/// owner can delete it.
 pub async fn delete_subscription(
     State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
     // 从 URL 路径中提取 ID 参数
     // Extract ID parameter from URL path
     Path(id): Path<i64>,
-) -> Result<Json<serde_json::Value>, String> {
-    // 执行删除操作
-    // Execute delete operation
-    sqlx::query("DELETE FROM subscriptions WHERE id = ?")
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+
+    // 执行删除操作，并取回被删除的行以便通知 (共享家庭组与分类)
+    // Execute delete operation, returning the deleted row for notification (shared household
+    // and category)
+    let deleted = sqlx::query_as::<_, Subscription>("DELETE FROM subscriptions WHERE id = ? AND owner_id = ? RETURNING *")
         .bind(id)
-        .execute(&pool)
+        .bind(user.user_id)
+        .fetch_optional(&pool)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let Some(deleted) = deleted else {
+        return Err((StatusCode::NOT_FOUND, "Subscription not found".to_string()));
+    };
+    logos::collect_garbage(&pool).await;
 
     // 返回简单的成功状态 JSON
     // Return simple success status JSON
-    let _ = BROADCAST.send(());
+    events::publish(
+        EventKind::SubscriptionDeleted,
+        Audience::of(&deleted),
+        Some(Subject::of(&deleted)),
+        serde_json::json!({ "id": id }),
+    );
     Ok(Json(serde_json::json!({ "status": "deleted" })))
 }
 
 /// 更新指定订阅 (PUT /api/subscriptions/:id)
 /// Update specific subscription
+///
+/// 家庭组成员可以看到共享订阅，但只有拥有者可以修改。
+/// Household members can see shared subscriptions, but only the owner can modify them.
 pub async fn update_subscription(
     State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
     Path(id): Path<i64>,
     Json(payload): Json<CreateSubscription>,
-) -> Result<Json<Subscription>, String> {
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+
     // 1. 数据验证 (与 Create 逻辑相同)
-    if payload.name.trim().is_empty() {
-        return Err("Name is required".to_string());
-    }
+    let (price, next_payment) = payload.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let category = normalize_category(payload.category.as_deref());
 
-    // 处理价格和日期逻辑
-    if ![-1,0,1,3,12].contains(&payload.frequency) {
-        return Err("Invalid frequency".to_string());
-    }
-    let (price, next_payment) = if payload.frequency == 0 {
-        (payload.price.unwrap_or(0.0), None)
-    } else {
-        if payload.price.is_none() {
-            return Err("Price is required for non-lifetime subscriptions".to_string());
+    if let Some(household_id) = payload.household_id {
+        if !households::is_member(&pool, household_id, user.user_id).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))? {
+            return Err((StatusCode::FORBIDDEN, "Not a member of this household".to_string()));
         }
-        if payload.next_payment.is_none() {
-            return Err("Next payment date is required for non-lifetime subscriptions".to_string());
-        }
-        (payload.price.unwrap(), payload.next_payment)
-    };
+    }
+
+    // 记录原共享家庭组，取消共享时也要通知原组成员；原价格用于记录价格变更；暂停状态保持不变
+    // Remember the previous household so its members are notified when sharing is removed;
+    // the previous price is used to record price changes; the pause state is left as is
+    let previous = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ? AND owner_id = ?")
+        .bind(id)
+        .bind(user.user_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((StatusCode::NOT_FOUND, "Subscription not found".to_string()))?;
 
     // 2. 更新数据库
     let result = sqlx::query(
         r#"
         UPDATE subscriptions 
-        SET name = ?, price = ?, currency = ?, next_payment = ?, frequency = ?, url = ?, logo = ?, start_date = ?
-        WHERE id = ?
+        SET name = ?, price = ?, currency = ?, next_payment = ?, frequency = ?, url = ?, logo = ?, start_date = ?, household_id = ?, category = ?
+        WHERE id = ? AND owner_id = ?
         "#
     )
     .bind(&payload.name)
@@ -705,17 +712,33 @@ pub async fn update_subscription(
     .bind(&payload.url)
     .bind(&payload.logo)
     .bind(&payload.start_date)
+    .bind(payload.household_id)
+    .bind(&category)
     .bind(id)
+    .bind(user.user_id)
     .execute(&pool)
     .await
-    .map_err(|e| e.to_string())?;
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
     if result.rows_affected() == 0 {
-        return Err("Subscription not found".to_string());
+        return Err((StatusCode::NOT_FOUND, "Subscription not found".to_string()));
+    }
+
+    // 同币种下的价格变化记入价格变更历史，供涨价提醒使用
+    // Price changes in the same currency go into the price history, used for price increase alerts
+    if previous.currency == payload.currency && (previous.price - price).abs() > f64::EPSILON {
+        sqlx::query("INSERT INTO price_changes (subscription_id, old_price, new_price, currency) VALUES (?, ?, ?, ?)")
+            .bind(id)
+            .bind(previous.price)
+            .bind(price)
+            .bind(&payload.currency)
+            .execute(&pool)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     }
 
     // 3. 返回更新后的对象
-    let sub = Subscription {
+    let mut sub = Subscription {
         id,
         name: payload.name,
         price,
@@ -725,9 +748,295 @@ pub async fn update_subscription(
         url: payload.url,
         logo: payload.logo,
         start_date: payload.start_date,
-        active: true,
+        active: previous.active,
+        owner_id: user.user_id,
+        household_id: payload.household_id,
+        category,
+        paused_until: previous.paused_until.clone(),
+        dominant_color: None,
+        accent_color: None,
+        catalog_suggestion: None,
     };
+    if previous.logo != sub.logo {
+        logos::collect_garbage(&pool).await;
+    }
+    icon_cache::attach_colors(&pool, std::slice::from_mut(&mut sub)).await;
+
+    // 先通知原家庭组移除，同时属于新家庭组的成员随后会收到更新
+    // Tell the previous household to drop it first, so members also in the new household then
+    // receive the update
+    // 分类被修改时记录原分类，按原分类过滤的通道据此收到离开通知
+    // When the category changed, record the previous one so channels filtering on it are told
+    // the subscription left
+    let mut subject = Subject::of(&sub);
+    subject.left_categories =
+        insights::category_of(&previous).into_iter().filter(|c| !subject.categories.contains(c)).collect();
+    if let Some(previous_household) = previous.household_id.filter(|hid| Some(*hid) != sub.household_id) {
+        events::publish(
+            EventKind::SubscriptionUnshared,
+            Audience::Household { household_id: previous_household, except: user.user_id },
+            Some(subject.clone()),
+            serde_json::json!({ "id": id, "household_id": previous_household }),
+        );
+    }
+    events::publish(
+        EventKind::SubscriptionUpdated,
+        Audience::of(&sub),
+        Some(subject),
+        serde_json::to_value(&sub).unwrap_or_default(),
+    );
+    Ok(Json(sub))
+}
 
-    let _ = BROADCAST.send(());
+#[derive(Deserialize)]
+pub struct PauseRequest {
+    /// 暂停截止日期 (YYYY-MM-DD)，为空表示无限期暂停
+    /// Pause end date (YYYY-MM-DD); empty means paused indefinitely
+    #[serde(default)]
+    pub until: Option<String>,
+}
+
+/// 暂停订阅 (POST /api/subscriptions/:id/pause)
+/// Pause a subscription
+///
+/// 暂停期间订阅不计入支出；指定截止日期时到期后自动恢复。只有拥有者可以暂停。
+/// While paused the subscription does not count towards spending; with an end date it resumes
+/// automatically when that date arrives. Only the owner can pause it.
+pub async fn pause_subscription(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+    Json(payload): Json<PauseRequest>,
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    let until = match payload.until.as_deref().map(str::trim).filter(|u| !u.is_empty()) {
+        Some(until) => {
+            let date = chrono::NaiveDate::parse_from_str(until, "%Y-%m-%d")
+                .map_err(|_| (StatusCode::BAD_REQUEST, "until must be YYYY-MM-DD".to_string()))?;
+            if date <= dates::today() {
+                return Err((StatusCode::BAD_REQUEST, "until must be in the future".to_string()));
+            }
+            Some(date.format("%Y-%m-%d").to_string())
+        }
+        None => None,
+    };
+    set_paused(&pool, &user, id, false, until).await
+}
+
+/// 恢复已暂停的订阅 (POST /api/subscriptions/:id/resume)
+/// Resume a paused subscription
+pub async fn resume_subscription(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    set_paused(&pool, &user, id, true, None).await
+}
+
+async fn set_paused(
+    pool: &DbPool,
+    user: &AuthUser,
+    id: i64,
+    active: bool,
+    until: Option<String>,
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    let mut sub = sqlx::query_as::<_, Subscription>(
+        "UPDATE subscriptions SET active = ?, paused_until = ? WHERE id = ? AND owner_id = ? RETURNING *",
+    )
+    .bind(active)
+    .bind(&until)
+    .bind(id)
+    .bind(user.user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .ok_or((StatusCode::NOT_FOUND, "Subscription not found".to_string()))?;
+
+    icon_cache::attach_colors(pool, std::slice::from_mut(&mut sub)).await;
+    let kind = if active { EventKind::SubscriptionResumed } else { EventKind::SubscriptionPaused };
+    events::publish_subscription(kind, &sub);
     Ok(Json(sub))
 }
+
+/// 检查到期暂停的间隔
+/// Interval for checking for pauses that have ended
+const RESUME_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// 启动后台任务：暂停截止日期已到的订阅自动恢复
+/// Start the background task that resumes subscriptions whose pause end date has arrived
+pub fn start_resume_task(pool: DbPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RESUME_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let resumed = sqlx::query_as::<_, Subscription>(
+                "UPDATE subscriptions SET active = 1, paused_until = NULL \
+                 WHERE active = 0 AND paused_until IS NOT NULL AND paused_until <= ? \
+                 RETURNING *",
+            )
+            .bind(dates::today().format("%Y-%m-%d").to_string())
+            .fetch_all(&pool)
+            .await;
+            match resumed {
+                Ok(mut subs) => {
+                    if !subs.is_empty() {
+                        info!("Resumed {} paused subscription(s)", subs.len());
+                    }
+                    icon_cache::attach_colors(&pool, &mut subs).await;
+                    for sub in &subs {
+                        events::publish_subscription(EventKind::SubscriptionResumed, sub);
+                    }
+                }
+                Err(e) => warn!("Failed to resume paused subscriptions: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::db;
+
+    /// 名称与目录完全一致时自动补全；近似匹配只返回建议
+    /// An exact catalog name is filled in automatically; an approximate match only returns a
+    /// suggestion
+    #[tokio::test]
+    async fn fills_in_exact_catalog_matches_only() {
+        let pool = db::test_pool().await;
+        let user = AuthUser { user_id: 1, role: Role::Admin, scopes: vec![Scope::Admin] };
+        let create = |name: &str| {
+            let payload: CreateSubscription = serde_json::from_value(serde_json::json!({
+                "name": name, "price": 15, "currency": "USD", "frequency": 1, "next_payment": "2026-11-01"
+            }))
+            .unwrap();
+            create_subscription(State(pool.clone()), Extension(user.clone()), Json(payload))
+        };
+
+        let Json(exact) = create("netflix").await.unwrap();
+        assert_eq!(exact.url.as_deref(), Some("https://www.netflix.com"));
+        assert_eq!(exact.category.as_deref(), Some("video"));
+        assert_eq!(exact.catalog_suggestion, None);
+
+        let Json(fuzzy) = create("Netflix  Premium").await.unwrap();
+        assert_eq!((fuzzy.url.as_deref(), fuzzy.logo.as_deref(), fuzzy.category.as_deref()), (None, None, None));
+        let suggestion = fuzzy.catalog_suggestion.unwrap();
+        assert_eq!((suggestion.service_id.as_str(), suggestion.category.as_str()), ("netflix", "video"));
+    }
+
+    /// 持续输出增量直到被取消的提供方；`stopped` 在其请求结束或被丢弃时置位
+    /// Provider that keeps emitting deltas until cancelled; `stopped` is set once its request ends or
+    /// is dropped
+    struct EndlessProvider {
+        deltas: std::sync::atomic::AtomicUsize,
+        stopped: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    struct SetOnDrop(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl LlmProvider for EndlessProvider {
+        fn name(&self) -> &'static str {
+            "endless"
+        }
+
+        fn model(&self) -> &str {
+            "endless"
+        }
+
+        fn supports_schema(&self) -> bool {
+            false
+        }
+
+        async fn chat(&self, _: &ChatRequest) -> Result<crate::llm::Completion, LlmError> {
+            Err(LlmError::Timeout)
+        }
+
+        async fn chat_stream(
+            &self,
+            _: &ChatRequest,
+            on_delta: &mut crate::llm::OnDelta<'_>,
+        ) -> Result<crate::llm::Completion, LlmError> {
+            let _guard = SetOnDrop(self.stopped.clone());
+            loop {
+                self.deltas.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                if !on_delta("tick ") {
+                    return Err(LlmError::Cancelled);
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    /// 客户端断开 (接收方被丢弃) 后停止读取模型输出
+    /// Reading the model output stops once the client disconnects (the receiver is dropped)
+    #[tokio::test]
+    async fn stream_analysis_stops_when_client_disconnects() {
+        use std::sync::atomic::Ordering;
+
+        let pool = db::test_pool().await;
+        let stopped = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let provider: &'static EndlessProvider =
+            Box::leak(Box::new(EndlessProvider { deltas: Default::default(), stopped: stopped.clone() }));
+        let request = ChatRequest {
+            messages: vec![ChatMessage::user(format!("disconnect test {:p}", provider))],
+            temperature: 0.3,
+            schema: None,
+        };
+
+        let mut rx = stream_analysis(pool, 1, Some(provider), request, Vec::new());
+        assert!(rx.recv().await.is_some());
+        assert!(rx.recv().await.is_some());
+        drop(rx);
+
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !stopped.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("model request still running after the client disconnected");
+        let deltas = provider.deltas.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(provider.deltas.load(Ordering::SeqCst), deltas);
+    }
+
+    /// If-None-Match 与 ETag 一致 (包括弱比较与列表) 时返回不带正文的 304
+    /// A matching If-None-Match (including weak tags and lists) returns 304 without a body
+    #[tokio::test]
+    async fn png_response_honours_if_none_match() {
+        let etag = icon_cache::etag(b"png");
+        let respond = |value: Option<String>| {
+            let mut headers = HeaderMap::new();
+            if let Some(value) = value {
+                headers.insert(IF_NONE_MATCH, HeaderValue::from_str(&value).unwrap());
+            }
+            png_response(&headers, b"png".to_vec(), &etag, "favicon")
+        };
+
+        for value in [etag.clone(), format!("W/{}", etag), format!("\"other\", {}", etag), "*".to_string()] {
+            let resp = respond(Some(value));
+            assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(resp.headers()[ETAG], etag.as_str());
+            assert!(resp.headers().get(CONTENT_TYPE).is_none());
+            assert!(axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap().is_empty());
+        }
+
+        for value in [None, Some("\"other\"".to_string())] {
+            let resp = respond(value);
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()[ETAG], etag.as_str());
+            assert_eq!(resp.headers()[CONTENT_TYPE], "image/png");
+            assert_eq!(resp.headers()["x-icon-source"], "favicon");
+            assert_eq!(axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap(), &b"png"[..]);
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! 家庭组模块
+//! Households module
+//!
+//! 家庭组成员可以查看组内共享的订阅，但只能编辑自己拥有的订阅。
+//! 本模块提供可见性判断所需的 SQL 片段，以及家庭组的创建与成员管理接口。
+//! Household members can see the subscriptions shared with their household but may only edit
+//! the ones they own. This module provides the SQL fragment used for visibility checks and
+//! endpoints to create households and manage their members.
+
+use crate::auth::{AuthUser, Role};
+use crate::db::DbPool;
+use crate::events::{self, Audience, EventKind, Subject};
+use crate::icon_cache;
+use crate::models::{AddHouseholdMember, CreateHousehold, Household, HouseholdMember, Subscription};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use tracing::info;
+
+/// 订阅可见性条件：本人拥有，或共享到本人所在的家庭组。需绑定两次用户 ID。
+/// Subscription visibility condition: owned by the user, or shared with one of the user's
+/// households. The user ID must be bound twice.
+pub const VISIBLE_TO_USER: &str =
+    "(owner_id = ? OR household_id IN (SELECT household_id FROM household_members WHERE user_id = ?))";
+
+/// 判断用户是否为家庭组成员
+/// Check whether a user is a member of a household
+pub async fn is_member(pool: &DbPool, household_id: i64, user_id: i64) -> Result<bool, sqlx::Error> {
+    let row: Option<(i64,)> =
+        sqlx::query_as("SELECT 1 FROM household_members WHERE household_id = ? AND user_id = ?")
+            .bind(household_id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(row.is_some())
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// 列出当前用户所在的家庭组及成员 (GET /api/households)
+/// List the current user's households and their members
+pub async fn list_households(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<Vec<serde_json::Value>>, (StatusCode, String)> {
+    let households = sqlx::query_as::<_, Household>(
+        r#"
+        SELECT households.* FROM households
+        JOIN household_members ON household_members.household_id = households.id
+        WHERE household_members.user_id = ?
+        ORDER BY households.name
+        "#,
+    )
+    .bind(user.user_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let mut result = Vec::with_capacity(households.len());
+    for household in households {
+        let members = sqlx::query_as::<_, HouseholdMember>(
+            r#"
+            SELECT users.id AS user_id, users.username, users.display_name, household_members.joined_at
+            FROM household_members
+            JOIN users ON users.id = household_members.user_id
+            WHERE household_members.household_id = ?
+            ORDER BY users.username
+            "#,
+        )
+        .bind(household.id)
+        .fetch_all(&pool)
+        .await
+        .map_err(db_error)?;
+        result.push(serde_json::json!({ "household": household, "members": members }));
+    }
+
+    Ok(Json(result))
+}
+
+/// 创建家庭组 (POST /api/households)
+/// Create a household
+///
+/// 创建者自动成为组长和第一个成员。
+/// The creator becomes the household owner and its first member.
+pub async fn create_household(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<CreateHousehold>,
+) -> Result<Json<Household>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Name is required".to_string()));
+    }
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+    let id = sqlx::query("INSERT INTO households (name, owner_id) VALUES (?, ?)")
+        .bind(name)
+        .bind(user.user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .last_insert_rowid();
+    sqlx::query("INSERT INTO household_members (household_id, user_id) VALUES (?, ?)")
+        .bind(id)
+        .bind(user.user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    tx.commit().await.map_err(db_error)?;
+
+    let household = sqlx::query_as::<_, Household>("SELECT * FROM households WHERE id = ?")
+        .bind(id)
+        .fetch_one(&pool)
+        .await
+        .map_err(db_error)?;
+
+    info!("User {} created household {} ({})", user.user_id, id, name);
+    Ok(Json(household))
+}
+
+/// 添加家庭组成员 (POST /api/households/:id/members)
+/// Add a household member
+///
+/// 仅组长可以添加成员，按用户名查找用户。
+/// Only the household owner can add members; users are looked up by username.
+pub async fn add_member(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(household_id): Path<i64>,
+    Json(payload): Json<AddHouseholdMember>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+
+    let owner: Option<(i64,)> = sqlx::query_as("SELECT owner_id FROM households WHERE id = ?")
+        .bind(household_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?;
+    match owner {
+        None => return Err((StatusCode::NOT_FOUND, "Household not found".to_string())),
+        Some((owner_id,)) if owner_id != user.user_id => {
+            return Err((StatusCode::FORBIDDEN, "Only the household owner can add members".to_string()))
+        }
+        _ => {}
+    }
+
+    let member: Option<(i64,)> = sqlx::query_as("SELECT id FROM users WHERE username = ?")
+        .bind(payload.username.trim())
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?;
+    let Some((member_id,)) = member else {
+        return Err((StatusCode::NOT_FOUND, "User not found".to_string()));
+    };
+
+    sqlx::query("INSERT OR IGNORE INTO household_members (household_id, user_id) VALUES (?, ?)")
+        .bind(household_id)
+        .bind(member_id)
+        .execute(&pool)
+        .await
+        .map_err(db_error)?;
+
+    info!("User {} joined household {}", member_id, household_id);
+    Ok(Json(serde_json::json!({ "status": "added", "user_id": member_id })))
+}
+
+/// 移除家庭组成员 (DELETE /api/households/:id/members/:user_id)
+/// Remove a household member
+///
+/// 组长可以移除任何其他成员，成员也可以自行退出；组长不能移除自己。
+/// 该成员共享到此组的订阅恢复为私有，其他成员会收到 `subscription.unshared` 事件。
+/// The owner can remove any other member and members can leave on their own; the owner
+/// cannot remove themselves. The member's subscriptions shared with the household become private
+/// again, and the other members receive `subscription.unshared` events.
+pub async fn remove_member(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path((household_id, member_id)): Path<(i64, i64)>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+
+    let owner: Option<(i64,)> = sqlx::query_as("SELECT owner_id FROM households WHERE id = ?")
+        .bind(household_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?;
+    let Some((owner_id,)) = owner else {
+        return Err((StatusCode::NOT_FOUND, "Household not found".to_string()));
+    };
+    if member_id == owner_id {
+        return Err((StatusCode::BAD_REQUEST, "The household owner cannot be removed".to_string()));
+    }
+    if user.user_id != owner_id && user.user_id != member_id {
+        return Err((StatusCode::FORBIDDEN, "Only the household owner can remove other members".to_string()));
+    }
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+    let result = sqlx::query("DELETE FROM household_members WHERE household_id = ? AND user_id = ?")
+        .bind(household_id)
+        .bind(member_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Member not found".to_string()));
+    }
+
+    // 离开家庭组后，该成员共享到此组的订阅恢复为私有
+    // After leaving, the member's subscriptions shared with this household become private again
+    let mut unshared = sqlx::query_as::<_, Subscription>(
+        "UPDATE subscriptions SET household_id = NULL WHERE household_id = ? AND owner_id = ? RETURNING *",
+    )
+    .bind(household_id)
+    .bind(member_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error)?;
+    tx.commit().await.map_err(db_error)?;
+
+    icon_cache::attach_colors(&pool, &mut unshared).await;
+    for sub in &unshared {
+        events::publish(
+            EventKind::SubscriptionUnshared,
+            Audience::Household { household_id, except: member_id },
+            Some(Subject::of(sub)),
+            serde_json::json!({ "id": sub.id, "household_id": household_id }),
+        );
+        events::publish_subscription(EventKind::SubscriptionUpdated, sub);
+    }
+    info!("User {} left household {}", member_id, household_id);
+    Ok(Json(serde_json::json!({ "status": "removed" })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::db;
+    use crate::handlers;
+    use crate::models::CreateSubscription;
+    use serde_json::json;
+
+    fn editor(user_id: i64) -> AuthUser {
+        AuthUser { user_id, role: Role::Editor, scopes: vec![Scope::Read, Scope::Write] }
+    }
+
+    /// 创建用户，返回其 ID / Create a user, returning its ID
+    async fn add_user(pool: &DbPool, username: &str) -> i64 {
+        sqlx::query("INSERT INTO users (username, role) VALUES (?, 'editor')")
+            .bind(username)
+            .execute(pool)
+            .await
+            .unwrap()
+            .last_insert_rowid()
+    }
+
+    fn payload(name: &str, household_id: Option<i64>) -> CreateSubscription {
+        serde_json::from_value(json!({
+            "name": name, "price": 10, "currency": "USD", "frequency": 1,
+            "next_payment": "2026-11-01", "household_id": household_id
+        }))
+        .unwrap()
+    }
+
+    async fn create(
+        pool: &DbPool,
+        user_id: i64,
+        name: &str,
+        household_id: Option<i64>,
+    ) -> Result<Subscription, (StatusCode, String)> {
+        let state = State(pool.clone());
+        let payload = Json(payload(name, household_id));
+        handlers::create_subscription(state, Extension(editor(user_id)), payload).await.map(|j| j.0)
+    }
+
+    async fn visible(pool: &DbPool, user_id: i64) -> Vec<i64> {
+        let Json(subs) = handlers::list_subscriptions(State(pool.clone()), Extension(editor(user_id))).await.unwrap();
+        subs.iter().map(|s| s.id).collect()
+    }
+
+    /// 创建家庭组并按用户名添加成员 / Create a household and add members by username
+    async fn household(pool: &DbPool, owner_id: i64, members: &[&str]) -> i64 {
+        let name: CreateHousehold = serde_json::from_value(json!({ "name": "Home" })).unwrap();
+        let state = State(pool.clone());
+        let Json(household) = create_household(state, Extension(editor(owner_id)), Json(name)).await.unwrap();
+        for username in members {
+            let member: AddHouseholdMember = serde_json::from_value(json!({ "username": username })).unwrap();
+            let state = State(pool.clone());
+            let added = add_member(state, Extension(editor(owner_id)), Path(household.id), Json(member)).await.unwrap();
+            assert_eq!(added["status"], "added");
+        }
+        household.id
+    }
+
+    /// 其他用户看不到也不能修改、删除或暂停私有订阅 (一律 404，不泄露订阅是否存在)
+    /// Other users can neither see nor update, delete or pause a private subscription (always 404,
+    /// so its existence is not revealed)
+    #[tokio::test]
+    async fn hides_private_subscriptions() {
+        let pool = db::test_pool().await;
+        let bob = add_user(&pool, "bob").await;
+        let private = create(&pool, 1, "Alice Private", None).await.unwrap();
+        let other = || (State(pool.clone()), Extension(editor(bob)));
+
+        assert!(!visible(&pool, bob).await.contains(&private.id));
+        let (state, user) = other();
+        let read = crate::splits::get_split(state, user, Path(private.id)).await.unwrap_err();
+        assert_eq!(read, (StatusCode::NOT_FOUND, "Subscription not found".to_string()));
+        let (state, user) = other();
+        let update = handlers::update_subscription(state, user, Path(private.id), Json(payload("Taken", None))).await;
+        assert_eq!(update.unwrap_err().0, StatusCode::NOT_FOUND);
+        let (state, user) = other();
+        let delete = handlers::delete_subscription(state, user, Path(private.id)).await;
+        assert_eq!(delete.unwrap_err().0, StatusCode::NOT_FOUND);
+        let (state, user) = other();
+        let until = Json(handlers::PauseRequest { until: None });
+        let pause = handlers::pause_subscription(state, user, Path(private.id), until).await;
+        assert_eq!(pause.unwrap_err().0, StatusCode::NOT_FOUND);
+
+        let (name, active): (String, bool) = sqlx::query_as("SELECT name, active FROM subscriptions WHERE id = ?")
+            .bind(private.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!((name.as_str(), active), ("Alice Private", true));
+    }
+
+    /// 家庭组成员能看到共享的订阅但不能修改或删除；
+    /// 非成员不能共享到该家庭组，也看不到其中的订阅
+    /// Household members see shared subscriptions but cannot update or delete them; non-members
+    /// can neither share into the household nor see its subscriptions
+    #[tokio::test]
+    async fn shares_read_only_with_members() {
+        let pool = db::test_pool().await;
+        let bob = add_user(&pool, "bob").await;
+        let carol = add_user(&pool, "carol").await;
+        let hid = household(&pool, 1, &["bob"]).await;
+        let shared = create(&pool, 1, "Family Video", Some(hid)).await.unwrap();
+
+        assert!(visible(&pool, bob).await.contains(&shared.id));
+        let state = State(pool.clone());
+        let split = crate::splits::get_split(state, Extension(editor(bob)), Path(shared.id)).await.unwrap_err();
+        assert_eq!(split.1, "No split defined", "members may read the shared subscription");
+        let state = State(pool.clone());
+        let mine = Json(payload("Mine", None));
+        let update = handlers::update_subscription(state, Extension(editor(bob)), Path(shared.id), mine);
+        assert_eq!(update.await.unwrap_err().0, StatusCode::NOT_FOUND);
+        let delete = handlers::delete_subscription(State(pool.clone()), Extension(editor(bob)), Path(shared.id)).await;
+        assert_eq!(delete.unwrap_err().0, StatusCode::NOT_FOUND);
+        let name: String = sqlx::query_scalar("SELECT name FROM subscriptions WHERE id = ?")
+            .bind(shared.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(name, "Family Video");
+
+        assert!(!visible(&pool, carol).await.contains(&shared.id));
+        assert_eq!(create(&pool, carol, "Carol Music", Some(hid)).await.unwrap_err().0, StatusCode::FORBIDDEN);
+        let own = create(&pool, carol, "Carol Music", None).await.unwrap();
+        let state = State(pool.clone());
+        let into_household = Json(payload("Carol Music", Some(hid)));
+        let share = handlers::update_subscription(state, Extension(editor(carol)), Path(own.id), into_household);
+        assert_eq!(share.await.unwrap_err().0, StatusCode::FORBIDDEN);
+        assert!(!visible(&pool, 1).await.contains(&own.id));
+    }
+
+    /// 移除成员后，其共享到此组的订阅恢复为私有，双方互相看不到对方的订阅，
+    /// 其他成员收到移除事件
+    /// After a member is removed, their subscriptions shared with the household are private again,
+    /// neither side sees the other's subscriptions and the other members receive unshare events
+    #[tokio::test]
+    async fn removing_member_unshares_subscriptions() {
+        let pool = db::test_pool().await;
+        let bob = add_user(&pool, "bob").await;
+        let hid = household(&pool, 1, &["bob"]).await;
+        let alices = create(&pool, 1, "Alice Video", Some(hid)).await.unwrap();
+        let bobs = create(&pool, bob, "Bob Music", Some(hid)).await.unwrap();
+        assert!(visible(&pool, bob).await.contains(&alices.id));
+        assert!(visible(&pool, 1).await.contains(&bobs.id));
+
+        let mut receiver = events::receiver();
+        let Json(removed) = remove_member(State(pool.clone()), Extension(editor(1)), Path((hid, bob))).await.unwrap();
+        assert_eq!(removed["status"], "removed");
+
+        let shared: Option<i64> = sqlx::query_scalar("SELECT household_id FROM subscriptions WHERE id = ?")
+            .bind(bobs.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(shared, None);
+        assert_eq!(visible(&pool, 1).await, vec![alices.id]);
+        assert_eq!(visible(&pool, bob).await, vec![bobs.id]);
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let event = receiver.recv().await.unwrap();
+                let about_bobs = event.subject.as_ref().is_some_and(|s| s.subscription_id == bobs.id);
+                if event.kind == EventKind::SubscriptionUnshared && about_bobs {
+                    break event;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        let Audience::Household { household_id, except } = event.audience else {
+            panic!("unexpected audience {:?}", event.audience);
+        };
+        assert_eq!((household_id, except), (hid, bob));
+        assert!(event.visible_to(&pool, 1).await);
+        assert!(!event.visible_to(&pool, bob).await);
+    }
+}
@@ -0,0 +1,765 @@
+//! 图标缓存模块
+//! Icon cache module
+//!
+//! 获取到的图标以 PNG 文件保存在独立的数据目录 (`ICON_CACHE_DIR`，默认 `data/icons`，不在公开的
+//! static 目录中)，SQLite 中的索引表记录域名、尺寸、来源、ETag、大小与获取时间。超过有效期的图标
+//! 仍然照常返回，由后台任务重新获取；缓存总大小超过上限时淘汰最久未使用的图标。写入时同时提取
+//! 图标的主色与强调色，随订阅与 `/api/icon/meta` 返回。管理员可以查看、清除或立即刷新图标。
+//! Fetched icons are stored as PNG files in a dedicated data directory (`ICON_CACHE_DIR`, by
+//! default `data/icons`, outside the public static directory), with an index table in SQLite
+//! recording the domain, size, source, ETag, byte size and fetch time. Icons past their lifetime
+//! are still served while a background task fetches them again; once the cache exceeds its size
+//! budget the least recently used icons are evicted. The dominant and accent colours are extracted
+//! on write and returned with subscriptions and by `/api/icon/meta`. Admins can inspect, purge or
+//! refresh icons.
+
+use crate::auth::{AuthUser, Role};
+use crate::db::DbPool;
+use crate::icons;
+use crate::logos;
+use crate::models::Subscription;
+use crate::palette;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, QueryBuilder, Sqlite};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs;
+use tracing::{debug, info, warn};
+
+/// 缓存配置 (启动后首次使用时读取环境变量)
+/// Cache configuration (environment variables are read on first use)
+struct CacheConfig {
+    /// 图标文件目录 / Directory holding the icon files
+    dir: PathBuf,
+    /// 所有图标文件的总大小上限 (字节) / Budget for the total size of all icon files (bytes)
+    max_bytes: i64,
+    /// 图标在重新获取前的有效期 (秒) / Lifetime of an icon before it is fetched again (seconds)
+    ttl_secs: i64,
+    /// 后台刷新任务的运行间隔 / Interval of the background refresh task
+    refresh_interval: Duration,
+    /// 每次运行最多刷新的图标数 / Maximum number of icons refreshed per run
+    refresh_batch: i64,
+}
+
+static CONFIG: Lazy<CacheConfig> = Lazy::new(|| {
+    let number = |name: &str, default: i64| {
+        std::env::var(name).ok().and_then(|v| v.trim().parse::<i64>().ok()).filter(|n| *n > 0).unwrap_or(default)
+    };
+    CacheConfig {
+        dir: std::env::var("ICON_CACHE_DIR")
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "data/icons".to_string())
+            .into(),
+        max_bytes: number("ICON_CACHE_MAX_MB", 100) * 1024 * 1024,
+        ttl_secs: number("ICON_CACHE_TTL_DAYS", 30) * 86400,
+        refresh_interval: Duration::from_secs(number("ICON_REFRESH_INTERVAL_MINUTES", 60) as u64 * 60),
+        refresh_batch: number("ICON_REFRESH_BATCH", 20),
+    }
+});
+
+/// 图标文件路径 (域名已在接口中过滤为字母、数字、点与连字符)
+/// Path of an icon file (the endpoint has already restricted the domain to letters, digits, dots
+/// and hyphens)
+fn file_path(domain: &str, size: u32) -> PathBuf {
+    CONFIG.dir.join(format!("{}_{}.png", domain, size))
+}
+
+/// 图标内容的强 ETag
+/// Strong ETag of the icon contents
+pub fn etag(png: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(png)[..16]))
+}
+
+/// 缓存命中的图标
+/// A cached icon
+pub struct CachedIcon {
+    pub png: Vec<u8>,
+    pub etag: String,
+    pub source: String,
+}
+
+/// 读取缓存的图标并更新使用时间；索引存在但文件丢失时删除索引
+/// Read a cached icon and update its last use; an index row whose file is missing is removed
+pub async fn get(pool: &DbPool, domain: &str, size: u32) -> Result<Option<CachedIcon>, sqlx::Error> {
+    // 用 fetch_all 让语句执行完毕；fetch_optional 读到第一行就停止，更新要等语句被重置时才提交
+    // fetch_all runs the statement to completion; fetch_optional stops at the first row, leaving
+    // the update uncommitted until the statement is reset
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "UPDATE icon_cache SET hits = hits + 1, last_used_at = CURRENT_TIMESTAMP \
+         WHERE domain = ? AND size = ? RETURNING etag, source",
+    )
+    .bind(domain)
+    .bind(size)
+    .fetch_all(pool)
+    .await?;
+    let Some((etag, source)) = rows.into_iter().next() else {
+        return Ok(None);
+    };
+    match fs::read(file_path(domain, size)).await {
+        Ok(png) => Ok(Some(CachedIcon { png, etag, source })),
+        Err(e) => {
+            warn!("Icon file for {} ({} px) is unreadable, dropping it: {}", domain, size, e);
+            sqlx::query("DELETE FROM icon_cache WHERE domain = ? AND size = ?")
+                .bind(domain)
+                .bind(size)
+                .execute(pool)
+                .await?;
+            Ok(None)
+        }
+    }
+}
+
+/// 写入 (或替换) 图标文件与索引，然后按大小上限淘汰；返回 ETag
+/// Write (or replace) an icon file and its index row, then enforce the size budget; returns the
+/// ETag
+pub async fn put(pool: &DbPool, domain: &str, size: u32, icon: &icons::Icon) -> Result<String, String> {
+    let etag = etag(&icon.png);
+    let colors = palette::extract_blocking(icon.png.clone()).await;
+    fs::create_dir_all(&CONFIG.dir).await.map_err(|e| e.to_string())?;
+    fs::write(file_path(domain, size), &icon.png).await.map_err(|e| e.to_string())?;
+    sqlx::query(
+        "INSERT INTO icon_cache (domain, size, source, etag, bytes, dominant_color, accent_color) \
+         VALUES (?, ?, ?, ?, ?, ?, ?) \
+         ON CONFLICT (domain, size) DO UPDATE SET source = excluded.source, etag = excluded.etag, \
+         bytes = excluded.bytes, dominant_color = excluded.dominant_color, accent_color = excluded.accent_color, \
+         fetched_at = CURRENT_TIMESTAMP, checked_at = CURRENT_TIMESTAMP",
+    )
+    .bind(domain)
+    .bind(size)
+    .bind(icon.source.name())
+    .bind(&etag)
+    .bind(icon.png.len() as i64)
+    .bind(colors.as_ref().map(|c| &c.dominant))
+    .bind(colors.as_ref().map(|c| &c.accent))
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    evict(pool, CONFIG.max_bytes).await.map_err(|e| e.to_string())?;
+    Ok(etag)
+}
+
+/// 写入缓存，失败时只记录日志 (缓存不可用时图标仍然可以返回)
+/// Write to the cache, only logging failures (the icon is still served when the cache is
+/// unavailable)
+pub async fn store(pool: &DbPool, domain: &str, size: u32, icon: &icons::Icon) -> String {
+    match put(pool, domain, size, icon).await {
+        Ok(etag) => etag,
+        Err(e) => {
+            warn!("Failed to cache icon for {} ({} px): {}", domain, size, e);
+            etag(&icon.png)
+        }
+    }
+}
+
+/// 删除索引行对应的文件
+/// Remove the files of the given index rows
+async fn remove_files(rows: &[(String, u32)]) {
+    for (domain, size) in rows {
+        if let Err(e) = fs::remove_file(file_path(domain, *size)).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove icon file for {} ({} px): {}", domain, size, e);
+            }
+        }
+    }
+}
+
+/// 总大小超过 `max_bytes` 时，按最近使用时间淘汰图标
+/// Evict icons by last use while the total size is above `max_bytes`
+async fn evict(pool: &DbPool, max_bytes: i64) -> Result<(), sqlx::Error> {
+    let evicted: Vec<(String, u32)> = sqlx::query_as(
+        "DELETE FROM icon_cache WHERE id IN (\
+         SELECT id FROM (SELECT id, SUM(bytes) OVER (ORDER BY last_used_at DESC, id DESC) AS running \
+         FROM icon_cache) WHERE running > ?) RETURNING domain, size",
+    )
+    .bind(max_bytes)
+    .fetch_all(pool)
+    .await?;
+    if !evicted.is_empty() {
+        debug!("Evicted {} icons from the cache", evicted.len());
+        remove_files(&evicted).await;
+    }
+    Ok(())
+}
+
+/// 重新获取一个图标；内容未变化时只更新检查时间。获取失败时保留旧图标，等到下一个有效期再试
+/// Fetch an icon again; when the contents are unchanged only the check time is updated. On
+/// failure the old icon is kept and retried after another lifetime
+async fn refresh(pool: &DbPool, domain: &str, size: u32, old_etag: &str) -> Result<bool, String> {
+    match icons::fetch(domain, size).await {
+        Some(icon) if etag(&icon.png) != old_etag => {
+            put(pool, domain, size, &icon).await?;
+            Ok(true)
+        }
+        found => {
+            sqlx::query(
+                "UPDATE icon_cache SET checked_at = CURRENT_TIMESTAMP, \
+                 fetched_at = CASE WHEN ? THEN CURRENT_TIMESTAMP ELSE fetched_at END \
+                 WHERE domain = ? AND size = ?",
+            )
+            .bind(found.is_some())
+            .bind(domain)
+            .bind(size)
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            Ok(false)
+        }
+    }
+}
+
+/// 删除目录中没有索引的图标文件 (例如写入后进程中断或旧版本留下的文件)
+/// Delete icon files in the directory that have no index row (for example when the process
+/// stopped right after a write)
+async fn sweep_orphans(pool: &DbPool) {
+    let Ok(mut entries) = fs::read_dir(&CONFIG.dir).await else {
+        return;
+    };
+    let indexed: Vec<(String, u32)> = match sqlx::query_as("SELECT domain, size FROM icon_cache").fetch_all(pool).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            warn!("Failed to read the icon cache index: {}", e);
+            return;
+        }
+    };
+    let indexed: std::collections::HashSet<String> =
+        indexed.into_iter().map(|(domain, size)| format!("{}_{}.png", domain, size)).collect();
+    let mut removed = 0;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".png") && !indexed.contains(&name) && fs::remove_file(entry.path()).await.is_ok() {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        info!("Removed {} unindexed icon files", removed);
+    }
+}
+
+/// 为旧版本缓存的图标补充配色
+/// Fill in the colours of icons cached by older versions
+async fn backfill_colors(pool: &DbPool) {
+    let missing: Vec<(i64, String, u32)> =
+        match sqlx::query_as("SELECT id, domain, size FROM icon_cache WHERE dominant_color IS NULL").fetch_all(pool).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                warn!("Failed to list icons without colours: {}", e);
+                return;
+            }
+        };
+    for (id, domain, size) in &missing {
+        let Ok(png) = fs::read(file_path(domain, *size)).await else { continue };
+        let Some(colors) = palette::extract_blocking(png).await else { continue };
+        let _ = sqlx::query("UPDATE icon_cache SET dominant_color = ?, accent_color = ? WHERE id = ?")
+            .bind(&colors.dominant)
+            .bind(&colors.accent)
+            .bind(id)
+            .execute(pool)
+            .await;
+    }
+}
+
+/// 启动后台任务：清理无索引的文件并补充配色，然后定期重新获取超过有效期的图标
+/// Start the background task: clean up unindexed files and fill in missing colours, then
+/// periodically fetch icons past their lifetime again
+pub fn start_refresh_task(pool: DbPool) {
+    tokio::spawn(async move {
+        sweep_orphans(&pool).await;
+        backfill_colors(&pool).await;
+        logos::backfill_colors(&pool).await;
+        let mut interval = tokio::time::interval(CONFIG.refresh_interval);
+        loop {
+            interval.tick().await;
+            let stale: Vec<(String, u32, String)> = match sqlx::query_as(
+                "SELECT domain, size, etag FROM icon_cache WHERE checked_at <= datetime('now', ?) \
+                 ORDER BY checked_at LIMIT ?",
+            )
+            .bind(format!("-{} seconds", CONFIG.ttl_secs))
+            .bind(CONFIG.refresh_batch)
+            .fetch_all(&pool)
+            .await
+            {
+                Ok(rows) => rows,
+                Err(e) => {
+                    warn!("Failed to list stale icons: {}", e);
+                    continue;
+                }
+            };
+            let mut changed = 0;
+            for (domain, size, old_etag) in &stale {
+                match refresh(&pool, domain, *size, old_etag).await {
+                    Ok(true) => changed += 1,
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to refresh icon for {} ({} px): {}", domain, size, e),
+                }
+            }
+            if !stale.is_empty() {
+                info!("Refreshed {} stale icons, {} changed", stale.len(), changed);
+            }
+        }
+    });
+}
+
+/// 规范化接口传入的域名：小写，只保留字母、数字、点与连字符，且必须包含点
+/// Normalise a domain passed to the API: lowercase, keeping only letters, digits, dots and
+/// hyphens, and it must contain a dot
+pub fn sanitize_domain(domain: &str) -> Option<String> {
+    let mut domain = domain.to_lowercase();
+    domain.retain(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    (!domain.is_empty() && domain.contains('.')).then_some(domain)
+}
+
+/// 订阅图标对应的缓存键：`/api/icon?domain=..&sz=..` 形式的 Logo，或没有 Logo 时官网的域名
+/// (与前端的回退方式相同)
+/// Cache key of a subscription's icon: a logo of the form `/api/icon?domain=..&sz=..`, or the
+/// website's domain when there is no logo (matching the frontend's fallback)
+fn icon_key(sub: &Subscription) -> Option<(String, u32)> {
+    match sub.logo.as_deref().map(str::trim).filter(|l| !l.is_empty()) {
+        Some(logo) => {
+            let url = url::Url::parse("http://localhost").ok()?.join(logo).ok()?;
+            if url.path() != "/api/icon" {
+                return None;
+            }
+            let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+            let size = param("sz").and_then(|s| s.parse().ok()).unwrap_or(64u32).clamp(icons::MIN_SIZE, icons::MAX_SIZE);
+            Some((sanitize_domain(&param("domain")?)?, size))
+        }
+        None => {
+            let site = sub.url.as_deref().map(str::trim).filter(|u| !u.is_empty())?;
+            let site = if site.contains("://") { site.to_string() } else { format!("https://{}", site) };
+            Some((sanitize_domain(url::Url::parse(&site).ok()?.host_str()?)?, 64))
+        }
+    }
+}
+
+/// 为订阅填入图标配色 (来自图标缓存或自定义 Logo)
+/// Fill in the icon colours of subscriptions (from the icon cache or the custom logo)
+pub async fn attach_colors(pool: &DbPool, subs: &mut [Subscription]) {
+    let keys: Vec<ColorKey> = subs.iter().map(color_key).collect();
+    let hashes: BTreeSet<&str> = keys.iter().filter_map(|k| match k {
+        ColorKey::Logo(hash) => Some(*hash),
+        _ => None,
+    }).collect();
+    let icons: BTreeSet<&(String, u32)> = keys.iter().filter_map(|k| match k {
+        ColorKey::Icon(key) => Some(key),
+        _ => None,
+    }).collect();
+    let logo_colors = match logo_colors(pool, &hashes).await {
+        Ok(colors) => colors,
+        Err(e) => {
+            warn!("Failed to read logo colours: {}", e);
+            HashMap::new()
+        }
+    };
+    let icon_colors = match icon_colors(pool, &icons).await {
+        Ok(colors) => colors,
+        Err(e) => {
+            warn!("Failed to read icon colours: {}", e);
+            HashMap::new()
+        }
+    };
+    let found: Vec<Option<ColorPair>> = keys
+        .iter()
+        .map(|key| match key {
+            ColorKey::Logo(hash) => logo_colors.get(*hash).cloned(),
+            ColorKey::Icon(key) => icon_colors.get(key).cloned(),
+            ColorKey::None => None,
+        })
+        .collect();
+    for (sub, colors) in subs.iter_mut().zip(found) {
+        if let Some((dominant, accent)) = colors {
+            sub.dominant_color = dominant;
+            sub.accent_color = accent;
+        }
+    }
+}
+
+/// 主色与强调色 / Dominant and accent colour
+type ColorPair = (Option<String>, Option<String>);
+
+/// 订阅配色的来源：上传的 Logo 或缓存的图标
+/// Where a subscription's colours come from: an uploaded logo or a cached icon
+enum ColorKey<'a> {
+    Logo(&'a str),
+    Icon((String, u32)),
+    None,
+}
+
+fn color_key(sub: &Subscription) -> ColorKey<'_> {
+    if let Some(hash) = sub.logo.as_deref().and_then(logos::hash_of) {
+        ColorKey::Logo(hash)
+    } else if let Some(key) = icon_key(sub) {
+        ColorKey::Icon(key)
+    } else {
+        ColorKey::None
+    }
+}
+
+/// 每条查询绑定的参数上限，远低于 SQLite 的限制
+/// Bound parameters per query, well below SQLite's limit
+const BATCH_SIZE: usize = 400;
+
+/// 批量读取 Logo 配色，每批一条 `IN (...)` 查询
+/// Read logo colours in batches, one `IN (...)` query per batch
+async fn logo_colors(pool: &DbPool, hashes: &BTreeSet<&str>) -> Result<HashMap<String, ColorPair>, sqlx::Error> {
+    let hashes: Vec<&str> = hashes.iter().copied().collect();
+    let mut colors = HashMap::new();
+    for batch in hashes.chunks(BATCH_SIZE) {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT hash, dominant_color, accent_color FROM logo_assets WHERE hash IN (");
+        let mut list = query.separated(", ");
+        for hash in batch {
+            list.push_bind(*hash);
+        }
+        query.push(")");
+        let rows: Vec<(String, Option<String>, Option<String>)> = query.build_query_as().fetch_all(pool).await?;
+        colors.extend(rows.into_iter().map(|(hash, dominant, accent)| (hash, (dominant, accent))));
+    }
+    Ok(colors)
+}
+
+/// 批量读取缓存图标的配色，每批一条按 (域名, 尺寸) 匹配的 `IN (...)` 查询
+/// Read cached icon colours in batches, one `IN (...)` query on (domain, size) per batch
+async fn icon_colors(
+    pool: &DbPool,
+    keys: &BTreeSet<&(String, u32)>,
+) -> Result<HashMap<(String, u32), ColorPair>, sqlx::Error> {
+    let keys: Vec<&(String, u32)> = keys.iter().copied().collect();
+    let mut colors = HashMap::new();
+    for batch in keys.chunks(BATCH_SIZE / 2) {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT domain, size, dominant_color, accent_color FROM icon_cache WHERE (domain, size) IN (VALUES ",
+        );
+        let mut list = query.separated(", ");
+        for (domain, size) in batch {
+            list.push("(").push_bind_unseparated(domain.as_str()).push_unseparated(", ").push_bind_unseparated(*size);
+            list.push_unseparated(")");
+        }
+        query.push(")");
+        let rows: Vec<(String, u32, Option<String>, Option<String>)> = query.build_query_as().fetch_all(pool).await?;
+        colors.extend(rows.into_iter().map(|(domain, size, dominant, accent)| ((domain, size), (dominant, accent))));
+    }
+    Ok(colors)
+}
+
+#[derive(Deserialize)]
+pub struct IconMetaQuery {
+    domain: String,
+    sz: Option<u32>,
+}
+
+/// 图标元数据
+/// Icon metadata
+#[derive(Serialize, FromRow)]
+pub struct IconMeta {
+    pub domain: String,
+    pub size: i64,
+    pub source: String,
+    pub etag: String,
+    pub bytes: i64,
+    pub fetched_at: String,
+    pub dominant_color: Option<String>,
+    pub accent_color: Option<String>,
+}
+
+/// 获取缓存图标的元数据与配色 (GET /api/icon/meta?domain=example.com&sz=64)；图标尚未缓存时返回 404
+/// Get the metadata and colours of a cached icon; returns 404 while the icon is not cached
+pub async fn icon_meta(
+    State(pool): State<DbPool>,
+    Query(params): Query<IconMetaQuery>,
+) -> Result<Json<IconMeta>, (StatusCode, String)> {
+    let domain = sanitize_domain(&params.domain).ok_or((StatusCode::BAD_REQUEST, "invalid domain".to_string()))?;
+    let size = params.sz.unwrap_or(64).clamp(icons::MIN_SIZE, icons::MAX_SIZE);
+    sqlx::query_as::<_, IconMeta>(
+        "SELECT domain, size, source, etag, bytes, fetched_at, dominant_color, accent_color \
+         FROM icon_cache WHERE domain = ? AND size = ?",
+    )
+    .bind(&domain)
+    .bind(size)
+    .fetch_optional(&pool)
+    .await
+    .map_err(db_error)?
+    .map(Json)
+    .ok_or((StatusCode::NOT_FOUND, "Icon not cached".to_string()))
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// 缓存的图标
+/// Cached icon
+#[derive(Serialize, FromRow)]
+pub struct IconEntry {
+    pub id: i64,
+    pub domain: String,
+    pub size: i64,
+    pub source: String,
+    pub etag: String,
+    pub bytes: i64,
+    pub hits: i64,
+    pub stale: bool,
+    pub dominant_color: Option<String>,
+    pub accent_color: Option<String>,
+    pub fetched_at: String,
+    pub checked_at: String,
+    pub last_used_at: String,
+}
+
+const ENTRY_COLUMNS: &str = "id, domain, size, source, etag, bytes, hits, \
+     (checked_at <= datetime('now', ?1)) AS stale, dominant_color, accent_color, fetched_at, checked_at, last_used_at";
+
+#[derive(Deserialize)]
+pub struct IconCacheQuery {
+    /// 按域名筛选 / Filter by domain
+    pub domain: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// 图标缓存概况与条目列表
+/// Icon cache overview and entry list
+#[derive(Serialize)]
+pub struct IconCacheReport {
+    pub total: i64,
+    pub bytes: i64,
+    pub stale: i64,
+    pub max_bytes: i64,
+    pub ttl_days: i64,
+    pub dir: String,
+    pub entries: Vec<IconEntry>,
+}
+
+/// 查看图标缓存 (GET /api/icon/cache，仅管理员)，按最近使用时间倒序
+/// Inspect the icon cache (admins only), most recently used first
+pub async fn list_cache(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<IconCacheQuery>,
+) -> Result<Json<IconCacheReport>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let age = format!("-{} seconds", CONFIG.ttl_secs);
+    let (total, bytes, stale): (i64, i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), COALESCE(SUM(bytes), 0), COALESCE(SUM(checked_at <= datetime('now', ?)), 0) FROM icon_cache",
+    )
+    .bind(&age)
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let filter = params.domain.as_deref().map(|d| format!("%{}%", d.trim().to_lowercase())).unwrap_or_else(|| "%".to_string());
+    let entries = sqlx::query_as::<_, IconEntry>(&format!(
+        "SELECT {} FROM icon_cache WHERE domain LIKE ?2 ORDER BY last_used_at DESC, id DESC LIMIT ?3",
+        ENTRY_COLUMNS
+    ))
+    .bind(&age)
+    .bind(filter)
+    .bind(params.limit.unwrap_or(100).clamp(1, 1000))
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(IconCacheReport {
+        total,
+        bytes,
+        stale,
+        max_bytes: CONFIG.max_bytes,
+        ttl_days: CONFIG.ttl_secs / 86400,
+        dir: CONFIG.dir.display().to_string(),
+        entries,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct IconDomainQuery {
+    /// 只处理该域名的图标 (所有尺寸) / Only the icons of this domain (all sizes)
+    pub domain: Option<String>,
+}
+
+/// 清除图标缓存 (DELETE /api/icon/cache?domain=，仅管理员)；不指定域名时清除全部
+/// Purge the icon cache (admins only); everything is purged when no domain is given
+pub async fn purge_cache(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<IconDomainQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let domain = params.domain.map(|d| d.trim().to_lowercase()).filter(|d| !d.is_empty());
+    let purged: Vec<(String, u32)> = sqlx::query_as("DELETE FROM icon_cache WHERE ? IS NULL OR domain = ? RETURNING domain, size")
+        .bind(&domain)
+        .bind(&domain)
+        .fetch_all(&pool)
+        .await
+        .map_err(db_error)?;
+    remove_files(&purged).await;
+    Ok(Json(serde_json::json!({ "status": "purged", "purged": purged.len() })))
+}
+
+/// 立即重新获取某个域名的所有图标 (POST /api/icon/cache/refresh?domain=，仅管理员)
+/// Fetch all icons of a domain again right away (admins only)
+pub async fn refresh_domain(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<IconDomainQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let domain = params
+        .domain
+        .map(|d| d.trim().to_lowercase())
+        .filter(|d| !d.is_empty())
+        .ok_or((StatusCode::BAD_REQUEST, "domain is required".to_string()))?;
+    let rows: Vec<(u32, String)> = sqlx::query_as("SELECT size, etag FROM icon_cache WHERE domain = ?")
+        .bind(&domain)
+        .fetch_all(&pool)
+        .await
+        .map_err(db_error)?;
+    if rows.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "Icon not cached".to_string()));
+    }
+    let mut changed = 0;
+    for (size, old_etag) in &rows {
+        if refresh(&pool, &domain, *size, old_etag).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))? {
+            changed += 1;
+        }
+    }
+    Ok(Json(serde_json::json!({ "status": "refreshed", "refreshed": rows.len(), "changed": changed })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn subscription(id: i64, url: Option<&str>, logo: Option<&str>) -> Subscription {
+        serde_json::from_value(serde_json::json!({
+            "id": id, "name": "Example", "price": 1.0, "currency": "USD", "frequency": 1,
+            "url": url, "logo": logo, "active": true, "owner_id": 1,
+        }))
+        .unwrap()
+    }
+
+    /// 一次批量查询为 Logo、图标链接与官网回退三种来源补充配色
+    /// Colours are filled in for uploaded logos, icon links and the website fallback in batches
+    #[tokio::test]
+    async fn attaches_colors_in_batches() {
+        let pool = db::test_pool().await;
+        let hash = "0123456789abcdef0123456789abcdef";
+        sqlx::query(
+            "INSERT INTO logo_assets (hash, format, original_bytes, dominant_color, accent_color) \
+             VALUES (?, 'png', 1, '#111111', '#222222')",
+        )
+        .bind(hash)
+        .execute(&pool)
+        .await
+        .unwrap();
+        for (domain, size, color) in [("example.com", 64, "#333333"), ("example.com", 128, "#444444")] {
+            sqlx::query(
+                "INSERT INTO icon_cache (domain, size, source, etag, bytes, dominant_color, accent_color) \
+                 VALUES (?, ?, 'favicon', 'etag', 1, ?, ?)",
+            )
+            .bind(domain)
+            .bind(size)
+            .bind(color)
+            .bind(color)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let mut subs = vec![
+            subscription(1, None, Some(&logos::url(hash))),
+            subscription(2, None, Some("/api/icon?domain=example.com&sz=128")),
+            subscription(3, Some("example.com"), None),
+            subscription(4, Some("https://unknown.example"), None),
+            subscription(5, None, Some("https://cdn.example/logo.png")),
+            subscription(6, None, Some(&logos::url("ffffffffffffffffffffffffffffffff"))),
+        ];
+        attach_colors(&pool, &mut subs).await;
+        let colors: Vec<_> = subs.iter().map(|s| (s.dominant_color.as_deref(), s.accent_color.as_deref())).collect();
+        assert_eq!(
+            colors,
+            vec![
+                (Some("#111111"), Some("#222222")),
+                (Some("#444444"), Some("#444444")),
+                (Some("#333333"), Some("#333333")),
+                (None, None),
+                (None, None),
+                (None, None),
+            ]
+        );
+    }
+
+    /// 插入一条指定大小与最近使用时间的索引行
+    /// Insert an index row with the given size and last use
+    async fn insert_icon(pool: &DbPool, domain: &str, bytes: i64, last_used_at: &str) {
+        sqlx::query(
+            "INSERT INTO icon_cache (domain, size, source, etag, bytes, last_used_at) \
+             VALUES (?, 64, 'favicon', 'etag', ?, ?)",
+        )
+        .bind(domain)
+        .bind(bytes)
+        .bind(last_used_at)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn cached_domains(pool: &DbPool) -> Vec<String> {
+        sqlx::query_scalar("SELECT domain FROM icon_cache ORDER BY domain").fetch_all(pool).await.unwrap()
+    }
+
+    /// 超过大小上限时先淘汰最久未使用的图标
+    /// Above the size budget the least recently used icons go first
+    #[tokio::test]
+    async fn evicts_least_recently_used_first() {
+        let pool = db::test_pool().await;
+        insert_icon(&pool, "a.lru-test.example", 40, "2000-01-01 00:00:00").await;
+        insert_icon(&pool, "b.lru-test.example", 40, "2000-01-03 00:00:00").await;
+        insert_icon(&pool, "c.lru-test.example", 40, "2000-01-02 00:00:00").await;
+        insert_icon(&pool, "d.lru-test.example", 40, "2000-01-04 00:00:00").await;
+
+        evict(&pool, 160).await.unwrap();
+        assert_eq!(cached_domains(&pool).await.len(), 4);
+        evict(&pool, 100).await.unwrap();
+        assert_eq!(cached_domains(&pool).await, ["b.lru-test.example", "d.lru-test.example"]);
+        evict(&pool, 79).await.unwrap();
+        assert_eq!(cached_domains(&pool).await, ["d.lru-test.example"]);
+    }
+
+    /// 缓存命中会更新最近使用时间，使该图标在淘汰时排在后面
+    /// A cache hit updates the last use, so that icon is evicted later
+    #[tokio::test]
+    async fn hits_bump_recency() {
+        let pool = db::test_pool().await;
+        let (old, new) = ("old.hit-test.example", "new.hit-test.example");
+        insert_icon(&pool, old, 40, "2000-01-01 00:00:00").await;
+        insert_icon(&pool, new, 40, "2000-01-02 00:00:00").await;
+        fs::create_dir_all(&CONFIG.dir).await.unwrap();
+        fs::write(file_path(old, 64), b"png").await.unwrap();
+
+        let hit = get(&pool, old, 64).await.unwrap().unwrap();
+        assert_eq!((hit.png.as_slice(), hit.etag.as_str(), hit.source.as_str()), (&b"png"[..], "etag", "favicon"));
+        let hits: i64 = sqlx::query_scalar("SELECT hits FROM icon_cache WHERE domain = ?")
+            .bind(old)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(hits, 1);
+
+        evict(&pool, 40).await.unwrap();
+        assert_eq!(cached_domains(&pool).await, [old]);
+        remove_files(&[(old.to_string(), 64)]).await;
+
+        // 文件丢失时命中视为未命中并删除索引
+        // A hit whose file is missing counts as a miss and drops the index row
+        assert!(get(&pool, old, 64).await.unwrap().is_none());
+        assert!(cached_domains(&pool).await.is_empty());
+    }
+}
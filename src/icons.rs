@@ -0,0 +1,639 @@
+//! 网站图标获取模块
+//! Website icon fetching module
+//!
+//! 按 `ICON_SOURCES` 配置的顺序从多个来源获取图标：站点的 `/favicon.ico`、首页 HTML 中的
+//! `<link rel=icon>` 与 apple-touch-icon、Web App Manifest 中的图标，最后是 Google 图标服务。
+//! 下载内容按文件头识别格式 (PNG、ICO、JPEG、GIF、WebP、BMP、SVG)，HTML 错误页与占位图标
+//! (地球图标、空白图片) 会被拒绝，有效图标统一转换为所需尺寸的正方形 PNG。第一个不小于所需尺寸的
+//! 图标被采用；都不够大时采用最大的一个。
+//! Fetches icons from several sources in the order configured by `ICON_SOURCES`: the site's
+//! `/favicon.ico`, `<link rel=icon>` and apple-touch-icon from the homepage HTML, icons in the
+//! Web App Manifest, and finally Google's favicon service. Downloads are identified by their
+//! magic bytes (PNG, ICO, JPEG, GIF, WebP, BMP, SVG); HTML error pages and placeholder icons
+//! (globe icons, blank images) are rejected, and valid icons are converted to a square PNG of the
+//! requested size. The first icon at least as large as the requested size wins; if none is large
+//! enough the largest one is used.
+
+use crate::outbound;
+use image::{imageops::FilterType, DynamicImage, ImageFormat, RgbaImage};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{Cursor, Read};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// 默认的来源顺序
+/// Default source order
+const DEFAULT_SOURCES: &str = "favicon,html,manifest,google";
+
+/// 单个图标文件的大小上限
+/// Size limit of a single icon file
+const MAX_ICON_BYTES: usize = 1024 * 1024;
+
+/// 首页 HTML 与 Manifest 的大小上限
+/// Size limit of the homepage HTML and the manifest
+const MAX_PAGE_BYTES: usize = 2 * 1024 * 1024;
+
+/// 输出尺寸范围
+/// Range of output sizes
+pub const MIN_SIZE: u32 = 16;
+pub const MAX_SIZE: u32 = 256;
+
+/// 图标来源
+/// Icon source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// 站点根目录的 /favicon.ico / The site's /favicon.ico
+    Favicon,
+    /// 首页 HTML 中的 link 标签 / Link tags in the homepage HTML
+    Html,
+    /// Web App Manifest 中的图标 / Icons in the Web App Manifest
+    Manifest,
+    /// Google 图标服务 / Google's favicon service
+    Google,
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Favicon => "favicon",
+            Source::Html => "html",
+            Source::Manifest => "manifest",
+            Source::Google => "google",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "favicon" => Some(Source::Favicon),
+            "html" => Some(Source::Html),
+            "manifest" => Some(Source::Manifest),
+            "google" => Some(Source::Google),
+            _ => None,
+        }
+    }
+}
+
+/// 启动时根据 `ICON_SOURCES` 确定的来源顺序
+/// Source order determined from `ICON_SOURCES` at startup
+static SOURCES: Lazy<Vec<Source>> = Lazy::new(|| {
+    let order = std::env::var("ICON_SOURCES").ok().filter(|v| !v.trim().is_empty());
+    let sources: Vec<Source> = order
+        .as_deref()
+        .unwrap_or(DEFAULT_SOURCES)
+        .split(',')
+        .map(|n| n.trim().to_lowercase())
+        .filter(|n| !n.is_empty())
+        .filter_map(|n| {
+            let source = Source::parse(&n);
+            if source.is_none() {
+                warn!("Unknown icon source '{}' ignored", n);
+            }
+            source
+        })
+        .collect();
+    let names: Vec<_> = sources.iter().map(|s| s.name()).collect();
+    info!("Icon sources: {}", names.join(" -> "));
+    sources
+});
+
+/// 已知占位图标的 SHA-256 (原始下载内容)，可通过 `ICON_PLACEHOLDER_HASHES` (逗号分隔) 补充
+/// SHA-256 of known placeholder icons (raw downloads), extendable with `ICON_PLACEHOLDER_HASHES`
+/// (comma-separated)
+static PLACEHOLDER_HASHES: Lazy<HashSet<String>> = Lazy::new(|| {
+    std::env::var("ICON_PLACEHOLDER_HASHES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|h| h.trim().to_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect()
+});
+
+/// 图标请求共用的 HTTP 客户端 (遵守出站策略：图标地址来自用户输入与第三方页面)
+/// HTTP client shared by icon requests (it enforces the outbound policy, since icon addresses
+/// come from user input and third-party pages)
+static CLIENT: Lazy<outbound::Client> = Lazy::new(|| {
+    outbound::Client::new(
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Safari/537.36",
+        Duration::from_secs(8),
+    )
+});
+
+/// 各类下载接受的内容类型 (图标再按文件头识别)
+/// Content types accepted for each kind of download (icons are also checked by magic bytes)
+const ICON_TYPES: &[&str] = &["image/", "application/octet-stream", "application/ico", "application/x-ico"];
+const PAGE_TYPES: &[&str] = &["text/html", "application/xhtml+xml"];
+const MANIFEST_TYPES: &[&str] = &["application/manifest+json", "application/json", "application/octet-stream", "text/plain"];
+
+/// 获取到的图标
+/// A fetched icon
+pub struct Icon {
+    /// 转换后的 PNG / The converted PNG
+    pub png: Vec<u8>,
+    pub source: Source,
+    /// 原图边长 (较短的一边；SVG 为 0) / Side of the original image (the shorter one; 0 for SVG)
+    pub original_size: u32,
+}
+
+/// 识别出的图片格式
+/// Detected image format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Raster(ImageFormat),
+    Svg,
+}
+
+/// 按文件头识别图片格式；HTML 等非图片内容返回 `None`
+/// Detect the image format from the magic bytes; non-images such as HTML return `None`
+pub(crate) fn sniff(bytes: &[u8]) -> Option<Kind> {
+    const SIGNATURES: [(&[u8], ImageFormat); 6] = [
+        (b"\x89PNG\r\n\x1a\n", ImageFormat::Png),
+        (b"\x00\x00\x01\x00", ImageFormat::Ico),
+        (b"\xff\xd8\xff", ImageFormat::Jpeg),
+        (b"GIF8", ImageFormat::Gif),
+        (b"BM", ImageFormat::Bmp),
+        (b"RIFF", ImageFormat::WebP),
+    ];
+    if let Some((_, format)) = SIGNATURES.iter().find(|(magic, _)| bytes.starts_with(magic)) {
+        if *format == ImageFormat::WebP && bytes.get(8..12) != Some(b"WEBP".as_slice()) {
+            return None;
+        }
+        return Some(Kind::Raster(*format));
+    }
+    // svgz 只解压开头一段，确认其中是 SVG (其他 gzip 内容不是图片)
+    // For svgz only the beginning is decompressed to confirm it holds an SVG (other gzip content
+    // is not an image)
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut head = Vec::new();
+        let _ = flate2::read::GzDecoder::new(bytes).take(SVG_HEAD_BYTES as u64).read_to_end(&mut head);
+        return is_svg_text(&head).then_some(Kind::Svg);
+    }
+    is_svg_text(bytes).then_some(Kind::Svg)
+}
+
+/// 识别 SVG 时检查的开头字节数
+/// Number of leading bytes checked to recognise an SVG
+const SVG_HEAD_BYTES: usize = 1024;
+
+/// SVG 是文本：跳过 BOM、空白与 XML 声明/注释后应以 <svg 开头
+/// SVG is text: after a BOM, whitespace and the XML prolog/comments it must start with <svg
+fn is_svg_text(bytes: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(SVG_HEAD_BYTES)]).to_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with("<svg") || (head.starts_with("<?xml") || head.starts_with("<!--")) && head.contains("<svg")
+}
+
+/// 解码时允许的最大边长，避免恶意图片耗尽内存
+/// Largest side allowed when decoding, so hostile images cannot exhaust memory
+const MAX_DECODE_SIDE: u32 = 4096;
+
+/// 解码图片；ICO 取其中最大的一张
+/// Decode an image; for ICO the largest entry is used
+pub(crate) fn decode(bytes: &[u8], kind: Kind, size: u32) -> Result<(DynamicImage, u32), String> {
+    match kind {
+        Kind::Raster(format) => {
+            let mut limits = image::Limits::default();
+            limits.max_image_width = Some(MAX_DECODE_SIDE);
+            limits.max_image_height = Some(MAX_DECODE_SIDE);
+            let mut reader = image::ImageReader::with_format(Cursor::new(bytes), format);
+            reader.limits(limits);
+            let image = reader.decode().map_err(|e| e.to_string())?;
+            let side = image.width().min(image.height());
+            Ok((image, side))
+        }
+        Kind::Svg => Ok((render_svg(bytes, size)?, 0)),
+    }
+}
+
+/// 把 SVG 栅格化为 `size` 大小 (保持比例，居中)
+/// Rasterise an SVG to `size` (keeping the aspect ratio, centred)
+///
+/// SVG 来自远程网站或用户上传，`<image>` 只能使用内嵌的 data: 图片；默认的解析器会读取服务器上的
+/// 本地文件，因此被禁用。
+/// SVGs come from remote sites or user uploads, so `<image>` may only use embedded data: images;
+/// the default resolver reads local files on the server and is therefore disabled.
+fn render_svg(bytes: &[u8], size: u32) -> Result<DynamicImage, String> {
+    use resvg::{tiny_skia, usvg};
+
+    let options = usvg::Options {
+        image_href_resolver: usvg::ImageHrefResolver {
+            resolve_data: usvg::ImageHrefResolver::default_data_resolver(),
+            resolve_string: Box::new(|_, _| None),
+        },
+        ..usvg::Options::default()
+    };
+    let tree = usvg::Tree::from_data(bytes, &options).map_err(|e| e.to_string())?;
+    let svg_size = tree.size();
+    let scale = size as f32 / svg_size.width().max(svg_size.height());
+    let mut pixmap = tiny_skia::Pixmap::new(size, size).ok_or("invalid size")?;
+    let dx = (size as f32 - svg_size.width() * scale) / 2.0;
+    let dy = (size as f32 - svg_size.height() * scale) / 2.0;
+    let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(dx, dy);
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+    let pixels: Vec<u8> = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect();
+    RgbaImage::from_raw(size, size, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| "invalid pixel buffer".to_string())
+}
+
+/// 是否为占位图标：已知的占位图片，或几乎全透明/单一颜色的图片
+/// Whether this is a placeholder: a known placeholder image, or an almost fully transparent or
+/// single-colour image
+fn is_placeholder(bytes: &[u8], image: &DynamicImage) -> bool {
+    let hash = hex::encode(Sha256::digest(bytes));
+    if PLACEHOLDER_HASHES.contains(&hash) {
+        return true;
+    }
+    debug!("Icon content hash: {}", hash);
+
+    let rgba = image.to_rgba8();
+    let total = rgba.pixels().len().max(1);
+    let visible: Vec<_> = rgba.pixels().filter(|p| p[3] > 16).collect();
+    if visible.len() * 20 < total {
+        return true;
+    }
+    let first = visible[0];
+    visible.iter().all(|p| p.0.iter().zip(first.0.iter()).all(|(a, b)| a.abs_diff(*b) <= 8))
+}
+
+/// 缩放为 `size` 大小的正方形 PNG (保持比例，透明填充)
+/// Scale into a square PNG of `size` (keeping the aspect ratio, padded with transparency)
+pub(crate) fn to_square_png(image: &DynamicImage, size: u32) -> Result<Vec<u8>, String> {
+    let filter = if image.width() > size || image.height() > size { FilterType::Lanczos3 } else { FilterType::CatmullRom };
+    let scaled = image.resize(size, size, filter).to_rgba8();
+    let mut canvas = RgbaImage::new(size, size);
+    let x = (size - scaled.width()) / 2;
+    let y = (size - scaled.height()) / 2;
+    image::imageops::overlay(&mut canvas, &scaled, x.into(), y.into());
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgba8(canvas)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+/// 把下载的图标转换为 `size` 大小的正方形 PNG，返回 PNG 与原始边长；解码与栅格化耗费 CPU，
+/// 调用方应在阻塞线程中执行
+/// Convert a downloaded icon into a square PNG of `size`, returning the PNG and the original side
+/// length; decoding and rasterising are CPU-heavy, so callers should run this on a blocking thread
+fn convert(bytes: &[u8], size: u32) -> Result<(Vec<u8>, u32), String> {
+    let kind = sniff(bytes).ok_or("not an image")?;
+    let (image, original_size) = decode(bytes, kind, size)?;
+    if is_placeholder(bytes, &image) {
+        return Err("placeholder icon".to_string());
+    }
+    Ok((to_square_png(&image, size)?, original_size))
+}
+
+/// 下载内容；超过上限、内容类型不符或非 2xx 状态都视为失败 (Google 对未知站点返回 404 与地球图标)
+/// Download content; exceeding the limit, an unexpected content type or a non-2xx status is a
+/// failure (Google answers unknown sites with a 404 and a globe icon)
+async fn download(url: &str, limit: usize, accept: &'static [&'static str]) -> Result<(url::Url, Vec<u8>), String> {
+    let response = CLIENT.get(url).max_bytes(limit).accept(accept).send().await?;
+    Ok((response.url, response.body))
+}
+
+/// 首页中声明的图标候选
+/// Icon candidate declared by the homepage
+struct Candidate {
+    url: url::Url,
+    /// 声明的尺寸 (用于排序) / Declared size (used for ordering)
+    size: u32,
+}
+
+/// 解析 `sizes` 属性 ("32x32 64x64" 或 "any")，返回最大边长
+/// Parse a `sizes` attribute ("32x32 64x64" or "any"), returning the largest side
+fn declared_size(sizes: Option<&str>, default: u32) -> u32 {
+    let Some(sizes) = sizes else {
+        return default;
+    };
+    if sizes.to_lowercase().split_whitespace().any(|s| s == "any") {
+        return 1024;
+    }
+    sizes
+        .to_lowercase()
+        .split_whitespace()
+        .filter_map(|s| s.split_once('x').and_then(|(w, h)| Some(w.parse::<u32>().ok()?.min(h.parse().ok()?))))
+        .max()
+        .unwrap_or(default)
+}
+
+/// 首页信息：link 标签中的图标与 Manifest 地址
+/// Homepage information: icons from link tags and the manifest address
+#[derive(Default)]
+struct Homepage {
+    icons: Vec<Candidate>,
+    manifest: Option<url::Url>,
+}
+
+/// 解析首页 HTML
+/// Parse the homepage HTML
+fn parse_homepage(base: &url::Url, html: &str) -> Homepage {
+    let document = scraper::Html::parse_document(html);
+    let selector = scraper::Selector::parse("link[rel][href]").expect("valid selector");
+    let mut homepage = Homepage::default();
+    for link in document.select(&selector) {
+        let rel = link.value().attr("rel").unwrap_or_default().to_lowercase();
+        let rels: Vec<&str> = rel.split_whitespace().collect();
+        let Some(url) = link.value().attr("href").and_then(|href| base.join(href.trim()).ok()) else {
+            continue;
+        };
+        if rels.contains(&"manifest") {
+            homepage.manifest.get_or_insert(url);
+        } else if rels.iter().any(|r| r.starts_with("apple-touch-icon")) {
+            homepage.icons.push(Candidate { size: declared_size(link.value().attr("sizes"), 180), url });
+        } else if rels.contains(&"icon") {
+            let svg = link.value().attr("type").is_some_and(|t| t.contains("svg")) || url.path().ends_with(".svg");
+            let default = if svg { 1024 } else { 32 };
+            homepage.icons.push(Candidate { size: declared_size(link.value().attr("sizes"), default), url });
+        }
+    }
+    homepage.icons.sort_by_key(|c| std::cmp::Reverse(c.size));
+    homepage
+}
+
+/// 解析 Manifest 中的图标 (跳过单色图标)
+/// Parse the icons of a manifest (skipping monochrome icons)
+fn parse_manifest(base: &url::Url, body: &[u8]) -> Vec<Candidate> {
+    let Ok(json) = serde_json::from_slice::<serde_json::Value>(body) else {
+        return Vec::new();
+    };
+    let mut icons: Vec<Candidate> = json["icons"]
+        .as_array()
+        .map(|icons| {
+            icons
+                .iter()
+                .filter(|icon| !icon["purpose"].as_str().is_some_and(|p| p.contains("monochrome")))
+                .filter_map(|icon| {
+                    let url = base.join(icon["src"].as_str()?).ok()?;
+                    Some(Candidate { size: declared_size(icon["sizes"].as_str(), 0), url })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    icons.sort_by_key(|c| std::cmp::Reverse(c.size));
+    icons
+}
+
+/// 一次获取过程：首页只下载一次，供 html 与 manifest 两个来源共用
+/// One fetch run: the homepage is downloaded once and shared by the html and manifest sources
+struct Fetch<'a> {
+    domain: &'a str,
+    size: u32,
+    homepage: Option<Homepage>,
+}
+
+impl Fetch<'_> {
+    async fn homepage(&mut self) -> &Homepage {
+        if self.homepage.is_none() {
+            let homepage = match download(&format!("https://{}/", self.domain), MAX_PAGE_BYTES, PAGE_TYPES).await {
+                Ok((base, body)) => parse_homepage(&base, &String::from_utf8_lossy(&body)),
+                Err(e) => {
+                    debug!("Homepage of {} unavailable: {}", self.domain, e);
+                    Homepage::default()
+                }
+            };
+            self.homepage = Some(homepage);
+        }
+        self.homepage.get_or_insert_with(Homepage::default)
+    }
+
+    /// 下载并转换一个图标地址
+    /// Download and convert one icon address
+    async fn load(&self, url: &str, source: Source) -> Result<Icon, String> {
+        let (_, bytes) = download(url, MAX_ICON_BYTES, ICON_TYPES).await?;
+        let size = self.size;
+        let (png, original_size) =
+            tokio::task::spawn_blocking(move || convert(&bytes, size)).await.map_err(|e| e.to_string())??;
+        Ok(Icon { png, source, original_size })
+    }
+
+    /// 依次尝试一组候选地址，返回第一个有效图标
+    /// Try a list of candidate addresses in turn, returning the first valid icon
+    async fn first_valid(&self, urls: Vec<String>, source: Source) -> Result<Option<Icon>, String> {
+        let mut last_error = None;
+        for url in urls {
+            match self.load(&url, source).await {
+                Ok(icon) => return Ok(Some(icon)),
+                Err(e) => {
+                    debug!("Icon candidate {} rejected: {}", url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        last_error.map_or(Ok(None), Err)
+    }
+
+    async fn try_source(&mut self, source: Source) -> Result<Option<Icon>, String> {
+        match source {
+            Source::Favicon => self.load(&format!("https://{}/favicon.ico", self.domain), source).await.map(Some),
+            Source::Html => {
+                let urls = self.homepage().await.icons.iter().map(|c| c.url.to_string()).collect();
+                self.first_valid(urls, source).await
+            }
+            Source::Manifest => {
+                let Some(manifest) = self.homepage().await.manifest.clone() else {
+                    return Ok(None);
+                };
+                let (base, body) = download(manifest.as_str(), MAX_PAGE_BYTES, MANIFEST_TYPES).await?;
+                let urls = parse_manifest(&base, &body).into_iter().map(|c| c.url.to_string()).collect();
+                self.first_valid(urls, source).await
+            }
+            Source::Google => {
+                let url = format!("https://www.google.com/s2/favicons?domain={}&sz={}", self.domain, self.size);
+                self.load(&url, source).await.map(Some)
+            }
+        }
+    }
+}
+
+/// 按配置的来源顺序获取图标，`size` 为输出边长
+/// Fetch an icon from the configured sources in order; `size` is the output side length
+pub async fn fetch(domain: &str, size: u32) -> Option<Icon> {
+    let mut run = Fetch { domain, size: size.clamp(MIN_SIZE, MAX_SIZE), homepage: None };
+    let mut best: Option<Icon> = None;
+    for source in SOURCES.iter().copied() {
+        match run.try_source(source).await {
+            Ok(Some(icon)) => {
+                debug!("Icon for {} from {} ({} px)", domain, source.name(), icon.original_size);
+                // SVG 可以任意缩放，视为足够大
+                // SVG scales freely and counts as large enough
+                let large_enough = icon.original_size == 0 || icon.original_size >= run.size;
+                let better = best.as_ref().is_none_or(|b| b.original_size != 0 && icon.original_size > b.original_size);
+                if large_enough {
+                    return Some(icon);
+                }
+                if better {
+                    best = Some(icon);
+                }
+            }
+            Ok(None) => debug!("No icon for {} from {}", domain, source.name()),
+            Err(e) => debug!("Icon source {} failed for {}: {}", source.name(), domain, e),
+        }
+    }
+    best
+}
+
+/// 在启动时确定来源顺序并输出
+/// Determine and log the source order at startup
+pub fn init() {
+    Lazy::force(&SOURCES);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use image::Rgba;
+    use std::io::Write;
+
+    const SVG: &[u8] = br##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 16 16"><circle cx="8" cy="8" r="6" fill="#0a0"/></svg>"##;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn urls(candidates: &[Candidate]) -> Vec<(&str, u32)> {
+        candidates.iter().map(|c| (c.url.as_str(), c.size)).collect()
+    }
+
+    #[test]
+    fn sniffs_formats() {
+        let raster = |format| Some(Kind::Raster(format));
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), raster(ImageFormat::Png));
+        assert_eq!(sniff(b"\x00\x00\x01\x00\x01\x00"), raster(ImageFormat::Ico));
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0"), raster(ImageFormat::Jpeg));
+        assert_eq!(sniff(b"GIF89a"), raster(ImageFormat::Gif));
+        assert_eq!(sniff(b"BM...."), raster(ImageFormat::Bmp));
+        assert_eq!(sniff(b"RIFF\x10\x00\x00\x00WEBPVP8 "), raster(ImageFormat::WebP));
+        assert_eq!(sniff(b"RIFF\x10\x00\x00\x00WAVEfmt "), None);
+
+        assert_eq!(sniff(SVG), Some(Kind::Svg));
+        assert_eq!(sniff(b"\xef\xbb\xbf\n  <?xml version=\"1.0\"?>\n<!-- logo -->\n<svg></svg>"), Some(Kind::Svg));
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?><feed></feed>"), None);
+        assert_eq!(sniff(b"<!DOCTYPE html><html><body>Not found</body></html>"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    /// 只有解压后是 SVG 的 gzip 才是 svgz
+    /// Only gzip content that decompresses to an SVG is svgz
+    #[test]
+    fn sniffs_compressed_svg() {
+        let svgz = gzip(SVG);
+        assert_eq!(sniff(&svgz), Some(Kind::Svg));
+        assert_eq!(sniff(&gzip(b"<!DOCTYPE html><html><body>Not found</body></html>")), None);
+        assert_eq!(sniff(&gzip(b"\x89PNG\r\n\x1a\n")), None);
+        assert_eq!(sniff(&svgz[..6]), None);
+
+        let (image, side) = decode(&svgz, Kind::Svg, 32).unwrap();
+        assert_eq!((image.width(), image.height(), side), (32, 32, 0));
+    }
+
+    /// SVG 不能引用服务器上的本地文件，内嵌的 data: 图片仍然可用
+    /// SVGs cannot reference local files on the server, while embedded data: images still work
+    #[test]
+    fn ignores_local_image_references() {
+        use base64::Engine;
+
+        let dir = std::env::temp_dir().join(format!("wallet-os-svg-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let red = RgbaImage::from_pixel(16, 16, Rgba([255, 0, 0, 255]));
+        let png = dir.join("secret.png");
+        DynamicImage::ImageRgba8(red).save_with_format(&png, ImageFormat::Png).unwrap();
+        let nested = br##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 16 16"><rect width="16" height="16" fill="#f00"/></svg>"##;
+        let svg = dir.join("secret.svg");
+        std::fs::write(&svg, nested).unwrap();
+
+        let visible = |bytes: &[u8]| {
+            let (image, _) = decode(bytes, Kind::Svg, 32).unwrap();
+            image.to_rgba8().pixels().filter(|p| p[3] > 0).count()
+        };
+        for path in [&png, &svg] {
+            let referencing = format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16"><image href="{}" width="16" height="16"/></svg>"#,
+                path.display()
+            );
+            assert_eq!(visible(referencing.as_bytes()), 0, "{}", path.display());
+        }
+        let embedded = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16"><image href="data:image/svg+xml;base64,{}" width="16" height="16"/></svg>"#,
+            base64::engine::general_purpose::STANDARD.encode(nested)
+        );
+        assert_eq!(visible(embedded.as_bytes()), 32 * 32);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// 多个不同尺寸的 link 图标按尺寸从大到小排列，相对地址按首页地址解析
+    /// Link icons of several sizes are ordered largest first, with relative addresses resolved
+    /// against the homepage
+    #[test]
+    fn parses_homepage_icons() {
+        let base = url::Url::parse("https://www.example.com/app/").unwrap();
+        let homepage = parse_homepage(&base, include_str!("../tests/fixtures/icons/homepage.html"));
+        assert_eq!(
+            urls(&homepage.icons),
+            vec![
+                ("https://cdn.example.net/logo.svg", 1024),
+                ("https://www.example.com/apple-touch-icon.png", 180),
+                ("https://www.example.com/apple-120.png", 120),
+                ("https://www.example.com/app/favicon-96.png", 96),
+                ("https://www.example.com/favicon-32.png", 32),
+                ("https://www.example.com/favicon.ico", 32),
+                ("https://www.example.com/favicon-16.png", 16),
+            ]
+        );
+        assert_eq!(homepage.manifest.as_ref().map(url::Url::as_str), Some("https://www.example.com/app/site.webmanifest"));
+        assert!(parse_homepage(&base, "<html><body>No icons</body></html>").icons.is_empty());
+    }
+
+    /// Manifest 中的相对地址按 Manifest 自身的地址解析，单色图标被跳过
+    /// Relative addresses in a manifest are resolved against the manifest itself, and monochrome
+    /// icons are skipped
+    #[test]
+    fn parses_manifest_icons() {
+        let base = url::Url::parse("https://www.example.com/app/site.webmanifest").unwrap();
+        let icons = parse_manifest(&base, include_bytes!("../tests/fixtures/icons/manifest.json"));
+        assert_eq!(
+            urls(&icons),
+            vec![
+                ("https://cdn.example.net/any.svg", 1024),
+                ("https://www.example.com/shared/512.png", 512),
+                ("https://www.example.com/app/icons/192.png", 192),
+                ("https://www.example.com/app/icons/unsized.png", 0),
+            ]
+        );
+        assert!(parse_manifest(&base, b"<html>not json</html>").is_empty());
+        assert!(parse_manifest(&base, b"{\"icons\": \"none\"}").is_empty());
+    }
+
+    #[test]
+    fn detects_placeholders() {
+        let image = |f: &dyn Fn(u32, u32) -> Rgba<u8>| DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, f));
+        let transparent = image(&|_, _| Rgba([0, 0, 0, 0]));
+        let mostly_transparent = image(&|x, y| if x + y < 2 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 0, 0]) });
+        let single_colour = image(&|x, _| Rgba([200, 200, 200 + (x % 4) as u8, 255]));
+        let logo = image(&|x, _| if x < 16 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) });
+        let framed = image(&|x, y| match (x, y) {
+            (8..=23, 8..=23) => Rgba([0, 128, 0, 255]),
+            (4..=27, 4..=27) => Rgba([255, 255, 255, 255]),
+            _ => Rgba([0, 0, 0, 0]),
+        });
+        assert!(is_placeholder(b"transparent", &transparent));
+        assert!(is_placeholder(b"mostly transparent", &mostly_transparent));
+        assert!(is_placeholder(b"single colour", &single_colour));
+        assert!(!is_placeholder(b"logo", &logo));
+        assert!(!is_placeholder(b"framed", &framed));
+    }
+}
@@ -0,0 +1,542 @@
+//! 消费洞察模块
+//! Spending insights module
+//!
+//! 基于规则、结果确定的消费分析：同类重复订阅、适合改为年付的月付订阅、即将续费、涨价，
+//! 以及闲置或已暂停的订阅。结果以结构化的发现返回，同时作为已确认事实写入 LLM 提示词，
+//! 使模型建议有据可依。
+//! Deterministic, rule-based spending analysis: duplicate services in the same category,
+//! monthly plans that would be cheaper as yearly, upcoming renewals, price increases, and idle
+//! or paused subscriptions. Results are returned as structured findings and are also written
+//! into the LLM prompt as confirmed facts, so that the model's advice is grounded.
+
+use crate::auth::{AuthUser, Role};
+use crate::dates;
+use crate::db::DbPool;
+use crate::events::{self, Audience, EventKind, Subject};
+use crate::households::VISIBLE_TO_USER;
+use crate::icon_cache;
+use crate::models::{Finding, Subscription};
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use chrono::{Days, NaiveDate};
+use sqlx::{QueryBuilder, Sqlite};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// 即将续费的提醒窗口 (天)
+/// Reminder window for upcoming renewals (days)
+const RENEWAL_WINDOW_DAYS: i64 = 7;
+
+/// 检查续费提醒的间隔
+/// Interval for checking renewal reminders
+const REMINDER_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// 下次付款日期过去超过该天数仍未更新，视为闲置
+/// A subscription whose next payment date passed more than this many days ago counts as idle
+const IDLE_AFTER_DAYS: i64 = 30;
+
+/// 连续月付达到该天数后建议改为年付
+/// Suggest switching to yearly after paying monthly for this many days
+const YEARLY_AFTER_DAYS: i64 = 180;
+
+/// 年付相对月付的常见折扣 (约等于免费两个月)；只是经验值，据此算出的节省标记为估算
+/// Typical yearly discount compared to paying monthly (about two months free); only a rule of
+/// thumb, so savings derived from it are marked as estimated
+const YEARLY_DISCOUNT: f64 = 2.0 / 12.0;
+
+/// 涨价提醒的回溯天数
+/// How far back price increases are reported (days)
+const PRICE_INCREASE_LOOKBACK_DAYS: i64 = 365;
+
+/// 未设置分类时按名称推断的内置分类表
+/// Built-in categories inferred from the name when no category is set
+pub const KNOWN_CATEGORIES: &[(&str, &[&str])] = &[
+    ("video", &["netflix", "disney", "hulu", "hbo", "prime video", "youtube premium", "apple tv", "爱奇艺", "腾讯视频", "优酷", "芒果tv", "bilibili", "哔哩哔哩"]),
+    ("music", &["spotify", "apple music", "tidal", "deezer", "youtube music", "qq音乐", "网易云音乐", "酷狗"]),
+    ("cloud", &["icloud", "google one", "dropbox", "onedrive", "百度网盘", "阿里云盘"]),
+    ("ai", &["chatgpt", "claude", "copilot", "gemini", "midjourney", "perplexity"]),
+];
+
+/// 价格变更记录
+/// Price change record
+#[derive(Debug, sqlx::FromRow)]
+pub struct PriceChange {
+    pub subscription_id: i64,
+    pub old_price: f64,
+    pub new_price: f64,
+    pub currency: String,
+    pub changed_at: String,
+}
+
+/// 订阅的分类：优先使用用户设置，否则按名称推断
+/// A subscription's category: the user's setting if present, otherwise inferred from the name
+pub fn category_of(sub: &Subscription) -> Option<String> {
+    if let Some(category) = sub.category.as_deref().map(str::trim).filter(|c| !c.is_empty()) {
+        return Some(category.to_lowercase());
+    }
+    infer_category(&sub.name).map(str::to_string)
+}
+
+/// 按名称推断内置分类
+/// Infer a built-in category from a name
+pub fn infer_category(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    KNOWN_CATEGORIES
+        .iter()
+        .find(|(_, keywords)| keywords.iter().any(|k| name.contains(k)))
+        .map(|(category, _)| *category)
+}
+
+fn parse_date(value: Option<&str>) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value?.get(..10)?, "%Y-%m-%d").ok()
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn finding(kind: &'static str, severity: &'static str, ids: Vec<i64>, message: String) -> Finding {
+    Finding { kind, severity, subscription_ids: ids, message, monthly_amount: None, currency: None, estimated: false }
+}
+
+/// 根据订阅与价格变更计算发现 (纯函数，结果只取决于输入与 `today`)
+/// Compute findings from subscriptions and price changes (pure: the result depends only on the
+/// inputs and `today`)
+pub fn evaluate(subs: &[Subscription], changes: &[PriceChange], today: NaiveDate) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut by_category: BTreeMap<String, Vec<&Subscription>> = BTreeMap::new();
+
+    for sub in subs {
+        // 已暂停的订阅只提示一次，不参与其他规则
+        // Paused subscriptions are reported once and skip the other rules
+        if !sub.active {
+            findings.push(finding("paused", "info", vec![sub.id], format!("{} 已暂停，如不再需要可直接删除。", sub.name)));
+            continue;
+        }
+        if sub.frequency == 0 {
+            continue;
+        }
+        if let Some(category) = category_of(sub) {
+            by_category.entry(category).or_default().push(sub);
+        }
+
+        let mut idle = false;
+        if let Some(next) = parse_date(sub.next_payment.as_deref()) {
+            let days = (next - today).num_days();
+            if (0..=RENEWAL_WINDOW_DAYS).contains(&days) {
+                let when = if days == 0 { "今天".to_string() } else { format!(" {} 天后", days) };
+                findings.push(finding(
+                    "renewal_soon",
+                    "info",
+                    vec![sub.id],
+                    format!("{} 将在{}（{}）续费 {} {}。", sub.name, when, next, sub.price, sub.currency),
+                ));
+            } else if days < -IDLE_AFTER_DAYS {
+                idle = true;
+                findings.push(finding(
+                    "idle",
+                    "warning",
+                    vec![sub.id],
+                    format!("{} 的下次付款日期 {} 已过去 {} 天仍未更新，可能已闲置或忘记续费。", sub.name, next, -days),
+                ));
+            }
+        }
+
+        // 闲置的订阅不再建议改为年付
+        // Idle subscriptions are not suggested for a yearly plan
+        if sub.frequency == 1 && !idle {
+            if let Some(start) = parse_date(sub.start_date.as_deref()) {
+                let held = (today - start).num_days();
+                if held >= YEARLY_AFTER_DAYS {
+                    let saving = round2(sub.price * YEARLY_DISCOUNT);
+                    findings.push(Finding {
+                        monthly_amount: Some(saving),
+                        currency: Some(sub.currency.clone()),
+                        estimated: true,
+                        ..finding(
+                            "yearly_savings",
+                            "info",
+                            vec![sub.id],
+                            format!(
+                                "{} 已连续月付约 {} 个月，若改为年付，按通常约省两个月费用估算，每月约可节省 {} {}（以服务商实际年付价格为准）。",
+                                sub.name,
+                                held / 30,
+                                saving,
+                                sub.currency
+                            ),
+                        )
+                    });
+                }
+            }
+        }
+    }
+
+    for (category, group) in &by_category {
+        if group.len() < 2 {
+            continue;
+        }
+        let names: Vec<&str> = group.iter().map(|s| s.name.as_str()).collect();
+        let single_currency = group.iter().all(|s| s.currency == group[0].currency);
+        // 预计节省：保留最贵的一个，取消其余 (仅在币种一致时计算)
+        // Estimated saving: keep the most expensive one and cancel the rest (only with a single currency)
+        let (amount, currency) = if single_currency {
+            let mut costs: Vec<f64> = group.iter().map(|s| s.monthly_cost()).collect();
+            costs.sort_by(|a, b| b.total_cmp(a));
+            (Some(round2(costs[1..].iter().sum())), Some(group[0].currency.clone()))
+        } else {
+            (None, None)
+        };
+        findings.push(Finding {
+            monthly_amount: amount,
+            currency,
+            ..finding(
+                "duplicate_category",
+                "warning",
+                group.iter().map(|s| s.id).collect(),
+                format!("同类订阅重复（{}）：{}，可考虑只保留一个。", category, names.join("、")),
+            )
+        });
+    }
+
+    for change in changes.iter().filter(|c| c.new_price > c.old_price) {
+        let Some(sub) = subs.iter().find(|s| s.id == change.subscription_id) else {
+            continue;
+        };
+        let Some(changed) = parse_date(Some(&change.changed_at)) else {
+            continue;
+        };
+        if (today - changed).num_days() > PRICE_INCREASE_LOOKBACK_DAYS {
+            continue;
+        }
+        let percent = if change.old_price > 0.0 {
+            format!("，涨幅 {:.0}%", (change.new_price - change.old_price) / change.old_price * 100.0)
+        } else {
+            String::new()
+        };
+        let per_month = if sub.frequency > 0 { sub.frequency as f64 } else { 1.0 };
+        findings.push(Finding {
+            monthly_amount: Some(round2((change.new_price - change.old_price) / per_month)),
+            currency: Some(change.currency.clone()),
+            ..finding(
+                "price_increase",
+                "warning",
+                vec![sub.id],
+                format!(
+                    "{} 于 {} 从 {} 涨价到 {} {}{}。",
+                    sub.name, changed, change.old_price, change.new_price, change.currency, percent
+                ),
+            )
+        });
+    }
+
+    findings
+}
+
+/// 计算用户可见订阅的发现
+/// Compute findings for the subscriptions visible to a user
+pub async fn compute(pool: &DbPool, user_id: i64, today: NaiveDate) -> Result<Vec<Finding>, sqlx::Error> {
+    let subs = sqlx::query_as::<_, Subscription>(&format!("SELECT * FROM subscriptions WHERE {}", VISIBLE_TO_USER))
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    let changes = price_changes(pool, &subs).await?;
+    Ok(evaluate(&subs, &changes, today))
+}
+
+/// 每条查询绑定的订阅 ID 上限，远低于 SQLite 的限制
+/// Subscription ids bound per query, well below SQLite's limit
+const BATCH_SIZE: usize = 400;
+
+/// 读取已查出的订阅的价格变更 (按 ID 批量查询，不再重复检查可见性)
+/// Read the price changes of subscriptions already loaded (queried by id in batches, without
+/// checking visibility again)
+async fn price_changes(pool: &DbPool, subs: &[Subscription]) -> Result<Vec<PriceChange>, sqlx::Error> {
+    let ids: Vec<i64> = subs.iter().map(|s| s.id).collect();
+    let mut changes = Vec::new();
+    for batch in ids.chunks(BATCH_SIZE) {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT subscription_id, old_price, new_price, currency, changed_at FROM price_changes WHERE subscription_id IN (",
+        );
+        let mut list = query.separated(", ");
+        for id in batch {
+            list.push_bind(*id);
+        }
+        query.push(")");
+        changes.extend(query.build_query_as::<PriceChange>().fetch_all(pool).await?);
+    }
+    changes.sort_by(|a, b| a.changed_at.cmp(&b.changed_at));
+    Ok(changes)
+}
+
+/// 将发现渲染为 Markdown 列表，用作提示词中的事实或 Mock 模式的建议
+/// Render findings as a Markdown list, used as prompt facts or as mock-mode advice
+pub fn to_markdown(findings: &[Finding]) -> String {
+    if findings.is_empty() {
+        return "- 暂未发现重复订阅、涨价、即将续费或闲置的订阅。".to_string();
+    }
+    findings.iter().map(|f| format!("- {}", f.message)).collect::<Vec<_>>().join("\n")
+}
+
+/// 消费洞察 (GET /api/insights)
+/// Spending insights
+pub async fn list_insights(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<Vec<Finding>>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+
+    compute(&pool, user.user_id, dates::today())
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// 启动后台任务：每天为进入提醒窗口与当天续费的订阅推送 `reminder.due` 事件
+/// Start the background task that pushes `reminder.due` events each day for subscriptions
+/// entering the reminder window and those renewing that day
+///
+/// 已推送的提醒按 (订阅, 日期) 记录在数据库中，服务当天重启不会重复推送。
+/// Pushed reminders are recorded by (subscription, date) in the database, so restarting the
+/// server the same day does not push them again.
+pub fn start_reminder_task(pool: DbPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REMINDER_CHECK_INTERVAL);
+        let mut last_run = None;
+        loop {
+            interval.tick().await;
+            let today = dates::today();
+            if last_run == Some(today) {
+                continue;
+            }
+            match publish_reminders(&pool, today).await {
+                Ok(count) => {
+                    last_run = Some(today);
+                    if count > 0 {
+                        info!("Published {} renewal reminder(s)", count);
+                    }
+                }
+                Err(e) => warn!("Failed to check renewal reminders: {}", e),
+            }
+        }
+    });
+}
+
+/// 推送当天的续费提醒，返回推送的数量；当天已推送过的订阅 (记录在 `reminders_sent` 中) 会被跳过
+/// Push the day's renewal reminders, returning how many were pushed; subscriptions already
+/// reminded that day (recorded in `reminders_sent`) are skipped
+async fn publish_reminders(pool: &DbPool, today: NaiveDate) -> Result<usize, sqlx::Error> {
+    let window_end = today + Days::new(RENEWAL_WINDOW_DAYS as u64);
+    let sent_on = today.format("%Y-%m-%d").to_string();
+    let mut subs = sqlx::query_as::<_, Subscription>(
+        "SELECT * FROM subscriptions WHERE active = 1 AND frequency != 0 AND substr(next_payment, 1, 10) IN (?, ?) \
+         AND id NOT IN (SELECT subscription_id FROM reminders_sent WHERE sent_on = ?)",
+    )
+    .bind(&sent_on)
+    .bind(window_end.format("%Y-%m-%d").to_string())
+    .bind(&sent_on)
+    .fetch_all(pool)
+    .await?;
+    icon_cache::attach_colors(pool, &mut subs).await;
+    let mut published = 0;
+    for sub in &subs {
+        // 先记录再推送：记录失败时宁可漏推，也不重复推送
+        // Record before pushing: when recording fails, skip the reminder rather than repeat it
+        let recorded = sqlx::query("INSERT OR IGNORE INTO reminders_sent (subscription_id, sent_on) VALUES (?, ?)")
+            .bind(sub.id)
+            .bind(&sent_on)
+            .execute(pool)
+            .await?;
+        if recorded.rows_affected() == 0 {
+            continue;
+        }
+        let days_left = parse_date(sub.next_payment.as_deref()).map_or(0, |next| (next - today).num_days());
+        events::publish(
+            EventKind::ReminderDue,
+            Audience::of(sub),
+            Some(Subject::of(sub)),
+            serde_json::json!({ "subscription": sub, "days_left": days_left }),
+        );
+        published += 1;
+    }
+    sqlx::query("DELETE FROM reminders_sent WHERE sent_on < ?")
+        .bind((today - Days::new(RENEWAL_WINDOW_DAYS as u64)).format("%Y-%m-%d").to_string())
+        .execute(pool)
+        .await?;
+    Ok(published)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use serde_json::json;
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 3, 10).unwrap()
+    }
+
+    /// 月付 9.99 USD 的订阅，`fields` 覆盖其中的字段
+    /// A monthly 9.99 USD subscription, with `fields` overriding its fields
+    fn sub(id: i64, name: &str, fields: serde_json::Value) -> Subscription {
+        let mut value = json!({
+            "id": id, "name": name, "price": 9.99, "currency": "USD", "frequency": 1,
+            "next_payment": "2026-04-01", "active": true, "owner_id": 1,
+        });
+        value.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn change(subscription_id: i64, old_price: f64, new_price: f64, changed_at: &str) -> PriceChange {
+        PriceChange { subscription_id, old_price, new_price, currency: "USD".to_string(), changed_at: changed_at.to_string() }
+    }
+
+    /// 每条规则的触发与边界：(说明, 订阅, 价格变更, 期望的 (类型, 订阅, 每月金额, 是否估算))
+    /// Each rule and its boundaries: (case, subscriptions, price changes, expected (kind,
+    /// subscriptions, monthly amount, estimated))
+    #[test]
+    fn evaluates_rules() {
+        type Expected = Vec<(&'static str, Vec<i64>, Option<f64>, bool)>;
+        let cases: Vec<(&str, Vec<Subscription>, Vec<PriceChange>, Expected)> = vec![
+            ("nothing to report", vec![sub(1, "Gym", json!({}))], vec![], vec![]),
+            (
+                "paused subscriptions skip the other rules",
+                vec![sub(1, "Gym", json!({ "active": false, "next_payment": "2026-03-10" }))],
+                vec![],
+                vec![("paused", vec![1], None, false)],
+            ),
+            ("lifetime purchases are ignored", vec![sub(1, "Gym", json!({ "frequency": 0, "next_payment": "2026-03-10" }))], vec![], vec![]),
+            (
+                "renewals today and at the end of the window",
+                vec![
+                    sub(1, "Gym", json!({ "next_payment": "2026-03-10" })),
+                    sub(2, "Club", json!({ "next_payment": "2026-03-17" })),
+                    sub(3, "Pool", json!({ "next_payment": "2026-03-18" })),
+                ],
+                vec![],
+                vec![("renewal_soon", vec![1], None, false), ("renewal_soon", vec![2], None, false)],
+            ),
+            (
+                "idle after 30 days past the payment date",
+                vec![
+                    sub(1, "Gym", json!({ "next_payment": "2026-02-07" })),
+                    sub(2, "Club", json!({ "next_payment": "2026-02-08" })),
+                ],
+                vec![],
+                vec![("idle", vec![1], None, false)],
+            ),
+            (
+                "yearly savings after paying monthly for 180 days are estimated",
+                vec![
+                    sub(1, "Gym", json!({ "price": 12.0, "start_date": "2025-09-11" })),
+                    sub(2, "Club", json!({ "start_date": "2025-09-12" })),
+                    sub(3, "Pool", json!({ "start_date": "2025-01-01", "frequency": 12 })),
+                ],
+                vec![],
+                vec![("yearly_savings", vec![1], Some(2.0), true)],
+            ),
+            (
+                "idle subscriptions get no yearly suggestion",
+                vec![sub(1, "Gym", json!({ "start_date": "2025-01-01", "next_payment": "2026-01-01" }))],
+                vec![],
+                vec![("idle", vec![1], None, false)],
+            ),
+            (
+                "duplicate categories save all but the most expensive",
+                vec![
+                    sub(1, "Netflix", json!({ "price": 15.0 })),
+                    sub(2, "Disney+", json!({ "price": 8.0 })),
+                    sub(3, "Hulu", json!({ "price": 36.0, "frequency": 3 })),
+                    sub(4, "Spotify", json!({})),
+                ],
+                vec![],
+                vec![("duplicate_category", vec![1, 2, 3], Some(20.0), false)],
+            ),
+            (
+                "no duplicate saving across currencies",
+                vec![sub(1, "Netflix", json!({})), sub(2, "爱奇艺", json!({ "currency": "CNY", "price": 25.0 }))],
+                vec![],
+                vec![("duplicate_category", vec![1, 2], None, false)],
+            ),
+            (
+                "explicit categories win over inferred ones",
+                vec![sub(1, "Netflix", json!({ "category": "Family" })), sub(2, "Spotify", json!({ "category": "family" }))],
+                vec![],
+                vec![("duplicate_category", vec![1, 2], Some(9.99), false)],
+            ),
+            (
+                "price increases within a year, per month",
+                vec![sub(1, "Gym", json!({})), sub(2, "Club", json!({ "frequency": 12, "price": 120.0 }))],
+                vec![
+                    change(1, 8.99, 9.99, "2026-01-01 10:00:00"),
+                    change(1, 10.99, 9.99, "2026-02-01 10:00:00"),
+                    change(2, 96.0, 120.0, "2025-06-01 10:00:00"),
+                    change(2, 60.0, 96.0, "2025-03-09 10:00:00"),
+                    change(3, 1.0, 2.0, "2026-03-01 10:00:00"),
+                ],
+                vec![("price_increase", vec![1], Some(1.0), false), ("price_increase", vec![2], Some(2.0), false)],
+            ),
+        ];
+        for (case, subs, changes, expected) in cases {
+            let found: Expected = evaluate(&subs, &changes, today())
+                .into_iter()
+                .map(|f| (f.kind, f.subscription_ids, f.monthly_amount, f.estimated))
+                .collect();
+            assert_eq!(found, expected, "{}", case);
+        }
+    }
+
+    /// 价格变更只读取可见订阅的记录
+    /// Only the price changes of visible subscriptions are read
+    #[tokio::test]
+    async fn computes_findings_for_visible_subscriptions() {
+        let pool = db::test_pool().await;
+        let other = sqlx::query("INSERT INTO users (username) VALUES ('other')").execute(&pool).await.unwrap().last_insert_rowid();
+        for owner_id in [1, other] {
+            let id = sqlx::query(
+                "INSERT INTO subscriptions (name, price, currency, next_payment, frequency, active, owner_id) \
+                 VALUES ('Gym', 9.99, 'USD', '2026-04-01', 1, 1, ?)",
+            )
+            .bind(owner_id)
+            .execute(&pool)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+            sqlx::query(
+                "INSERT INTO price_changes (subscription_id, old_price, new_price, currency, changed_at) \
+                 VALUES (?, 8.99, 9.99, 'USD', '2026-01-01 10:00:00')",
+            )
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let findings = compute(&pool, 1, today()).await.unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].kind, "price_increase");
+    }
+
+    /// 同一天再次检查 (例如服务重启后) 不会重复推送提醒
+    /// Checking again the same day (for example after a restart) does not push reminders twice
+    #[tokio::test]
+    async fn publishes_reminders_once_per_day() {
+        let pool = db::test_pool().await;
+        let today = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap();
+        for (name, next_payment) in [("Today", "2026-03-10"), ("Next week", "2026-03-17"), ("Later", "2026-03-20")] {
+            sqlx::query(
+                "INSERT INTO subscriptions (name, price, currency, next_payment, frequency, active, owner_id) \
+                 VALUES (?, 9.99, 'USD', ?, 1, 1, 1)",
+            )
+            .bind(name)
+            .bind(next_payment)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(publish_reminders(&pool, today).await.unwrap(), 2);
+        assert_eq!(publish_reminders(&pool, today).await.unwrap(), 0);
+        assert_eq!(publish_reminders(&pool, today + Days::new(1)).await.unwrap(), 0);
+        assert_eq!(publish_reminders(&pool, NaiveDate::from_ymd_opt(2026, 3, 13).unwrap()).await.unwrap(), 1);
+    }
+}
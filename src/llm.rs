@@ -0,0 +1,1026 @@
+//! LLM 提供方抽象模块
+//! LLM provider abstraction module
+//!
+//! 所有 AI 功能通过 `LlmProvider` trait 调用模型，后端在启动时根据环境变量选择一次：
+//! OpenAI 兼容接口 (`/chat/completions`)、Ollama 原生接口 (`/api/chat`) 或 Anthropic 风格的
+//! Messages 接口 (`/messages`)。所有后端共享同一个 HTTP 客户端，并统一处理超时、重试与错误映射。
+//! Every AI feature talks to the model through the `LlmProvider` trait. The backend is selected
+//! once at startup from environment variables: an OpenAI-compatible API (`/chat/completions`),
+//! the native Ollama API (`/api/chat`) or an Anthropic-style Messages API (`/messages`). All
+//! backends share one HTTP client and handle timeouts, retries and error mapping the same way.
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// 所有后端共享的 HTTP 客户端 (超时按请求单独设置)
+/// HTTP client shared by all backends (timeouts are set per request)
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to build LLM HTTP client")
+});
+
+/// 启动时选定的提供方；未配置时为 `None`，调用方应降级为 Mock 模式
+/// Provider selected at startup; `None` when unconfigured, in which case callers fall back to mock mode
+static PROVIDER: Lazy<Option<Box<dyn LlmProvider>>> = Lazy::new(|| {
+    let provider = load_provider();
+    match &provider {
+        Some(p) => info!("LLM provider: {} (model {})", p.name(), p.model()),
+        None => info!("LLM provider not configured, AI features run in mock mode"),
+    }
+    provider
+});
+
+/// 调用 LLM 时可能出现的错误
+/// Errors that can occur when calling an LLM
+#[derive(Debug)]
+pub enum LlmError {
+    /// 请求超时 / The request timed out
+    Timeout,
+    /// 网络或连接错误 / Network or connection error
+    Http(String),
+    /// 服务端返回非 2xx 状态码 / The server returned a non-2xx status
+    Status { status: u16, body: String },
+    /// 响应无法解析或缺少内容 / The response could not be parsed or had no content
+    InvalidResponse(String),
+    /// 流式输出的接收方已断开 / The receiver of a streamed reply went away
+    Cancelled,
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::Timeout => write!(f, "LLM request timed out"),
+            LlmError::Http(e) => write!(f, "LLM request failed: {}", e),
+            LlmError::Status { status, body } => write!(f, "LLM returned HTTP {}: {}", status, body),
+            LlmError::InvalidResponse(e) => write!(f, "Invalid LLM response: {}", e),
+            LlmError::Cancelled => write!(f, "LLM request cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+/// 对话消息
+/// Chat message
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    pub role: &'static str,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        ChatMessage { role: "system", content: content.into() }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        ChatMessage { role: "user", content: content.into() }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        ChatMessage { role: "assistant", content: content.into() }
+    }
+}
+
+/// 结构化输出约束：要求模型返回符合 JSON Schema 的对象
+/// Structured output constraint: the model must return an object matching a JSON Schema
+#[derive(Debug, Clone)]
+pub struct OutputSchema {
+    /// 模式名称 (OpenAI `json_schema.name` / Anthropic 工具名)
+    /// Schema name (OpenAI `json_schema.name` / Anthropic tool name)
+    pub name: &'static str,
+    pub schema: serde_json::Value,
+}
+
+/// 对话请求
+/// Chat request
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub messages: Vec<ChatMessage>,
+    pub temperature: f32,
+    /// 输出模式约束，仅当 `supports_schema()` 为真时设置
+    /// Output schema constraint; only set when `supports_schema()` is true
+    pub schema: Option<OutputSchema>,
+}
+
+/// 单次请求的 token 用量
+/// Token usage of a single request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+}
+
+impl Usage {
+    /// 从响应 JSON 中读取两个计数字段，均缺失时返回 `None`
+    /// Read the two counters from response JSON; returns `None` when both are missing
+    fn from_json(prompt: &serde_json::Value, completion: &serde_json::Value) -> Option<Usage> {
+        if prompt.is_null() && completion.is_null() {
+            return None;
+        }
+        Some(Usage {
+            prompt_tokens: prompt.as_i64().unwrap_or_default(),
+            completion_tokens: completion.as_i64().unwrap_or_default(),
+        })
+    }
+}
+
+/// 模型回复：文本与提供方报告的用量 (部分本地服务不报告用量)
+/// Model reply: the text plus the usage reported by the provider (some local servers report none)
+#[derive(Debug, Clone, Default)]
+pub struct Completion {
+    pub content: String,
+    pub usage: Option<Usage>,
+}
+
+/// 流式增量回调：返回 `false` 表示接收方已断开
+/// Streaming delta callback: returning `false` means the receiver has gone away
+pub type OnDelta<'a> = dyn FnMut(&str) -> bool + Send + 'a;
+
+/// LLM 提供方
+/// LLM provider
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// 后端名称，用于日志 / Backend name, used for logging
+    fn name(&self) -> &'static str;
+
+    /// 使用的模型 / Model in use
+    fn model(&self) -> &str;
+
+    /// 是否启用结构化输出 (JSON Schema / 函数调用)
+    /// Whether structured output (JSON Schema / function calling) is enabled
+    fn supports_schema(&self) -> bool;
+
+    /// 发送对话并返回模型回复的文本；使用结构化输出时返回 JSON 文本
+    /// Send a chat and return the text of the model's reply; with structured output this is JSON text
+    async fn chat(&self, request: &ChatRequest) -> Result<Completion, LlmError>;
+
+    /// 流式对话：每收到一段增量文本就调用 `on_delta`，返回完整回复。
+    /// `on_delta` 返回 `false` 表示接收方已断开，此时中止请求并返回 `LlmError::Cancelled`。
+    /// Streaming chat: `on_delta` is called with each text delta as it arrives and the full reply is
+    /// returned. When `on_delta` returns `false` the receiver has gone away, so the request is
+    /// aborted with `LlmError::Cancelled`.
+    async fn chat_stream(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut OnDelta<'_>,
+    ) -> Result<Completion, LlmError>;
+}
+
+/// 获取当前配置的提供方
+/// Get the configured provider
+pub fn provider() -> Option<&'static dyn LlmProvider> {
+    PROVIDER.as_deref()
+}
+
+/// 在启动时加载配置并输出所选后端
+/// Load the configuration at startup and log the selected backend
+pub fn init() {
+    Lazy::force(&PROVIDER);
+}
+
+/// 各后端共用的连接参数
+/// Connection settings shared by every backend
+struct Endpoint {
+    api_base: String,
+    api_key: Option<String>,
+    model: String,
+    timeout: Duration,
+    max_retries: u32,
+    structured_output: bool,
+}
+
+impl Endpoint {
+    /// 发送 JSON 请求；超时、连接失败、429 与 5xx 会按指数退避重试
+    /// Send a JSON request; timeouts, connection failures, 429 and 5xx are retried with exponential backoff
+    async fn post_json(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &serde_json::Value,
+    ) -> Result<serde_json::Value, LlmError> {
+        self.send(path, headers, body, true)
+            .await?
+            .json()
+            .await
+            .map_err(|e| LlmError::InvalidResponse(e.to_string()))
+    }
+
+    /// 发送流式请求并返回逐行读取器；重试只作用于建立连接阶段，读取时按单块超时
+    /// Send a streaming request and return a line reader; retries only cover establishing the
+    /// response, and reading applies the timeout to each chunk
+    async fn post_stream(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &serde_json::Value,
+    ) -> Result<LineReader, LlmError> {
+        let response = self.send(path, headers, body, false).await?;
+        Ok(LineReader { response, buffer: Vec::new(), timeout: self.timeout })
+    }
+
+    async fn send(
+        &self,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &serde_json::Value,
+        whole_request_timeout: bool,
+    ) -> Result<reqwest::Response, LlmError> {
+        let url = format!("{}{}", self.api_base.trim_end_matches('/'), path);
+        let mut attempt = 0;
+        loop {
+            let mut req = CLIENT.post(&url).json(body);
+            if whole_request_timeout {
+                req = req.timeout(self.timeout);
+            }
+            for (name, value) in headers {
+                req = req.header(*name, *value);
+            }
+
+            let send = tokio::time::timeout(self.timeout, req.send());
+            let err = match send.await {
+                Ok(Ok(res)) if res.status().is_success() => return Ok(res),
+                Ok(Ok(res)) => {
+                    let status = res.status();
+                    let body = res.text().await.unwrap_or_default();
+                    let err = LlmError::Status { status: status.as_u16(), body };
+                    if status != reqwest::StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+                        return Err(err);
+                    }
+                    err
+                }
+                Ok(Err(e)) if e.is_timeout() => LlmError::Timeout,
+                Err(_) => LlmError::Timeout,
+                Ok(Err(e)) if e.is_connect() => LlmError::Http(e.to_string()),
+                Ok(Err(e)) => return Err(LlmError::Http(e.to_string())),
+            };
+
+            if attempt >= self.max_retries {
+                return Err(err);
+            }
+            attempt += 1;
+            warn!("{} (attempt {}/{}), retrying", err, attempt, self.max_retries + 1);
+            tokio::time::sleep(backoff(attempt)).await;
+        }
+    }
+}
+
+/// 两次重试之间的最长等待 / Longest wait between two retries
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// 第 `attempt` 次重试前的等待：500ms 起每次翻倍，不超过 `MAX_BACKOFF`
+/// Wait before retry number `attempt`: 500ms doubling each time, at most `MAX_BACKOFF`
+fn backoff(attempt: u32) -> Duration {
+    let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+    Duration::from_millis(500u64.saturating_mul(factor)).min(MAX_BACKOFF)
+}
+
+/// 流式响应的逐行读取器 (SSE 与 NDJSON 均按行分隔)
+/// Line reader over a streamed response (both SSE and NDJSON are line-delimited)
+struct LineReader {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+    timeout: Duration,
+}
+
+impl LineReader {
+    /// 读取下一行 (不含换行符)；响应结束时返回 `None`
+    /// Read the next line (without the newline); returns `None` at the end of the response
+    async fn next_line(&mut self) -> Result<Option<String>, LlmError> {
+        loop {
+            if let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+                return Ok(Some(String::from_utf8_lossy(&line).trim_end().to_string()));
+            }
+            let chunk = tokio::time::timeout(self.timeout, self.response.chunk())
+                .await
+                .map_err(|_| LlmError::Timeout)?
+                .map_err(|e| LlmError::Http(e.to_string()))?;
+            match chunk {
+                Some(bytes) => self.buffer.extend_from_slice(&bytes),
+                None if self.buffer.is_empty() => return Ok(None),
+                None => {
+                    let line = String::from_utf8_lossy(&self.buffer).trim_end().to_string();
+                    self.buffer.clear();
+                    return Ok(Some(line));
+                }
+            }
+        }
+    }
+
+    /// 读取下一个 SSE `data:` 负载，忽略事件名、注释与空行
+    /// Read the next SSE `data:` payload, skipping event names, comments and blank lines
+    async fn next_data(&mut self) -> Result<Option<String>, LlmError> {
+        while let Some(line) = self.next_line().await? {
+            if let Some(data) = line.strip_prefix("data:") {
+                return Ok(Some(data.trim().to_string()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// 解析流式响应中的一段 JSON
+/// Parse one JSON fragment of a streamed response
+fn parse_fragment(data: &str) -> Result<serde_json::Value, LlmError> {
+    serde_json::from_str(data).map_err(|e| LlmError::InvalidResponse(format!("{} in stream fragment", e)))
+}
+
+/// 转发增量文本并累积到完整回复中
+/// Forward a text delta and append it to the full reply
+fn forward(delta: &str, full: &mut String, on_delta: &mut OnDelta<'_>) -> Result<(), LlmError> {
+    if delta.is_empty() {
+        return Ok(());
+    }
+    full.push_str(delta);
+    if on_delta(delta) {
+        Ok(())
+    } else {
+        Err(LlmError::Cancelled)
+    }
+}
+
+/// OpenAI 兼容后端 (OpenAI、DeepSeek、vLLM、llama.cpp server 等)
+/// OpenAI-compatible backend (OpenAI, DeepSeek, vLLM, llama.cpp server, ...)
+struct OpenAiProvider(Endpoint);
+
+impl OpenAiProvider {
+    fn body(&self, request: &ChatRequest, stream: bool) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "model": self.0.model,
+            "messages": request.messages,
+            "temperature": request.temperature,
+            "stream": stream,
+        });
+        if stream {
+            // 要求在最后一个分片中附带用量
+            // Ask for usage in the final chunk
+            body["stream_options"] = serde_json::json!({ "include_usage": true });
+        }
+        if let Some(schema) = request.schema.as_ref() {
+            body["response_format"] = serde_json::json!({
+                "type": "json_schema",
+                "json_schema": { "name": schema.name, "schema": schema.schema },
+            });
+        }
+        body
+    }
+
+    fn auth(&self) -> Option<String> {
+        self.0.api_key.as_ref().map(|key| format!("Bearer {}", key))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.0.model
+    }
+
+    fn supports_schema(&self) -> bool {
+        self.0.structured_output
+    }
+
+    async fn chat(&self, request: &ChatRequest) -> Result<Completion, LlmError> {
+        let auth = self.auth();
+        let headers: Vec<(&str, &str)> = auth.iter().map(|v| ("Authorization", v.as_str())).collect();
+
+        let json = self.0.post_json("/chat/completions", &headers, &self.body(request, false)).await?;
+        let content = json["choices"][0]["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| LlmError::InvalidResponse("missing choices[0].message.content".to_string()))?;
+        let usage = Usage::from_json(&json["usage"]["prompt_tokens"], &json["usage"]["completion_tokens"]);
+        Ok(Completion { content, usage })
+    }
+
+    async fn chat_stream(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut OnDelta<'_>,
+    ) -> Result<Completion, LlmError> {
+        let auth = self.auth();
+        let headers: Vec<(&str, &str)> = auth.iter().map(|v| ("Authorization", v.as_str())).collect();
+
+        let mut reader = self.0.post_stream("/chat/completions", &headers, &self.body(request, true)).await?;
+        let mut full = String::new();
+        let mut usage = None;
+        while let Some(data) = reader.next_data().await? {
+            if data == "[DONE]" {
+                break;
+            }
+            let json = parse_fragment(&data)?;
+            let delta = json["choices"][0]["delta"]["content"].as_str().unwrap_or_default();
+            forward(delta, &mut full, on_delta)?;
+            usage = Usage::from_json(&json["usage"]["prompt_tokens"], &json["usage"]["completion_tokens"]).or(usage);
+        }
+        Ok(Completion { content: full, usage })
+    }
+}
+
+/// Ollama 原生后端 (`/api/chat`)，适合离线的家庭服务器
+/// Native Ollama backend (`/api/chat`), suited to an offline home server
+struct OllamaProvider(Endpoint);
+
+impl OllamaProvider {
+    fn body(&self, request: &ChatRequest, stream: bool) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "model": self.0.model,
+            "messages": request.messages,
+            "stream": stream,
+            "options": { "temperature": request.temperature },
+        });
+        if let Some(schema) = request.schema.as_ref() {
+            body["format"] = schema.schema.clone();
+        }
+        body
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.0.model
+    }
+
+    fn supports_schema(&self) -> bool {
+        self.0.structured_output
+    }
+
+    async fn chat(&self, request: &ChatRequest) -> Result<Completion, LlmError> {
+        let json = self.0.post_json("/api/chat", &[], &self.body(request, false)).await?;
+        let content = json["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| LlmError::InvalidResponse("missing message.content".to_string()))?;
+        let usage = Usage::from_json(&json["prompt_eval_count"], &json["eval_count"]);
+        Ok(Completion { content, usage })
+    }
+
+    /// Ollama 的流式响应为 NDJSON，每行一个对象，最后一行 `done` 为真并携带用量
+    /// Ollama streams NDJSON: one object per line, the last one has `done` set and carries the usage
+    async fn chat_stream(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut OnDelta<'_>,
+    ) -> Result<Completion, LlmError> {
+        let mut reader = self.0.post_stream("/api/chat", &[], &self.body(request, true)).await?;
+        let mut full = String::new();
+        while let Some(line) = reader.next_line().await? {
+            if line.is_empty() {
+                continue;
+            }
+            let json = parse_fragment(&line)?;
+            if let Some(error) = json["error"].as_str() {
+                return Err(LlmError::InvalidResponse(error.to_string()));
+            }
+            forward(json["message"]["content"].as_str().unwrap_or_default(), &mut full, on_delta)?;
+            if json["done"] == true {
+                let usage = Usage::from_json(&json["prompt_eval_count"], &json["eval_count"]);
+                return Ok(Completion { content: full, usage });
+            }
+        }
+        Ok(Completion { content: full, usage: None })
+    }
+}
+
+/// Anthropic 风格的 Messages 后端：系统提示词放在顶层 `system` 字段，且必须指定 `max_tokens`。
+/// 结构化输出通过强制调用单个工具实现，工具参数即为结果。
+/// Anthropic-style Messages backend: the system prompt goes in the top-level `system` field and
+/// `max_tokens` is mandatory. Structured output forces a call to a single tool whose input is
+/// the result.
+struct AnthropicProvider(Endpoint);
+
+impl AnthropicProvider {
+    fn body(&self, request: &ChatRequest, stream: bool) -> serde_json::Value {
+        let (system, messages): (Vec<_>, Vec<_>) =
+            request.messages.iter().partition(|m| m.role == "system");
+        let system = system.iter().map(|m| m.content.as_str()).collect::<Vec<_>>().join("\n\n");
+        let mut body = serde_json::json!({
+            "model": self.0.model,
+            "system": system,
+            "messages": messages,
+            "temperature": request.temperature,
+            "max_tokens": 2048,
+            "stream": stream,
+        });
+        if let Some(schema) = request.schema.as_ref() {
+            body["tools"] = serde_json::json!([{ "name": schema.name, "input_schema": schema.schema }]);
+            body["tool_choice"] = serde_json::json!({ "type": "tool", "name": schema.name });
+        }
+        body
+    }
+
+    fn headers(&self) -> [(&str, &str); 2] {
+        [("x-api-key", self.0.api_key.as_deref().unwrap_or_default()), ("anthropic-version", "2023-06-01")]
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn model(&self) -> &str {
+        &self.0.model
+    }
+
+    fn supports_schema(&self) -> bool {
+        self.0.structured_output
+    }
+
+    async fn chat(&self, request: &ChatRequest) -> Result<Completion, LlmError> {
+        let json = self.0.post_json("/messages", &self.headers(), &self.body(request, false)).await?;
+        let blocks = json["content"]
+            .as_array()
+            .ok_or_else(|| LlmError::InvalidResponse("missing content".to_string()))?;
+        let usage = Usage::from_json(&json["usage"]["input_tokens"], &json["usage"]["output_tokens"]);
+        let tool = blocks.iter().find(|b| b["type"] == "tool_use").filter(|_| request.schema.is_some());
+        let content = match tool {
+            Some(tool) => tool["input"].to_string(),
+            None => blocks
+                .iter()
+                .filter(|b| b["type"] == "text")
+                .filter_map(|b| b["text"].as_str())
+                .collect(),
+        };
+        Ok(Completion { content, usage })
+    }
+
+    /// 流式事件中 `content_block_delta` 携带增量文本，`message_stop` 表示结束；
+    /// 输入用量在 `message_start` 中，输出用量在 `message_delta` 中
+    /// In the event stream `content_block_delta` carries text deltas and `message_stop` ends it;
+    /// input usage arrives in `message_start` and output usage in `message_delta`
+    async fn chat_stream(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut OnDelta<'_>,
+    ) -> Result<Completion, LlmError> {
+        let mut reader = self.0.post_stream("/messages", &self.headers(), &self.body(request, true)).await?;
+        let mut full = String::new();
+        let mut usage: Option<Usage> = None;
+        while let Some(data) = reader.next_data().await? {
+            let json = parse_fragment(&data)?;
+            match json["type"].as_str() {
+                Some("content_block_delta") => {
+                    forward(json["delta"]["text"].as_str().unwrap_or_default(), &mut full, on_delta)?
+                }
+                Some("message_start") => {
+                    let u = &json["message"]["usage"];
+                    usage = Usage::from_json(&u["input_tokens"], &u["output_tokens"]);
+                }
+                Some("message_delta") => {
+                    if let Some(tokens) = json["usage"]["output_tokens"].as_i64() {
+                        usage.get_or_insert_with(Usage::default).completion_tokens = tokens;
+                    }
+                }
+                Some("error") => return Err(LlmError::InvalidResponse(json["error"]["message"].to_string())),
+                Some("message_stop") => break,
+                _ => {}
+            }
+        }
+        Ok(Completion { content: full, usage })
+    }
+}
+
+/// 读取环境变量，空字符串视为未设置
+/// Read an environment variable, treating an empty string as unset
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+/// 根据环境变量构造提供方
+/// Build the provider from environment variables
+///
+/// `LLM_PROVIDER` 可选 `openai` (默认)、`ollama`、`anthropic` 或 `mock`。旧的 `OPENAI_*` 变量仍作为回退。
+/// OpenAI 兼容后端需要 API Key 或显式的 `LLM_API_BASE` (本地服务)，Anthropic 后端需要 API Key，Ollama 无需密钥。
+/// `LLM_PROVIDER` is one of `openai` (default), `ollama`, `anthropic` or `mock`. The older `OPENAI_*`
+/// variables are still honoured as fallbacks. The OpenAI-compatible backend needs an API key or an
+/// explicit `LLM_API_BASE` (for local servers), Anthropic needs an API key, Ollama needs none.
+fn load_provider() -> Option<Box<dyn LlmProvider>> {
+    let kind = env_var("LLM_PROVIDER").unwrap_or_else(|| "openai".to_string()).to_lowercase();
+    let explicit_base = env_var("LLM_API_BASE");
+    let (default_base, default_model, key_fallback) = match kind.as_str() {
+        "openai" => ("https://api.openai.com/v1", "gpt-3.5-turbo", "OPENAI_API_KEY"),
+        "ollama" => ("http://localhost:11434", "llama3.1", ""),
+        "anthropic" => ("https://api.anthropic.com/v1", "claude-3-5-haiku-latest", "ANTHROPIC_API_KEY"),
+        "mock" | "none" => return None,
+        other => {
+            warn!("Unknown LLM_PROVIDER '{}', AI features run in mock mode", other);
+            return None;
+        }
+    };
+    let api_key = env_var("LLM_API_KEY").or_else(|| env_var(key_fallback));
+
+    let enabled = match kind.as_str() {
+        "openai" => api_key.is_some() || explicit_base.is_some(),
+        "anthropic" => api_key.is_some(),
+        _ => true,
+    };
+    if !enabled {
+        return None;
+    }
+
+    let api_base = explicit_base
+        .or_else(|| if kind == "openai" { env_var("OPENAI_API_BASE") } else { None })
+        .unwrap_or_else(|| default_base.to_string());
+    let model = env_var("LLM_MODEL")
+        .or_else(|| if kind == "openai" { env_var("OPENAI_MODEL") } else { None })
+        .unwrap_or_else(|| default_model.to_string());
+    let timeout = env_var("LLM_TIMEOUT_SECS").and_then(|v| v.parse().ok()).unwrap_or(60);
+    let max_retries = env_var("LLM_MAX_RETRIES").and_then(|v| v.parse().ok()).unwrap_or(2);
+    let structured_output = env_var("LLM_STRUCTURED_OUTPUT").map(|v| v != "false" && v != "0").unwrap_or(true);
+
+    let endpoint = Endpoint {
+        api_base,
+        api_key,
+        model,
+        timeout: Duration::from_secs(timeout),
+        max_retries,
+        structured_output,
+    };
+    Some(match kind.as_str() {
+        "ollama" => Box::new(OllamaProvider(endpoint)),
+        "anthropic" => Box::new(AnthropicProvider(endpoint)),
+        _ => Box::new(OpenAiProvider(endpoint)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use serde_json::json;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// 模拟服务端对一次请求的回复
+    /// Reply of the mock server to one request
+    enum Reply {
+        Status(u16, &'static str),
+        /// 分块传输，每块之间稍作停顿 / Chunked transfer with a short pause between chunks
+        Chunks(Vec<&'static str>),
+        /// 永不回复 / Never answer
+        Hang,
+    }
+
+    /// 模拟服务端收到的请求
+    /// Request received by the mock server
+    #[derive(Debug, Clone)]
+    struct Received {
+        path: String,
+        headers: Vec<String>,
+        body: serde_json::Value,
+    }
+
+    impl Received {
+        fn header(&self, name: &str) -> Option<&str> {
+            let prefix = format!("{}:", name.to_lowercase());
+            self.headers
+                .iter()
+                .find(|h| h.to_lowercase().starts_with(&prefix))
+                .map(|h| h[prefix.len()..].trim())
+        }
+    }
+
+    /// 在本地端口启动按脚本逐个回复的服务端，返回地址与已收到的请求
+    /// Start a local server answering with the scripted replies in order; returns its address and
+    /// the requests it received
+    async fn mock_server(replies: Vec<Reply>) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        tokio::spawn(async move {
+            let mut replies = replies.into_iter();
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let Some(request) = read_request(&mut socket).await else { continue };
+                log.lock().push(request);
+                let reply = replies.next().unwrap_or(Reply::Status(500, "no reply scripted"));
+                tokio::spawn(async move { write_reply(socket, reply).await });
+            }
+        });
+        (format!("http://{}", addr), received)
+    }
+
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> Option<Received> {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        let head_end = loop {
+            if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+            let n = socket.read(&mut buf).await.ok().filter(|n| *n > 0)?;
+            data.extend_from_slice(&buf[..n]);
+        };
+        let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+        let mut lines = head.lines();
+        let path = lines.next()?.split(' ').nth(1)?.to_string();
+        let headers: Vec<String> = lines.filter(|l| !l.is_empty()).map(str::to_string).collect();
+        let length: usize = headers
+            .iter()
+            .find_map(|h| h.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        while data.len() < head_end + length {
+            let n = socket.read(&mut buf).await.ok().filter(|n| *n > 0)?;
+            data.extend_from_slice(&buf[..n]);
+        }
+        let body = serde_json::from_slice(&data[head_end..head_end + length]).unwrap_or_default();
+        Some(Received { path, headers, body })
+    }
+
+    async fn write_reply(mut socket: tokio::net::TcpStream, reply: Reply) {
+        match reply {
+            Reply::Status(status, body) => {
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+            Reply::Chunks(chunks) => {
+                let head = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n";
+                let _ = socket.write_all(head.as_bytes()).await;
+                for chunk in chunks {
+                    let _ = socket.write_all(format!("{:x}\r\n{}\r\n", chunk.len(), chunk).as_bytes()).await;
+                    let _ = socket.flush().await;
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                let _ = socket.write_all(b"0\r\n\r\n").await;
+            }
+            Reply::Hang => tokio::time::sleep(Duration::from_secs(10)).await,
+        }
+        let _ = socket.shutdown().await;
+    }
+
+    fn endpoint(api_base: &str, max_retries: u32) -> Endpoint {
+        Endpoint {
+            api_base: api_base.to_string(),
+            api_key: Some("key".to_string()),
+            model: "m".to_string(),
+            timeout: Duration::from_millis(500),
+            max_retries,
+            structured_output: true,
+        }
+    }
+
+    fn request(schema: bool) -> ChatRequest {
+        ChatRequest {
+            messages: vec![ChatMessage::system("be brief"), ChatMessage::user("hi"), ChatMessage::system("use JSON")],
+            temperature: 0.2,
+            schema: schema.then(|| OutputSchema { name: "result", schema: json!({ "type": "object" }) }),
+        }
+    }
+
+    const OPENAI_REPLY: &str = r#"{"choices":[{"message":{"content":"hello"}}],
+        "usage":{"prompt_tokens":12,"completion_tokens":3}}"#;
+
+    #[test]
+    fn backoff_is_bounded() {
+        assert_eq!(backoff(1), Duration::from_millis(500));
+        assert_eq!(backoff(3), Duration::from_secs(2));
+        assert_eq!(backoff(7), MAX_BACKOFF);
+        assert_eq!(backoff(64), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    /// 429 与 5xx 会重试，成功后返回回复
+    /// 429 and 5xx are retried and the eventual reply is returned
+    #[tokio::test]
+    async fn retries_rate_limits_and_server_errors() {
+        let replies =
+            vec![Reply::Status(429, "slow down"), Reply::Status(503, "busy"), Reply::Status(200, OPENAI_REPLY)];
+        let (base, received) = mock_server(replies).await;
+        let completion = OpenAiProvider(endpoint(&base, 2)).chat(&request(false)).await.unwrap();
+        assert_eq!(completion.content, "hello");
+        assert_eq!(received.lock().len(), 3);
+
+        let (base, received) = mock_server(vec![Reply::Status(500, "down"), Reply::Status(502, "still down")]).await;
+        match OpenAiProvider(endpoint(&base, 1)).chat(&request(false)).await {
+            Err(LlmError::Status { status: 502, body }) => assert_eq!(body, "still down"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(received.lock().len(), 2);
+    }
+
+    /// 其他 4xx 不重试，直接返回状态错误
+    /// Other 4xx responses are not retried and return a status error straight away
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let replies = vec![Reply::Status(400, "bad request"), Reply::Status(200, OPENAI_REPLY)];
+        let (base, received) = mock_server(replies).await;
+        match OpenAiProvider(endpoint(&base, 2)).chat(&request(false)).await {
+            Err(LlmError::Status { status: 400, body }) => assert_eq!(body, "bad request"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(received.lock().len(), 1);
+    }
+
+    /// 服务端不回复时返回 `LlmError::Timeout`
+    /// A server that never answers yields `LlmError::Timeout`
+    #[tokio::test]
+    async fn maps_timeouts() {
+        let (base, received) = mock_server(vec![Reply::Hang]).await;
+        let result = OllamaProvider(endpoint(&base, 0)).chat(&request(false)).await;
+        assert!(matches!(result, Err(LlmError::Timeout)), "unexpected result: {:?}", result);
+        assert_eq!(received.lock().len(), 1);
+    }
+
+    /// 各后端的请求体：OpenAI 的 `response_format`、Ollama 的 `format`、
+    /// Anthropic 拆出的系统提示词与工具
+    /// Request bodies of each backend: OpenAI `response_format`, Ollama `format`, Anthropic's split
+    /// system prompt and tool
+    #[test]
+    fn builds_backend_bodies() {
+        let openai = OpenAiProvider(endpoint("http://unused", 0));
+        let body = openai.body(&request(true), false);
+        assert_eq!(body["model"], "m");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(body["stream"], false);
+        assert!(body.get("stream_options").is_none());
+        assert_eq!(
+            body["response_format"],
+            json!({ "type": "json_schema", "json_schema": { "name": "result", "schema": { "type": "object" } } })
+        );
+        let body = openai.body(&request(false), true);
+        assert!(body.get("response_format").is_none());
+        assert_eq!(body["stream_options"], json!({ "include_usage": true }));
+
+        let ollama = OllamaProvider(endpoint("http://unused", 0));
+        let body = ollama.body(&request(true), true);
+        assert_eq!(body["format"], json!({ "type": "object" }));
+        assert_eq!(body["options"]["temperature"].as_f64().unwrap() as f32, 0.2);
+        assert_eq!(body["stream"], true);
+        assert!(ollama.body(&request(false), false).get("format").is_none());
+
+        let anthropic = AnthropicProvider(endpoint("http://unused", 0));
+        let body = anthropic.body(&request(true), false);
+        assert_eq!(body["system"], "be brief\n\nuse JSON");
+        assert_eq!(body["messages"], json!([{ "role": "user", "content": "hi" }]));
+        assert_eq!(body["max_tokens"], 2048);
+        assert_eq!(body["tools"], json!([{ "name": "result", "input_schema": { "type": "object" } }]));
+        assert_eq!(body["tool_choice"], json!({ "type": "tool", "name": "result" }));
+        assert!(anthropic.body(&request(false), false).get("tools").is_none());
+    }
+
+    /// 解析各后端非流式响应中的内容与用量，并检查路径与认证头
+    /// Parse content and usage from each backend's non-streaming reply and check paths and auth headers
+    #[tokio::test]
+    async fn parses_backend_replies() {
+        let (base, received) = mock_server(vec![Reply::Status(200, OPENAI_REPLY)]).await;
+        let completion = OpenAiProvider(endpoint(&base, 0)).chat(&request(false)).await.unwrap();
+        assert_eq!(completion.content, "hello");
+        assert_eq!(completion.usage, Some(Usage { prompt_tokens: 12, completion_tokens: 3 }));
+        let sent = received.lock()[0].clone();
+        assert_eq!(sent.path, "/chat/completions");
+        assert_eq!(sent.header("authorization"), Some("Bearer key"));
+        assert_eq!(sent.body["stream"], false);
+
+        let reply = r#"{"message":{"content":"hola"},"done":true,"prompt_eval_count":7,"eval_count":2}"#;
+        let replies = vec![Reply::Status(200, reply), Reply::Status(200, r#"{"done":true}"#)];
+        let (base, received) = mock_server(replies).await;
+        let ollama = OllamaProvider(endpoint(&base, 0));
+        let completion = ollama.chat(&request(false)).await.unwrap();
+        assert_eq!(completion.content, "hola");
+        assert_eq!(completion.usage, Some(Usage { prompt_tokens: 7, completion_tokens: 2 }));
+        assert!(matches!(ollama.chat(&request(false)).await, Err(LlmError::InvalidResponse(_))));
+        assert_eq!(received.lock()[0].path, "/api/chat");
+        assert_eq!(received.lock()[0].header("authorization"), None);
+
+        let text = r#"{"content":[{"type":"text","text":"hi "},{"type":"text","text":"there"}],
+            "usage":{"input_tokens":20,"output_tokens":4}}"#;
+        let tool = r#"{"content":[{"type":"text","text":"ignored"},
+            {"type":"tool_use","name":"result","input":{"amount":9.99}}],"usage":{"input_tokens":30}}"#;
+        let (base, received) = mock_server(vec![Reply::Status(200, text), Reply::Status(200, tool)]).await;
+        let anthropic = AnthropicProvider(endpoint(&base, 0));
+        let completion = anthropic.chat(&request(false)).await.unwrap();
+        assert_eq!(completion.content, "hi there");
+        assert_eq!(completion.usage, Some(Usage { prompt_tokens: 20, completion_tokens: 4 }));
+        let completion = anthropic.chat(&request(true)).await.unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&completion.content).unwrap(), json!({ "amount": 9.99 }));
+        assert_eq!(completion.usage, Some(Usage { prompt_tokens: 30, completion_tokens: 0 }));
+        let sent = received.lock()[0].clone();
+        assert_eq!(sent.path, "/messages");
+        assert_eq!(sent.header("x-api-key"), Some("key"));
+        assert_eq!(sent.header("anthropic-version"), Some("2023-06-01"));
+    }
+
+    /// 收集流式增量的回调
+    /// Callback collecting the streamed deltas
+    fn collect(deltas: &mut Vec<String>) -> impl FnMut(&str) -> bool + Send + '_ {
+        |delta: &str| {
+            deltas.push(delta.to_string());
+            true
+        }
+    }
+
+    /// OpenAI SSE：行在分块中间断开，最后一个分片携带用量，`[DONE]` 之后的内容被忽略
+    /// OpenAI SSE: lines break in the middle of chunks, the last fragment carries usage and anything
+    /// after `[DONE]` is ignored
+    #[tokio::test]
+    async fn streams_openai_events() {
+        let chunks = vec![
+            ": keep-alive\n\ndata: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\ndata: {\"choi",
+            "ces\":[{\"delta\":{\"content\":\"Hel\"}}]}\n",
+            "\ndata: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\ndata: {\"choices\":[],\"usage\":",
+            "{\"prompt_tokens\":5,\"completion_tokens\":2}}\n\ndata: [DO",
+            "NE]\n\ndata: {\"choices\":[{\"delta\":{\"content\":\"late\"}}]}\n\n",
+        ];
+        let (base, received) = mock_server(vec![Reply::Chunks(chunks)]).await;
+        let mut deltas = Vec::new();
+        let completion =
+            OpenAiProvider(endpoint(&base, 0)).chat_stream(&request(false), &mut collect(&mut deltas)).await.unwrap();
+        assert_eq!(deltas, ["Hel", "lo"]);
+        assert_eq!(completion.content, "Hello");
+        assert_eq!(completion.usage, Some(Usage { prompt_tokens: 5, completion_tokens: 2 }));
+        assert_eq!(received.lock()[0].body["stream"], true);
+    }
+
+    /// Ollama NDJSON：最后一行 `done` 携带用量；没有换行结尾的最后一行同样被读取
+    /// Ollama NDJSON: the final `done` line carries usage; a last line without a trailing newline is
+    /// still read
+    #[tokio::test]
+    async fn streams_ollama_lines() {
+        let chunks = vec![
+            "{\"message\":{\"content\":\"Bon\"},\"done\":false}\n{\"message\":{\"con",
+            "tent\":\"jour\"},\"done\":false}\n\n",
+            "{\"message\":{\"content\":\"\"},\"done\":true,\"prompt_eval_count\":9,\"eval_count\":4}",
+        ];
+        let (base, _) = mock_server(vec![Reply::Chunks(chunks)]).await;
+        let mut deltas = Vec::new();
+        let completion =
+            OllamaProvider(endpoint(&base, 0)).chat_stream(&request(false), &mut collect(&mut deltas)).await.unwrap();
+        assert_eq!(deltas, ["Bon", "jour"]);
+        assert_eq!(completion.content, "Bonjour");
+        assert_eq!(completion.usage, Some(Usage { prompt_tokens: 9, completion_tokens: 4 }));
+
+        let chunks = vec!["{\"message\":{\"content\":\"a\"},\"done\":false}\n{\"error\":\"model unloaded\"}\n"];
+        let (base, _) = mock_server(vec![Reply::Chunks(chunks)]).await;
+        match OllamaProvider(endpoint(&base, 0)).chat_stream(&request(false), &mut |_: &str| true).await {
+            Err(LlmError::InvalidResponse(message)) => assert_eq!(message, "model unloaded"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    /// Anthropic 事件流：`message_start` 给出输入用量，`content_block_delta` 给出文本，
+    /// `message_delta` 给出输出用量，`message_stop` 结束
+    /// Anthropic event stream: `message_start` gives input usage, `content_block_delta` the text,
+    /// `message_delta` the output usage and `message_stop` ends it
+    #[tokio::test]
+    async fn streams_anthropic_events() {
+        let chunks = vec![
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"usage\":",
+            "{\"input_tokens\":15,\"output_tokens\":1}}}\n\nevent: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0}\n\nevent: ping\ndata: {\"type\":\"ping\"}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"Ho",
+            "la\"}}\n\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\" mundo\"}}\n\n",
+            "event: message_delta\ndata: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":6}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"late\"}}\n\n",
+        ];
+        let (base, _) = mock_server(vec![Reply::Chunks(chunks)]).await;
+        let mut deltas = Vec::new();
+        let provider = AnthropicProvider(endpoint(&base, 0));
+        let completion = provider.chat_stream(&request(false), &mut collect(&mut deltas)).await.unwrap();
+        assert_eq!(deltas, ["Hola", " mundo"]);
+        assert_eq!(completion.content, "Hola mundo");
+        assert_eq!(completion.usage, Some(Usage { prompt_tokens: 15, completion_tokens: 6 }));
+    }
+
+    /// 接收方断开后中止流式请求并返回 `LlmError::Cancelled`
+    /// Once the receiver goes away the streamed request is aborted with `LlmError::Cancelled`
+    #[tokio::test]
+    async fn stream_stops_when_receiver_is_dropped() {
+        let chunks = vec![
+            "data: {\"choices\":[{\"delta\":{\"content\":\"one\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"two\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        ];
+        let (base, _) = mock_server(vec![Reply::Chunks(chunks)]).await;
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        drop(rx);
+        let mut on_delta = |delta: &str| tx.send(delta.to_string()).is_ok();
+        let result = OpenAiProvider(endpoint(&base, 0)).chat_stream(&request(false), &mut on_delta).await;
+        assert!(matches!(result, Err(LlmError::Cancelled)), "unexpected result: {:?}", result);
+    }
+}
@@ -0,0 +1,406 @@
+//! 自定义 Logo 模块
+//! Custom logo module
+//!
+//! 用户可以为订阅上传自己的 Logo。上传的图片按文件头校验类型与大小后，统一转换为若干标准尺寸的
+//! 正方形 PNG，按内容哈希保存在 `LOGO_DIR` (默认 `data/logos`)，相同的图片只保存一份。订阅的
+//! `logo` 字段引用 `/api/logos/<hash>?sz=64`；不再被任何订阅引用的 Logo 会被删除。
+//! Users can upload their own logo for a subscription. The upload is checked for type (by its
+//! magic bytes) and size, converted to square PNGs at a few standard sizes and stored by content
+//! hash in `LOGO_DIR` (by default `data/logos`), so identical images are stored once. The
+//! subscription's `logo` field references `/api/logos/<hash>?sz=64`; logos no longer referenced by
+//! any subscription are deleted.
+
+use crate::auth::{AuthUser, Role};
+use crate::db::DbPool;
+use crate::events::{self, EventKind};
+use crate::icon_cache;
+use crate::icons::{self, Kind};
+use crate::models::Subscription;
+use crate::palette;
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG},
+        HeaderValue, StatusCode,
+    },
+    response::Response,
+    Extension, Json,
+};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tokio::fs;
+use tracing::{info, warn};
+
+/// 保存的标准尺寸 (像素)
+/// Standard sizes that are stored (pixels)
+pub const SIZES: [u32; 4] = [32, 64, 128, 256];
+
+/// 位图的最小边长，更小的图片放大后会模糊
+/// Smallest side accepted for raster images; smaller ones look blurry when scaled up
+const MIN_SIDE: u32 = 16;
+
+/// 引用 Logo 的地址前缀
+/// Prefix of the URLs that reference a logo
+const URL_PREFIX: &str = "/api/logos/";
+
+/// 配置 (启动后首次使用时读取环境变量)
+/// Configuration (environment variables are read on first use)
+struct LogoConfig {
+    /// Logo 文件目录 / Directory holding the logo files
+    dir: PathBuf,
+    /// 上传文件的大小上限 (字节) / Maximum size of an uploaded file (bytes)
+    max_bytes: usize,
+}
+
+static CONFIG: Lazy<LogoConfig> = Lazy::new(|| LogoConfig {
+    dir: std::env::var("LOGO_DIR")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "data/logos".to_string())
+        .into(),
+    max_bytes: std::env::var("LOGO_MAX_KB")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|kb| *kb > 0)
+        .unwrap_or(1024)
+        * 1024,
+});
+
+/// 上传接口的请求体上限：文件上限加上 multipart 的表单开销
+/// Request body limit for the upload endpoint: the file limit plus the multipart overhead
+pub fn body_limit() -> usize {
+    CONFIG.max_bytes + 64 * 1024
+}
+
+fn file_path(hash: &str, size: u32) -> PathBuf {
+    CONFIG.dir.join(format!("{}_{}.png", hash, size))
+}
+
+/// 引用该 Logo 的地址
+/// URL referencing a logo
+pub fn url(hash: &str) -> String {
+    format!("{}{}?sz=64", URL_PREFIX, hash)
+}
+
+fn is_hash(s: &str) -> bool {
+    s.len() == 32 && s.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+}
+
+/// 从订阅的 `logo` 字段中取出上传 Logo 的哈希
+/// Take the hash of an uploaded logo from a subscription's `logo` field
+pub fn hash_of(logo: &str) -> Option<&str> {
+    let hash = logo.trim().strip_prefix(URL_PREFIX)?.split(['?', '#']).next()?;
+    is_hash(hash).then_some(hash)
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// 标准化后的 Logo
+/// A normalised logo
+struct Normalized {
+    hash: String,
+    format: &'static str,
+    /// 按 `SIZES` 顺序的 PNG / PNGs in the order of `SIZES`
+    pngs: Vec<Vec<u8>>,
+    colors: Option<palette::Colors>,
+}
+
+/// 校验并转换上传的图片 (在阻塞线程中调用)
+/// Validate and convert an uploaded image (called on a blocking thread)
+fn normalize(bytes: &[u8]) -> Result<Normalized, (StatusCode, String)> {
+    let unsupported = || (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unsupported image type; use PNG, JPEG, GIF, WebP, BMP, ICO or SVG".to_string());
+    let kind = icons::sniff(bytes).ok_or_else(unsupported)?;
+    let largest = SIZES[SIZES.len() - 1];
+    let (image, side) = icons::decode(bytes, kind, largest).map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid image: {}", e)))?;
+    if matches!(kind, Kind::Raster(_)) && side < MIN_SIDE {
+        return Err((StatusCode::BAD_REQUEST, format!("Image must be at least {}x{} pixels", MIN_SIDE, MIN_SIDE)));
+    }
+    let pngs = SIZES
+        .iter()
+        .map(|size| icons::to_square_png(&image, *size))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    // 以最大尺寸的 PNG 作为内容标识，同一张图片无论原格式如何都得到同一个哈希
+    // The largest PNG identifies the content, so the same picture gets the same hash whatever its
+    // original format
+    let hash = hex::encode(&Sha256::digest(&pngs[pngs.len() - 1])[..16]);
+    let format = match kind {
+        Kind::Raster(format) => format.extensions_str().first().copied().unwrap_or("image"),
+        Kind::Svg => "svg",
+    };
+    let colors = palette::extract(&pngs[pngs.len() - 1]);
+    Ok(Normalized { hash, format, pngs, colors })
+}
+
+/// 保存 Logo 记录与文件；已存在的文件不会重复写入。先写记录并刷新上传时间，避免清理任务删除刚上传的 Logo
+/// Store the logo record and files; existing files are not written again. The record (with a
+/// fresh upload time) comes first so the cleanup does not delete a logo that was just uploaded
+async fn save(pool: &DbPool, logo: &Normalized, original_bytes: usize, user_id: i64) -> Result<(), (StatusCode, String)> {
+    sqlx::query(
+        "INSERT INTO logo_assets (hash, format, original_bytes, uploaded_by, dominant_color, accent_color) \
+         VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT (hash) DO UPDATE SET uploaded_at = CURRENT_TIMESTAMP",
+    )
+    .bind(&logo.hash)
+    .bind(logo.format)
+    .bind(original_bytes as i64)
+    .bind(user_id)
+    .bind(logo.colors.as_ref().map(|c| &c.dominant))
+    .bind(logo.colors.as_ref().map(|c| &c.accent))
+    .execute(pool)
+    .await
+    .map_err(db_error)?;
+
+    let io_error = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to store logo: {}", e));
+    fs::create_dir_all(&CONFIG.dir).await.map_err(io_error)?;
+    for (size, png) in SIZES.iter().zip(&logo.pngs) {
+        let path = file_path(&logo.hash, *size);
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            continue;
+        }
+        fs::write(&path, png).await.map_err(io_error)?;
+    }
+    Ok(())
+}
+
+/// 删除不再被任何订阅引用的 Logo (一分钟内上传的除外，它们可能还没有被引用)
+/// Delete logos that are no longer referenced by any subscription (except those uploaded within
+/// the last minute, which may not be referenced yet)
+///
+/// 删除记录与文件在同一个写事务中完成：同时上传同一 Logo 的 `save` 会等待事务结束，
+/// 再重新写入记录与 (已被删除的) 文件，不会出现有记录却没有文件的情况。
+/// Records and files are deleted within one write transaction: a concurrent `save` of the same
+/// logo waits for it to finish and then writes the record and the (now deleted) files again, so
+/// a record never ends up without its files.
+pub async fn collect_garbage(pool: &DbPool) {
+    if let Err(e) = remove_unused(pool).await {
+        warn!("Failed to remove unused logos: {}", e);
+    }
+}
+
+async fn remove_unused(pool: &DbPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let unused: Vec<(String,)> = sqlx::query_as(
+        "DELETE FROM logo_assets WHERE uploaded_at <= datetime('now', '-1 minutes') AND NOT EXISTS (\
+         SELECT 1 FROM subscriptions WHERE logo LIKE ? || logo_assets.hash || '%') RETURNING hash",
+    )
+    .bind(URL_PREFIX)
+    .fetch_all(&mut *tx)
+    .await?;
+    for (hash,) in &unused {
+        for size in SIZES {
+            let _ = fs::remove_file(file_path(hash, size)).await;
+        }
+    }
+    tx.commit().await?;
+    if !unused.is_empty() {
+        info!("Removed {} unused logos", unused.len());
+    }
+    Ok(())
+}
+
+/// 为旧版本上传的 Logo 补充配色
+/// Fill in the colours of logos uploaded by older versions
+pub async fn backfill_colors(pool: &DbPool) {
+    let missing: Vec<(String,)> = sqlx::query_as("SELECT hash FROM logo_assets WHERE dominant_color IS NULL")
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default();
+    for (hash,) in &missing {
+        let largest = SIZES[SIZES.len() - 1];
+        let Ok(png) = fs::read(file_path(hash, largest)).await else { continue };
+        let Some(colors) = palette::extract_blocking(png).await else { continue };
+        let _ = sqlx::query("UPDATE logo_assets SET dominant_color = ?, accent_color = ? WHERE hash = ?")
+            .bind(&colors.dominant)
+            .bind(&colors.accent)
+            .bind(hash)
+            .execute(pool)
+            .await;
+    }
+}
+
+/// 读取 multipart 中的图片字段 (`file`)，超过上限时立即停止读取
+/// Read the image field (`file`) of the multipart body, stopping as soon as it exceeds the limit
+async fn read_file(multipart: &mut Multipart) -> Result<Vec<u8>, (StatusCode, String)> {
+    let bad_request = |e: axum::extract::multipart::MultipartError| (StatusCode::BAD_REQUEST, e.body_text());
+    while let Some(mut field) = multipart.next_field().await.map_err(bad_request)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        if let Some(content_type) = field.content_type() {
+            let generic = content_type == "application/octet-stream";
+            if !generic && !content_type.starts_with("image/") {
+                return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("Unsupported content type {}", content_type)));
+            }
+        }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.chunk().await.map_err(bad_request)? {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > CONFIG.max_bytes {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("Logo must be at most {} KB", CONFIG.max_bytes / 1024)));
+            }
+        }
+        if bytes.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "Empty file".to_string()));
+        }
+        return Ok(bytes);
+    }
+    Err((StatusCode::BAD_REQUEST, "Missing file field".to_string()))
+}
+
+/// 上传订阅的自定义 Logo (POST /api/subscriptions/:id/logo，multipart 字段 `file`)
+/// Upload a custom logo for a subscription (multipart field `file`)
+///
+/// 只有拥有者可以上传。图片转换为标准尺寸的 PNG 后，订阅的 `logo` 改为引用该 Logo，返回更新后的订阅。
+/// Only the owner can upload. After the image is converted to PNGs at the standard sizes the
+/// subscription's `logo` references it, and the updated subscription is returned.
+pub async fn upload_logo(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+    mut multipart: Multipart,
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    let owned: Option<(i64,)> = sqlx::query_as("SELECT id FROM subscriptions WHERE id = ? AND owner_id = ?")
+        .bind(id)
+        .bind(user.user_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?;
+    if owned.is_none() {
+        return Err((StatusCode::NOT_FOUND, "Subscription not found".to_string()));
+    }
+
+    let bytes = read_file(&mut multipart).await?;
+    let original_bytes = bytes.len();
+    let logo = tokio::task::spawn_blocking(move || normalize(&bytes))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+    save(&pool, &logo, original_bytes, user.user_id).await?;
+
+    let mut sub = sqlx::query_as::<_, Subscription>("UPDATE subscriptions SET logo = ? WHERE id = ? AND owner_id = ? RETURNING *")
+        .bind(url(&logo.hash))
+        .bind(id)
+        .bind(user.user_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Subscription not found".to_string()))?;
+    info!("Stored logo {} ({}) for subscription {}", logo.hash, logo.format, id);
+    collect_garbage(&pool).await;
+    icon_cache::attach_colors(&pool, std::slice::from_mut(&mut sub)).await;
+    events::publish_subscription(EventKind::SubscriptionUpdated, &sub);
+    Ok(Json(sub))
+}
+
+/// 移除订阅的 Logo (DELETE /api/subscriptions/:id/logo)，前端回退到按官网获取的图标
+/// Remove a subscription's logo; the frontend falls back to the icon of the website
+pub async fn remove_logo(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    let mut sub = sqlx::query_as::<_, Subscription>("UPDATE subscriptions SET logo = NULL WHERE id = ? AND owner_id = ? RETURNING *")
+        .bind(id)
+        .bind(user.user_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Subscription not found".to_string()))?;
+    collect_garbage(&pool).await;
+    icon_cache::attach_colors(&pool, std::slice::from_mut(&mut sub)).await;
+    events::publish_subscription(EventKind::SubscriptionUpdated, &sub);
+    Ok(Json(sub))
+}
+
+#[derive(Deserialize)]
+pub struct LogoQuery {
+    sz: Option<u32>,
+}
+
+/// 获取 Logo (GET /api/logos/:hash?sz=64)，返回不小于所需尺寸的最小标准尺寸
+/// Get a logo; the smallest standard size not below the requested one is returned
+///
+/// 内容由哈希确定、永不改变，因此允许浏览器长期缓存。
+/// The content is fixed by its hash and never changes, so browsers may cache it for long.
+pub async fn get_logo(
+    Extension(user): Extension<AuthUser>,
+    Path(hash): Path<String>,
+    Query(params): Query<LogoQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+    let not_found = || (StatusCode::NOT_FOUND, "Logo not found".to_string());
+    if !is_hash(&hash) {
+        return Err(not_found());
+    }
+    let wanted = params.sz.unwrap_or(64);
+    let size = SIZES.iter().copied().find(|s| *s >= wanted).unwrap_or(SIZES[SIZES.len() - 1]);
+    let png = fs::read(file_path(&hash, size)).await.map_err(|_| not_found())?;
+
+    let mut resp = Response::new(png.into());
+    resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+    resp.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("private, max-age=31536000, immutable"));
+    if let Ok(etag) = HeaderValue::from_str(&format!("\"{}-{}\"", hash, size)) {
+        resp.headers_mut().insert(ETAG, etag);
+    }
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    fn png(side: u32) -> Vec<u8> {
+        let image = RgbaImage::from_pixel(side, side, Rgba([220, 20, 60, 255]));
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).unwrap();
+        bytes
+    }
+
+    /// 同一张图片的 SVG 与 PNG 得到同一个哈希
+    /// The SVG and PNG of the same picture get the same hash
+    #[test]
+    fn normalizes_to_content_hash() {
+        let svg = br##"<svg xmlns="http://www.w3.org/2000/svg" width="256" height="256"><rect width="256" height="256" fill="#dc143c"/></svg>"##;
+        let from_svg = normalize(svg).unwrap();
+        let from_png = normalize(&png(256)).unwrap();
+        assert_eq!(from_svg.hash, from_png.hash);
+        assert_eq!((from_svg.format, from_png.format), ("svg", "png"));
+        assert_eq!(from_png.pngs.len(), SIZES.len());
+        assert!(is_hash(&from_png.hash));
+        assert_eq!(from_png.colors.map(|c| c.dominant), Some("#dc143c".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_images() {
+        let status = |bytes: &[u8]| normalize(bytes).err().map(|(status, _)| status);
+        assert_eq!(status(&png(8)), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(status(b"hello, not an image"), Some(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        assert_eq!(status(b"%PDF-1.7"), Some(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        // 文件头是 PNG 但内容损坏 / PNG magic bytes with a corrupt body
+        assert_eq!(status(&png(64)[..40]), Some(StatusCode::BAD_REQUEST));
+        assert!(normalize(&png(MIN_SIDE)).is_ok());
+    }
+
+    #[test]
+    fn parses_logo_references() {
+        let hash = "0123456789abcdef0123456789abcdef";
+        assert!(is_hash(hash));
+        assert!(!is_hash("0123456789ABCDEF0123456789abcdef"));
+        assert!(!is_hash(&hash[1..]));
+        assert!(!is_hash("0123456789abcdef0123456789abcdeg"));
+        assert_eq!(hash_of(&url(hash)), Some(hash));
+        assert_eq!(hash_of(&format!(" /api/logos/{}#x ", hash)), Some(hash));
+        assert_eq!(hash_of(&format!("/api/logos/{}", hash)), Some(hash));
+        assert_eq!(hash_of(&format!("/api/logos/../{}", hash)), None);
+        assert_eq!(hash_of(&format!("https://example.com/api/logos/{}", hash)), None);
+        assert_eq!(hash_of("/api/icon?domain=example.com&sz=64"), None);
+    }
+}
@@ -0,0 +1,472 @@
+//! 邮件导入模块
+//! Email ingestion module
+//!
+//! 定期轮询 IMAP 文件夹中的新邮件，提取正文后交给智能解析，并把结果放入待审核队列；
+//! 订阅只会在审核通过后创建 (见 `review` 模块)。已处理的邮件按 (文件夹, UIDVALIDITY, UID)
+//! 记录，不会重复导入。IMAP 客户端基于 tokio 与 tokio-rustls 实现，只使用 LOGIN、SELECT、
+//! UID SEARCH、UID FETCH 与 LOGOUT 命令，并以 `BODY.PEEK[]` 读取邮件，不会改变已读状态。
+//! Periodically polls an IMAP folder for new messages, extracts their text, runs it through
+//! smart parse and puts the result into the review queue; subscriptions are only created after
+//! approval (see the `review` module). Processed messages are recorded by (folder, UIDVALIDITY,
+//! UID) and never imported twice. The IMAP client is built on tokio and tokio-rustls, uses only
+//! the LOGIN, SELECT, UID SEARCH, UID FETCH and LOGOUT commands, and reads messages with
+//! `BODY.PEEK[]` so their seen flag is left untouched.
+
+use crate::auth::{AuthUser, Role};
+use crate::db::DbPool;
+use crate::mime;
+use crate::smart_parse;
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+use tracing::{info, warn};
+
+/// 单次网络读写的超时
+/// Timeout for a single network read or write
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 每封邮件最多读取的字节数 (超出部分截断)
+/// Maximum bytes read per message (the rest is truncated)
+const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+/// 每次轮询最多导入的邮件数
+/// Maximum messages imported per poll
+const MAX_MESSAGES_PER_POLL: usize = 50;
+
+/// 送入智能解析的正文长度上限 (字符)
+/// Maximum body length passed to smart parse (characters)
+const MAX_TEXT_CHARS: usize = 4000;
+
+/// IMAP 配置，来自 `IMAP_*` 环境变量；未设置 `IMAP_HOST` 时不启用邮件导入
+/// IMAP configuration from the `IMAP_*` environment variables; email ingestion is disabled
+/// when `IMAP_HOST` is not set
+pub struct MailConfig {
+    host: String,
+    port: u16,
+    tls: bool,
+    username: String,
+    password: String,
+    folder: String,
+    interval: Duration,
+    user_id: i64,
+}
+
+impl MailConfig {
+    /// 从环境变量读取配置；未设置 `IMAP_HOST` 时为 `None`，端口无效时返回错误
+    /// Read the configuration from the environment; `None` without `IMAP_HOST`, an error for an
+    /// invalid port
+    fn from_env() -> Result<Option<Self>, String> {
+        let Some(host) = std::env::var("IMAP_HOST").ok().filter(|h| !h.trim().is_empty()) else {
+            return Ok(None);
+        };
+        let tls = std::env::var("IMAP_TLS").map(|v| v != "false" && v != "0").unwrap_or(true);
+        let parse = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        let port = match std::env::var("IMAP_PORT") {
+            Ok(value) => parse_port(&value).ok_or_else(|| format!("IMAP_PORT must be 1-65535, got '{}'", value))?,
+            Err(_) if tls => 993,
+            Err(_) => 143,
+        };
+        Ok(Some(MailConfig {
+            host,
+            port,
+            tls,
+            username: std::env::var("IMAP_USERNAME").unwrap_or_default(),
+            password: std::env::var("IMAP_PASSWORD").unwrap_or_default(),
+            folder: std::env::var("IMAP_FOLDER").unwrap_or_else(|_| "INBOX".to_string()),
+            interval: Duration::from_secs(parse("IMAP_POLL_SECS").unwrap_or(300).max(30)),
+            user_id: std::env::var("IMAP_USER_ID").ok().and_then(|v| v.parse().ok()).unwrap_or(1),
+        }))
+    }
+}
+
+fn parse_port(value: &str) -> Option<u16> {
+    value.trim().parse::<u16>().ok().filter(|p| *p > 0)
+}
+
+static CONFIG: Lazy<Option<MailConfig>> = Lazy::new(|| {
+    MailConfig::from_env().unwrap_or_else(|e| {
+        warn!("Email ingestion disabled: {}", e);
+        None
+    })
+});
+
+/// 防止定时轮询与手动触发同时运行
+/// Prevents the scheduled poll and a manual trigger from running at the same time
+static POLL_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+/// 启动后台轮询任务 (仅在配置了 `IMAP_HOST` 时)
+/// Start the background polling task (only when `IMAP_HOST` is configured)
+pub fn start(pool: DbPool) {
+    let Some(config) = CONFIG.as_ref() else {
+        return;
+    };
+    info!(
+        "Email ingestion enabled: {}:{} folder {} every {}s",
+        config.host,
+        config.port,
+        config.folder,
+        config.interval.as_secs()
+    );
+    tokio::spawn(async move {
+        loop {
+            match poll(&pool, config).await {
+                Ok(0) => {}
+                Ok(n) => info!("Queued {} email(s) for review", n),
+                Err(e) => warn!("IMAP poll failed: {}", e),
+            }
+            tokio::time::sleep(config.interval).await;
+        }
+    });
+}
+
+/// 轮询结果
+/// Poll result
+#[derive(Serialize)]
+pub struct PollResult {
+    pub queued: usize,
+}
+
+/// 立即轮询一次 (POST /api/review/poll，仅管理员)
+/// Poll once right now (admins only)
+pub async fn poll_now(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<PollResult>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let config = CONFIG
+        .as_ref()
+        .ok_or((StatusCode::SERVICE_UNAVAILABLE, "Email ingestion is not configured (IMAP_HOST)".to_string()))?;
+    poll(&pool, config)
+        .await
+        .map(|queued| Json(PollResult { queued }))
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e))
+}
+
+/// 拉取新邮件并放入审核队列，返回入队数量
+/// Fetch new messages and queue them for review, returning how many were queued
+async fn poll(pool: &DbPool, config: &MailConfig) -> Result<usize, String> {
+    let _guard = POLL_LOCK.lock().await;
+    let mut session = Session::connect(config).await?;
+    session.command(&format!("LOGIN {} {}", quote(&config.username), quote(&config.password))).await?;
+    let uid_validity = session.select(&config.folder).await?;
+
+    let db_err = |e: sqlx::Error| e.to_string();
+    let (last,): (i64,) = sqlx::query_as(
+        "SELECT COALESCE(MAX(uid), 0) FROM mail_processed WHERE folder = ? AND uid_validity = ?",
+    )
+    .bind(&config.folder)
+    .bind(uid_validity)
+    .fetch_one(pool)
+    .await
+    .map_err(db_err)?;
+
+    // "n:*" 在没有更大 UID 时仍会返回最后一封，因此再按 UID 过滤
+    // "n:*" still returns the last message when there is no larger UID, hence the extra filter
+    let uids: Vec<i64> = session
+        .search(&format!("UID {}:*", last + 1))
+        .await?
+        .into_iter()
+        .filter(|uid| *uid > last)
+        .take(MAX_MESSAGES_PER_POLL)
+        .collect();
+
+    let mut queued = 0;
+    for uid in uids {
+        let raw = session.fetch(uid).await?;
+        let message = mime::parse(&raw);
+        let body: String = message.text.chars().take(MAX_TEXT_CHARS).collect();
+        let text = match &message.subject {
+            Some(subject) => format!("{}\n{}", subject, body),
+            None => body,
+        };
+
+        // 解析在事务之外进行 (可能调用模型)；入队与已处理记录在同一事务中写入，不会重复或遗漏
+        // Parsing happens outside the transaction (it may call the model); the queue entry and the
+        // processed record are written in one transaction, so a message is never queued twice or lost
+        let parsed = if text.trim().is_empty() {
+            None
+        } else {
+            Some(smart_parse::parse(pool, config.user_id, &text).await.map_err(db_err)?)
+        };
+        let mut tx = pool.begin().await.map_err(db_err)?;
+        if let Some(parsed) = parsed {
+            sqlx::query(
+                "INSERT INTO review_queue (user_id, source, subject, sender, received_at, excerpt, parsed) VALUES (?, 'email', ?, ?, ?, ?, ?)",
+            )
+            .bind(config.user_id)
+            .bind(&message.subject)
+            .bind(&message.from)
+            .bind(&message.date)
+            .bind(&text)
+            .bind(serde_json::to_string(&parsed).unwrap_or_default())
+            .execute(&mut *tx)
+            .await
+            .map_err(db_err)?;
+            queued += 1;
+        }
+
+        sqlx::query("INSERT OR IGNORE INTO mail_processed (folder, uid_validity, uid) VALUES (?, ?, ?)")
+            .bind(&config.folder)
+            .bind(uid_validity)
+            .bind(uid)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_err)?;
+        tx.commit().await.map_err(db_err)?;
+    }
+
+    let _ = session.command("LOGOUT").await;
+    Ok(queued)
+}
+
+/// IMAP 带引号字符串
+/// IMAP quoted string
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// 可读写的连接 (明文 TCP 或 TLS)
+/// A readable and writable connection (plain TCP or TLS)
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// 一行服务器响应及其后附带的字面量数据
+/// One line of server response with any literal data that followed it
+struct ResponseLine {
+    text: String,
+    literal: Vec<u8>,
+}
+
+/// IMAP 会话
+/// IMAP session
+struct Session {
+    stream: BufReader<Box<dyn Connection>>,
+    tag: u32,
+}
+
+impl Session {
+    async fn connect(config: &MailConfig) -> Result<Self, String> {
+        let tcp = with_timeout(TcpStream::connect((config.host.as_str(), config.port))).await?;
+        let stream: Box<dyn Connection> = if config.tls {
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+            let tls_config = rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            let server_name = rustls::ServerName::try_from(config.host.as_str()).map_err(|e| e.to_string())?;
+            let connector = tokio_rustls::TlsConnector::from(Arc::new(tls_config));
+            Box::new(with_timeout(connector.connect(server_name, tcp)).await?)
+        } else {
+            Box::new(tcp)
+        };
+
+        let mut session = Session { stream: BufReader::new(stream), tag: 0 };
+        let greeting = session.read_line().await?;
+        if !greeting.text.starts_with("* OK") && !greeting.text.starts_with("* PREAUTH") {
+            return Err(format!("unexpected IMAP greeting: {}", greeting.text));
+        }
+        Ok(session)
+    }
+
+    /// 读取一行响应；行尾为 `{n}` 时继续读取 n 字节字面量及其后的内容
+    /// Read one response line; when it ends with `{n}`, also read the n-byte literal and what
+    /// follows it
+    async fn read_line(&mut self) -> Result<ResponseLine, String> {
+        let mut line = ResponseLine { text: String::new(), literal: Vec::new() };
+        loop {
+            let mut buf = Vec::new();
+            let read = with_timeout(self.stream.read_until(b'\n', &mut buf)).await?;
+            if read == 0 {
+                return Err("IMAP connection closed".to_string());
+            }
+            let part = String::from_utf8_lossy(&buf);
+            let part = part.trim_end_matches(['\r', '\n']);
+            let size = part
+                .strip_suffix('}')
+                .and_then(|p| p.rsplit_once('{'))
+                .and_then(|(_, n)| n.parse::<usize>().ok());
+            line.text.push_str(part);
+            let Some(size) = size else {
+                return Ok(line);
+            };
+            if size > MAX_MESSAGE_BYTES * 2 {
+                return Err(format!("IMAP literal of {} bytes is too large", size));
+            }
+            let mut literal = vec![0; size];
+            with_timeout(self.stream.read_exact(&mut literal)).await?;
+            line.literal.extend_from_slice(&literal);
+        }
+    }
+
+    /// 发送命令并收集未标记的响应，直到收到带标签的完成响应
+    /// Send a command and collect untagged responses until the tagged completion response
+    async fn command(&mut self, command: &str) -> Result<Vec<ResponseLine>, String> {
+        self.tag += 1;
+        let tag = format!("A{}", self.tag);
+        let stream = self.stream.get_mut();
+        with_timeout(stream.write_all(format!("{} {}\r\n", tag, command).as_bytes())).await?;
+        with_timeout(stream.flush()).await?;
+
+        let mut responses = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            if let Some(status) = line.text.strip_prefix(&format!("{} ", tag)) {
+                if status.starts_with("OK") {
+                    return Ok(responses);
+                }
+                // 不在错误中回显 LOGIN 命令 (包含密码)
+                // Do not echo the LOGIN command (which contains the password) in errors
+                let verb = command.split_whitespace().next().unwrap_or_default();
+                return Err(format!("IMAP {} failed: {}", verb, status));
+            }
+            responses.push(line);
+        }
+    }
+
+    /// 选择文件夹，返回 UIDVALIDITY
+    /// Select a folder, returning its UIDVALIDITY
+    async fn select(&mut self, folder: &str) -> Result<i64, String> {
+        let responses = self.command(&format!("SELECT {}", quote(folder))).await?;
+        responses
+            .iter()
+            .find_map(|r| {
+                let rest = r.text.split("[UIDVALIDITY ").nth(1)?;
+                rest.split(']').next()?.trim().parse().ok()
+            })
+            .ok_or_else(|| "IMAP SELECT returned no UIDVALIDITY".to_string())
+    }
+
+    /// UID SEARCH，返回升序 UID
+    /// UID SEARCH, returning UIDs in ascending order
+    async fn search(&mut self, criteria: &str) -> Result<Vec<i64>, String> {
+        let responses = self.command(&format!("UID SEARCH {}", criteria)).await?;
+        let mut uids: Vec<i64> = responses
+            .iter()
+            .filter_map(|r| r.text.strip_prefix("* SEARCH"))
+            .flat_map(|rest| rest.split_whitespace().filter_map(|n| n.parse().ok()))
+            .collect();
+        uids.sort_unstable();
+        Ok(uids)
+    }
+
+    /// 读取整封邮件 (最多 `MAX_MESSAGE_BYTES` 字节，不设置已读标记)
+    /// Read a whole message (at most `MAX_MESSAGE_BYTES`, without setting the seen flag)
+    async fn fetch(&mut self, uid: i64) -> Result<Vec<u8>, String> {
+        let responses = self.command(&format!("UID FETCH {} (BODY.PEEK[]<0.{}>)", uid, MAX_MESSAGE_BYTES)).await?;
+        Ok(responses
+            .into_iter()
+            .find(|r| r.text.contains("FETCH") && !r.literal.is_empty())
+            .map(|r| r.literal)
+            .unwrap_or_default())
+    }
+}
+
+async fn with_timeout<T, E: std::fmt::Display>(future: impl std::future::Future<Output = Result<T, E>>) -> Result<T, String> {
+    match tokio::time::timeout(IO_TIMEOUT, future).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("IMAP operation timed out".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn rejects_invalid_ports() {
+        assert_eq!(parse_port(" 1143 "), Some(1143));
+        for value in ["0", "65536", "70000", "-1", "imap"] {
+            assert_eq!(parse_port(value), None, "{}", value);
+        }
+    }
+
+    /// 最小的明文 IMAP 服务器：按 UID 提供给定的邮件
+    /// Minimal plaintext IMAP server serving the given messages by UID
+    async fn fake_server(messages: Vec<&'static [u8]>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let messages = messages.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(socket);
+                    stream.get_mut().write_all(b"* OK fake IMAP ready\r\n").await.unwrap();
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                        let (tag, command) = line.trim_end().split_once(' ').unwrap();
+                        let mut reply: Vec<u8> = Vec::new();
+                        if command.starts_with("SELECT") {
+                            reply.extend_from_slice(b"* OK [UIDVALIDITY 7] UIDs valid\r\n");
+                        } else if let Some(range) = command.strip_prefix("UID SEARCH UID ") {
+                            let from: usize = range.trim_end_matches(":*").parse().unwrap();
+                            // 与真实服务器一样，没有更大的 UID 时仍返回最后一封
+                            // Like real servers, return the last message when there is no larger UID
+                            let uids: Vec<String> = (from.clamp(1, messages.len())..=messages.len()).map(|n| n.to_string()).collect();
+                            reply.extend_from_slice(format!("* SEARCH {}\r\n", uids.join(" ")).as_bytes());
+                        } else if let Some(rest) = command.strip_prefix("UID FETCH ") {
+                            let uid: usize = rest.split(' ').next().unwrap().parse().unwrap();
+                            let body = messages[uid - 1];
+                            reply.extend_from_slice(format!("* {} FETCH (UID {} BODY[]<0> {{{}}}\r\n", uid, uid, body.len()).as_bytes());
+                            reply.extend_from_slice(body);
+                            reply.extend_from_slice(b")\r\n");
+                        }
+                        reply.extend_from_slice(format!("{} OK done\r\n", tag).as_bytes());
+                        stream.get_mut().write_all(&reply).await.unwrap();
+                        line.clear();
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    /// 新邮件进入审核队列并记录为已处理，再次轮询不会重复导入
+    /// New messages enter the review queue and are recorded as processed; polling again imports
+    /// nothing twice
+    #[tokio::test]
+    async fn polls_local_imap_server() {
+        let pool = db::test_pool().await;
+        let port = fake_server(vec![
+            b"Subject: Netflix receipt\r\n\r\nYour Netflix plan renews at $15.49/month.\r\n",
+            b"Subject: \r\n\r\n\r\n",
+        ])
+        .await;
+        let config = MailConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            tls: false,
+            username: "user".to_string(),
+            password: "secret".to_string(),
+            folder: "INBOX".to_string(),
+            interval: Duration::from_secs(300),
+            user_id: 1,
+        };
+
+        assert_eq!(poll(&pool, &config).await.unwrap(), 1);
+        assert_eq!(poll(&pool, &config).await.unwrap(), 0);
+        let (queued, processed): (i64, i64) = sqlx::query_as(
+            "SELECT (SELECT COUNT(*) FROM review_queue), (SELECT COUNT(*) FROM mail_processed WHERE uid_validity = 7)",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((queued, processed), (1, 2));
+        let (subject,): (String,) = sqlx::query_as("SELECT subject FROM review_queue").fetch_one(&pool).await.unwrap();
+        assert_eq!(subject, "Netflix receipt");
+    }
+}
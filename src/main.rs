@@ -1,9 +1,37 @@
+mod ai_usage;
+mod assistant;
+mod auth;
+mod catalog;
+mod dates;
 mod db;
+mod events;
+mod extractor;
 mod handlers;
+mod households;
+mod icon_cache;
+mod icons;
+mod insights;
+mod llm;
+mod logos;
+mod mail;
+mod mime;
 mod models;
+mod oidc;
+mod outbound;
+mod palette;
+mod prompts;
+mod resolver;
+mod review;
+mod search_cache;
+mod smart_parse;
+mod splits;
+mod users;
+mod ws;
 
 use axum::{
-    routing::{get, delete, post},
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{get, delete, post, put},
     Router,
 };
 use std::net::SocketAddr;
@@ -63,6 +91,45 @@ async fn main() {
     //    If initialization fails, the program will panic and exit.
     let pool = db::init_db().await.expect("Failed to initialize DB");
 
+    // 加载 LLM 提供方配置 (仅在启动时读取一次环境变量)
+    // Load the LLM provider configuration (environment variables are read once, at startup)
+    llm::init();
+
+    // 初始化事件总线 (事件日志大小由 EVENT_LOG_SIZE 决定)
+    // Initialise the event bus (the event log size comes from EVENT_LOG_SIZE)
+    events::init();
+
+    // 启动 AI 响应缓存的失效任务并加载价格表与预算
+    // Start the AI response cache invalidation task and load the price table and budget
+    ai_usage::start();
+
+    // 按 DOMAIN_RESOLVERS 构造域名解析链，按 ICON_SOURCES 确定图标来源顺序，并输出出站请求策略
+    // Build the domain resolver chain from DOMAIN_RESOLVERS and the icon source order from
+    // ICON_SOURCES, and log the outbound request policy
+    outbound::init();
+    resolver::init();
+    icons::init();
+
+    // 加载提示词版本并监视提示词文件
+    // Load the prompt versions and watch the prompts file
+    prompts::init(&pool).await.expect("Failed to load prompts");
+
+    // 启动邮件导入 (仅在配置了 IMAP_HOST 时)
+    // Start email ingestion (only when IMAP_HOST is configured)
+    mail::start(pool.clone());
+
+    // 启动暂停到期自动恢复任务
+    // Start the task that resumes subscriptions when their pause ends
+    handlers::start_resume_task(pool.clone());
+
+    // 启动续费提醒任务 (推送 reminder.due 事件)
+    // Start the renewal reminder task (pushes reminder.due events)
+    insights::start_reminder_task(pool.clone());
+
+    // 启动图标缓存的刷新任务 (重新获取超过有效期的图标)
+    // Start the icon cache refresh task (fetches icons past their lifetime again)
+    icon_cache::start_refresh_task(pool.clone());
+
     // 3. 构建应用程序路由 (Router)
     //    定义 URL 路径与处理函数之间的映射关系。
     //    Build the application router.
@@ -72,18 +139,103 @@ async fn main() {
         // API Routes: Get all subscriptions (GET) and Create new subscription (POST)
         .route("/api/subscriptions", get(handlers::list_subscriptions).post(handlers::create_subscription))
         .route("/api/stream", get(handlers::stream_updates))
+        .route("/api/ws", get(ws::connect))
         
         // API 路由：根据 ID 删除特定订阅 (DELETE) 或 更新特定订阅 (PUT)
         // API Routes: Delete a specific subscription by ID (DELETE) or Update specific subscription (PUT)
         .route("/api/subscriptions/:id", delete(handlers::delete_subscription).put(handlers::update_subscription))
+        .route("/api/subscriptions/:id/pause", post(handlers::pause_subscription))
+        .route("/api/subscriptions/:id/resume", post(handlers::resume_subscription))
+        .route(
+            "/api/subscriptions/:id/logo",
+            post(logos::upload_logo).delete(logos::remove_logo).layer(DefaultBodyLimit::max(logos::body_limit())),
+        )
+        .route("/api/logos/:hash", get(logos::get_logo))
 
         // API 路由：搜索域名 (GET)
         // API Routes: Search domain (GET)
-        .route("/api/search", get(|state, query| async move { handlers::search_domain(state, query).await }))
+        .route("/api/search", get(handlers::search_domain))
+        .route("/api/search/overrides", get(resolver::list_overrides).put(resolver::set_override))
+        .route("/api/search/overrides/:id", delete(resolver::delete_override))
+        .route("/api/search/resolvers", get(resolver::resolver_status))
+        .route("/api/search/cache", get(search_cache::list_cache).delete(search_cache::purge_cache))
+        .route("/api/search/cache/:id", delete(search_cache::delete_entry))
+        .route("/api/search/cache/:id/pin", post(search_cache::pin_entry))
+        .route("/api/search/cache/:id/unpin", post(search_cache::unpin_entry))
         .route("/api/icon", get(handlers::get_icon))
+        .route("/api/icon/meta", get(icon_cache::icon_meta))
+        .route("/api/icon/cache", get(icon_cache::list_cache).delete(icon_cache::purge_cache))
+        .route("/api/icon/cache/refresh", post(icon_cache::refresh_domain))
+        .route("/api/catalog", get(catalog::list_catalog))
+        .route("/api/catalog/:id", get(catalog::get_service))
         .route("/api/smart-parse", post(handlers::smart_parse))
         .route("/api/analyze", post(handlers::analyze_spending))
+        .route("/api/analyze/stream", get(handlers::analyze_spending_stream))
+        .route("/api/ai/usage", get(ai_usage::get_usage))
+
+        // API 路由：对话助手 (写操作需要确认)
+        // API Routes: Conversational assistant (write actions need confirmation)
+        .route("/api/chat", post(assistant::chat))
+        .route("/api/chat/conversations", get(assistant::list_conversations))
+        .route("/api/chat/conversations/:id", get(assistant::get_conversation).delete(assistant::delete_conversation))
+        .route("/api/chat/actions/:id/confirm", post(assistant::confirm_action))
+        .route("/api/chat/actions/:id/cancel", post(assistant::cancel_action))
+
+        // API 路由：基于规则的消费洞察
+        // API Routes: Rule-based spending insights
+        .route("/api/insights", get(insights::list_insights))
+
+        // API 路由：提示词管理 (仅管理员)
+        // API Routes: Prompt management (admins only)
+        .route("/api/prompts", get(prompts::get_prompts_api).put(prompts::update_prompts))
+        .route("/api/prompts/versions", get(prompts::list_versions))
+        .route("/api/prompts/versions/:version/restore", post(prompts::restore_version))
+
+        // API 路由：邮件导入的审核队列
+        // API Routes: Review queue for email ingestion
+        .route("/api/review", get(review::list_review))
+        .route("/api/review/poll", post(mail::poll_now))
+        .route("/api/review/:id/approve", post(review::approve))
+        .route("/api/review/:id/reject", post(review::reject))
+
+        // API 路由：个人访问令牌的创建、列出与吊销
+        // API Routes: Create, list and revoke personal access tokens
+        .route("/api/tokens", get(auth::list_tokens).post(auth::create_token))
+        .route("/api/tokens/:id", delete(auth::revoke_token))
+        .route("/api/me", get(auth::me))
+
+        // API 路由：家庭组与成员管理
+        // API Routes: Households and member management
+        .route("/api/households", get(households::list_households).post(households::create_household))
+        .route("/api/households/:id/members", post(households::add_member))
+        .route("/api/households/:id/members/:user_id", delete(households::remove_member))
+
+        // API 路由：费用分摊配置、报表与结清
+        // API Routes: Cost split definitions, reports and settlements
+        .route("/api/subscriptions/:id/split", get(splits::get_split).put(splits::set_split).delete(splits::delete_split))
+        .route("/api/splits/report", get(splits::report))
+        .route("/api/splits/settle", post(splits::settle))
+
+        // 用户管理：列出用户与修改角色 (仅管理员)
+        // User management: list users and change roles (admins only)
+        .route("/api/users", get(users::list_users))
+        .route("/api/users/:id/role", put(users::update_role))
+
+        // 中间件：认证 (仅作用于以上 API 路由)
+        // 解析 `Authorization: Bearer` 令牌并校验权限范围。
+        // Middleware: Authentication (applies only to the API routes above).
+        // Resolves `Authorization: Bearer` tokens and checks scopes.
+        .route_layer(middleware::from_fn_with_state(pool.clone(), auth::authenticate))
+
+        // 登录路由：OIDC 单点登录与退出 (不经过认证中间件)
+        // Login routes: OIDC single sign-on and logout (outside the authentication middleware)
+        .merge(oidc::routes(oidc::OidcConfig::from_env()))
+        .route("/auth/logout", post(auth::logout))
         
+        // 旧版本放在 static 目录下的提示词文件不再公开
+        // The legacy prompts file under the static directory is no longer served
+        .route("/prompts.json", get(|| async { axum::http::StatusCode::NOT_FOUND }))
+
         // 静态文件服务
         // 将根路径 "/" 映射到本地的 "static" 目录，用于托管前端页面 (HTML, CSS, JS)。
         // Static file service.
@@ -0,0 +1,341 @@
+//! MIME 邮件解析模块
+//! MIME message parsing module
+//!
+//! 从原始邮件中取出主题、发件人与正文文本：解码 RFC 2047 编码的头部、base64 与
+//! quoted-printable 传输编码以及常见字符集 (UTF-8、GBK、Big5 等)，遍历 multipart 结构，
+//! 优先使用 text/plain，没有时将 text/html 转为纯文本。附件会被忽略。
+//! Extracts the subject, sender and body text from a raw message: decodes RFC 2047 encoded
+//! headers, base64 and quoted-printable transfer encodings and common charsets (UTF-8, GBK,
+//! Big5, ...), walks multipart structures, and prefers text/plain, converting text/html to plain
+//! text when there is none. Attachments are ignored.
+
+use base64::Engine;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// 解析后的邮件
+/// A parsed message
+#[derive(Debug, Default)]
+pub struct Message {
+    pub subject: Option<String>,
+    pub from: Option<String>,
+    pub date: Option<String>,
+    pub text: String,
+}
+
+/// 邮件头部 (名称为小写)
+/// Message headers (names are lowercase)
+struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// 正文部分：纯文本与 HTML 分别收集
+/// Body parts: plain text and HTML collected separately
+#[derive(Default)]
+struct Parts {
+    plain: Vec<String>,
+    html: Vec<String>,
+}
+
+/// multipart 嵌套层数上限
+/// Maximum multipart nesting depth
+const MAX_DEPTH: usize = 8;
+
+static ENCODED_WORD_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"=\?([^?]+)\?([bBqQ])\?([^?]*)\?=").unwrap());
+static ADJACENT_WORDS_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\?=\s+=\?").unwrap());
+static BLOCK_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?is)<(script|style|head)\b.*?</(script|style|head)\s*>").unwrap());
+static BREAK_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)<\s*(br|/p|/div|/tr|/li|/h[1-6]|/table)\b[^>]*>").unwrap());
+static TAG_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<[^>]*>").unwrap());
+static ENTITY_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"&(#x?[0-9a-fA-F]+|[a-zA-Z]+);").unwrap());
+
+/// 解析原始邮件
+/// Parse a raw message
+pub fn parse(raw: &[u8]) -> Message {
+    let (headers, body) = split_headers(raw);
+    let mut parts = Parts::default();
+    collect(&headers, body, 0, &mut parts);
+
+    let text = if parts.plain.iter().any(|p| !p.trim().is_empty()) {
+        parts.plain.join("\n")
+    } else {
+        parts.html.iter().map(|h| html_to_text(h)).collect::<Vec<_>>().join("\n")
+    };
+    Message {
+        subject: headers.get("subject").map(decode_header),
+        from: headers.get("from").map(decode_header),
+        date: headers.get("date").map(str::to_string),
+        text: text.trim().to_string(),
+    }
+}
+
+/// 拆分头部与正文，并展开折叠的头部行
+/// Split headers from the body and unfold folded header lines
+fn split_headers(raw: &[u8]) -> (Headers, &[u8]) {
+    let (head, body) = match find(raw, b"\r\n\r\n") {
+        Some(pos) => (&raw[..pos], &raw[pos + 4..]),
+        None => match find(raw, b"\n\n") {
+            Some(pos) => (&raw[..pos], &raw[pos + 2..]),
+            None => (raw, &raw[raw.len()..]),
+        },
+    };
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in String::from_utf8_lossy(head).lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    (Headers(headers), body)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// 解析 `Content-Type` 等头部的主值与参数
+/// Parse the main value and parameters of headers such as `Content-Type`
+fn header_params(value: &str) -> (String, Vec<(String, String)>) {
+    let mut items = value.split(';');
+    let main = items.next().unwrap_or_default().trim().to_lowercase();
+    let params = items
+        .filter_map(|item| item.split_once('='))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().trim_matches('"').to_string()))
+        .collect();
+    (main, params)
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+}
+
+/// 递归收集正文部分
+/// Recursively collect body parts
+fn collect(headers: &Headers, body: &[u8], depth: usize, parts: &mut Parts) {
+    let (content_type, params) = header_params(headers.get("content-type").unwrap_or("text/plain"));
+    let disposition = headers.get("content-disposition").map(|d| header_params(d).0);
+    if disposition.as_deref() == Some("attachment") {
+        return;
+    }
+
+    if content_type.starts_with("multipart/") {
+        let Some(boundary) = param(&params, "boundary") else {
+            return;
+        };
+        if depth >= MAX_DEPTH {
+            return;
+        }
+        for part in split_multipart(body, boundary) {
+            let (part_headers, part_body) = split_headers(part);
+            collect(&part_headers, part_body, depth + 1, parts);
+        }
+        return;
+    }
+    if content_type != "text/plain" && content_type != "text/html" {
+        return;
+    }
+
+    let encoding = headers.get("content-transfer-encoding").unwrap_or("7bit").trim().to_lowercase();
+    let bytes = match encoding.as_str() {
+        "base64" => decode_base64(body),
+        "quoted-printable" => decode_quoted_printable(body, false),
+        _ => body.to_vec(),
+    };
+    let text = decode_charset(&bytes, param(&params, "charset").unwrap_or("utf-8"));
+    if content_type == "text/html" {
+        parts.html.push(text);
+    } else {
+        parts.plain.push(text);
+    }
+}
+
+/// 按边界拆分 multipart 正文 (忽略前导与结尾部分)
+/// Split a multipart body on its boundary (ignoring the preamble and epilogue)
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let mut parts = Vec::new();
+    let mut rest = body;
+    let Some(first) = find(rest, delimiter) else {
+        return parts;
+    };
+    rest = &rest[first + delimiter.len()..];
+    while !rest.starts_with(b"--") {
+        // 跳过分隔行的剩余部分
+        // Skip the rest of the delimiter line
+        let line_end = rest.iter().position(|b| *b == b'\n').map_or(rest.len(), |p| p + 1);
+        rest = &rest[line_end..];
+        let end = find(rest, delimiter).unwrap_or(rest.len());
+        let mut part = &rest[..end];
+        while part.ends_with(b"\n") || part.ends_with(b"\r") {
+            part = &part[..part.len() - 1];
+        }
+        parts.push(part);
+        if end == rest.len() {
+            break;
+        }
+        rest = &rest[end + delimiter.len()..];
+    }
+    parts
+}
+
+fn decode_base64(body: &[u8]) -> Vec<u8> {
+    // 去掉空白与填充后按无填充格式解码，兼容缺少填充的邮件
+    // Decode without padding after stripping whitespace and padding, tolerating unpadded mail
+    let cleaned: Vec<u8> = body.iter().copied().filter(|b| !b.is_ascii_whitespace() && *b != b'=').collect();
+    base64::engine::general_purpose::STANDARD_NO_PAD.decode(cleaned).unwrap_or_default()
+}
+
+/// 解码 quoted-printable；`header` 为真时按 RFC 2047 将下划线视为空格
+/// Decode quoted-printable; with `header` set, underscores are spaces as in RFC 2047
+fn decode_quoted_printable(body: &[u8], header: bool) -> Vec<u8> {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut out = Vec::with_capacity(body.len());
+    let mut i = 0;
+    while i < body.len() {
+        match body[i] {
+            b'=' if body.get(i + 1) == Some(&b'\r') && body.get(i + 2) == Some(&b'\n') => i += 3,
+            b'=' if body.get(i + 1) == Some(&b'\n') => i += 2,
+            b'=' => match (body.get(i + 1).copied().and_then(hex), body.get(i + 2).copied().and_then(hex)) {
+                (Some(high), Some(low)) => {
+                    out.push(high << 4 | low);
+                    i += 3;
+                }
+                _ => {
+                    out.push(b'=');
+                    i += 1;
+                }
+            },
+            b'_' if header => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// 按字符集解码，未知字符集按 UTF-8 处理
+/// Decode with a charset; unknown charsets are treated as UTF-8
+fn decode_charset(bytes: &[u8], charset: &str) -> String {
+    let encoding = encoding_rs::Encoding::for_label(charset.trim().as_bytes()).unwrap_or(encoding_rs::UTF_8);
+    encoding.decode(bytes).0.into_owned()
+}
+
+/// 解码 RFC 2047 编码的头部，例如 `=?UTF-8?B?5ZOU5ZOp?=`
+/// Decode an RFC 2047 encoded header such as `=?UTF-8?B?5ZOU5ZOp?=`
+fn decode_header(value: &str) -> String {
+    // 相邻编码字之间的空白需要去掉
+    // Whitespace between adjacent encoded words is dropped
+    let joined = ADJACENT_WORDS_RE.replace_all(value, "?==?");
+    ENCODED_WORD_RE
+        .replace_all(&joined, |c: &regex::Captures| {
+            let bytes = if c[2].eq_ignore_ascii_case("b") {
+                decode_base64(c[3].as_bytes())
+            } else {
+                decode_quoted_printable(c[3].as_bytes(), true)
+            };
+            decode_charset(&bytes, &c[1])
+        })
+        .into_owned()
+}
+
+/// 将 HTML 转为纯文本：去掉脚本与样式，块级结束标签换行，去标签并解码实体
+/// Convert HTML to plain text: drop scripts and styles, break lines at block ends, strip tags
+/// and decode entities
+fn html_to_text(html: &str) -> String {
+    let text = BLOCK_RE.replace_all(html, " ");
+    let text = BREAK_RE.replace_all(&text, "\n");
+    let text = TAG_RE.replace_all(&text, " ");
+    let text = ENTITY_RE.replace_all(&text, |c: &regex::Captures| {
+        let entity = &c[1];
+        let decoded = match entity {
+            "nbsp" => Some(' '),
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "yen" => Some('¥'),
+            "euro" => Some('€'),
+            "pound" => Some('£'),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|d| d.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        decoded.map_or_else(|| c[0].to_string(), |ch| ch.to_string())
+    });
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_encoded_words() {
+        assert_eq!(decode_header("=?UTF-8?B?5ZOU5ZOp?= Premium"), "哔哩 Premium");
+        assert_eq!(decode_header("=?iso-8859-1?q?Caf=E9_cr=E8me?="), "Café crème");
+        // 相邻编码字之间的空白被去掉，普通文本保持不变
+        // Whitespace between adjacent encoded words is dropped; plain text is left alone
+        assert_eq!(decode_header("=?UTF-8?Q?a?= =?UTF-8?Q?b?= c"), "ab c");
+        assert_eq!(decode_header("Plain subject"), "Plain subject");
+    }
+
+    #[test]
+    fn decodes_transfer_encodings() {
+        assert_eq!(decode_quoted_printable(b"a=3Db=\r\nc_d", false), b"a=bc_d");
+        assert_eq!(decode_quoted_printable(b"a_b=ZZ", true), b"a b=ZZ");
+        assert_eq!(decode_base64(b"aGVs\r\nbG8"), b"hello");
+        assert_eq!(decode_base64(b"aGVsbG8="), b"hello");
+        assert_eq!(decode_charset(&[0xC4, 0xFA, 0xBA, 0xC3], "gb2312"), "您好");
+        assert_eq!(decode_charset("ok".as_bytes(), "x-unknown"), "ok");
+    }
+
+    #[test]
+    fn converts_html_to_text() {
+        let html = "<head><title>t</title></head><p>Total&nbsp;&yen;25</p><br>&#x4E2D;&#25991; &bogus; <b>x</b>";
+        assert_eq!(html_to_text(html), "Total ¥25\n中文 &bogus; x");
+    }
+
+    /// multipart/alternative 中优先使用 (GB2312、quoted-printable 编码的) 纯文本部分
+    /// In multipart/alternative the plain part (GB2312, quoted-printable) is preferred
+    #[test]
+    fn parses_alternative_message_with_charset() {
+        let message = parse(include_bytes!("../tests/fixtures/mail/alternative_gbk.eml"));
+        assert_eq!(message.subject.as_deref(), Some("爱奇艺会员 续费"));
+        assert_eq!(message.from.as_deref(), Some("爱奇艺 <vip@iqiyi.com>"));
+        assert_eq!(message.date.as_deref(), Some("Sun, 18 Oct 2026 09:30:00 +0800"));
+        assert_eq!(message.text, "您的爱奇艺黄金会员已续费 25.00 元，下次扣款日期 2026-11-18。");
+    }
+
+    /// 只有 HTML 时转为文本，附件被忽略
+    /// With HTML only it is converted to text, and attachments are ignored
+    #[test]
+    fn parses_html_only_message() {
+        let message = parse(include_bytes!("../tests/fixtures/mail/html_only.eml"));
+        assert_eq!(message.subject.as_deref(), Some("Your receipt"));
+        assert_eq!(message.text, "Spotify Premium\nTotal €10.99");
+    }
+}
@@ -1,10 +1,10 @@
-/// 数据模型定义模块
-/// Data models definition module
-/// 
-/// 本模块定义了应用程序中使用的数据结构，包括对应数据库表的结构体 (Entity)
-/// 和用于 API 请求的传输对象 (DTO)。
-/// This module defines the data structures used in the application, including structs
-/// corresponding to database tables (Entities) and Data Transfer Objects (DTOs) for API requests.
+//! 数据模型定义模块
+//! Data models definition module
+//! 
+//! 本模块定义了应用程序中使用的数据结构，包括对应数据库表的结构体 (Entity)
+//! 和用于 API 请求的传输对象 (DTO)。
+//! This module defines the data structures used in the application, including structs
+//! corresponding to database tables (Entities) and Data Transfer Objects (DTOs) for API requests.
 
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -62,6 +62,65 @@ pub struct Subscription {
     /// 是否处于激活状态 (true = 激活, false = 停用)
     /// Whether it is active
     pub active: bool,
+
+    /// 拥有者用户 ID (只有拥有者可以编辑)
+    /// Owner user ID (only the owner can edit)
+    pub owner_id: i64,
+
+    /// 共享到的家庭组 ID (可选，为空表示私有)
+    /// Household the subscription is shared with (optional, empty means private)
+    pub household_id: Option<i64>,
+
+    /// 分类 (可选，例如: video, music, cloud)
+    /// Category (optional, e.g. video, music, cloud)
+    pub category: Option<String>,
+
+    /// 暂停截止日期 (格式: YYYY-MM-DD，仅在暂停时有值；为空且未激活表示无限期暂停)
+    /// Pause end date (Format: YYYY-MM-DD, only set while paused; inactive with no date means
+    /// paused indefinitely)
+    pub paused_until: Option<String>,
+
+    /// 图标的主色与强调色 (#rrggbb，来自图标缓存或自定义 Logo，不保存在订阅表中；尚未获取图标时为空)
+    /// Dominant and accent colours of the icon (#rrggbb, taken from the icon cache or the custom
+    /// logo rather than stored in the subscriptions table; empty until the icon has been fetched)
+    #[sqlx(default)]
+    #[serde(default)]
+    pub dominant_color: Option<String>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub accent_color: Option<String>,
+
+    /// 名称与服务目录只是近似匹配时的建议 (仅出现在创建订阅的响应中)；近似匹配不会自动补全
+    /// Suggestion when the name only approximately matches the service catalog (only present in
+    /// create responses); approximate matches are not filled in automatically
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catalog_suggestion: Option<CatalogSuggestion>,
+}
+
+/// 服务目录建议：客户端可询问用户后用这些值更新订阅
+/// Service catalog suggestion: clients may ask the user and then update the subscription with
+/// these values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatalogSuggestion {
+    pub service_id: String,
+    pub name: String,
+    pub url: String,
+    pub logo: String,
+    pub category: String,
+}
+
+impl Subscription {
+    /// 折算后的每月费用 (永久订阅不计入经常性支出，返回 0)
+    /// Effective monthly cost (lifetime subscriptions are not recurring and return 0)
+    pub fn monthly_cost(&self) -> f64 {
+        match self.frequency {
+            -1 => self.price * 365.0 / 12.0,
+            0 => 0.0,
+            n if n > 0 => self.price / n as f64,
+            _ => 0.0,
+        }
+    }
 }
 
 /// 创建订阅请求载荷结构体
@@ -104,4 +163,490 @@ pub struct CreateSubscription {
     /// 订阅开始日期 (可选)
     /// Subscription start date (Optional)
     pub start_date: Option<String>,
+
+    /// 共享到的家庭组 ID (可选，必须是当前用户所在的家庭组)
+    /// Household to share with (Optional, must be one of the current user's households)
+    #[serde(default)]
+    pub household_id: Option<i64>,
+
+    /// 分类 (可选)
+    /// Category (Optional)
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+impl CreateSubscription {
+    /// 校验载荷，返回最终写入的价格与下次付款日期
+    /// Validate the payload, returning the price and next payment date to store
+    ///
+    /// 永久订阅的价格可选 (默认为 0) 且无需下次付款日期；其他订阅两者必填。
+    /// Lifetime subscriptions have an optional price (default 0) and no next payment date;
+    /// all others require both.
+    pub fn validate(&self) -> Result<(f64, Option<String>), String> {
+        if self.name.trim().is_empty() {
+            return Err("Name is required".to_string());
+        }
+        if ![-1, 0, 1, 3, 12].contains(&self.frequency) {
+            return Err("Invalid frequency".to_string());
+        }
+        if self.frequency == 0 {
+            return Ok((self.price.unwrap_or(0.0), None));
+        }
+        let Some(price) = self.price else {
+            return Err("Price is required for non-lifetime subscriptions".to_string());
+        };
+        if self.next_payment.is_none() {
+            return Err("Next payment date is required for non-lifetime subscriptions".to_string());
+        }
+        Ok((price, self.next_payment.clone()))
+    }
+}
+
+/// 智能解析结果结构体
+/// Smart Parse Result Struct
+///
+/// `POST /api/smart-parse` 的响应，字段与 `CreateSubscription` 一致，并附带每个字段的置信度。
+/// Response of `POST /api/smart-parse`. The fields mirror `CreateSubscription`, with a
+/// confidence value for each field.
+#[derive(Debug, Clone, Serialize)]
+pub struct SmartParseResult {
+    /// 订阅名称
+    /// Subscription name
+    pub name: String,
+
+    /// 价格
+    /// Price
+    pub price: Option<f64>,
+
+    /// 货币类型 (ISO 4217 代码)
+    /// Currency (ISO 4217 code)
+    pub currency: String,
+
+    /// 付款频率 (-1=Daily, 1=Monthly, 3=Quarterly, 12=Yearly, 0=Lifetime)
+    /// Payment frequency
+    pub frequency: i64,
+
+    /// 订阅开始日期 (格式: YYYY-MM-DD)
+    /// Subscription start date (Format: YYYY-MM-DD)
+    pub start_date: Option<String>,
+
+    /// 下次付款日期 (格式: YYYY-MM-DD)
+    /// Next payment date (Format: YYYY-MM-DD)
+    pub next_payment: Option<String>,
+
+    /// 官网链接
+    /// Official website URL
+    pub url: Option<String>,
+
+    /// 分类 (小写，例如 video、music)
+    /// Category (lowercase, e.g. video, music)
+    pub category: Option<String>,
+
+    /// 各字段置信度 (0.0 - 1.0)
+    /// Per-field confidence (0.0 - 1.0)
+    pub confidence: FieldConfidence,
+
+    /// 结果来源: llm 或 rules (离线规则解析)
+    /// Result source: llm or rules (offline rule-based extraction)
+    pub source: &'static str,
+}
+
+/// 智能解析字段置信度
+/// Smart parse field confidence
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FieldConfidence {
+    pub name: f64,
+    pub price: f64,
+    pub currency: f64,
+    pub frequency: f64,
+    pub start_date: f64,
+    pub next_payment: f64,
+}
+
+/// 消费洞察结构体
+/// Spending Insight Struct
+///
+/// 由规则引擎根据订阅数据计算出的发现，`GET /api/insights` 返回，并作为事实提供给 LLM。
+/// A finding computed by the rule engine from subscription data. Returned by
+/// `GET /api/insights` and passed to the LLM as grounded facts.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    /// 类型: duplicate_category, yearly_savings, renewal_soon, price_increase, idle, paused
+    /// Kind: duplicate_category, yearly_savings, renewal_soon, price_increase, idle, paused
+    pub kind: &'static str,
+
+    /// 严重程度: info, warning
+    /// Severity: info, warning
+    pub severity: &'static str,
+
+    /// 涉及的订阅 ID
+    /// Subscriptions involved
+    pub subscription_ids: Vec<i64>,
+
+    /// 描述
+    /// Description
+    pub message: String,
+
+    /// 预计每月可节省或增加的金额 (可选)
+    /// Estimated monthly saving or increase (Optional)
+    pub monthly_amount: Option<f64>,
+
+    /// 金额的货币类型 (可选)
+    /// Currency of the amount (Optional)
+    pub currency: Option<String>,
+
+    /// 金额是按经验比例估算的，而不是根据订阅数据计算的 (例如年付折扣)
+    /// The amount is estimated from a rule of thumb rather than computed from subscription data
+    /// (such as the yearly discount)
+    pub estimated: bool,
+}
+
+/// 用户结构体
+/// User struct
+///
+/// 对应数据库中的 `users` 表 (不含 OIDC 关联字段)。
+/// Corresponds to the `users` table (without the OIDC link columns).
+#[derive(Debug, FromRow, Serialize)]
+pub struct User {
+    /// 唯一标识符
+    /// Unique identifier
+    pub id: i64,
+
+    /// 用户名
+    /// Username
+    pub username: String,
+
+    /// 邮箱 (可选)
+    /// Email (optional)
+    pub email: Option<String>,
+
+    /// 显示名称 (可选)
+    /// Display name (optional)
+    pub display_name: Option<String>,
+
+    /// 角色: viewer, editor, admin
+    /// Role: viewer, editor, admin
+    pub role: String,
+
+    /// 创建时间 (UTC)
+    /// Creation time (UTC)
+    pub created_at: String,
+}
+
+/// 个人访问令牌结构体
+/// Personal access token struct
+///
+/// 对应数据库中的 `api_tokens` 表。不包含令牌哈希，以免其出现在 API 响应中。
+/// Corresponds to the `api_tokens` table. The token hash is left out so it never reaches API responses.
+#[derive(Debug, FromRow, Serialize)]
+pub struct ApiToken {
+    /// 唯一标识符
+    /// Unique identifier
+    pub id: i64,
+
+    /// 所属用户 ID
+    /// Owning user ID
+    pub user_id: i64,
+
+    /// 令牌名称 (例如: "cron backup", "home dashboard")
+    /// Token name
+    pub name: String,
+
+    /// 令牌前缀，用于在列表中辨认令牌
+    /// Token prefix, used to recognise a token in listings
+    pub token_prefix: String,
+
+    /// 权限范围，逗号分隔 (read, write, admin)
+    /// Scopes, comma separated (read, write, admin)
+    pub scopes: String,
+
+    /// 过期时间 (UTC, 格式: YYYY-MM-DD HH:MM:SS, 可选)
+    /// Expiry time (UTC, optional)
+    pub expires_at: Option<String>,
+
+    /// 最近一次使用时间 (UTC)
+    /// Last used time (UTC)
+    pub last_used_at: Option<String>,
+
+    /// 创建时间 (UTC)
+    /// Creation time (UTC)
+    pub created_at: String,
+
+    /// 吊销时间 (UTC)，为空表示仍然有效
+    /// Revocation time (UTC); empty means still valid
+    pub revoked_at: Option<String>,
+}
+
+/// 创建令牌请求载荷结构体
+/// Create Token Request Payload Struct
+///
+/// 用于接收 `POST /api/tokens` 请求提交的 JSON 数据。
+/// Used to receive JSON data submitted by `POST /api/tokens`.
+#[derive(Debug, Deserialize)]
+pub struct CreateApiToken {
+    /// 令牌名称 (必填)
+    /// Token name (Required)
+    pub name: String,
+
+    /// 权限范围 (默认为 ["read"])
+    /// Scopes (Default: ["read"])
+    #[serde(default)]
+    pub scopes: Vec<String>,
+
+    /// 有效天数 (可选，1–3650，不填表示永不过期)
+    /// Validity in days (Optional, 1–3650; omitted means never expires)
+    pub expires_in_days: Option<i64>,
+}
+
+/// 家庭组结构体
+/// Household struct
+///
+/// 对应数据库中的 `households` 表。
+/// Corresponds to the `households` table.
+#[derive(Debug, FromRow, Serialize)]
+pub struct Household {
+    /// 唯一标识符
+    /// Unique identifier
+    pub id: i64,
+
+    /// 家庭组名称
+    /// Household name
+    pub name: String,
+
+    /// 组长用户 ID (可以管理成员)
+    /// Owner user ID (can manage members)
+    pub owner_id: i64,
+
+    /// 创建时间 (UTC)
+    /// Creation time (UTC)
+    pub created_at: String,
+}
+
+/// 家庭组成员结构体
+/// Household member struct
+#[derive(Debug, FromRow, Serialize)]
+pub struct HouseholdMember {
+    /// 用户 ID
+    /// User ID
+    pub user_id: i64,
+
+    /// 用户名
+    /// Username
+    pub username: String,
+
+    /// 显示名称 (可选)
+    /// Display name (optional)
+    pub display_name: Option<String>,
+
+    /// 加入时间 (UTC)
+    /// Join time (UTC)
+    pub joined_at: String,
+}
+
+/// 创建家庭组请求载荷结构体
+/// Create Household Request Payload Struct
+#[derive(Debug, Deserialize)]
+pub struct CreateHousehold {
+    /// 家庭组名称 (必填)
+    /// Household name (Required)
+    pub name: String,
+}
+
+/// 添加家庭组成员请求载荷结构体
+/// Add Household Member Request Payload Struct
+#[derive(Debug, Deserialize)]
+pub struct AddHouseholdMember {
+    /// 要添加的用户名
+    /// Username to add
+    pub username: String,
+}
+
+/// 费用分摊成员配置
+/// Cost split member entry
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SplitMember {
+    /// 成员用户 ID
+    /// Member user ID
+    pub user_id: i64,
+
+    /// 分摊份额：`percentage` 模式为百分比，`fixed` 模式为每期固定金额，`equal` 模式忽略
+    /// Share: a percentage in `percentage` mode, a fixed amount per payment in `fixed` mode,
+    /// ignored in `equal` mode
+    pub share: Option<f64>,
+}
+
+/// 设置费用分摊请求载荷结构体
+/// Set Cost Split Request Payload Struct
+///
+/// 用于 `PUT /api/subscriptions/:id/split`。
+/// Used by `PUT /api/subscriptions/:id/split`.
+#[derive(Debug, Deserialize)]
+pub struct SetSplit {
+    /// 分摊方式: equal (平均), percentage (百分比), fixed (固定金额)
+    /// Split method: equal, percentage, fixed
+    pub method: String,
+
+    /// 参与分摊的成员
+    /// Members taking part in the split
+    pub members: Vec<SplitMember>,
+}
+
+/// 标记结清请求载荷结构体
+/// Mark Settled Request Payload Struct
+///
+/// 用于 `POST /api/splits/settle`。
+/// Used by `POST /api/splits/settle`.
+#[derive(Debug, Deserialize)]
+pub struct SettleSplit {
+    /// 订阅 ID
+    /// Subscription ID
+    pub subscription_id: i64,
+
+    /// 结清的成员用户 ID
+    /// Member user ID being settled
+    pub user_id: i64,
+
+    /// 账单周期 (格式: YYYY-MM，默认为当月)
+    /// Billing period (Format: YYYY-MM, defaults to the current month)
+    pub period: Option<String>,
+}
+
+/// 修改用户角色请求载荷结构体
+/// Update User Role Request Payload Struct
+///
+/// 用于 `PUT /api/users/:id/role`。
+/// Used by `PUT /api/users/:id/role`.
+#[derive(Debug, Deserialize)]
+pub struct UpdateUserRole {
+    /// 新角色: viewer, editor, admin
+    /// New role: viewer, editor, admin
+    pub role: String,
+}
+
+/// 待审核条目结构体
+/// Review Queue Item Struct
+///
+/// 对应数据库中的 `review_queue` 表：邮件等来源解析出的订阅，审核通过后才会创建。
+/// Corresponds to the `review_queue` table: subscriptions parsed from sources such as email,
+/// created only after approval.
+#[derive(Debug, FromRow, Serialize)]
+pub struct ReviewItem {
+    pub id: i64,
+
+    /// 来源 (目前为 email)
+    /// Source (currently email)
+    pub source: String,
+
+    /// 邮件主题
+    /// Email subject
+    pub subject: Option<String>,
+
+    /// 发件人
+    /// Sender
+    pub sender: Option<String>,
+
+    /// 邮件日期 (Date 头部原文)
+    /// Email date (raw Date header)
+    pub received_at: Option<String>,
+
+    /// 送入解析的文本
+    /// Text that was parsed
+    pub excerpt: String,
+
+    /// 智能解析结果 (与 `POST /api/smart-parse` 的响应相同)
+    /// Smart parse result (same as the `POST /api/smart-parse` response)
+    #[sqlx(json)]
+    pub parsed: serde_json::Value,
+
+    /// 状态: pending, approved, rejected
+    /// Status: pending, approved, rejected
+    pub status: String,
+
+    /// 审核通过后创建的订阅 ID
+    /// ID of the subscription created on approval
+    pub subscription_id: Option<i64>,
+
+    pub created_at: String,
+}
+
+/// 助手对话结构体
+/// Assistant Conversation Struct
+///
+/// 对应数据库中的 `chat_conversations` 表，每个用户的对话彼此独立。
+/// Corresponds to the `chat_conversations` table; every user's conversations are separate.
+#[derive(Debug, FromRow, Serialize)]
+pub struct ChatConversation {
+    pub id: i64,
+
+    /// 标题 (取自第一条消息)
+    /// Title (taken from the first message)
+    pub title: String,
+
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// 助手对话消息结构体
+/// Assistant Conversation Message Struct
+///
+/// 对应数据库中的 `chat_messages` 表。
+/// Corresponds to the `chat_messages` table.
+#[derive(Debug, FromRow, Serialize)]
+pub struct ChatMessageRecord {
+    pub id: i64,
+
+    /// 角色: user, assistant, tool
+    /// Role: user, assistant, tool
+    pub role: String,
+
+    /// 消息文本
+    /// Message text
+    pub content: String,
+
+    /// 调用的工具 (assistant 消息) 或产生结果的工具 (tool 消息)
+    /// Tool called (assistant messages) or the tool that produced the result (tool messages)
+    pub tool: Option<String>,
+
+    /// 工具参数 (assistant 消息) 或工具结果 (tool 消息)
+    /// Tool arguments (assistant messages) or tool result (tool messages)
+    #[sqlx(json)]
+    pub payload: serde_json::Value,
+
+    pub created_at: String,
+}
+
+/// 助手提出的写操作结构体
+/// Assistant Write Action Struct
+///
+/// 对应数据库中的 `chat_actions` 表：助手提出的创建、修改、暂停等操作需要用户确认后才会执行。
+/// Corresponds to the `chat_actions` table: creating, changing or pausing subscriptions proposed
+/// by the assistant only runs once the user confirms it.
+#[derive(Debug, FromRow, Serialize)]
+pub struct ChatAction {
+    pub id: i64,
+    pub conversation_id: i64,
+
+    /// 工具名称
+    /// Tool name
+    pub tool: String,
+
+    /// 执行时使用的完整参数
+    /// Full arguments used on execution
+    #[sqlx(json)]
+    pub arguments: serde_json::Value,
+
+    /// 供用户确认的操作说明
+    /// Description of the action for the user to confirm
+    pub summary: String,
+
+    /// 状态: pending, confirmed, cancelled, failed
+    /// Status: pending, confirmed, cancelled, failed
+    pub status: String,
+
+    /// 执行结果 (订阅或错误信息)
+    /// Execution result (the subscription or an error)
+    #[sqlx(json)]
+    pub result: serde_json::Value,
+
+    pub created_at: String,
 }
@@ -0,0 +1,734 @@
+//! OpenID Connect 单点登录模块
+//! OpenID Connect single sign-on module
+//!
+//! 实现带 PKCE 的授权码登录流程：从签发方 (issuer) 读取发现文档，跳转到授权端点，
+//! 在回调中用授权码换取 ID Token，将声明 (claims) 映射为本地用户并签发浏览器会话。
+//! Implements the authorization-code login flow with PKCE: reads the issuer's discovery
+//! document, redirects to the authorization endpoint, exchanges the code for an ID token in the
+//! callback, maps the claims to a local user and issues a browser session.
+//!
+//! ID Token 通过 TLS 直接从令牌端点获取，按 OIDC Core 3.1.3.7 以 TLS 校验代替签名校验，
+//! 但仍会校验 `iss`、`aud`、`exp` 与 `nonce`。
+//! The ID token is received directly from the token endpoint, so per OIDC Core 3.1.3.7 TLS
+//! server validation stands in for signature validation; `iss`, `aud`, `exp` and `nonce` are
+//! still checked.
+
+use crate::auth::{self, Role};
+use crate::db::DbPool;
+use axum::{
+    extract::{Query, State},
+    http::{header::SET_COOKIE, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Extension, Router,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+
+/// 登录流程的最长等待时间 (从跳转到回调)
+/// Maximum time between the login redirect and the callback
+const PENDING_TTL: Duration = Duration::from_secs(600);
+
+/// 同时等待回调的登录请求上限，超过时丢弃最早的请求
+/// Maximum number of logins awaiting their callback; beyond it the oldest one is dropped
+const MAX_PENDING: usize = 1000;
+
+/// 保存 `state` 的 Cookie，把登录回调绑定到发起登录的浏览器，防止登录 CSRF
+/// Cookie holding the `state`, which binds the callback to the browser that started the login and
+/// prevents login CSRF
+const STATE_COOKIE: &str = "wallet_oidc_state";
+
+/// OIDC 配置 (启动时从环境变量读取，通过 `routes` 注入处理函数)
+/// OIDC configuration (read from environment variables at startup and injected into the
+/// handlers by `routes`)
+pub struct OidcConfig {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: String,
+    scopes: String,
+    username_claim: String,
+    groups_claim: String,
+    admin_group: Option<String>,
+    default_role: Role,
+    /// 签发方的发现文档，首次使用时读取 / The issuer's discovery document, read on first use
+    discovery: OnceCell<Discovery>,
+}
+
+impl OidcConfig {
+    /// 读取 OIDC 配置；未设置 `OIDC_ISSUER`、`OIDC_CLIENT_ID` 或 `OIDC_REDIRECT_URL` 时视为未启用
+    /// Read the OIDC configuration; disabled unless `OIDC_ISSUER`, `OIDC_CLIENT_ID` and
+    /// `OIDC_REDIRECT_URL` are all set
+    pub fn from_env() -> Option<Self> {
+        let env = |key: &str| std::env::var(key).ok().filter(|v| !v.trim().is_empty());
+        Some(OidcConfig {
+            issuer: env("OIDC_ISSUER")?.trim_end_matches('/').to_string(),
+            client_id: env("OIDC_CLIENT_ID")?,
+            client_secret: env("OIDC_CLIENT_SECRET"),
+            redirect_url: env("OIDC_REDIRECT_URL")?,
+            scopes: env("OIDC_SCOPES").unwrap_or_else(|| "openid profile email".to_string()),
+            username_claim: env("OIDC_USERNAME_CLAIM").unwrap_or_else(|| "preferred_username".to_string()),
+            groups_claim: env("OIDC_GROUPS_CLAIM").unwrap_or_else(|| "groups".to_string()),
+            admin_group: env("OIDC_ADMIN_GROUP"),
+            default_role: env("OIDC_DEFAULT_ROLE").and_then(|r| Role::parse(&r)).unwrap_or(Role::Editor),
+            discovery: OnceCell::new(),
+        })
+    }
+}
+
+/// 登录路由 (不经过认证中间件)；`config` 为 `None` 时返回 404
+/// Login routes (outside the authentication middleware); they return 404 when `config` is `None`
+pub fn routes(config: Option<OidcConfig>) -> Router<DbPool> {
+    Router::new()
+        .route("/auth/oidc/login", get(login))
+        .route("/auth/oidc/callback", get(callback))
+        .layer(Extension(config.map(Arc::new)))
+}
+
+type Config = Extension<Option<Arc<OidcConfig>>>;
+
+/// 签发方发现文档 (`/.well-known/openid-configuration`) 中用到的字段
+/// Fields used from the issuer discovery document (`/.well-known/openid-configuration`)
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to build OIDC HTTP client")
+});
+
+/// 等待回调的登录请求 (以 `state` 为键)
+/// Login attempt awaiting its callback (keyed by `state`)
+struct PendingLogin {
+    nonce: String,
+    code_verifier: String,
+    created: Instant,
+}
+
+static PENDING: Lazy<Mutex<HashMap<String, PendingLogin>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 记录等待回调的登录：先清理过期请求，达到上限时丢弃最早的请求
+/// Record a login awaiting its callback: expired logins are pruned first, and at the cap the
+/// oldest one is dropped
+fn insert_pending(pending: &mut HashMap<String, PendingLogin>, state: String, login: PendingLogin) {
+    pending.retain(|_, p| p.created.elapsed() < PENDING_TTL);
+    while pending.len() >= MAX_PENDING {
+        let Some(oldest) = pending.iter().min_by_key(|(_, p)| p.created).map(|(k, _)| k.clone()) else {
+            break;
+        };
+        pending.remove(&oldest);
+    }
+    pending.insert(state, login);
+}
+
+/// `state` Cookie；`value` 为空时删除 Cookie
+/// The `state` cookie; an empty `value` deletes it
+fn state_cookie(value: &str, secure: bool) -> HeaderValue {
+    let max_age = if value.is_empty() { 0 } else { PENDING_TTL.as_secs() };
+    let cookie = format!(
+        "{}={}; Path=/auth/oidc; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        STATE_COOKIE,
+        value,
+        max_age,
+        if secure { "; Secure" } else { "" }
+    );
+    HeaderValue::from_str(&cookie).expect("cookie is ASCII")
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    access_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+fn config(config: &Option<Arc<OidcConfig>>) -> Result<&OidcConfig, (StatusCode, String)> {
+    config
+        .as_deref()
+        .ok_or((StatusCode::NOT_FOUND, "OIDC is not configured".to_string()))
+}
+
+async fn discovery(cfg: &OidcConfig) -> Result<&Discovery, (StatusCode, String)> {
+    cfg.discovery
+        .get_or_try_init(|| async {
+            let url = format!("{}/.well-known/openid-configuration", cfg.issuer);
+            let doc = CLIENT
+                .get(&url)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| (StatusCode::BAD_GATEWAY, format!("OIDC discovery failed: {}", e)))?
+                .json::<Discovery>()
+                .await
+                .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Invalid OIDC discovery document: {}", e)))?;
+            if doc.issuer.trim_end_matches('/') != cfg.issuer {
+                return Err((StatusCode::BAD_GATEWAY, "OIDC discovery issuer mismatch".to_string()));
+            }
+            info!("Loaded OIDC discovery document from {}", url);
+            Ok(doc)
+        })
+        .await
+}
+
+/// 发起 OIDC 登录 (GET /auth/oidc/login)
+/// Start an OIDC login
+///
+/// 生成 `state`、`nonce` 与 PKCE `code_verifier`，把 `state` 写入 Cookie，然后跳转到授权端点。
+/// Generates `state`, `nonce` and the PKCE `code_verifier`, stores the `state` in a cookie, then
+/// redirects to the authorization endpoint.
+pub async fn login(Extension(cfg): Config) -> Response {
+    let cfg = match config(&cfg) {
+        Ok(c) => c,
+        Err(e) => return e.into_response(),
+    };
+    let doc = match discovery(cfg).await {
+        Ok(d) => d,
+        Err(e) => return e.into_response(),
+    };
+
+    let state = auth::random_secret();
+    let nonce = auth::random_secret();
+    let code_verifier = URL_SAFE_NO_PAD.encode(auth::random_secret());
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+    let login = PendingLogin { nonce: nonce.clone(), code_verifier, created: Instant::now() };
+    insert_pending(&mut PENDING.lock(), state.clone(), login);
+
+    let mut url = match url::Url::parse(&doc.authorization_endpoint) {
+        Ok(u) => u,
+        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    };
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &cfg.client_id)
+        .append_pair("redirect_uri", &cfg.redirect_url)
+        .append_pair("scope", &cfg.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &code_challenge)
+        .append_pair("code_challenge_method", "S256");
+
+    let mut resp = Redirect::to(url.as_str()).into_response();
+    resp.headers_mut().insert(SET_COOKIE, state_cookie(&state, cfg.redirect_url.starts_with("https://")));
+    resp
+}
+
+/// 解码 JWT 载荷 (不校验签名，见模块说明)
+/// Decode a JWT payload (signature not checked, see module docs)
+fn decode_jwt_claims(jwt: &str) -> Option<serde_json::Map<String, serde_json::Value>> {
+    let payload = jwt.split('.').nth(1)?;
+    let bytes = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// 校验 ID Token 的 `iss`、`aud`、`exp` 与 `nonce`，返回非空的 `sub`
+/// Validate the `iss`, `aud`, `exp` and `nonce` of an ID token, returning its non-empty `sub`
+fn validate_claims(
+    claims: &serde_json::Map<String, serde_json::Value>,
+    cfg: &OidcConfig,
+    nonce: &str,
+) -> Result<String, String> {
+    let iss = claims.get("iss").and_then(|v| v.as_str()).unwrap_or_default();
+    if iss.trim_end_matches('/') != cfg.issuer {
+        return Err(format!("unexpected issuer {}", iss));
+    }
+    let aud_ok = match claims.get("aud") {
+        Some(serde_json::Value::String(a)) => *a == cfg.client_id,
+        Some(serde_json::Value::Array(list)) => list.iter().any(|a| a.as_str() == Some(cfg.client_id.as_str())),
+        _ => false,
+    };
+    if !aud_ok {
+        return Err("audience does not include client id".to_string());
+    }
+    let exp = claims.get("exp").and_then(|v| v.as_i64()).unwrap_or(0);
+    if exp <= chrono::Utc::now().timestamp() {
+        return Err("token expired".to_string());
+    }
+    if claims.get("nonce").and_then(|v| v.as_str()) != Some(nonce) {
+        return Err("nonce mismatch".to_string());
+    }
+    match claims.get("sub").and_then(|v| v.as_str()) {
+        Some(sub) if !sub.trim().is_empty() => Ok(sub.to_string()),
+        _ => Err("missing subject".to_string()),
+    }
+}
+
+/// 将声明映射为本地用户 (`subject` 为已校验的 `sub`)，首次登录时自动创建
+/// Map claims to a local user (`subject` is the validated `sub`), creating it on first login
+async fn upsert_user(
+    pool: &DbPool,
+    cfg: &OidcConfig,
+    subject: &str,
+    claims: &serde_json::Map<String, serde_json::Value>,
+) -> Result<i64, sqlx::Error> {
+    let claim = |key: &str| claims.get(key).and_then(|v| v.as_str()).map(|s| s.to_string());
+    let email = claim("email");
+    let display_name = claim("name");
+    let username = claim(&cfg.username_claim)
+        .or_else(|| email.clone())
+        .unwrap_or_else(|| subject.to_string());
+
+    // 管理员映射：仅在配置了 OIDC_ADMIN_GROUP 时每次登录同步
+    // 组内用户提升为 admin，移出分组的管理员降为 editor
+    // Admin mapping: synced on every login only when OIDC_ADMIN_GROUP is configured.
+    // Group members become admin; admins no longer in the group drop to editor
+    let is_admin = cfg.admin_group.as_ref().map(|group| match claims.get(&cfg.groups_claim) {
+        Some(serde_json::Value::Array(list)) => list.iter().any(|g| g.as_str() == Some(group.as_str())),
+        Some(serde_json::Value::String(g)) => g.split([',', ' ']).any(|g| g == group),
+        _ => false,
+    });
+
+    let existing: Option<(i64,)> = sqlx::query_as("SELECT id FROM users WHERE oidc_issuer = ? AND oidc_subject = ?")
+        .bind(&cfg.issuer)
+        .bind(subject)
+        .fetch_optional(pool)
+        .await?;
+
+    if let Some((id,)) = existing {
+        sqlx::query(
+            r#"
+            UPDATE users SET email = ?, display_name = ?,
+                role = CASE
+                    WHEN ? IS NULL THEN role
+                    WHEN ? THEN 'admin'
+                    WHEN role = 'admin' THEN 'editor'
+                    ELSE role
+                END
+            WHERE id = ?
+            "#,
+        )
+        .bind(&email)
+        .bind(&display_name)
+        .bind(is_admin)
+        .bind(is_admin)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        return Ok(id);
+    }
+
+    // 用户名已被占用时依次追加更长的 subject 片段，最后追加随机后缀；并发登录抢占同名时同样重试
+    // When the username is taken, append ever longer pieces of the subject and finally a random
+    // suffix; a concurrent login taking the same name is retried the same way
+    let role = if is_admin == Some(true) { Role::Admin } else { cfg.default_role };
+    let subject_chars: Vec<char> = subject.chars().collect();
+    let mut candidates = vec![username.clone()];
+    for len in [8, 16, subject_chars.len()] {
+        let candidate = format!("{}-{}", username, subject_chars[..len.min(subject_chars.len())].iter().collect::<String>());
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+    candidates.push(format!("{}-{}", username, &auth::random_secret()[..8]));
+
+    let mut inserted = None;
+    for candidate in candidates {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO users (username, email, display_name, oidc_issuer, oidc_subject, role)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&candidate)
+        .bind(&email)
+        .bind(&display_name)
+        .bind(&cfg.issuer)
+        .bind(subject)
+        .bind(role.as_str())
+        .execute(pool)
+        .await;
+        match result {
+            Ok(r) => {
+                inserted = Some((r.last_insert_rowid(), candidate));
+                break;
+            }
+            Err(e) if is_username_taken(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    let Some((id, username)) = inserted else {
+        return Err(sqlx::Error::Protocol(format!("no free username for OIDC subject {}", subject)));
+    };
+
+    info!("Created user {} ({}) from OIDC login", id, username);
+    Ok(id)
+}
+
+fn is_username_taken(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|d| d.is_unique_violation() && d.message().contains("users.username"))
+}
+
+/// OIDC 回调 (GET /auth/oidc/callback)
+/// OIDC callback
+///
+/// 校验 `state` (必须与发起登录的浏览器中的 Cookie 一致)，用授权码与 PKCE `code_verifier`
+/// 换取令牌，校验 ID Token，签发会话 Cookie 后跳转回首页。
+/// Checks `state` (which must match the cookie in the browser that started the login), exchanges
+/// the code and PKCE `code_verifier` for tokens, validates the ID token, issues a session cookie
+/// and redirects back to the home page.
+pub async fn callback(
+    State(pool): State<DbPool>,
+    Extension(cfg): Config,
+    headers: HeaderMap,
+    Query(params): Query<CallbackQuery>,
+) -> Response {
+    let cfg = match config(&cfg) {
+        Ok(c) => c,
+        Err(e) => return e.into_response(),
+    };
+    if let Some(err) = params.error {
+        warn!("OIDC provider returned error: {} {:?}", err, params.error_description);
+        return (StatusCode::UNAUTHORIZED, format!("OIDC error: {}", err)).into_response();
+    }
+    let (Some(code), Some(state)) = (params.code, params.state) else {
+        return (StatusCode::BAD_REQUEST, "Missing code or state".to_string()).into_response();
+    };
+    if auth::cookie(&headers, STATE_COOKIE) != Some(state.as_str()) {
+        warn!("Rejected OIDC callback whose state does not match the browser's state cookie");
+        return (StatusCode::BAD_REQUEST, "Login state does not match this browser".to_string()).into_response();
+    }
+    let pending = match PENDING.lock().remove(&state) {
+        Some(p) if p.created.elapsed() < PENDING_TTL => p,
+        _ => return (StatusCode::BAD_REQUEST, "Unknown or expired login state".to_string()).into_response(),
+    };
+    let doc = match discovery(cfg).await {
+        Ok(d) => d,
+        Err(e) => return e.into_response(),
+    };
+
+    // 1. 用授权码换取令牌
+    //    Exchange the authorization code for tokens
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code.as_str()),
+        ("redirect_uri", cfg.redirect_url.as_str()),
+        ("client_id", cfg.client_id.as_str()),
+        ("code_verifier", pending.code_verifier.as_str()),
+    ];
+    if let Some(secret) = &cfg.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+    let tokens = match CLIENT.post(&doc.token_endpoint).form(&form).send().await.and_then(|r| r.error_for_status()) {
+        Ok(r) => match r.json::<TokenResponse>().await {
+            Ok(t) => t,
+            Err(e) => return (StatusCode::BAD_GATEWAY, format!("Invalid token response: {}", e)).into_response(),
+        },
+        Err(e) => {
+            warn!("OIDC token exchange failed: {}", e);
+            return (StatusCode::BAD_GATEWAY, format!("Token exchange failed: {}", e)).into_response();
+        }
+    };
+
+    // 2. 校验 ID Token
+    //    Validate the ID token
+    let Some(mut claims) = decode_jwt_claims(&tokens.id_token) else {
+        return (StatusCode::BAD_GATEWAY, "Malformed ID token".to_string()).into_response();
+    };
+    let subject = match validate_claims(&claims, cfg, &pending.nonce) {
+        Ok(subject) => subject,
+        Err(e) => {
+            warn!("Rejected OIDC ID token: {}", e);
+            return (StatusCode::UNAUTHORIZED, format!("Invalid ID token: {}", e)).into_response();
+        }
+    };
+
+    // 3. 合并 userinfo 声明 (部分签发方只在 userinfo 中返回邮箱或分组)
+    //    Merge userinfo claims (some issuers only return email or groups from userinfo)
+    if let (Some(endpoint), Some(access_token)) = (&doc.userinfo_endpoint, &tokens.access_token) {
+        match CLIENT.get(endpoint).bearer_auth(access_token).send().await {
+            Ok(r) => {
+                if let Ok(serde_json::Value::Object(info)) = r.json::<serde_json::Value>().await {
+                    if info.get("sub") == claims.get("sub") {
+                        for (k, v) in info {
+                            claims.entry(k).or_insert(v);
+                        }
+                    }
+                }
+            }
+            Err(e) => warn!("OIDC userinfo request failed: {}", e),
+        }
+    }
+
+    // 4. 映射本地用户并签发会话
+    //    Map to a local user and issue a session
+    let user_id = match upsert_user(&pool, cfg, &subject, &claims).await {
+        Ok(id) => id,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let secure = cfg.redirect_url.starts_with("https://");
+    let cookie = match auth::create_session(&pool, user_id, secure).await {
+        Ok(c) => c,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    info!("User {} logged in via OIDC", user_id);
+    let mut resp = Redirect::to("/").into_response();
+    resp.headers_mut().insert(SET_COOKIE, cookie);
+    resp.headers_mut().append(SET_COOKIE, state_cookie("", secure));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use axum::{extract::Form, routing::post, Json};
+
+    /// 模拟签发方为下一次令牌请求准备的数据
+    /// What the mock issuer answers to the next token request
+    #[derive(Default)]
+    struct Issued {
+        code_challenge: String,
+        nonce: String,
+        claims: serde_json::Value,
+    }
+
+    /// 进程内的模拟签发方：发现文档与令牌端点 (校验授权码与 PKCE)
+    /// In-process mock issuer: discovery document and token endpoint (checking the code and PKCE)
+    async fn mock_issuer() -> (String, Arc<Mutex<Issued>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let issued = Arc::new(Mutex::new(Issued::default()));
+        let doc = serde_json::json!({
+            "issuer": base,
+            "authorization_endpoint": format!("{}/authorize", base),
+            "token_endpoint": format!("{}/token", base),
+        });
+        let state = issued.clone();
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(move || async move { Json(doc) }))
+            .route(
+                "/token",
+                post(move |Form(form): Form<HashMap<String, String>>| async move {
+                    let issued = state.lock();
+                    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+                    if form["code"] != "code-1" || challenge != issued.code_challenge {
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                    let mut claims = issued.claims.clone();
+                    claims["nonce"] = issued.nonce.clone().into();
+                    let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+                    Ok(Json(serde_json::json!({ "id_token": format!("e30.{}.sig", payload) })))
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base, issued)
+    }
+
+    fn test_config(issuer: &str) -> Option<Arc<OidcConfig>> {
+        Some(Arc::new(OidcConfig {
+            issuer: issuer.to_string(),
+            client_id: "wallet".to_string(),
+            client_secret: None,
+            redirect_url: "http://localhost/auth/oidc/callback".to_string(),
+            scopes: "openid".to_string(),
+            username_claim: "preferred_username".to_string(),
+            groups_claim: "groups".to_string(),
+            admin_group: Some("admins".to_string()),
+            default_role: Role::Editor,
+            discovery: OnceCell::new(),
+        }))
+    }
+
+    fn cookie_header(cookie: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(axum::http::header::COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    fn callback_query(state: &str) -> Query<CallbackQuery> {
+        Query(CallbackQuery {
+            code: Some("code-1".to_string()),
+            state: Some(state.to_string()),
+            error: None,
+            error_description: None,
+        })
+    }
+
+    /// 发起登录，返回授权地址的查询参数与浏览器保存的 `state` Cookie
+    /// Start a login, returning the query of the authorization address and the `state` cookie the
+    /// browser stores
+    async fn start_login(cfg: &Option<Arc<OidcConfig>>) -> (HashMap<String, String>, String) {
+        let redirect = login(Extension(cfg.clone())).await;
+        let location = url::Url::parse(redirect.headers()["location"].to_str().unwrap()).unwrap();
+        let query: HashMap<String, String> = location.query_pairs().into_owned().collect();
+        assert_eq!(query["client_id"], "wallet");
+        let set_cookie = redirect.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.contains("HttpOnly") && set_cookie.contains("SameSite=Lax"));
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+        assert_eq!(cookie, format!("{}={}", STATE_COOKIE, query["state"]));
+        (query, cookie)
+    }
+
+    /// 完整的登录流程：跳转、换取令牌、校验 ID Token，返回回调响应
+    /// The full login flow: redirect, code exchange and ID token validation; returns the callback
+    /// response
+    async fn sign_in(
+        pool: &DbPool,
+        cfg: &Option<Arc<OidcConfig>>,
+        issued: &Mutex<Issued>,
+        claims: serde_json::Value,
+    ) -> Response {
+        let (query, cookie) = start_login(cfg).await;
+
+        let issuer = cfg.as_ref().unwrap().issuer.clone();
+        let mut claims = claims;
+        claims["iss"] = issuer.into();
+        claims["aud"] = "wallet".into();
+        claims["exp"] = (chrono::Utc::now().timestamp() + 60).into();
+        *issued.lock() =
+            Issued { code_challenge: query["code_challenge"].clone(), nonce: query["nonce"].clone(), claims };
+
+        let headers = cookie_header(&cookie);
+        callback(State(pool.clone()), Extension(cfg.clone()), headers, callback_query(&query["state"])).await
+    }
+
+    async fn user(pool: &DbPool, subject: &str) -> Option<(i64, String, String)> {
+        sqlx::query_as("SELECT id, username, role FROM users WHERE oidc_subject = ?")
+            .bind(subject)
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn signs_in_against_mock_issuer() {
+        let pool = db::test_pool().await;
+        let (issuer, issued) = mock_issuer().await;
+        let cfg = test_config(&issuer);
+
+        let response = sign_in(
+            &pool,
+            &cfg,
+            &issued,
+            serde_json::json!({ "sub": "a1", "preferred_username": "alice", "groups": ["admins"] }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(response.headers().contains_key(SET_COOKIE));
+        let (id, username, role) = user(&pool, "a1").await.unwrap();
+        assert_eq!((username.as_str(), role.as_str()), ("alice", "admin"));
+
+        // 再次登录映射到同一用户，移出管理员分组后降为 editor
+        // Signing in again maps to the same user, who drops to editor once out of the admin group
+        let response = sign_in(&pool, &cfg, &issued, serde_json::json!({ "sub": "a1", "preferred_username": "alice" })).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(user(&pool, "a1").await.unwrap(), (id, "alice".to_string(), "editor".to_string()));
+
+        // 未知的 state 被拒绝 / An unknown state is rejected
+        let headers = cookie_header(&format!("{}=unknown", STATE_COOKIE));
+        let replay = callback(State(pool.clone()), Extension(cfg.clone()), headers, callback_query("unknown")).await;
+        assert_eq!(replay.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// 回调必须带有发起登录的浏览器中的 `state` Cookie，否则被拒绝且不消耗该 state
+    /// The callback must carry the `state` cookie of the browser that started the login; otherwise
+    /// it is rejected without using up the state
+    #[tokio::test]
+    async fn rejects_callback_from_another_browser() {
+        let pool = db::test_pool().await;
+        let (issuer, issued) = mock_issuer().await;
+        let cfg = test_config(&issuer);
+        let (query, cookie) = start_login(&cfg).await;
+        let claims = serde_json::json!({
+            "sub": "csrf-1", "iss": issuer, "aud": "wallet", "exp": chrono::Utc::now().timestamp() + 60
+        });
+        let (code_challenge, nonce) = (query["code_challenge"].clone(), query["nonce"].clone());
+        *issued.lock() = Issued { code_challenge, nonce, claims };
+
+        let (_, other_cookie) = start_login(&cfg).await;
+        for headers in [HeaderMap::new(), cookie_header(&other_cookie), cookie_header(&format!("{}=", STATE_COOKIE))] {
+            let response =
+                callback(State(pool.clone()), Extension(cfg.clone()), headers, callback_query(&query["state"])).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+        assert!(user(&pool, "csrf-1").await.is_none());
+
+        let headers = cookie_header(&cookie);
+        let response = callback(State(pool.clone()), Extension(cfg), headers, callback_query(&query["state"])).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let cleared = response.headers().get_all(SET_COOKIE).iter().any(|v| {
+            v.to_str().unwrap().starts_with(&format!("{}=;", STATE_COOKIE))
+        });
+        assert!(cleared);
+        assert!(user(&pool, "csrf-1").await.is_some());
+    }
+
+    /// 等待回调的登录数量有上限，超过时丢弃最早的请求
+    /// The number of pending logins is capped, dropping the oldest beyond it
+    #[test]
+    fn caps_pending_logins() {
+        let mut pending = HashMap::new();
+        let start = Instant::now();
+        for i in 0..MAX_PENDING + 10 {
+            let login = PendingLogin {
+                nonce: String::new(),
+                code_verifier: String::new(),
+                created: start + Duration::from_millis(i as u64),
+            };
+            insert_pending(&mut pending, format!("state-{}", i), login);
+        }
+        assert_eq!(pending.len(), MAX_PENDING);
+        assert!(!pending.contains_key("state-9"));
+        assert!(pending.contains_key("state-10"));
+        assert!(pending.contains_key(&format!("state-{}", MAX_PENDING + 9)));
+    }
+
+    /// 没有 `sub` 的 ID Token 被拒绝，不会创建用户
+    /// An ID token without `sub` is rejected and creates no user
+    #[tokio::test]
+    async fn rejects_token_without_subject() {
+        let pool = db::test_pool().await;
+        let (issuer, issued) = mock_issuer().await;
+        let cfg = test_config(&issuer);
+
+        for claims in [serde_json::json!({ "preferred_username": "mallory" }), serde_json::json!({ "sub": " " })] {
+            let response = sign_in(&pool, &cfg, &issued, claims).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM users WHERE oidc_subject IS NOT NULL").fetch_one(&pool).await.unwrap();
+        assert_eq!(count, 0);
+    }
+
+    /// 用户名被占用时追加更长的 subject 片段
+    /// Ever longer pieces of the subject are appended while the username is taken
+    #[tokio::test]
+    async fn retries_taken_usernames() {
+        let pool = db::test_pool().await;
+        let (issuer, issued) = mock_issuer().await;
+        let cfg = test_config(&issuer);
+        for taken in ["bob", "bob-12345678"] {
+            sqlx::query("INSERT INTO users (username) VALUES (?)").bind(taken).execute(&pool).await.unwrap();
+        }
+
+        let subject = "1234567890abcdefXYZ";
+        let response = sign_in(&pool, &cfg, &issued, serde_json::json!({ "sub": subject, "preferred_username": "bob" })).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(user(&pool, subject).await.unwrap().1, "bob-1234567890abcdef");
+    }
+}
@@ -0,0 +1,430 @@
+//! 出站请求策略模块
+//! Outbound request policy module
+//!
+//! 图标获取与域名搜索会请求由用户输入决定的地址，因此统一经过这里的客户端：
+//! - DNS 解析后丢弃私有、回环、链路本地等内部地址，直接写 IP 的地址同样检查，防止 SSRF；
+//! - 只允许 http/https，限制重定向次数，重定向的每一跳同样检查并计入限流；
+//! - 限制响应大小，只接受预期的内容类型；
+//! - 按目标主机限流 (令牌桶)，避免被 DuckDuckGo 等服务封禁。
+//!
+//! Icon fetching and domain search request addresses derived from user input, so they go
+//! through the client here:
+//! - after DNS resolution, internal addresses (private, loopback, link-local, ...) are dropped,
+//!   and literal IP addresses are checked the same way, to prevent SSRF;
+//! - only http/https is allowed, redirects are capped and every hop is checked and rate limited
+//!   too;
+//! - responses are size-limited and only the expected content types are accepted;
+//! - requests are rate limited per destination host (token bucket) so services such as
+//!   DuckDuckGo do not ban us.
+
+use hyper::client::connect::dns::Name;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::error::Error as _;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// 默认的按主机限流规则 (后缀=每分钟请求数)
+/// Default per-host rate limit rules (suffix=requests per minute)
+const DEFAULT_HOST_RATES: &str = "duckduckgo.com=20,google.com=60";
+
+/// 策略配置 (启动后首次使用时读取环境变量)
+/// Policy configuration (environment variables are read on first use)
+struct Policy {
+    /// 允许访问内部地址 (仅用于开发或内网部署) / Allow internal addresses (development or intranet only)
+    allow_private: bool,
+    max_redirects: usize,
+    /// 未匹配规则的主机每分钟的请求数 / Requests per minute for hosts without a rule
+    default_rate: u32,
+    /// 主机后缀与每分钟请求数 / Host suffixes with their requests per minute
+    host_rates: Vec<(String, u32)>,
+    /// 等待令牌的最长时间，超过时直接失败 / Longest wait for a token before failing
+    max_wait: Duration,
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+static POLICY: Lazy<Policy> = Lazy::new(|| {
+    let host_rates = env_var("OUTBOUND_HOST_RATES")
+        .unwrap_or_else(|| DEFAULT_HOST_RATES.to_string())
+        .split(',')
+        .filter_map(|rule| {
+            let (host, rate) = rule.split_once('=')?;
+            Some((host.trim().trim_start_matches('.').to_lowercase(), rate.trim().parse().ok().filter(|r| *r > 0)?))
+        })
+        .collect();
+    Policy {
+        allow_private: env_var("OUTBOUND_ALLOW_PRIVATE").is_some_and(|v| v == "1" || v.eq_ignore_ascii_case("true")),
+        max_redirects: env_var("OUTBOUND_MAX_REDIRECTS").and_then(|v| v.parse().ok()).unwrap_or(3),
+        default_rate: env_var("OUTBOUND_RATE_PER_MINUTE").and_then(|v| v.parse().ok()).filter(|r| *r > 0).unwrap_or(60),
+        host_rates,
+        max_wait: Duration::from_secs(env_var("OUTBOUND_RATE_MAX_WAIT_SECS").and_then(|v| v.parse().ok()).unwrap_or(5)),
+    }
+});
+
+/// 是否为不允许访问的内部地址
+/// Whether an address is internal and must not be contacted
+fn is_forbidden(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_forbidden_v4(v4),
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_forbidden_v4(v4);
+            }
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // 唯一本地地址 fc00::/7 / Unique local fc00::/7
+                || first & 0xfe00 == 0xfc00
+                // 链路本地 fe80::/10 / Link-local fe80::/10
+                || first & 0xffc0 == 0xfe80
+                // 文档地址 2001:db8::/32 / Documentation 2001:db8::/32
+                || (first == 0x2001 && v6.segments()[1] == 0x0db8)
+                // NAT64 64:ff9b::/96 可能指向内部 IPv4 / NAT64 64:ff9b::/96 may reach internal IPv4
+                || (first == 0x64 && v6.segments()[1] == 0xff9b && v6.segments()[2..6] == [0, 0, 0, 0]
+                    && is_forbidden_v4(Ipv4Addr::from(((v6.segments()[6] as u32) << 16) | v6.segments()[7] as u32)))
+        }
+    }
+}
+
+fn is_forbidden_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // 0.0.0.0/8、运营商级 NAT 100.64.0.0/10、基准测试 198.18.0.0/15、保留 240.0.0.0/4
+        // 0.0.0.0/8, carrier-grade NAT 100.64.0.0/10, benchmarking 198.18.0.0/15, reserved 240.0.0.0/4
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 198 && (b == 18 || b == 19))
+        || a >= 240
+}
+
+/// 只返回允许访问的地址的 DNS 解析器；全部为内部地址时解析失败
+/// DNS resolver that only returns allowed addresses; resolution fails when all are internal
+struct GuardedResolver;
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let resolved: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if POLICY.allow_private {
+                return Ok(Box::new(resolved.into_iter()) as Addrs);
+            }
+            let allowed: Vec<SocketAddr> = resolved.iter().copied().filter(|addr| !is_forbidden(addr.ip())).collect();
+            if allowed.is_empty() {
+                return Err(format!("{} resolves to an internal address", host).into());
+            }
+            Ok(Box::new(allowed.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// 检查地址的协议与直接写出的 IP (主机名由解析器检查)
+/// Check the scheme of a URL and a literal IP host (host names are checked by the resolver)
+fn check_url(url: &url::Url) -> Result<(), String> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("scheme {} is not allowed", url.scheme()));
+    }
+    let ip = match url.host() {
+        Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+        Some(url::Host::Domain(_)) => return Ok(()),
+        None => return Err("URL has no host".to_string()),
+    };
+    if !POLICY.allow_private && is_forbidden(ip) {
+        return Err(format!("{} is an internal address", ip));
+    }
+    Ok(())
+}
+
+/// 令牌桶状态 / Token bucket state
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+static BUCKETS: Lazy<Mutex<HashMap<String, Bucket>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 主机对应的限流键与每分钟请求数：匹配规则的主机共享该规则的令牌桶
+/// Rate limit key and requests per minute of a host: hosts matching a rule share its bucket
+fn rate_for(host: &str) -> (String, u32) {
+    POLICY
+        .host_rates
+        .iter()
+        .find(|(suffix, _)| host == suffix || host.ends_with(&format!(".{}", suffix)))
+        .map(|(suffix, rate)| (suffix.clone(), *rate))
+        .unwrap_or_else(|| (host.to_string(), POLICY.default_rate))
+}
+
+/// 取得一个令牌，必要时等待；需要等待过久时返回错误
+/// Take a token, waiting if needed; fails when the wait would be too long
+async fn acquire(host: &str) -> Result<(), String> {
+    let (key, rate) = rate_for(host);
+    let per_sec = rate as f64 / 60.0;
+    // 允许短时间内的少量突发 (例如获取同一站点的首页与图标)
+    // Allow a small burst (such as the homepage and icon of the same site)
+    let capacity = (rate as f64 / 4.0).max(1.0);
+    let wait = {
+        let mut buckets = BUCKETS.lock();
+        let now = Instant::now();
+        // 十分钟未使用的令牌桶早已装满，可以丢弃
+        // Buckets unused for ten minutes are full again and can be dropped
+        if buckets.len() > 1024 {
+            buckets.retain(|_, b| now.duration_since(b.updated).as_secs() < 600);
+        }
+        let bucket = buckets.entry(key.clone()).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_sec).min(capacity);
+        bucket.updated = now;
+        let wait = Duration::from_secs_f64(((1.0 - bucket.tokens) / per_sec).max(0.0));
+        if wait > POLICY.max_wait {
+            return Err(format!("rate limit for {} exceeded", key));
+        }
+        // 预留令牌 (可能为负)，等待期间的其他请求排在后面
+        // Reserve the token (possibly going negative) so other requests queue up behind this one
+        bucket.tokens -= 1.0;
+        wait
+    };
+    if !wait.is_zero() {
+        debug!("Waiting {:?} for the {} rate limit", wait, key);
+        tokio::time::sleep(wait).await;
+    }
+    Ok(())
+}
+
+/// 包含底层原因的错误描述 (例如被拒绝的内部地址)
+/// Error description including the underlying causes (such as a rejected internal address)
+fn describe(e: &reqwest::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        let cause_text = cause.to_string();
+        if !message.contains(&cause_text) {
+            message.push_str(": ");
+            message.push_str(&cause_text);
+        }
+        source = cause.source();
+    }
+    message
+}
+
+/// 遵守出站策略的 HTTP 客户端
+/// HTTP client that enforces the outbound policy
+pub struct Client {
+    inner: reqwest::Client,
+}
+
+impl Client {
+    /// 构造客户端；不使用系统代理，否则 DNS 检查会被代理绕过。重定向由 `Request::send` 逐跳处理
+    /// Build a client; system proxies are not used, since they would bypass the DNS check.
+    /// Redirects are followed hop by hop in `Request::send`
+    pub fn new(user_agent: &str, timeout: Duration) -> Self {
+        // 构造失败时不能退回到不受策略约束的默认客户端
+        // A failed build must not fall back to a default client that ignores the policy
+        let inner = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .no_proxy()
+            .dns_resolver(Arc::new(GuardedResolver))
+            .build()
+            .expect("Failed to build outbound HTTP client");
+        Client { inner }
+    }
+
+    /// 构造 GET 请求 / Build a GET request
+    pub fn get(&self, url: &str) -> Request<'_> {
+        Request { client: &self.inner, url: url.to_string(), headers: Vec::new(), max_bytes: 1024 * 1024, accept: &[] }
+    }
+}
+
+/// 出站 GET 请求
+/// Outbound GET request
+pub struct Request<'a> {
+    client: &'a reqwest::Client,
+    url: String,
+    headers: Vec<(&'static str, String)>,
+    max_bytes: usize,
+    accept: &'static [&'static str],
+}
+
+impl Request<'_> {
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    /// 响应体大小上限 (默认 1 MB) / Response body limit (1 MB by default)
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// 接受的内容类型前缀；没有 Content-Type 的响应也接受 (由调用方识别内容)
+    /// Accepted content type prefixes; responses without a Content-Type are accepted too (the
+    /// caller inspects the content)
+    pub fn accept(mut self, accept: &'static [&'static str]) -> Self {
+        self.accept = accept;
+        self
+    }
+
+    /// 发送请求并读取响应；非 2xx 状态、意外的内容类型与过大的响应都视为失败
+    /// Send the request and read the response; non-2xx statuses, unexpected content types and
+    /// oversized responses are failures
+    pub async fn send(self) -> Result<Response, String> {
+        let mut url = url::Url::parse(&self.url).map_err(|e| e.to_string())?;
+        let mut redirects = 0;
+        // 每一跳 (包括重定向) 都检查地址并取得目标主机的令牌
+        // Every hop (redirects included) checks the address and takes a token for its host
+        let mut response = loop {
+            check_url(&url)?;
+            acquire(&url.host_str().unwrap_or_default().to_lowercase()).await?;
+            let mut builder = self.client.get(url.clone());
+            for (name, value) in &self.headers {
+                builder = builder.header(*name, value);
+            }
+            let response = builder.send().await.map_err(|e| describe(&e))?;
+            let Some(next) = redirect_target(&url, response.status(), response.headers()) else {
+                break response;
+            };
+            if redirects == POLICY.max_redirects {
+                return Err(format!("more than {} redirects", POLICY.max_redirects));
+            }
+            redirects += 1;
+            url = next?;
+        };
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.split(';').next().unwrap_or_default().trim().to_lowercase());
+        if let Some(content_type) = &content_type {
+            if !self.accept.is_empty() && !self.accept.iter().any(|a| content_type.starts_with(a)) {
+                return Err(format!("unexpected content type {}", content_type));
+            }
+        }
+        if response.content_length().is_some_and(|len| len as usize > self.max_bytes) {
+            return Err("response too large".to_string());
+        }
+        let url = response.url().clone();
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| describe(&e))? {
+            body.extend_from_slice(&chunk);
+            if body.len() > self.max_bytes {
+                return Err("response too large".to_string());
+            }
+        }
+        Ok(Response { url, body })
+    }
+}
+
+/// 重定向响应的目标地址 (相对地址按当前地址解析)；不是重定向时返回 `None`
+/// Target of a redirect response (relative locations resolve against the current URL); `None`
+/// when the response is not a redirect
+fn redirect_target(
+    url: &url::Url,
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+) -> Option<Result<url::Url, String>> {
+    if !status.is_redirection() || status == reqwest::StatusCode::NOT_MODIFIED {
+        return None;
+    }
+    let location = headers.get(reqwest::header::LOCATION)?;
+    Some(
+        location
+            .to_str()
+            .map_err(|e| e.to_string())
+            .and_then(|location| url.join(location).map_err(|e| format!("invalid redirect: {}", e))),
+    )
+}
+
+/// 已读取的响应
+/// A fully read response
+pub struct Response {
+    /// 重定向后的最终地址 / Final URL after redirects
+    pub url: url::Url,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, String> {
+        serde_json::from_slice(&self.body).map_err(|e| e.to_string())
+    }
+}
+
+/// 在启动时读取并输出策略
+/// Read and log the policy at startup
+pub fn init() {
+    let rules: Vec<String> = POLICY.host_rates.iter().map(|(host, rate)| format!("{}={}/min", host, rate)).collect();
+    info!(
+        "Outbound policy: internal addresses {}, {} redirects max, {}/min per host ({})",
+        if POLICY.allow_private { "allowed" } else { "blocked" },
+        POLICY.max_redirects,
+        POLICY.default_rate,
+        rules.join(", ")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 内部地址被拒绝，公网地址允许；匹配规则的主机共享限流键
+    /// Internal addresses are rejected and public ones allowed; hosts matching a rule share a key
+    #[test]
+    fn blocks_internal_addresses() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "64:ff9b::a9fe:a9fe"] {
+            assert!(is_forbidden(ip.parse().unwrap()), "{} should be blocked", ip);
+        }
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700::1111", "64:ff9b::101:101"] {
+            assert!(!is_forbidden(ip.parse().unwrap()), "{} should be allowed", ip);
+        }
+        assert!(check_url(&url::Url::parse("http://[::1]:8080/").unwrap()).is_err());
+        assert!(check_url(&url::Url::parse("file:///etc/passwd").unwrap()).is_err());
+        assert!(check_url(&url::Url::parse("https://example.com/").unwrap()).is_ok());
+        assert_eq!(rate_for("html.duckduckgo.com").0, "duckduckgo.com");
+        assert_eq!(rate_for("notduckduckgo.com").0, "notduckduckgo.com");
+    }
+
+    #[test]
+    fn resolves_redirect_targets() {
+        let base = url::Url::parse("https://example.com/a/b").unwrap();
+        let target = |status: u16, location: Option<&str>| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            if let Some(location) = location {
+                headers.insert(reqwest::header::LOCATION, location.parse().unwrap());
+            }
+            redirect_target(&base, reqwest::StatusCode::from_u16(status).unwrap(), &headers)
+                .map(|r| r.map(|u| u.to_string()))
+        };
+        assert_eq!(target(301, Some("/icon.png")), Some(Ok("https://example.com/icon.png".to_string())));
+        assert_eq!(target(302, Some("c")), Some(Ok("https://example.com/a/c".to_string())));
+        assert_eq!(target(307, Some("http://127.0.0.1/")), Some(Ok("http://127.0.0.1/".to_string())));
+        assert_eq!(target(200, Some("/elsewhere")), None);
+        assert_eq!(target(304, Some("/elsewhere")), None);
+        assert_eq!(target(302, None), None);
+        // 重定向目标同样经过地址检查 / Redirect targets go through the same address check
+        assert!(check_url(&url::Url::parse("http://127.0.0.1/").unwrap()).is_err());
+    }
+}
+
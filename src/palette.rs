@@ -0,0 +1,146 @@
+//! 图标配色提取模块
+//! Icon colour extraction module
+//!
+//! 用 NeuQuant 调色板量化 (color_quant) 把图标的可见像素归纳为少量颜色：出现最多的颜色为主色；
+//! 与主色差别明显、出现较多且饱和度较高的颜色为强调色 (没有时与主色相同)。前端可以用它们为卡片
+//! 着色。
+//! Reduces the visible pixels of an icon to a few colours with NeuQuant palette quantisation
+//! (color_quant): the most frequent colour is the dominant colour; a frequent, saturated colour
+//! clearly different from it is the accent colour (the dominant colour when there is none). The
+//! frontend can use them to tint cards.
+
+use color_quant::NeuQuant;
+use image::ImageFormat;
+
+/// 量化的颜色数 / Number of quantised colours
+const PALETTE_SIZE: usize = 8;
+
+/// 视为同一颜色的最大距离 (RGB 欧氏距离)
+/// Largest distance treated as the same colour (Euclidean RGB distance)
+const MERGE_DISTANCE: f64 = 24.0;
+
+/// 强调色与主色的最小距离 / Minimum distance between the accent and dominant colours
+const ACCENT_DISTANCE: f64 = 64.0;
+
+/// 图标配色
+/// Icon colours
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colors {
+    /// 主色 (#rrggbb) / Dominant colour (#rrggbb)
+    pub dominant: String,
+    /// 强调色 (#rrggbb) / Accent colour (#rrggbb)
+    pub accent: String,
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (*x as f64 - *y as f64).powi(2)).sum::<f64>().sqrt()
+}
+
+/// HSL 饱和度 (0–1) / HSL saturation (0–1)
+fn saturation([r, g, b]: [u8; 3]) -> f64 {
+    let max = r.max(g).max(b) as f64 / 255.0;
+    let min = r.min(g).min(b) as f64 / 255.0;
+    let lightness = (max + min) / 2.0;
+    if max == min {
+        0.0
+    } else {
+        (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+    }
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// 在阻塞线程池中提取配色 (解码与量化较耗时，不应占用异步工作线程)
+/// Extract the colours on the blocking thread pool (decoding and quantisation are too slow for an
+/// async worker thread)
+pub async fn extract_blocking(png: Vec<u8>) -> Option<Colors> {
+    tokio::task::spawn_blocking(move || extract(&png)).await.ok().flatten()
+}
+
+/// 从 PNG 中提取配色；几乎全透明的图片返回 `None`
+/// Extract the colours of a PNG; almost fully transparent images return `None`
+pub fn extract(png: &[u8]) -> Option<Colors> {
+    let rgba = image::load_from_memory_with_format(png, ImageFormat::Png).ok()?.to_rgba8();
+    // 只统计基本不透明的像素 / Only count mostly opaque pixels
+    let visible: Vec<u8> = rgba.pixels().filter(|p| p[3] >= 128).flat_map(|p| [p[0], p[1], p[2], 255]).collect();
+    if visible.len() < 16 * 4 {
+        return None;
+    }
+
+    let quant = NeuQuant::new(10, PALETTE_SIZE, &visible);
+    let mut counts = [0usize; PALETTE_SIZE];
+    for pixel in visible.chunks_exact(4) {
+        counts[quant.index_of(pixel)] += 1;
+    }
+    let palette = quant.color_map_rgb();
+    let mut entries: Vec<([u8; 3], usize)> = palette
+        .chunks_exact(3)
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|(c, count)| ([c[0], c[1], c[2]], count))
+        .collect();
+    entries.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    // 合并量化后几乎相同的颜色 (单色图标会得到多个相近的条目)
+    // Merge nearly identical quantised colours (a single-colour icon yields several close entries)
+    let mut groups: Vec<([u8; 3], usize)> = Vec::new();
+    for (color, count) in entries {
+        match groups.iter_mut().find(|(g, _)| distance(*g, color) <= MERGE_DISTANCE) {
+            Some(group) => group.1 += count,
+            None => groups.push((color, count)),
+        }
+    }
+    groups.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    let total = visible.len() / 4;
+    let (dominant, _) = *groups.first()?;
+    let accent = groups
+        .iter()
+        .skip(1)
+        .filter(|(color, count)| *count * 100 >= total * 3 && distance(*color, dominant) >= ACCENT_DISTANCE)
+        .max_by(|a, b| {
+            let score = |(color, count): &([u8; 3], usize)| *count as f64 * (0.3 + saturation(*color));
+            score(a).total_cmp(&score(b))
+        })
+        .map_or(dominant, |(color, _)| *color);
+    Some(Colors { dominant: hex(dominant), accent: hex(accent) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    fn png(image: RgbaImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(image).write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).unwrap();
+        bytes
+    }
+
+    fn rgb(hex: &str) -> [u8; 3] {
+        let v = u32::from_str_radix(&hex[1..], 16).unwrap();
+        [(v >> 16) as u8, (v >> 8) as u8, v as u8]
+    }
+
+    /// 白底红标：主色为白色、强调色为红色；透明像素不计入；单色图标的强调色与主色相同
+    /// Red mark on white: white dominates and red is the accent; transparent pixels are ignored;
+    /// a single-colour icon uses its dominant colour as the accent
+    #[test]
+    fn extracts_dominant_and_accent() {
+        let image = RgbaImage::from_fn(32, 32, |x, y| match (x, y) {
+            (0..=3, _) => Rgba([0, 0, 255, 0]),
+            (10..=17, 10..=17) => Rgba([220, 20, 30, 255]),
+            _ => Rgba([255, 255, 255, 255]),
+        });
+        let colors = extract(&png(image)).unwrap();
+        assert!(distance(rgb(&colors.dominant), [255, 255, 255]) < 16.0, "{:?}", colors);
+        assert!(distance(rgb(&colors.accent), [220, 20, 30]) < 32.0, "{:?}", colors);
+
+        let solid = extract(&png(RgbaImage::from_pixel(16, 16, Rgba([30, 120, 200, 255])))).unwrap();
+        assert_eq!(solid.dominant, solid.accent);
+        assert!(extract(&png(RgbaImage::new(16, 16))).is_none());
+    }
+}
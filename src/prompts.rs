@@ -0,0 +1,568 @@
+//! 提示词模块
+//! Prompts module
+//!
+//! 管理 AI 提示词并渲染模板变量。提示词按版本保存在数据库中，当前版本缓存在内存；
+//! 提示词文件 (`PROMPTS_FILE`，默认 `config/prompts.json`) 变更时自动导入为新版本，
+//! 管理员也可以通过 `GET/PUT /api/prompts` 查看和修改。保存前会校验模板变量。
+//! 除各接口自身的变量 (`{text}`、`{list}`、`{facts}`) 外，所有模板都可以使用：
+//! `{today}` 今天的日期、`{timezone}` 服务器时区、`{base_currency}` 默认货币、
+//! `{known_categories}` 已知的订阅分类。
+//! Manages the AI prompts and renders template variables. Prompts are stored in the database as
+//! versions with the current one cached in memory; changes to the prompts file (`PROMPTS_FILE`,
+//! default `config/prompts.json`) are imported as new versions automatically, and admins can view
+//! and edit them through `GET/PUT /api/prompts`. Template variables are validated before saving.
+//! Besides each endpoint's own variables (`{text}`, `{list}`, `{facts}`),
+//! every template can use `{today}` for today's date, `{timezone}` for the server time zone,
+//! `{base_currency}` for the default currency and `{known_categories}` for the known
+//! subscription categories.
+
+use crate::auth::{AuthUser, Role};
+use crate::dates;
+use crate::db::DbPool;
+use crate::households::VISIBLE_TO_USER;
+use crate::insights;
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::NaiveDate;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// 默认货币，来自 `BASE_CURRENCY` 环境变量 (默认 CNY)
+/// Default currency, from the `BASE_CURRENCY` environment variable (default CNY)
+static BASE_CURRENCY: Lazy<String> = Lazy::new(|| {
+    std::env::var("BASE_CURRENCY")
+        .ok()
+        .map(|c| c.trim().to_uppercase())
+        .filter(|c| !c.is_empty())
+        .unwrap_or_else(|| "CNY".to_string())
+});
+
+/// 默认货币
+/// Default currency
+pub fn base_currency() -> &'static str {
+    &BASE_CURRENCY
+}
+
+/// 提示词文件路径，来自 `PROMPTS_FILE` 环境变量 (默认 `config/prompts.json`)
+/// Prompts file path, from the `PROMPTS_FILE` environment variable (default `config/prompts.json`)
+static PROMPTS_FILE: Lazy<PathBuf> =
+    Lazy::new(|| std::env::var("PROMPTS_FILE").unwrap_or_else(|_| "config/prompts.json".to_string()).into());
+
+/// 旧版本的提示词位置 (曾由静态文件服务公开)，首次启动时导入
+/// Legacy prompts location (formerly exposed by the static file service), imported on first start
+const LEGACY_PROMPTS_FILE: &str = "static/prompts.json";
+
+/// 检查提示词文件变更的间隔
+/// Interval for checking the prompts file for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 各接口共用的模板变量
+/// Template variables shared by all endpoints
+pub const SHARED_PLACEHOLDERS: [&str; 4] = ["today", "timezone", "base_currency", "known_categories"];
+
+/// 每个提示词字段自身允许的变量与必需的变量
+/// Each prompt field's own allowed variables and required variables
+const FIELD_PLACEHOLDERS: [(&str, &[&str], &[&str]); 5] = [
+    ("smart_parse_system", &[], &[]),
+    ("smart_parse_user_template", &["text"], &["text"]),
+    ("analyze_system", &[], &[]),
+    ("analyze_user_template", &["list", "facts"], &["list"]),
+    ("chat_system", &[], &[]),
+];
+
+static PLACEHOLDER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{([a-z_]+)\}").unwrap());
+
+/// 当前生效的提示词
+/// The prompts currently in effect
+static CURRENT: Lazy<RwLock<Prompts>> = Lazy::new(|| RwLock::new(Prompts::default()));
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Prompts {
+    pub smart_parse_system: String,
+    pub smart_parse_user_template: String,
+    pub analyze_system: String,
+    pub analyze_user_template: String,
+    pub chat_system: String,
+}
+
+impl Default for Prompts {
+    fn default() -> Self {
+        Prompts {
+            smart_parse_system: "You are a helpful assistant that extracts JSON.".to_string(),
+            smart_parse_user_template: "You are a subscription data extractor. Today is {today} ({timezone}). Extract details from this text: '{text}'. Return ONLY a valid JSON object with these fields: name (string), price (number), currency (string ISO 4217 code, {base_currency} if not stated), start_date (string YYYY-MM-DD; resolve relative dates such as 'today', 'next Friday', 'in 30 days' or '明天' against today's date), next_payment (string YYYY-MM-DD, synonymous with end_date; one billing period after start_date if not stated), frequency (number: -1=daily, 1=monthly, 3=quarterly, 12=yearly, 0=lifetime), category (one of {known_categories}, or a short lowercase word). If missing, guess or leave null.".to_string(),
+            analyze_system: "You are a financial advisor.".to_string(),
+            analyze_user_template: "作为订阅优化顾问，请仅依据下面的订阅信息给出 3–5 条中文建议（Markdown 列表）。今天是 {today}（{timezone}）。不要进行任何金额计算或估算，涉及金额时只引用已确认的事实。关注冗余订阅、升级/降级机会、取消指引、以及临近到期的提醒。\n\n列表：\n{list}\n\n已确认的事实（由系统根据数据计算）：\n{facts}".to_string(),
+            chat_system: "You are the assistant of a subscription tracker. Today is {today} ({timezone}); the default currency is {base_currency}. Answer questions about the user's subscriptions and help change them, replying in the user's language. Use the tools to look up data instead of guessing, and never invent amounts or exchange rates: report totals per currency as the summary tool returns them. To change a subscription, first find its id with list_subscriptions. Resolve relative dates such as 'until March' against today's date into YYYY-MM-DD. Known categories: {known_categories}.".to_string(),
+        }
+    }
+}
+
+impl Prompts {
+    fn field(&self, name: &str) -> &str {
+        match name {
+            "smart_parse_system" => &self.smart_parse_system,
+            "smart_parse_user_template" => &self.smart_parse_user_template,
+            "analyze_system" => &self.analyze_system,
+            "chat_system" => &self.chat_system,
+            _ => &self.analyze_user_template,
+        }
+    }
+
+    /// 校验模板：不能为空，只能使用允许的变量，且包含必需的变量
+    /// Validate the templates: not empty, only allowed variables, and all required variables present
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, own, required) in FIELD_PLACEHOLDERS {
+            let template = self.field(name);
+            if template.trim().is_empty() {
+                return Err(format!("{} must not be empty", name));
+            }
+            for c in PLACEHOLDER_RE.captures_iter(template) {
+                let placeholder = &c[1];
+                if !own.contains(&placeholder) && !SHARED_PLACEHOLDERS.contains(&placeholder) {
+                    return Err(format!("{} uses unknown placeholder {{{}}}", name, placeholder));
+                }
+            }
+            if let Some(missing) = required.iter().find(|p| !template.contains(&format!("{{{}}}", p))) {
+                return Err(format!("{} must contain {{{}}}", name, missing));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 当前生效的提示词 (内存副本，不读磁盘)
+/// The prompts currently in effect (in-memory copy, no disk access)
+pub fn get_prompts() -> Prompts {
+    CURRENT.read().clone()
+}
+
+/// 提示词版本记录
+/// Prompt version record
+#[derive(Serialize, FromRow)]
+pub struct PromptVersion {
+    pub version: i64,
+    /// 来源: default, file, api, rollback
+    /// Source: default, file, api, rollback
+    pub source: String,
+    pub created_by: Option<i64>,
+    pub created_at: String,
+}
+
+fn sha256(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+/// 解析并校验提示词文件内容
+/// Parse and validate prompts file content
+fn parse_file(content: &str) -> Result<Prompts, String> {
+    let prompts: Prompts = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    prompts.validate()?;
+    Ok(prompts)
+}
+
+/// 保存新版本并设为当前版本
+/// Save a new version and make it current
+async fn save_version(
+    pool: &DbPool,
+    prompts: &Prompts,
+    source: &str,
+    file_hash: Option<String>,
+    created_by: Option<i64>,
+) -> Result<i64, sqlx::Error> {
+    let content = serde_json::to_string(prompts).unwrap_or_default();
+    let version = sqlx::query("INSERT INTO prompt_versions (content, source, file_hash, created_by) VALUES (?, ?, ?, ?)")
+        .bind(content)
+        .bind(source)
+        .bind(file_hash)
+        .bind(created_by)
+        .execute(pool)
+        .await?
+        .last_insert_rowid();
+    *CURRENT.write() = prompts.clone();
+    info!("Prompts version {} is now active (source: {})", version, source);
+    Ok(version)
+}
+
+/// 提示词文件有变化时导入为新版本
+/// Import the prompts file as a new version when it has changed
+///
+/// 仅当文件内容与上次导入或写入的内容不同时才导入，因此通过 API 保存后重启不会被旧文件覆盖；
+/// 文件无效时保留当前版本并记录警告。
+/// Only imports when the file differs from what was last imported or written, so a restart after
+/// saving through the API is not overridden by a stale file; an invalid file keeps the current
+/// version and logs a warning.
+async fn sync_file(pool: &DbPool, file: &Path) -> Result<(), sqlx::Error> {
+    let Ok(content) = tokio::fs::read_to_string(file).await else {
+        return Ok(());
+    };
+    let hash = sha256(&content);
+    let last: Option<(String,)> =
+        sqlx::query_as("SELECT file_hash FROM prompt_versions WHERE file_hash IS NOT NULL ORDER BY id DESC LIMIT 1")
+            .fetch_optional(pool)
+            .await?;
+    if last.is_some_and(|(h,)| h == hash) {
+        return Ok(());
+    }
+    match parse_file(&content) {
+        Ok(prompts) => {
+            save_version(pool, &prompts, "file", Some(hash), None).await?;
+        }
+        Err(e) => warn!("Ignoring invalid prompts file {}: {}", file.display(), e),
+    }
+    Ok(())
+}
+
+/// 写回提示词文件，返回应记录的文件哈希
+/// Write the prompts file back, returning the file hash to record
+///
+/// 写入失败时只记录警告，并返回文件现有内容的哈希：这样旧文件被视为已导入，重启后不会覆盖
+/// 新版本，之后对文件的修改仍会导入。
+/// A failed write is only logged, and the hash of the file's existing content is returned: the
+/// stale file then counts as imported, so a restart does not override the new version, while
+/// later edits to the file are still imported.
+async fn write_file(prompts: &Prompts, file: &Path) -> Option<String> {
+    let content = serde_json::to_string_pretty(prompts).ok()? + "\n";
+    if let Some(parent) = file.parent().filter(|p| !p.as_os_str().is_empty()) {
+        let _ = tokio::fs::create_dir_all(parent).await;
+    }
+    match tokio::fs::write(file, &content).await {
+        Ok(()) => Some(sha256(&content)),
+        Err(e) => {
+            warn!("Could not write prompts file {}: {}", file.display(), e);
+            tokio::fs::read_to_string(file).await.ok().map(|existing| sha256(&existing))
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// 加载当前版本，首次启动时从文件 (或旧位置、内置默认值) 初始化，并开始监视文件变更
+/// Load the current version, seeding from the file (or the legacy location, or the built-in
+/// defaults) on first start, and start watching the file for changes
+pub async fn init(pool: &DbPool) -> Result<(), sqlx::Error> {
+    load(pool, &PROMPTS_FILE).await?;
+    if Path::new(LEGACY_PROMPTS_FILE).exists() {
+        warn!("{} is no longer read or served; prompts now live in {}", LEGACY_PROMPTS_FILE, PROMPTS_FILE.display());
+    }
+
+    let pool = pool.clone();
+    tokio::spawn(async move {
+        let mut last_modified = modified(&PROMPTS_FILE);
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            let current = modified(&PROMPTS_FILE);
+            if current != last_modified {
+                last_modified = current;
+                if let Err(e) = sync_file(&pool, &PROMPTS_FILE).await {
+                    warn!("Failed to reload prompts: {}", e);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// 加载当前版本并导入有变化的提示词文件；最终总会存在至少一个版本
+/// Load the current version and import the prompts file if it changed; afterwards at least one
+/// version always exists
+async fn load(pool: &DbPool, file: &Path) -> Result<(), sqlx::Error> {
+    let latest: Option<(String,)> = sqlx::query_as("SELECT content FROM prompt_versions ORDER BY id DESC LIMIT 1")
+        .fetch_optional(pool)
+        .await?;
+    match latest.and_then(|(content,)| serde_json::from_str::<Prompts>(&content).ok()) {
+        Some(prompts) => *CURRENT.write() = prompts,
+        None if !file.exists() && Path::new(LEGACY_PROMPTS_FILE).exists() => {
+            let content = std::fs::read_to_string(LEGACY_PROMPTS_FILE).unwrap_or_default();
+            match parse_file(&content) {
+                Ok(prompts) => {
+                    save_version(pool, &prompts, "file", None, None).await?;
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid legacy prompts file {}: {}", LEGACY_PROMPTS_FILE, e);
+                    save_version(pool, &Prompts::default(), "default", None, None).await?;
+                }
+            }
+        }
+        None if !file.exists() => {
+            save_version(pool, &Prompts::default(), "default", None, None).await?;
+        }
+        None => {}
+    }
+    sync_file(pool, file).await?;
+
+    // 首次启动时文件无效：以内置默认值作为第一个版本，修正文件后会导入为新版本
+    // An invalid file on first start: the built-in defaults become the first version, and the
+    // corrected file is imported as a new one
+    let (versions,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM prompt_versions").fetch_one(pool).await?;
+    if versions == 0 {
+        save_version(pool, &Prompts::default(), "default", None, None).await?;
+    }
+    Ok(())
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// 提示词与版本信息
+/// Prompts with version information
+#[derive(Serialize)]
+pub struct PromptsResponse {
+    pub version: PromptVersion,
+    pub prompts: Prompts,
+    /// 每个字段允许使用的变量
+    /// Variables each field may use
+    pub placeholders: BTreeMap<&'static str, Vec<&'static str>>,
+}
+
+/// 修改提示词请求，未提供的字段保持不变
+/// Update prompts request; omitted fields are left unchanged
+#[derive(Deserialize)]
+pub struct UpdatePrompts {
+    pub smart_parse_system: Option<String>,
+    pub smart_parse_user_template: Option<String>,
+    pub analyze_system: Option<String>,
+    pub analyze_user_template: Option<String>,
+    pub chat_system: Option<String>,
+}
+
+async fn current_response(pool: &DbPool) -> Result<Json<PromptsResponse>, (StatusCode, String)> {
+    let version = sqlx::query_as::<_, PromptVersion>(
+        "SELECT id AS version, source, created_by, created_at FROM prompt_versions ORDER BY id DESC LIMIT 1",
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(db_error)?;
+    let placeholders = FIELD_PLACEHOLDERS
+        .iter()
+        .map(|(name, own, _)| (*name, own.iter().chain(SHARED_PLACEHOLDERS.iter()).copied().collect()))
+        .collect();
+    Ok(Json(PromptsResponse { version, prompts: get_prompts(), placeholders }))
+}
+
+/// 查看当前提示词 (GET /api/prompts，仅管理员)
+/// Get the current prompts (admins only)
+pub async fn get_prompts_api(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<PromptsResponse>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    current_response(&pool).await
+}
+
+/// 修改提示词并保存为新版本 (PUT /api/prompts，仅管理员)
+/// Update the prompts and save them as a new version (admins only)
+///
+/// 同时写回提示词文件，使文件与数据库保持一致。
+/// Also writes the prompts file back so that the file and the database stay in sync.
+pub async fn update_prompts(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<UpdatePrompts>,
+) -> Result<Json<PromptsResponse>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let current = get_prompts();
+    let prompts = Prompts {
+        smart_parse_system: payload.smart_parse_system.unwrap_or(current.smart_parse_system),
+        smart_parse_user_template: payload.smart_parse_user_template.unwrap_or(current.smart_parse_user_template),
+        analyze_system: payload.analyze_system.unwrap_or(current.analyze_system),
+        analyze_user_template: payload.analyze_user_template.unwrap_or(current.analyze_user_template),
+        chat_system: payload.chat_system.unwrap_or(current.chat_system),
+    };
+    prompts.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let file_hash = write_file(&prompts, &PROMPTS_FILE).await;
+    save_version(&pool, &prompts, "api", file_hash, Some(user.user_id)).await.map_err(db_error)?;
+    current_response(&pool).await
+}
+
+/// 列出提示词版本 (GET /api/prompts/versions，仅管理员)
+/// List prompt versions (admins only)
+pub async fn list_versions(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<Vec<PromptVersion>>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    sqlx::query_as::<_, PromptVersion>(
+        "SELECT id AS version, source, created_by, created_at FROM prompt_versions ORDER BY id DESC",
+    )
+    .fetch_all(&pool)
+    .await
+    .map(Json)
+    .map_err(db_error)
+}
+
+/// 回滚到指定版本 (POST /api/prompts/versions/:version/restore，仅管理员)
+/// Roll back to a given version (admins only)
+///
+/// 回滚会以该版本的内容创建一个新版本，历史记录保持不变。
+/// A rollback creates a new version with that version's content; history is left unchanged.
+pub async fn restore_version(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    UrlPath(version): UrlPath<i64>,
+) -> Result<Json<PromptsResponse>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let row: Option<(String,)> = sqlx::query_as("SELECT content FROM prompt_versions WHERE id = ?")
+        .bind(version)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?;
+    let (content,) = row.ok_or((StatusCode::NOT_FOUND, "Prompt version not found".to_string()))?;
+    let prompts: Prompts = serde_json::from_str(&content).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let file_hash = write_file(&prompts, &PROMPTS_FILE).await;
+    save_version(&pool, &prompts, "rollback", file_hash, Some(user.user_id)).await.map_err(db_error)?;
+    current_response(&pool).await
+}
+
+/// 模板渲染上下文
+/// Template rendering context
+pub struct PromptContext {
+    pub today: NaiveDate,
+    pub timezone: &'static str,
+    pub base_currency: &'static str,
+    pub known_categories: Vec<String>,
+}
+
+impl PromptContext {
+    /// 为用户构造上下文：已知分类包括内置分类与用户可见订阅中已使用的分类
+    /// Build the context for a user: known categories are the built-in ones plus those already
+    /// used by subscriptions visible to the user
+    pub async fn load(pool: &DbPool, user_id: i64) -> Result<Self, sqlx::Error> {
+        let mut categories: BTreeSet<String> =
+            insights::KNOWN_CATEGORIES.iter().map(|(category, _)| category.to_string()).collect();
+        let used: Vec<(String,)> = sqlx::query_as(&format!(
+            "SELECT DISTINCT category FROM subscriptions WHERE category IS NOT NULL AND {}",
+            VISIBLE_TO_USER
+        ))
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        categories.extend(used.into_iter().map(|(category,)| category));
+
+        Ok(PromptContext {
+            today: dates::today(),
+            timezone: dates::timezone(),
+            base_currency: base_currency(),
+            known_categories: categories.into_iter().collect(),
+        })
+    }
+
+    /// 渲染模板：一次扫描替换所有占位符，替换进去的值 (分类名、用户输入等) 中的占位符不会再被展开；
+    /// 未知的占位符原样保留
+    /// Render a template: all placeholders are replaced in a single pass, so placeholders inside
+    /// substituted values (category names, user input, ...) are never expanded; unknown
+    /// placeholders are left as they are
+    pub fn render(&self, template: &str, vars: &[(&str, &str)]) -> String {
+        PLACEHOLDER_RE
+            .replace_all(template, |c: &regex::Captures| match &c[1] {
+                "today" => self.today.format("%Y-%m-%d").to_string(),
+                "timezone" => self.timezone.to_string(),
+                "base_currency" => self.base_currency.to_string(),
+                "known_categories" => self.known_categories.join(", "),
+                name => vars
+                    .iter()
+                    .find(|(var, _)| *var == name)
+                    .map_or_else(|| c[0].to_string(), |(_, value)| value.to_string()),
+            })
+            .into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> PromptContext {
+        PromptContext {
+            today: NaiveDate::from_ymd_opt(2026, 10, 18).unwrap(),
+            timezone: "UTC (UTC+00:00)",
+            base_currency: "CNY",
+            known_categories: vec!["video".to_string(), "{text}".to_string()],
+        }
+    }
+
+    /// 替换进去的值中的占位符不会被再次展开
+    /// Placeholders inside substituted values are not expanded again
+    #[test]
+    fn renders_in_a_single_pass() {
+        let rendered = context().render(
+            "{today} {base_currency} [{known_categories}] {text} {unknown}",
+            &[("text", "pay {today} {list}"), ("list", "never")],
+        );
+        assert_eq!(rendered, "2026-10-18 CNY [video, {text}] pay {today} {list} {unknown}");
+    }
+
+    #[test]
+    fn validates_placeholders() {
+        assert_eq!(Prompts::default().validate(), Ok(()));
+        let with = |change: fn(&mut Prompts)| {
+            let mut prompts = Prompts::default();
+            change(&mut prompts);
+            prompts.validate()
+        };
+        assert_eq!(with(|p| p.chat_system = " ".to_string()), Err("chat_system must not be empty".to_string()));
+        assert_eq!(
+            with(|p| p.smart_parse_user_template = "Parse this".to_string()),
+            Err("smart_parse_user_template must contain {text}".to_string())
+        );
+        // 其他字段自身的变量不可用，共用变量在所有字段中可用
+        // Another field's own variables are not allowed; shared variables work in every field
+        assert_eq!(
+            with(|p| p.smart_parse_user_template = "{text} {list}".to_string()),
+            Err("smart_parse_user_template uses unknown placeholder {list}".to_string())
+        );
+        assert_eq!(
+            with(|p| p.analyze_system = "{secret}".to_string()),
+            Err("analyze_system uses unknown placeholder {secret}".to_string())
+        );
+        assert_eq!(with(|p| p.analyze_system = "{today} {timezone} {base_currency} {known_categories}".to_string()), Ok(()));
+        assert_eq!(with(|p| p.analyze_user_template = "{list}".to_string()), Ok(()));
+        // 不是占位符的花括号 (如 JSON 示例) 不受限制
+        // Braces that are not placeholders (such as JSON examples) are not restricted
+        assert_eq!(with(|p| p.chat_system = r#"Reply like {"ok": true} or {Name}"#.to_string()), Ok(()));
+    }
+
+    /// 首次启动时提示词文件无效：以内置默认值作为第一个版本，修正文件后导入为新版本
+    /// An invalid prompts file on first start: the defaults become the first version, and the
+    /// corrected file is imported as a new one
+    #[tokio::test]
+    async fn falls_back_to_defaults_for_invalid_file() {
+        let pool = crate::db::test_pool().await;
+        let file = std::env::temp_dir().join(format!("wallet-os-test-prompts-{}.json", std::process::id()));
+        std::fs::write(&file, "{ not json").unwrap();
+
+        load(&pool, &file).await.unwrap();
+        let Json(current) = current_response(&pool).await.unwrap();
+        assert_eq!((current.version.version, current.version.source.as_str()), (1, "default"));
+
+        let fixed = Prompts { chat_system: "Be brief. Today is {today}.".to_string(), ..Default::default() };
+        std::fs::write(&file, serde_json::to_string(&fixed).unwrap()).unwrap();
+        sync_file(&pool, &file).await.unwrap();
+        let Json(current) = current_response(&pool).await.unwrap();
+        assert_eq!((current.version.version, current.version.source.as_str()), (2, "file"));
+        assert!(current.prompts == fixed);
+
+        // 未变化的文件不会再次导入 / An unchanged file is not imported again
+        sync_file(&pool, &file).await.unwrap();
+        assert_eq!(current_response(&pool).await.unwrap().0.version.version, 2);
+        *CURRENT.write() = Prompts::default();
+        let _ = std::fs::remove_file(&file);
+    }
+}
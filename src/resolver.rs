@@ -0,0 +1,723 @@
+//! 域名解析链模块
+//! Domain resolver chain module
+//!
+//! 根据服务名称查找官网域名。每个来源实现 `DomainResolver` trait，按 `DOMAIN_RESOLVERS` 配置的顺序组成
+//! 解析链：用户覆盖表、本地服务目录、SearXNG (自建)、DuckDuckGo API 与 DuckDuckGo HTML。每个来源给出
+//! 置信度，第一个达到阈值的结果被采用；都未达到时采用置信度最高的结果。每次尝试都会记录耗时，
+//! 并汇总到各来源的统计中，便于发现并移除失效的来源。
+//! Looks up the official website domain of a service name. Every source implements the
+//! `DomainResolver` trait, and they form a chain in the order configured by `DOMAIN_RESOLVERS`:
+//! the user override table, the local service catalog, SearXNG (self-hosted), the DuckDuckGo API
+//! and DuckDuckGo HTML. Each source reports a confidence; the first result reaching the threshold
+//! wins, otherwise the most confident result is used. Every attempt records its duration and is
+//! added to per-source statistics, making broken sources easy to spot and drop.
+
+use crate::auth::{AuthUser, Role};
+use crate::catalog;
+use crate::db::DbPool;
+use crate::outbound;
+use crate::search_cache;
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// 默认的解析顺序
+/// Default resolver order
+const DEFAULT_CHAIN: &str = "override,catalog,searxng,ddg_api,ddg_html";
+
+/// 默认的 User-Agent (部分搜索页面会拒绝非浏览器请求)
+/// Default User-Agent (some search pages reject non-browser requests)
+const DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36";
+
+/// DuckDuckGo HTML 结果的默认选择器
+/// Default selector for DuckDuckGo HTML results
+const DEFAULT_DDG_SELECTOR: &str = ".result__a, .result__url, .links_main a";
+
+/// 读取环境变量，空字符串视为未设置
+/// Read an environment variable, treating an empty string as unset
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+/// 外部搜索共用的 HTTP 客户端
+/// HTTP client shared by the web search resolvers
+static CLIENT: Lazy<outbound::Client> = Lazy::new(|| {
+    let timeout = env_var("SEARCH_TIMEOUT_SECS").and_then(|v| v.parse().ok()).unwrap_or(8);
+    outbound::Client::new(
+        &env_var("SEARCH_USER_AGENT").unwrap_or_else(|| DEFAULT_USER_AGENT.to_string()),
+        Duration::from_secs(timeout),
+    )
+});
+
+/// SearXNG 使用的客户端：地址由管理员配置，通常部署在内网，因此不套用出站策略
+/// Client used for SearXNG: its address is configured by the admin and usually lives on a
+/// private network, so the outbound policy does not apply
+static SEARXNG_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    let timeout = env_var("SEARCH_TIMEOUT_SECS").and_then(|v| v.parse().ok()).unwrap_or(8);
+    reqwest::Client::builder()
+        .user_agent(env_var("SEARCH_USER_AGENT").unwrap_or_else(|| DEFAULT_USER_AGENT.to_string()))
+        .timeout(Duration::from_secs(timeout))
+        .build()
+        .unwrap_or_default()
+});
+
+/// 启动时根据环境变量构造的解析链
+/// Resolver chain built from environment variables at startup
+static CHAIN: Lazy<ResolverChain> = Lazy::new(ResolverChain::from_env);
+
+/// 各来源的累计统计
+/// Cumulative statistics per source
+static STATS: Lazy<Mutex<HashMap<&'static str, ResolverStats>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 一次查找的上下文
+/// Context of one lookup
+pub struct Lookup<'a> {
+    pub pool: &'a DbPool,
+    pub user_id: i64,
+    /// 去除首尾空白后的查询名称
+    /// The query name with surrounding whitespace removed
+    pub query: &'a str,
+}
+
+/// 解析结果候选
+/// Resolution candidate
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    pub domain: String,
+    /// 置信度 (0–1)
+    /// Confidence (0–1)
+    pub confidence: f32,
+    /// 来源内部的依据，例如 `OfficialWebsite`
+    /// Evidence within the source, such as `OfficialWebsite`
+    pub evidence: &'static str,
+}
+
+/// 域名解析来源
+/// Domain resolution source
+#[async_trait]
+pub trait DomainResolver: Send + Sync {
+    /// 来源名称，用于配置与统计 / Source name, used in configuration and statistics
+    fn name(&self) -> &'static str;
+
+    /// 查找域名；没有结果时返回 `Ok(None)`，来源不可用时返回错误
+    /// Look up the domain; `Ok(None)` when there is no result, an error when the source failed
+    async fn resolve(&self, lookup: &Lookup<'_>) -> Result<Option<Candidate>, String>;
+}
+
+/// 单个来源的一次尝试
+/// One attempt of a single source
+#[derive(Debug, Clone, Serialize)]
+pub struct Attempt {
+    pub resolver: &'static str,
+    pub elapsed_ms: u64,
+    /// found, miss 或 error
+    /// found, miss or error
+    pub outcome: &'static str,
+    pub domain: Option<String>,
+    pub confidence: Option<f32>,
+    pub error: Option<String>,
+}
+
+/// 解析链的结果：采用的候选、其来源与全部尝试
+/// Result of the chain: the chosen candidate, its source and all attempts
+#[derive(Debug, Serialize)]
+pub struct Resolution {
+    pub candidate: Option<Candidate>,
+    pub source: Option<&'static str>,
+    pub attempts: Vec<Attempt>,
+}
+
+/// 来源的累计统计
+/// Cumulative statistics of a source
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ResolverStats {
+    pub calls: u64,
+    pub found: u64,
+    pub misses: u64,
+    pub errors: u64,
+    pub total_ms: u64,
+    pub last_error: Option<String>,
+}
+
+/// 有序的解析链
+/// Ordered resolver chain
+pub struct ResolverChain {
+    resolvers: Vec<Box<dyn DomainResolver>>,
+    /// 直接采用结果所需的最低置信度
+    /// Minimum confidence for a result to be taken immediately
+    min_confidence: f32,
+}
+
+impl ResolverChain {
+    pub fn new(resolvers: Vec<Box<dyn DomainResolver>>, min_confidence: f32) -> Self {
+        ResolverChain { resolvers, min_confidence }
+    }
+
+    /// 根据 `DOMAIN_RESOLVERS` (逗号分隔的来源名称) 构造解析链；未配置 `SEARXNG_URL` 时跳过 SearXNG
+    /// Build the chain from `DOMAIN_RESOLVERS` (comma-separated source names); SearXNG is skipped
+    /// when `SEARXNG_URL` is not configured
+    fn from_env() -> Self {
+        let order = env_var("DOMAIN_RESOLVERS").unwrap_or_else(|| DEFAULT_CHAIN.to_string());
+        let mut resolvers: Vec<Box<dyn DomainResolver>> = Vec::new();
+        for name in order.split(',').map(|n| n.trim().to_lowercase()).filter(|n| !n.is_empty()) {
+            match name.as_str() {
+                "override" => resolvers.push(Box::new(OverrideResolver)),
+                "catalog" => resolvers.push(Box::new(CatalogResolver)),
+                "ddg_api" => resolvers.push(Box::new(DdgApiResolver)),
+                "ddg_html" => match scraper::Selector::parse(
+                    &env_var("DDG_HTML_SELECTOR").unwrap_or_else(|| DEFAULT_DDG_SELECTOR.to_string()),
+                ) {
+                    Ok(selector) => resolvers.push(Box::new(DdgHtmlResolver { selector })),
+                    Err(e) => warn!("Invalid DDG_HTML_SELECTOR, ddg_html resolver disabled: {:?}", e),
+                },
+                "searxng" => match env_var("SEARXNG_URL") {
+                    Some(base) => resolvers.push(Box::new(SearxngResolver { base })),
+                    None => debug!("SEARXNG_URL not set, searxng resolver skipped"),
+                },
+                other => warn!("Unknown domain resolver '{}' ignored", other),
+            }
+        }
+        let min_confidence =
+            env_var("DOMAIN_RESOLVER_MIN_CONFIDENCE").and_then(|v| v.parse().ok()).unwrap_or(0.5);
+        let names: Vec<_> = resolvers.iter().map(|r| r.name()).collect();
+        info!("Domain resolvers: {}", names.join(" -> "));
+        ResolverChain::new(resolvers, min_confidence)
+    }
+
+    /// 按顺序尝试给定的来源，使用本链的置信度阈值
+    /// Try the given sources in order, using this chain's confidence threshold
+    async fn run<R: AsRef<dyn DomainResolver>>(&self, resolvers: &[R], lookup: &Lookup<'_>) -> Resolution {
+        let mut attempts = Vec::new();
+        let mut best: Option<(Candidate, &'static str)> = None;
+        for resolver in resolvers.iter().map(|r| r.as_ref()) {
+            let started = Instant::now();
+            let result = resolver.resolve(lookup).await;
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            let attempt = match &result {
+                Ok(Some(c)) => Attempt {
+                    resolver: resolver.name(),
+                    elapsed_ms,
+                    outcome: "found",
+                    domain: Some(c.domain.clone()),
+                    confidence: Some(c.confidence),
+                    error: None,
+                },
+                Ok(None) => Attempt { resolver: resolver.name(), elapsed_ms, outcome: "miss", domain: None, confidence: None, error: None },
+                Err(e) => Attempt {
+                    resolver: resolver.name(),
+                    elapsed_ms,
+                    outcome: "error",
+                    domain: None,
+                    confidence: None,
+                    error: Some(e.clone()),
+                },
+            };
+            record(&attempt);
+            debug!("Resolver {} for '{}': {} in {} ms", attempt.resolver, lookup.query, attempt.outcome, elapsed_ms);
+            attempts.push(attempt);
+
+            if let Ok(Some(candidate)) = result {
+                let accept = candidate.confidence >= self.min_confidence;
+                if best.as_ref().is_none_or(|(b, _)| candidate.confidence > b.confidence) {
+                    best = Some((candidate, resolver.name()));
+                }
+                if accept {
+                    break;
+                }
+            }
+        }
+        let (candidate, source) = match best {
+            Some((candidate, source)) => (Some(candidate), Some(source)),
+            None => (None, None),
+        };
+        Resolution { candidate, source, attempts }
+    }
+}
+
+/// 把一次尝试计入统计
+/// Add an attempt to the statistics
+fn record(attempt: &Attempt) {
+    let mut stats = STATS.lock();
+    let entry = stats.entry(attempt.resolver).or_default();
+    entry.calls += 1;
+    entry.total_ms += attempt.elapsed_ms;
+    match attempt.outcome {
+        "found" => entry.found += 1,
+        "miss" => entry.misses += 1,
+        _ => {
+            entry.errors += 1;
+            entry.last_error = attempt.error.clone();
+        }
+    }
+}
+
+/// 本地来源：查询开销小且不需要联网，结果不进入搜索缓存 (用户覆盖因人而异，目录更新后应立即生效)
+/// Local sources: cheap and offline, their results stay out of the search cache (overrides
+/// differ per user, and catalog updates should take effect immediately)
+const LOCAL_RESOLVERS: [&str; 2] = ["override", "catalog"];
+
+/// 只查询已启用的本地来源 (按解析链中的顺序)，没有结果时返回 `None`
+/// Query only the enabled local sources (in chain order), returning `None` without a result
+pub async fn resolve_local(lookup: &Lookup<'_>) -> Option<Resolution> {
+    let resolvers: Vec<_> = CHAIN.resolvers.iter().filter(|r| LOCAL_RESOLVERS.contains(&r.name())).collect();
+    if resolvers.is_empty() {
+        return None;
+    }
+    let resolution = CHAIN.run(&resolvers, lookup).await;
+    resolution.candidate.is_some().then_some(resolution)
+}
+
+/// 查询其余 (联网的) 来源，结果可以在用户之间共享
+/// Query the remaining (online) sources, whose results can be shared between users
+pub async fn resolve_remote(lookup: &Lookup<'_>) -> Resolution {
+    let resolvers: Vec<_> = CHAIN.resolvers.iter().filter(|r| !LOCAL_RESOLVERS.contains(&r.name())).collect();
+    CHAIN.run(&resolvers, lookup).await
+}
+
+/// 在启动时构造解析链并输出顺序
+/// Build the chain at startup and log its order
+pub fn init() {
+    Lazy::force(&CHAIN);
+}
+
+/// 从 URL 中取出主机名，跳过非 HTTP(S) 链接
+/// Take the host name from a URL, skipping non-HTTP(S) links
+fn host_of(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url).ok()?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return None;
+    }
+    parsed.host_str().map(|h| h.to_lowercase())
+}
+
+/// 域名是否包含查询名称 (只比较字母与数字)，用于提高搜索结果的置信度
+/// Whether the domain contains the query name (letters and digits only), used to raise the
+/// confidence of search results
+fn domain_matches(domain: &str, query: &str) -> bool {
+    let compact: String = query.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase();
+    compact.len() >= 3 && domain.replace(['.', '-'], "").contains(&compact)
+}
+
+/// 用户覆盖表：用户手动指定的名称与域名对应关系，优先于其他来源
+/// User override table: names mapped to domains by users, taking precedence over other sources
+struct OverrideResolver;
+
+#[async_trait]
+impl DomainResolver for OverrideResolver {
+    fn name(&self) -> &'static str {
+        "override"
+    }
+
+    async fn resolve(&self, lookup: &Lookup<'_>) -> Result<Option<Candidate>, String> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT domain FROM domain_overrides WHERE user_id = ? AND query = ?")
+            .bind(lookup.user_id)
+            .bind(search_cache::normalize_key(lookup.query))
+            .fetch_optional(lookup.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(row.map(|(domain,)| Candidate { domain, confidence: 1.0, evidence: "user override" }))
+    }
+}
+
+/// 本地服务目录
+/// Local service catalog
+struct CatalogResolver;
+
+#[async_trait]
+impl DomainResolver for CatalogResolver {
+    fn name(&self) -> &'static str {
+        "catalog"
+    }
+
+    async fn resolve(&self, lookup: &Lookup<'_>) -> Result<Option<Candidate>, String> {
+        Ok(catalog::lookup(lookup.query).map(|m| Candidate {
+            domain: m.service.domain.clone(),
+            confidence: if m.exact { 0.95 } else { 0.8 },
+            evidence: if m.exact { "alias" } else { "alias in name" },
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+struct DdgResponse {
+    #[serde(rename = "OfficialWebsite")]
+    official_website: Option<String>,
+    #[serde(rename = "Results")]
+    results: Option<Vec<DdgResult>>,
+    #[serde(rename = "AbstractURL")]
+    abstract_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DdgResult {
+    #[serde(rename = "FirstURL")]
+    first_url: Option<String>,
+}
+
+/// DuckDuckGo Instant Answer API：优先 OfficialWebsite，其次 AbstractURL (排除维基百科)，最后 Results
+/// DuckDuckGo Instant Answer API: OfficialWebsite first, then AbstractURL (excluding Wikipedia),
+/// then Results
+struct DdgApiResolver;
+
+#[async_trait]
+impl DomainResolver for DdgApiResolver {
+    fn name(&self) -> &'static str {
+        "ddg_api"
+    }
+
+    async fn resolve(&self, lookup: &Lookup<'_>) -> Result<Option<Candidate>, String> {
+        let url = format!("https://api.duckduckgo.com/?q={}&format=json", urlencoding::encode(lookup.query));
+        // DuckDuckGo 的 JSON 接口以 application/x-javascript 返回
+        // DuckDuckGo's JSON API answers with application/x-javascript
+        let response: DdgResponse = CLIENT
+            .get(&url)
+            .accept(&["application/json", "application/x-javascript", "text/javascript"])
+            .send()
+            .await?
+            .json()?;
+
+        let official = response.official_website.as_deref().and_then(host_of);
+        let abstract_url = response.abstract_url.as_deref().and_then(host_of).filter(|d| !d.contains("wikipedia.org"));
+        let first = response
+            .results
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|r| r.first_url.as_deref().and_then(host_of));
+        Ok(official
+            .map(|domain| Candidate { domain, confidence: 0.9, evidence: "OfficialWebsite" })
+            .or_else(|| abstract_url.map(|domain| Candidate { domain, confidence: 0.7, evidence: "AbstractURL" }))
+            .or_else(|| first.map(|domain| Candidate { domain, confidence: 0.6, evidence: "Results" })))
+    }
+}
+
+/// DuckDuckGo HTML 搜索：取第一个非 DuckDuckGo 的结果链接
+/// DuckDuckGo HTML search: take the first result link outside DuckDuckGo
+struct DdgHtmlResolver {
+    selector: scraper::Selector,
+}
+
+#[async_trait]
+impl DomainResolver for DdgHtmlResolver {
+    fn name(&self) -> &'static str {
+        "ddg_html"
+    }
+
+    async fn resolve(&self, lookup: &Lookup<'_>) -> Result<Option<Candidate>, String> {
+        let url = format!("https://html.duckduckgo.com/html/?q={}", urlencoding::encode(lookup.query));
+        let body = CLIENT
+            .get(&url)
+            .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")
+            .header("Accept-Language", "zh-CN,zh;q=0.9,en;q=0.8")
+            .header("Referer", "https://html.duckduckgo.com/")
+            .accept(&["text/html"])
+            .max_bytes(2 * 1024 * 1024)
+            .send()
+            .await?
+            .text();
+
+        let document = scraper::Html::parse_document(&body);
+        let domain = document
+            .select(&self.selector)
+            .filter_map(|element| element.value().attr("href"))
+            .filter_map(|href| {
+                // 结果链接可能是 /l/?uddg=<编码后的目标地址> 形式的跳转
+                // Result links may be redirects of the form /l/?uddg=<encoded target>
+                if href.starts_with("/l/") || href.starts_with("//duckduckgo.com/l/") {
+                    let encoded = &href[href.find("uddg=")? + 5..];
+                    let end = encoded.find('&').unwrap_or(encoded.len());
+                    urlencoding::decode(&encoded[..end]).ok().map(|d| d.into_owned())
+                } else {
+                    Some(href.to_string())
+                }
+            })
+            .filter_map(|url| host_of(&url))
+            .find(|domain| !domain.contains("duckduckgo.com"));
+        Ok(domain.map(|domain| {
+            let matches = domain_matches(&domain, lookup.query);
+            Candidate {
+                confidence: if matches { 0.7 } else { 0.5 },
+                evidence: if matches { "first result, name in domain" } else { "first result" },
+                domain,
+            }
+        }))
+    }
+}
+
+/// 自建 SearXNG 实例的 JSON 搜索接口 (需要在实例中启用 json 格式)
+/// JSON search API of a self-hosted SearXNG instance (the json format must be enabled on it)
+struct SearxngResolver {
+    base: String,
+}
+
+#[async_trait]
+impl DomainResolver for SearxngResolver {
+    fn name(&self) -> &'static str {
+        "searxng"
+    }
+
+    async fn resolve(&self, lookup: &Lookup<'_>) -> Result<Option<Candidate>, String> {
+        let url = format!("{}/search?q={}&format=json", self.base.trim_end_matches('/'), urlencoding::encode(lookup.query));
+        let response = SEARXNG_CLIENT.get(&url).send().await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        let json: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
+        let domains: Vec<String> = json["results"]
+            .as_array()
+            .map(|results| results.iter().filter_map(|r| r["url"].as_str().and_then(host_of)).collect())
+            .unwrap_or_default();
+        let domains: Vec<String> = domains.into_iter().filter(|d| !d.contains("wikipedia.org")).collect();
+
+        // 优先采用域名包含查询名称的结果
+        // Prefer a result whose domain contains the query name
+        Ok(match domains.iter().find(|d| domain_matches(d, lookup.query)) {
+            Some(domain) => Some(Candidate { domain: domain.clone(), confidence: 0.8, evidence: "result, name in domain" }),
+            None => domains.into_iter().next().map(|domain| Candidate { domain, confidence: 0.55, evidence: "first result" }),
+        })
+    }
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// 域名覆盖记录
+/// Domain override record
+#[derive(Serialize, FromRow)]
+pub struct DomainOverride {
+    pub id: i64,
+    pub query: String,
+    pub domain: String,
+    pub created_at: String,
+}
+
+#[derive(Deserialize)]
+pub struct SetOverride {
+    pub query: String,
+    /// 域名或完整网址
+    /// Domain or full URL
+    pub domain: String,
+}
+
+/// 列出当前用户的域名覆盖 (GET /api/search/overrides)
+/// List the current user's domain overrides
+pub async fn list_overrides(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<Vec<DomainOverride>>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+    let overrides = sqlx::query_as::<_, DomainOverride>(
+        "SELECT id, query, domain, created_at FROM domain_overrides WHERE user_id = ? ORDER BY query",
+    )
+    .bind(user.user_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+    Ok(Json(overrides))
+}
+
+/// 设置域名覆盖 (PUT /api/search/overrides)，同一名称重复设置时替换
+/// Set a domain override; setting the same name again replaces it
+pub async fn set_override(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<SetOverride>,
+) -> Result<Json<DomainOverride>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    let query = search_cache::normalize_key(&payload.query);
+    if query.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Query is empty".to_string()));
+    }
+    let raw = payload.domain.trim();
+    let domain = if raw.contains("://") { host_of(raw) } else { host_of(&format!("https://{}", raw)) }
+        .filter(|d| d.contains('.'))
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid domain".to_string()))?;
+
+    let row = sqlx::query_as::<_, DomainOverride>(
+        "INSERT INTO domain_overrides (user_id, query, domain) VALUES (?, ?, ?) \
+         ON CONFLICT (user_id, query) DO UPDATE SET domain = excluded.domain, created_at = CURRENT_TIMESTAMP \
+         RETURNING id, query, domain, created_at",
+    )
+    .bind(user.user_id)
+    .bind(query)
+    .bind(domain)
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+    Ok(Json(row))
+}
+
+/// 删除域名覆盖 (DELETE /api/search/overrides/:id)
+/// Delete a domain override
+pub async fn delete_override(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    let result = sqlx::query("DELETE FROM domain_overrides WHERE id = ? AND user_id = ?")
+        .bind(id)
+        .bind(user.user_id)
+        .execute(&pool)
+        .await
+        .map_err(db_error)?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Override not found".to_string()));
+    }
+    Ok(Json(serde_json::json!({ "status": "deleted" })))
+}
+
+/// 解析链状态
+/// Resolver chain status
+#[derive(Serialize)]
+pub struct ChainStatus {
+    pub min_confidence: f32,
+    pub resolvers: Vec<ResolverStatus>,
+}
+
+#[derive(Serialize)]
+pub struct ResolverStatus {
+    pub name: &'static str,
+    pub stats: ResolverStats,
+    /// 平均耗时 (毫秒)
+    /// Average duration (milliseconds)
+    pub avg_ms: Option<u64>,
+}
+
+/// 查看解析链顺序与各来源统计 (GET /api/search/resolvers，仅管理员)
+/// Show the chain order and per-source statistics (admins only)
+pub async fn resolver_status(Extension(user): Extension<AuthUser>) -> Result<Json<ChainStatus>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let stats = STATS.lock();
+    let resolvers = CHAIN
+        .resolvers
+        .iter()
+        .map(|r| {
+            let stats = stats.get(r.name()).cloned().unwrap_or_default();
+            let avg_ms = (stats.calls > 0).then(|| stats.total_ms / stats.calls);
+            ResolverStatus { name: r.name(), stats, avg_ms }
+        })
+        .collect();
+    Ok(Json(ChainStatus { min_confidence: CHAIN.min_confidence, resolvers }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::handlers;
+    use axum::extract::Query;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// 返回固定结果并记录调用次数的来源
+    /// Source returning a fixed result and counting its calls
+    struct Fake {
+        name: &'static str,
+        result: Result<Option<f32>, &'static str>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl DomainResolver for Fake {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn resolve(&self, _lookup: &Lookup<'_>) -> Result<Option<Candidate>, String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let domain = format!("{}.example", self.name);
+            self.result
+                .map(|found| found.map(|confidence| Candidate { domain, confidence, evidence: "fake" }))
+                .map_err(str::to_string)
+        }
+    }
+
+    fn fake(name: &'static str, result: Result<Option<f32>, &'static str>) -> (Box<dyn DomainResolver>, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        (Box::new(Fake { name, result, calls: calls.clone() }), calls)
+    }
+
+    async fn run(chain: Vec<(Box<dyn DomainResolver>, Arc<AtomicUsize>)>) -> (Resolution, Vec<usize>) {
+        let pool = db::test_pool().await;
+        let (resolvers, calls): (Vec<_>, Vec<_>) = chain.into_iter().unzip();
+        let chain = ResolverChain::new(resolvers, 0.5);
+        let resolution = chain.run(&chain.resolvers, &Lookup { pool: &pool, user_id: 1, query: "acme" }).await;
+        (resolution, calls.iter().map(|c| c.load(Ordering::SeqCst)).collect())
+    }
+
+    /// 错误与未找到时继续尝试下一个来源，第一个达到阈值的结果被采用，之后的来源不再调用
+    /// Errors and misses fall through to the next source; the first result reaching the threshold
+    /// wins and later sources are not called
+    #[tokio::test]
+    async fn falls_through_in_order() {
+        let (resolution, calls) = run(vec![
+            fake("broken", Err("timeout")),
+            fake("empty", Ok(None)),
+            fake("good", Ok(Some(0.9))),
+            fake("later", Ok(Some(1.0))),
+        ])
+        .await;
+        assert_eq!(resolution.source, Some("good"));
+        assert_eq!(resolution.candidate.unwrap().domain, "good.example");
+        let outcomes: Vec<_> = resolution.attempts.iter().map(|a| (a.resolver, a.outcome)).collect();
+        assert_eq!(outcomes, vec![("broken", "error"), ("empty", "miss"), ("good", "found")]);
+        assert_eq!(resolution.attempts[0].error.as_deref(), Some("timeout"));
+        assert_eq!(calls, vec![1, 1, 1, 0]);
+    }
+
+    /// 都未达到阈值时采用置信度最高的结果 (相同时取靠前的来源)
+    /// Below the threshold the most confident result is used (the earlier source on a tie)
+    #[tokio::test]
+    async fn uses_best_result_below_threshold() {
+        let (resolution, calls) =
+            run(vec![fake("weak", Ok(Some(0.2))), fake("better", Ok(Some(0.4))), fake("tie", Ok(Some(0.4)))]).await;
+        assert_eq!(resolution.source, Some("better"));
+        assert_eq!(calls, vec![1, 1, 1]);
+
+        let (resolution, _) = run(vec![fake("empty", Ok(None)), fake("broken", Err("down"))]).await;
+        assert!(resolution.candidate.is_none() && resolution.source.is_none());
+        assert_eq!(resolution.attempts.len(), 2);
+    }
+
+    async fn search(pool: &DbPool, query: &str) -> serde_json::Value {
+        let user = AuthUser { user_id: 1, role: Role::Admin, scopes: vec![crate::auth::Scope::Admin] };
+        let uri = format!("/api/search?q={}", query).parse().unwrap();
+        let response =
+            handlers::search_domain(State(pool.clone()), Extension(user), Query::try_from_uri(&uri).unwrap()).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// 用户覆盖在搜索缓存之前查询，缓存中的旧结果不会覆盖用户的指定
+    /// User overrides are checked before the search cache, so a stale cached result never beats
+    /// the user's choice
+    #[tokio::test]
+    async fn override_beats_cache() {
+        let pool = db::test_pool().await;
+        search_cache::store(&pool, "Acme Cloud", Some("cached.example"), Some("ddg_api"), Some(0.9)).await;
+        let cached = search(&pool, "Acme%20Cloud").await;
+        assert_eq!((cached["domain"].as_str(), cached["cached"].as_bool()), (Some("cached.example"), Some(true)));
+
+        sqlx::query("INSERT INTO domain_overrides (user_id, query, domain) VALUES (1, ?, 'acme.example')")
+            .bind(search_cache::normalize_key("Acme Cloud"))
+            .execute(&pool)
+            .await
+            .unwrap();
+        let found = search(&pool, "Acme%20Cloud").await;
+        assert_eq!(found["domain"], "acme.example");
+        assert_eq!(found["source"], "override");
+        assert_eq!(found["cached"], false);
+    }
+}
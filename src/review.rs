@@ -0,0 +1,193 @@
+//! 审核队列模块
+//! Review queue module
+//!
+//! 邮件导入等自动来源解析出的订阅先进入审核队列，由用户确认 (可修改字段) 后才创建订阅，
+//! 或直接拒绝。
+//! Subscriptions parsed from automatic sources such as email ingestion wait in the review queue
+//! until the user approves them (optionally editing the fields), which creates the
+//! subscription, or rejects them.
+
+use crate::auth::{AuthUser, Role};
+use crate::db::DbPool;
+use crate::handlers;
+use crate::models::{CreateSubscription, ReviewItem, Subscription};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::Deserialize;
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct ReviewQuery {
+    /// 按状态过滤，默认 pending
+    /// Filter by status, defaults to pending
+    pub status: Option<String>,
+}
+
+/// 列出当前用户的待审核条目 (GET /api/review)
+/// List the current user's review items
+pub async fn list_review(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(query): Query<ReviewQuery>,
+) -> Result<Json<Vec<ReviewItem>>, (StatusCode, String)> {
+    user.require_role(Role::Viewer)?;
+    let status = query.status.unwrap_or_else(|| "pending".to_string());
+    let items = sqlx::query_as::<_, ReviewItem>(
+        "SELECT id, source, subject, sender, received_at, excerpt, parsed, status, subscription_id, created_at \
+         FROM review_queue WHERE user_id = ? AND status = ? ORDER BY id DESC",
+    )
+    .bind(user.user_id)
+    .bind(status)
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+    Ok(Json(items))
+}
+
+/// `approving` 状态的最长时间 (秒)；超过后视为中断 (例如进程退出或最终更新失败)，条目可以重新审核
+/// Longest time an item stays `approving` (seconds); past it the approval is treated as
+/// interrupted (for example the process stopped or the final update failed) and the item can be
+/// reviewed again
+const CLAIM_TIMEOUT_SECS: i64 = 300;
+
+/// 原子地把当前用户的一个待审核条目改为指定状态并返回其解析结果；并发的审核只有一个能成功。
+/// 超过 `CLAIM_TIMEOUT_SECS` 仍处于 `approving` 的条目同样可以认领。
+/// Atomically move one of the current user's pending items to the given status and return its
+/// parse result; of several concurrent reviews only one succeeds. Items left `approving` for
+/// longer than `CLAIM_TIMEOUT_SECS` can be claimed as well.
+async fn claim(pool: &DbPool, user_id: i64, id: i64, status: &str) -> Result<String, (StatusCode, String)> {
+    // fetch_all 让语句执行完毕后再返回，认领立即提交
+    // fetch_all runs the statement to completion, so the claim is committed before returning
+    let rows: Vec<(String,)> = sqlx::query_as(
+        "UPDATE review_queue SET status = ?, claimed_at = CURRENT_TIMESTAMP WHERE id = ? AND user_id = ? \
+         AND (status = 'pending' OR (status = 'approving' AND claimed_at <= datetime('now', ?))) RETURNING parsed",
+    )
+    .bind(status)
+    .bind(id)
+    .bind(user_id)
+    .bind(format!("-{} seconds", CLAIM_TIMEOUT_SECS))
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)?;
+    rows.into_iter()
+        .next()
+        .map(|(parsed,)| parsed)
+        .ok_or((StatusCode::NOT_FOUND, "Pending review item not found".to_string()))
+}
+
+/// 审核通过并创建订阅 (POST /api/review/:id/approve)
+/// Approve an item and create the subscription
+///
+/// 请求体可选：提供时使用修改后的字段，否则使用解析结果。创建规则与 `POST /api/subscriptions` 相同。
+/// 创建期间条目处于 `approving` 状态，创建失败时恢复为 `pending`，可以修改后重试；
+/// 进程中断或最终状态更新失败时，条目在 `CLAIM_TIMEOUT_SECS` 后可以重新审核。
+/// The request body is optional: when given, the edited fields are used, otherwise the parse
+/// result. Creation follows the same rules as `POST /api/subscriptions`. While the subscription
+/// is created the item is `approving`; if creation fails it goes back to `pending`, so it can be
+/// edited and retried. If the process stops or the final status update fails, the item can be
+/// reviewed again after `CLAIM_TIMEOUT_SECS`.
+pub async fn approve(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+    payload: Option<Json<CreateSubscription>>,
+) -> Result<Json<Subscription>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    let parsed = claim(&pool, user.user_id, id, "approving").await?;
+    let created = match payload {
+        Some(Json(payload)) => Ok(payload),
+        None => serde_json::from_str(&parsed).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string())),
+    };
+    let created = match created {
+        Ok(payload) => handlers::create_subscription(State(pool.clone()), Extension(user), Json(payload)).await,
+        Err(e) => Err(e),
+    };
+    let (status, subscription_id) = match &created {
+        Ok(Json(sub)) => ("approved", Some(sub.id)),
+        Err(_) => ("pending", None),
+    };
+    sqlx::query("UPDATE review_queue SET status = ?, subscription_id = ? WHERE id = ?")
+        .bind(status)
+        .bind(subscription_id)
+        .bind(id)
+        .execute(&pool)
+        .await
+        .map_err(db_error)?;
+    created
+}
+
+/// 拒绝条目 (POST /api/review/:id/reject)
+/// Reject an item
+pub async fn reject(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+    claim(&pool, user.user_id, id, "rejected").await?;
+    Ok(Json(serde_json::json!({ "status": "rejected" })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    /// 同一条目只能被认领一次，并发的通过与拒绝中只有一个成功
+    /// An item can be claimed only once, so only one of a concurrent approve and reject succeeds
+    #[tokio::test]
+    async fn claims_pending_item_once() {
+        let pool = db::test_pool().await;
+        let id = sqlx::query("INSERT INTO review_queue (user_id, source, excerpt, parsed) VALUES (1, 'email', 'x', '{}')")
+            .execute(&pool)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+
+        let (first, second) = tokio::join!(claim(&pool, 1, id, "approving"), claim(&pool, 1, id, "rejected"));
+        assert_eq!(first.is_ok() as u8 + second.is_ok() as u8, 1);
+        assert_eq!(claim(&pool, 1, id, "rejected").await.unwrap_err().0, StatusCode::NOT_FOUND);
+        assert_eq!(claim(&pool, 2, id, "rejected").await.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+
+    /// 中断后停留在 `approving` 的条目超时后可以重新认领，未超时的不行
+    /// An item left `approving` by an interrupted approval can be claimed again after the timeout,
+    /// but not before
+    #[tokio::test]
+    async fn reclaims_interrupted_approvals() {
+        let pool = db::test_pool().await;
+        let id = sqlx::query("INSERT INTO review_queue (user_id, source, excerpt, parsed) VALUES (1, 'email', 'x', '{}')")
+            .execute(&pool)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+        claim(&pool, 1, id, "approving").await.unwrap();
+        assert_eq!(claim(&pool, 1, id, "approving").await.unwrap_err().0, StatusCode::NOT_FOUND);
+
+        let age = format!("-{} seconds", CLAIM_TIMEOUT_SECS + 1);
+        sqlx::query("UPDATE review_queue SET claimed_at = datetime('now', ?) WHERE id = ?")
+            .bind(&age)
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        claim(&pool, 1, id, "rejected").await.unwrap();
+
+        // 已完成的审核不会因为超时被重新认领
+        // Finished reviews are never claimed again, however old
+        sqlx::query("UPDATE review_queue SET claimed_at = datetime('now', ?) WHERE id = ?")
+            .bind(&age)
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(claim(&pool, 1, id, "approving").await.unwrap_err().0, StatusCode::NOT_FOUND);
+        assert_eq!(claim(&pool, 2, id, "approving").await.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+}
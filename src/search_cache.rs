@@ -0,0 +1,449 @@
+//! 域名搜索缓存模块
+//! Domain search cache module
+//!
+//! 把域名解析链的结果保存在 SQLite 中，重启后仍然有效。键为忽略大小写并合并空白后的查询名称；
+//! 找到的域名与“未找到”分别有各自的有效期，条目数超过上限时淘汰最久未使用的条目。固定 (pinned)
+//! 的条目不会过期也不会被淘汰，管理员可以查看、清除或固定条目。
+//! Keeps the results of the domain resolver chain in SQLite so they survive restarts. The key is
+//! the query name with case ignored and whitespace collapsed; found domains and "not found"
+//! results have their own lifetimes, and the least recently used entries are evicted once the
+//! entry count exceeds the cap. Pinned entries never expire and are never evicted; admins can
+//! inspect, purge or pin entries.
+
+use crate::auth::{AuthUser, Role};
+use crate::db::DbPool;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::{debug, warn};
+
+/// 缓存配置 (启动后首次使用时读取环境变量)
+/// Cache configuration (environment variables are read on first use)
+struct CacheConfig {
+    /// 找到域名的结果的有效期 (秒) / Lifetime of found domains (seconds)
+    ttl_secs: i64,
+    /// 未找到结果的有效期 (秒) / Lifetime of "not found" results (seconds)
+    negative_ttl_secs: i64,
+    /// 非固定条目的数量上限 / Maximum number of unpinned entries
+    max_entries: i64,
+}
+
+/// 有效期上限 (100 年)，避免换算为秒时溢出或超出 SQLite 的日期范围
+/// Upper bound of the lifetimes (100 years), so converting to seconds cannot overflow or leave
+/// SQLite's date range
+const MAX_TTL_HOURS: i64 = 24 * 365 * 100;
+
+impl CacheConfig {
+    /// 从变量读取配置；无效值使用默认值，过长的有效期截断为上限
+    /// Read the configuration from variables; invalid values use the defaults and overly long
+    /// lifetimes are clamped to the upper bound
+    fn parse(var: impl Fn(&str) -> Option<String>) -> Self {
+        let secs = |name: &str, default: i64| {
+            let hours = var(name).and_then(|v| v.trim().parse::<i64>().ok()).filter(|h| *h >= 0).unwrap_or(default);
+            if hours > MAX_TTL_HOURS {
+                warn!("{} is too large, using {} hours", name, MAX_TTL_HOURS);
+            }
+            hours.min(MAX_TTL_HOURS) * 3600
+        };
+        CacheConfig {
+            ttl_secs: secs("SEARCH_CACHE_TTL_HOURS", 24 * 30),
+            negative_ttl_secs: secs("SEARCH_CACHE_NEGATIVE_TTL_HOURS", 24),
+            max_entries: var("SEARCH_CACHE_MAX_ENTRIES")
+                .and_then(|v| v.trim().parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(5000),
+        }
+    }
+}
+
+static CONFIG: Lazy<CacheConfig> = Lazy::new(|| CacheConfig::parse(|name| std::env::var(name).ok()));
+
+/// 缓存键：小写并合并空白 (与域名覆盖表使用相同的规则)
+/// Cache key: lowercase with whitespace collapsed (the same rule as the domain override table)
+pub fn normalize_key(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// 缓存命中的结果；`domain` 为空表示缓存的“未找到”
+/// A cache hit; an empty `domain` is a cached "not found"
+pub struct CachedLookup {
+    pub domain: Option<String>,
+    pub source: Option<String>,
+    pub confidence: Option<f32>,
+}
+
+/// 查找未过期的缓存条目，命中时更新使用时间与命中次数
+/// Look up an unexpired cache entry, updating its last use and hit count on a hit
+pub async fn get(pool: &DbPool, query: &str) -> Result<Option<CachedLookup>, sqlx::Error> {
+    let row: Option<(Option<String>, Option<String>, Option<f32>)> = sqlx::query_as(
+        "UPDATE search_cache SET hits = hits + 1, last_used_at = CURRENT_TIMESTAMP \
+         WHERE key = ? AND (pinned OR expires_at > CURRENT_TIMESTAMP) \
+         RETURNING domain, source, confidence",
+    )
+    .bind(normalize_key(query))
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|(domain, source, confidence)| CachedLookup { domain, source, confidence }))
+}
+
+/// 写入 (或替换) 缓存条目并执行过期清理与容量淘汰；已固定的条目保持不变
+/// Write (or replace) a cache entry, then drop expired entries and enforce the cap; pinned
+/// entries are left untouched
+pub async fn put(
+    pool: &DbPool,
+    query: &str,
+    domain: Option<&str>,
+    source: Option<&str>,
+    confidence: Option<f32>,
+) -> Result<(), sqlx::Error> {
+    insert(pool, &CONFIG, query, domain, source, confidence).await
+}
+
+async fn insert(
+    pool: &DbPool,
+    config: &CacheConfig,
+    query: &str,
+    domain: Option<&str>,
+    source: Option<&str>,
+    confidence: Option<f32>,
+) -> Result<(), sqlx::Error> {
+    let ttl = if domain.is_some() { config.ttl_secs } else { config.negative_ttl_secs };
+    if ttl == 0 {
+        return Ok(());
+    }
+    sqlx::query(
+        "INSERT INTO search_cache (key, query, domain, source, confidence, expires_at) \
+         VALUES (?, ?, ?, ?, ?, datetime('now', ?)) \
+         ON CONFLICT (key) DO UPDATE SET query = excluded.query, domain = excluded.domain, \
+         source = excluded.source, confidence = excluded.confidence, hits = 0, \
+         created_at = CURRENT_TIMESTAMP, last_used_at = CURRENT_TIMESTAMP, expires_at = excluded.expires_at \
+         WHERE NOT search_cache.pinned",
+    )
+    .bind(normalize_key(query))
+    .bind(query.trim())
+    .bind(domain)
+    .bind(source)
+    .bind(confidence)
+    .bind(format!("+{} seconds", ttl))
+    .execute(pool)
+    .await?;
+    evict(pool, config.max_entries).await
+}
+
+/// 删除过期条目，并在超过上限时按最近使用时间淘汰非固定条目
+/// Delete expired entries and, above the cap, evict unpinned entries by last use
+async fn evict(pool: &DbPool, max_entries: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM search_cache WHERE NOT pinned AND expires_at <= CURRENT_TIMESTAMP")
+        .execute(pool)
+        .await?;
+    let evicted = sqlx::query(
+        "DELETE FROM search_cache WHERE id IN (\
+         SELECT id FROM search_cache WHERE NOT pinned ORDER BY last_used_at DESC, id DESC LIMIT -1 OFFSET ?)",
+    )
+    .bind(max_entries)
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if evicted > 0 {
+        debug!("Evicted {} search cache entries", evicted);
+    }
+    Ok(())
+}
+
+/// 写入缓存，失败时只记录日志 (缓存不可用不应影响搜索)
+/// Write to the cache, only logging failures (an unavailable cache must not break search)
+pub async fn store(pool: &DbPool, query: &str, domain: Option<&str>, source: Option<&str>, confidence: Option<f32>) {
+    if let Err(e) = put(pool, query, domain, source, confidence).await {
+        warn!("Failed to write search cache for '{}': {}", query, e);
+    }
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// 缓存条目
+/// Cache entry
+#[derive(Serialize, FromRow)]
+pub struct CacheEntry {
+    pub id: i64,
+    pub key: String,
+    pub query: String,
+    pub domain: Option<String>,
+    pub source: Option<String>,
+    pub confidence: Option<f32>,
+    pub pinned: bool,
+    pub hits: i64,
+    pub expired: bool,
+    pub created_at: String,
+    pub last_used_at: String,
+    pub expires_at: String,
+}
+
+const ENTRY_COLUMNS: &str = "id, key, query, domain, source, confidence, pinned, hits, \
+     (NOT pinned AND expires_at <= CURRENT_TIMESTAMP) AS expired, created_at, last_used_at, expires_at";
+
+#[derive(Deserialize)]
+pub struct CacheListQuery {
+    /// 按查询名称或域名筛选 / Filter by query name or domain
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// 缓存概况与条目列表
+/// Cache overview and entry list
+#[derive(Serialize)]
+pub struct CacheReport {
+    pub total: i64,
+    pub negative: i64,
+    pub pinned: i64,
+    pub expired: i64,
+    pub max_entries: i64,
+    pub ttl_hours: i64,
+    pub negative_ttl_hours: i64,
+    pub entries: Vec<CacheEntry>,
+}
+
+/// 查看搜索缓存 (GET /api/search/cache，仅管理员)，按最近使用时间倒序
+/// Inspect the search cache (admins only), most recently used first
+pub async fn list_cache(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<CacheListQuery>,
+) -> Result<Json<CacheReport>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let (total, negative, pinned, expired): (i64, i64, i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), COALESCE(SUM(domain IS NULL), 0), COALESCE(SUM(pinned), 0), \
+         COALESCE(SUM(NOT pinned AND expires_at <= CURRENT_TIMESTAMP), 0) FROM search_cache",
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let filter = params.q.as_deref().map(|q| format!("%{}%", normalize_key(q))).unwrap_or_else(|| "%".to_string());
+    let entries = sqlx::query_as::<_, CacheEntry>(&format!(
+        "SELECT {} FROM search_cache WHERE key LIKE ?1 OR COALESCE(domain, '') LIKE ?1 \
+         ORDER BY last_used_at DESC, id DESC LIMIT ?2",
+        ENTRY_COLUMNS
+    ))
+    .bind(filter)
+    .bind(params.limit.unwrap_or(100).clamp(1, 1000))
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(CacheReport {
+        total,
+        negative,
+        pinned,
+        expired,
+        max_entries: CONFIG.max_entries,
+        ttl_hours: CONFIG.ttl_secs / 3600,
+        negative_ttl_hours: CONFIG.negative_ttl_secs / 3600,
+        entries,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct PurgeQuery {
+    /// 只清除过期条目 / Only purge expired entries
+    #[serde(default)]
+    pub expired: bool,
+    /// 同时清除固定的条目 / Purge pinned entries too
+    #[serde(default)]
+    pub include_pinned: bool,
+}
+
+/// 清除搜索缓存 (DELETE /api/search/cache，仅管理员)；默认保留固定的条目
+/// Purge the search cache (admins only); pinned entries are kept by default
+pub async fn purge_cache(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<PurgeQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let sql = match (params.expired, params.include_pinned) {
+        (true, _) => "DELETE FROM search_cache WHERE NOT pinned AND expires_at <= CURRENT_TIMESTAMP",
+        (false, true) => "DELETE FROM search_cache",
+        (false, false) => "DELETE FROM search_cache WHERE NOT pinned",
+    };
+    let purged = sqlx::query(sql).execute(&pool).await.map_err(db_error)?.rows_affected();
+    Ok(Json(serde_json::json!({ "status": "purged", "purged": purged })))
+}
+
+/// 删除单个缓存条目 (DELETE /api/search/cache/:id，仅管理员)
+/// Delete a single cache entry (admins only)
+pub async fn delete_entry(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    let result = sqlx::query("DELETE FROM search_cache WHERE id = ?")
+        .bind(id)
+        .execute(&pool)
+        .await
+        .map_err(db_error)?;
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "Cache entry not found".to_string()));
+    }
+    Ok(Json(serde_json::json!({ "status": "deleted" })))
+}
+
+/// 固定缓存条目 (POST /api/search/cache/:id/pin，仅管理员)：不再过期，也不会被淘汰
+/// Pin a cache entry (admins only): it no longer expires and is never evicted
+pub async fn pin_entry(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<CacheEntry>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    set_pinned(&pool, id, true).await.map(Json)
+}
+
+/// 取消固定 (POST /api/search/cache/:id/unpin，仅管理员)，条目从现在起重新计算有效期
+/// Unpin an entry (admins only); its lifetime restarts from now
+pub async fn unpin_entry(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<CacheEntry>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+    set_pinned(&pool, id, false).await.map(Json)
+}
+
+async fn set_pinned(pool: &DbPool, id: i64, pinned: bool) -> Result<CacheEntry, (StatusCode, String)> {
+    sqlx::query_as::<_, CacheEntry>(&format!(
+        "UPDATE search_cache SET pinned = ?, expires_at = CASE WHEN ? THEN expires_at \
+         ELSE datetime('now', CASE WHEN domain IS NULL THEN ? ELSE ? END) END \
+         WHERE id = ? RETURNING {}",
+        ENTRY_COLUMNS
+    ))
+    .bind(pinned)
+    .bind(pinned)
+    .bind(format!("+{} seconds", CONFIG.negative_ttl_secs))
+    .bind(format!("+{} seconds", CONFIG.ttl_secs))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(db_error)?
+    .ok_or((StatusCode::NOT_FOUND, "Cache entry not found".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn test_config(ttl_hours: i64, negative_ttl_hours: i64, max_entries: i64) -> CacheConfig {
+        CacheConfig { ttl_secs: ttl_hours * 3600, negative_ttl_secs: negative_ttl_hours * 3600, max_entries }
+    }
+
+    /// 条目的剩余有效期 (小时) / Remaining lifetime of an entry (hours)
+    async fn hours_left(pool: &DbPool, query: &str) -> Option<i64> {
+        sqlx::query_scalar(
+            "SELECT CAST(round((julianday(expires_at) - julianday('now')) * 24) AS INTEGER) \
+             FROM search_cache WHERE key = ?",
+        )
+            .bind(normalize_key(query))
+            .fetch_optional(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn normalizes_keys() {
+        assert_eq!(normalize_key("  Disney+   Hotstar "), "disney+ hotstar");
+        assert_eq!(normalize_key("YouTube\tPremium\n"), "youtube premium");
+        assert_eq!(normalize_key("爱奇艺 VIP"), "爱奇艺 vip");
+        assert_eq!(normalize_key("   "), "");
+    }
+
+    #[test]
+    fn parses_config() {
+        let parse = |vars: &[(&str, &str)]| {
+            let vars: Vec<(String, String)> =
+                vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            CacheConfig::parse(|name| vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone()))
+        };
+        let defaults = parse(&[]);
+        let defaults = (defaults.ttl_secs, defaults.negative_ttl_secs, defaults.max_entries);
+        assert_eq!(defaults, (720 * 3600, 24 * 3600, 5000));
+
+        let custom = parse(&[
+            ("SEARCH_CACHE_TTL_HOURS", " 48 "),
+            ("SEARCH_CACHE_NEGATIVE_TTL_HOURS", "0"),
+            ("SEARCH_CACHE_MAX_ENTRIES", "10"),
+        ]);
+        assert_eq!((custom.ttl_secs, custom.negative_ttl_secs, custom.max_entries), (48 * 3600, 0, 10));
+
+        // 过大的值不会溢出，截断为上限 / Huge values do not overflow but are clamped
+        let max = i64::MAX.to_string();
+        let huge = parse(&[("SEARCH_CACHE_TTL_HOURS", &max), ("SEARCH_CACHE_NEGATIVE_TTL_HOURS", "-1")]);
+        assert_eq!((huge.ttl_secs, huge.negative_ttl_secs), (MAX_TTL_HOURS * 3600, 24 * 3600));
+    }
+
+    /// 找到的域名与“未找到”各自使用自己的有效期，过期后不再命中，固定的条目不过期
+    /// Found domains and "not found" results use their own lifetimes, expired entries no longer
+    /// hit and pinned entries never expire
+    #[tokio::test]
+    async fn expires_entries() {
+        let pool = db::test_pool().await;
+        let config = test_config(48, 2, 100);
+        insert(&pool, &config, "Netflix", Some("netflix.com"), Some("catalog"), Some(0.9)).await.unwrap();
+        insert(&pool, &config, "Unknown Service", None, None, None).await.unwrap();
+        assert_eq!(hours_left(&pool, "netflix").await, Some(48));
+        assert_eq!(hours_left(&pool, "unknown service").await, Some(2));
+
+        let hit = get(&pool, "  NETFLIX ").await.unwrap().unwrap();
+        assert_eq!((hit.domain.as_deref(), hit.source.as_deref()), (Some("netflix.com"), Some("catalog")));
+        let miss = get(&pool, "unknown   service").await.unwrap().unwrap();
+        assert_eq!(miss.domain, None);
+        assert!(get(&pool, "Hulu").await.unwrap().is_none());
+
+        sqlx::query("UPDATE search_cache SET expires_at = datetime('now', '-1 seconds')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE search_cache SET pinned = 1 WHERE key = 'netflix'").execute(&pool).await.unwrap();
+        assert!(get(&pool, "Netflix").await.unwrap().is_some());
+        assert!(get(&pool, "Unknown Service").await.unwrap().is_none());
+
+        // 有效期为 0 时不缓存否定结果 / A zero lifetime disables negative caching
+        insert(&pool, &test_config(48, 0, 100), "Another Unknown", None, None, None).await.unwrap();
+        assert_eq!(hours_left(&pool, "another unknown").await, None);
+        // 固定的条目不会被覆盖 / Pinned entries are not overwritten
+        insert(&pool, &config, "Netflix", Some("example.com"), None, None).await.unwrap();
+        assert_eq!(get(&pool, "Netflix").await.unwrap().unwrap().domain.as_deref(), Some("netflix.com"));
+    }
+
+    /// 超过上限时淘汰最久未使用的非固定条目
+    /// Above the cap, the least recently used unpinned entries are evicted
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let pool = db::test_pool().await;
+        let config = test_config(48, 2, 2);
+        for (minutes_ago, query) in [(30, "Pinned"), (20, "Old"), (10, "Recent")] {
+            insert(&pool, &config, query, Some("example.com"), None, None).await.unwrap();
+            let sql = "UPDATE search_cache SET last_used_at = datetime('now', ?), pinned = key = 'pinned' WHERE key = ?";
+            sqlx::query(sql)
+                .bind(format!("-{} minutes", minutes_ago))
+                .bind(normalize_key(query))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        // 命中会刷新使用时间 / A hit refreshes the last use
+        assert!(get(&pool, "Old").await.unwrap().is_some());
+
+        insert(&pool, &config, "New", Some("example.com"), None, None).await.unwrap();
+        let keys: Vec<String> =
+            sqlx::query_scalar("SELECT key FROM search_cache ORDER BY key").fetch_all(&pool).await.unwrap();
+        assert_eq!(keys, vec!["new", "old", "pinned"]);
+    }
+}
@@ -0,0 +1,462 @@
+//! 智能解析模块
+//! Smart parse module
+//!
+//! 将模型输出解析为与 `CreateSubscription` 一致的强类型结果，并使用与创建订阅相同的规则校验。
+//! 输出无效时会把错误反馈给模型并自动修复重试一次；支持结构化输出的后端会附带 JSON Schema。
+//! Parses model output into a typed result mirroring `CreateSubscription` and validates it with
+//! the same rules as subscription creation. Invalid output is sent back to the model with the
+//! error for one automatic repair retry; backends with structured output also get a JSON Schema.
+
+use crate::ai_usage;
+use crate::catalog;
+use crate::db::DbPool;
+use crate::extractor;
+use crate::llm::{ChatMessage, ChatRequest, LlmProvider, OutputSchema};
+use crate::models::{CreateSubscription, FieldConfidence, SmartParseResult};
+use crate::prompts::{self, get_prompts, PromptContext};
+use chrono::NaiveDate;
+use tracing::warn;
+
+/// 追加在用户提示词之后的输出约定，保证自定义提示词也会返回置信度
+/// Output contract appended to the user prompt, so that custom prompts still return confidences
+const OUTPUT_CONTRACT: &str = "Respond with a single JSON object with the keys name, price, currency (ISO 4217 code), frequency (-1, 0, 1, 3 or 12), start_date and next_payment (YYYY-MM-DD or null), url (or null), category (short lowercase word or null), and confidence: an object giving a number between 0 and 1 for each of name, price, currency, frequency, start_date and next_payment.";
+
+/// 修复重试的提示词，`{error}` 为校验错误
+/// Prompt for the repair retry; `{error}` is the validation error
+const REPAIR_PROMPT: &str = "That response was rejected: {error}. Reply with the corrected JSON object only.";
+
+/// 需要给出置信度的字段
+/// Fields that carry a confidence value
+const CONFIDENCE_FIELDS: [&str; 6] = ["name", "price", "currency", "frequency", "start_date", "next_payment"];
+
+/// 智能解析结果的 JSON Schema
+/// JSON Schema of the smart parse result
+fn output_schema() -> OutputSchema {
+    let confidence: serde_json::Map<String, serde_json::Value> = CONFIDENCE_FIELDS
+        .iter()
+        .map(|f| (f.to_string(), serde_json::json!({ "type": "number", "minimum": 0, "maximum": 1 })))
+        .collect();
+    OutputSchema {
+        name: "subscription",
+        schema: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "price": { "type": ["number", "null"] },
+                "currency": { "type": "string", "pattern": "^[A-Z]{3}$" },
+                "frequency": { "type": "integer", "enum": [-1, 0, 1, 3, 12] },
+                "start_date": { "type": ["string", "null"], "format": "date" },
+                "next_payment": { "type": ["string", "null"], "format": "date" },
+                "url": { "type": ["string", "null"] },
+                "category": { "type": ["string", "null"] },
+                "confidence": { "type": "object", "properties": confidence, "required": CONFIDENCE_FIELDS },
+            },
+            "required": ["name", "price", "currency", "frequency", "start_date", "next_payment", "url", "category", "confidence"],
+        }),
+    }
+}
+
+/// 解析订阅文本：已配置 LLM 时调用模型，失败或未配置时使用离线规则解析；结果再用服务目录补全
+/// Parse subscription text: call the model when an LLM is configured, falling back to the
+/// offline rule-based extractor when it fails or is not configured; the result is then completed
+/// from the service catalog
+pub async fn parse(pool: &DbPool, user_id: i64, text: &str) -> Result<SmartParseResult, sqlx::Error> {
+    let provider = ai_usage::provider(pool).await;
+    parse_with(pool, user_id, provider, text).await
+}
+
+/// 使用给定的提供方解析 (`None` 时只用离线规则)
+/// Parse with the given provider (`None` uses only the offline rules)
+async fn parse_with(
+    pool: &DbPool,
+    user_id: i64,
+    provider: Option<&dyn LlmProvider>,
+    text: &str,
+) -> Result<SmartParseResult, sqlx::Error> {
+    let context = PromptContext::load(pool, user_id).await?;
+
+    if let Some(provider) = provider {
+        let prompts = get_prompts();
+        let prompt = context.render(&prompts.smart_parse_user_template, &[("text", text)]);
+        let system = context.render(&prompts.smart_parse_system, &[]);
+
+        match parse_with_llm(pool, user_id, provider, system, prompt).await {
+            Ok(mut result) => {
+                catalog::enrich(&mut result);
+                return Ok(result);
+            }
+            Err(e) => warn!("LLM smart parse failed, falling back to offline extraction: {}", e),
+        }
+    }
+
+    let mut result = extractor::extract(text, context.today);
+    catalog::enrich(&mut result);
+    Ok(result)
+}
+
+/// 调用模型解析订阅文本，输出无效时自动修复重试一次
+/// Ask the model to parse subscription text, with one automatic repair retry on invalid output
+async fn parse_with_llm(
+    pool: &DbPool,
+    user_id: i64,
+    provider: &dyn LlmProvider,
+    system: String,
+    prompt: String,
+) -> Result<SmartParseResult, String> {
+    let mut request = ChatRequest {
+        messages: vec![
+            ChatMessage::system(system),
+            ChatMessage::user(format!("{}\n\n{}", prompt, OUTPUT_CONTRACT)),
+        ],
+        temperature: 0.1,
+        schema: provider.supports_schema().then(output_schema),
+    };
+
+    let mut repaired = false;
+    loop {
+        let content = ai_usage::chat(pool, user_id, "smart_parse", provider, &request)
+            .await
+            .map_err(|e| e.to_string())?;
+        let error = match interpret(&content) {
+            Ok(result) => return Ok(result),
+            Err(e) if repaired => return Err(format!("invalid output after repair: {}", e)),
+            Err(e) => e,
+        };
+
+        warn!("Invalid smart parse output ({}), asking the model to repair it", error);
+        request.messages.push(ChatMessage::assistant(content));
+        request.messages.push(ChatMessage::user(REPAIR_PROMPT.replace("{error}", &error)));
+        repaired = true;
+    }
+}
+
+/// 从模型回复中取出 JSON 对象 (去除 Markdown 代码块与前后说明文字)
+/// Extract the JSON object from a model reply (dropping Markdown fences and surrounding prose)
+pub fn extract_json(content: &str) -> &str {
+    match (content.find('{'), content.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &content[start..=end],
+        _ => content.trim(),
+    }
+}
+
+/// 将常见日期格式规范为 YYYY-MM-DD
+/// Normalise common date formats to YYYY-MM-DD
+pub fn normalize_date(value: &str) -> Option<String> {
+    const FORMATS: [&str; 7] = ["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d", "%Y年%m月%d日", "%d %B %Y", "%B %d, %Y", "%b %d, %Y"];
+    let value = value.trim();
+    // 带时间的 ISO 8601 只取日期部分
+    // Keep only the date part of ISO 8601 timestamps
+    let date_part = value.split('T').next().unwrap_or(value);
+    FORMATS
+        .iter()
+        .find_map(|f| NaiveDate::parse_from_str(date_part, f).ok().or_else(|| NaiveDate::parse_from_str(value, f).ok()))
+        .map(|d| d.format("%Y-%m-%d").to_string())
+}
+
+/// 将频率描述转换为频率编码
+/// Convert a frequency description to its frequency code
+fn parse_frequency(value: &serde_json::Value) -> Option<i64> {
+    if let Some(n) = value.as_i64() {
+        return Some(n);
+    }
+    if let Some(n) = value.as_f64().filter(|n| n.fract() == 0.0) {
+        return Some(n as i64);
+    }
+    match value.as_str()?.trim().to_lowercase().as_str() {
+        "daily" | "day" => Some(-1),
+        "monthly" | "month" => Some(1),
+        "quarterly" | "quarter" => Some(3),
+        "yearly" | "annual" | "annually" | "year" => Some(12),
+        "lifetime" | "once" | "one-time" => Some(0),
+        other => other.parse().ok(),
+    }
+}
+
+/// 读取价格，兼容带货币符号或千分位的字符串 ("€17,99"、"1.199,88")；拒绝负数
+/// Read the price, accepting strings with currency symbols or thousand separators ("€17,99",
+/// "1.199,88"); negative prices are rejected
+fn parse_price(value: &serde_json::Value) -> Result<Option<f64>, String> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    if let Some(n) = value.as_f64() {
+        if n < 0.0 {
+            return Err(format!("price {} must not be negative", n));
+        }
+        return Ok(Some(n));
+    }
+    let raw = value.as_str().ok_or("price must be a number")?;
+    if raw.contains(['-', '−']) {
+        return Err(format!("price '{}' must not be negative", raw));
+    }
+    extractor::parse_single_amount(raw)
+        .map(Some)
+        .ok_or_else(|| format!("price '{}' is not a number", raw))
+}
+
+/// 解析并校验模型输出
+/// Parse and validate the model output
+fn interpret(content: &str) -> Result<SmartParseResult, String> {
+    let json: serde_json::Value =
+        serde_json::from_str(extract_json(content)).map_err(|e| format!("not valid JSON ({})", e))?;
+    if !json.is_object() {
+        return Err("expected a JSON object".to_string());
+    }
+    let text = |key: &str| json[key].as_str().map(str::trim).filter(|s| !s.is_empty()).map(str::to_string);
+
+    let price = parse_price(&json["price"])?;
+    let currency = match text("currency") {
+        Some(c) if c.len() == 3 && c.chars().all(|ch| ch.is_ascii_alphabetic()) => c.to_uppercase(),
+        Some(c) => return Err(format!("currency '{}' is not an ISO 4217 code", c)),
+        None => prompts::base_currency().to_string(),
+    };
+    let frequency = match &json["frequency"] {
+        serde_json::Value::Null => 1,
+        v => parse_frequency(v)
+            .filter(|f| [-1, 0, 1, 3, 12].contains(f))
+            .ok_or_else(|| format!("frequency {} is not one of -1, 0, 1, 3, 12", v))?,
+    };
+    let date = |key: &str| -> Result<Option<String>, String> {
+        match text(key) {
+            None => Ok(None),
+            Some(d) => normalize_date(&d)
+                .map(Some)
+                .ok_or_else(|| format!("{} '{}' is not a date in YYYY-MM-DD format", key, d)),
+        }
+    };
+    let start_date = date("start_date")?;
+    let next_payment = date("next_payment")?.or_else(|| text("end_date").and_then(|d| normalize_date(&d)));
+
+    let payload = CreateSubscription {
+        name: text("name").unwrap_or_default(),
+        price,
+        currency,
+        next_payment,
+        frequency,
+        url: text("url"),
+        logo: None,
+        start_date,
+        household_id: None,
+        category: text("category").map(|c| c.to_lowercase()),
+    };
+    let (price, next_payment) = payload.validate()?;
+
+    // 未给出的置信度按 0.5 处理，空字段置信度为 0
+    // Missing confidences count as 0.5; empty fields have confidence 0
+    let confidence = |key: &str, present: bool| {
+        if !present {
+            return 0.0;
+        }
+        json["confidence"][key].as_f64().unwrap_or(0.5).clamp(0.0, 1.0)
+    };
+    let currency_present = text("currency").is_some();
+    let frequency_present = !json["frequency"].is_null();
+    Ok(SmartParseResult {
+        confidence: FieldConfidence {
+            name: confidence("name", true),
+            price: confidence("price", payload.price.is_some()),
+            currency: confidence("currency", currency_present),
+            frequency: confidence("frequency", frequency_present),
+            start_date: confidence("start_date", payload.start_date.is_some()),
+            next_payment: confidence("next_payment", next_payment.is_some()),
+        },
+        name: payload.name,
+        price: Some(price),
+        currency: payload.currency,
+        frequency: payload.frequency,
+        start_date: payload.start_date,
+        next_payment,
+        url: payload.url,
+        category: payload.category,
+        source: "llm",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::llm::{Completion, LlmError, OnDelta};
+    use serde_json::json;
+
+    #[test]
+    fn parses_prices() {
+        let price = |v: serde_json::Value| parse_price(&v);
+        assert_eq!(price(json!(null)), Ok(None));
+        assert_eq!(price(json!(9.99)), Ok(Some(9.99)));
+        assert_eq!(price(json!("9.99")), Ok(Some(9.99)));
+        assert_eq!(price(json!("9,99")), Ok(Some(9.99)));
+        assert_eq!(price(json!("€17,99")), Ok(Some(17.99)));
+        assert_eq!(price(json!("USD 1,199.88")), Ok(Some(1199.88)));
+        assert_eq!(price(json!("1.199,88 €")), Ok(Some(1199.88)));
+        assert_eq!(price(json!("¥1,000")), Ok(Some(1000.0)));
+        assert_eq!(price(json!("25")), Ok(Some(25.0)));
+    }
+
+    /// 负数、没有数字或包含多个数字的价格被拒绝，不会被悄悄改写
+    /// Negative prices, prices without a number and prices with several numbers are rejected
+    /// rather than silently rewritten
+    #[test]
+    fn rejects_invalid_prices() {
+        for value in [json!(-5), json!(-0.5), json!("-5"), json!("−9.99"), json!("$-9.99"), json!("free"), json!("5-10"),
+            json!("9.99 or 19.99"), json!(true)]
+        {
+            assert!(parse_price(&value).is_err(), "{} should be rejected", value);
+        }
+    }
+
+    /// 无效的频率与日期被拒绝，错误信息会原样写入修复提示词
+    /// Invalid frequencies and dates are rejected with the messages that go into the repair prompt
+    #[test]
+    fn rejects_invalid_frequency_and_dates() {
+        let reply = |extra: serde_json::Value| {
+            let mut value = json!({ "name": "Netflix", "price": 15.99, "currency": "USD", "frequency": 1,
+                "next_payment": "2026-11-01" });
+            value.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            interpret(&value.to_string()).map(|r| r.name)
+        };
+        assert_eq!(reply(json!({})), Ok("Netflix".to_string()));
+        assert_eq!(reply(json!({ "frequency": 7 })), Err("frequency 7 is not one of -1, 0, 1, 3, 12".to_string()));
+        assert_eq!(
+            reply(json!({ "frequency": "fortnightly" })),
+            Err("frequency \"fortnightly\" is not one of -1, 0, 1, 3, 12".to_string())
+        );
+        assert_eq!(
+            reply(json!({ "next_payment": "sometime" })),
+            Err("next_payment 'sometime' is not a date in YYYY-MM-DD format".to_string())
+        );
+        assert_eq!(
+            reply(json!({ "currency": "dollars" })),
+            Err("currency 'dollars' is not an ISO 4217 code".to_string())
+        );
+        assert!(interpret("no json here").unwrap_err().starts_with("not valid JSON"));
+        assert_eq!(interpret("[1, 2]").unwrap_err(), "expected a JSON object");
+    }
+
+    /// 代码块或说明文字包裹的 JSON 也能被接受
+    /// JSON wrapped in a Markdown fence or in prose is accepted
+    #[test]
+    fn accepts_wrapped_json() {
+        let object = r#"{"name": "Spotify", "price": "9,99", "currency": "eur", "frequency": "monthly",
+            "next_payment": "2026/11/05", "category": "Music"}"#;
+        for content in [
+            format!("```json\n{}\n```", object),
+            format!("Sure! Here is the result:\n{}\nLet me know if you need anything else.", object),
+        ] {
+            let result = interpret(&content).unwrap();
+            assert_eq!(result.name, "Spotify");
+            assert_eq!(result.price, Some(9.99));
+            assert_eq!(result.currency, "EUR");
+            assert_eq!(result.frequency, 1);
+            assert_eq!(result.next_payment.as_deref(), Some("2026-11-05"));
+            assert_eq!(result.category.as_deref(), Some("music"));
+            assert_eq!(result.source, "llm");
+        }
+    }
+
+    /// 缺失的置信度按 0.5 处理，越界值被截断，空字段置信度为 0
+    /// Missing confidences become 0.5, out-of-range values are clamped and empty fields get 0
+    #[test]
+    fn normalises_confidences() {
+        let result = interpret(
+            r#"{"name": "Adobe", "price": 20, "currency": "USD", "frequency": 12, "next_payment": "2027-01-01",
+                "start_date": null, "confidence": {"name": 1.7, "price": -0.3, "currency": 0.8, "start_date": 0.9}}"#,
+        )
+        .unwrap();
+        let c = result.confidence;
+        assert_eq!((c.name, c.price, c.currency), (1.0, 0.0, 0.8));
+        assert_eq!((c.frequency, c.next_payment), (0.5, 0.5));
+        assert_eq!(c.start_date, 0.0);
+
+        let result = interpret(r#"{"name": "Lifetime", "frequency": 0, "confidence": {"price": 0.9}}"#).unwrap();
+        let c = result.confidence;
+        assert_eq!((c.name, c.frequency), (0.5, 0.5));
+        assert_eq!((c.price, c.currency, c.start_date, c.next_payment), (0.0, 0.0, 0.0, 0.0));
+        assert_eq!(result.currency, prompts::base_currency());
+    }
+
+    /// 按顺序返回预设回复的提供方，并记录收到的请求
+    /// Provider returning scripted replies in order and recording the requests it receives
+    struct ScriptedProvider {
+        replies: parking_lot::Mutex<Vec<&'static str>>,
+        requests: parking_lot::Mutex<Vec<ChatRequest>>,
+    }
+
+    impl ScriptedProvider {
+        fn new(replies: Vec<&'static str>) -> Self {
+            ScriptedProvider { replies: parking_lot::Mutex::new(replies), requests: Default::default() }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl LlmProvider for ScriptedProvider {
+        fn name(&self) -> &'static str {
+            "scripted"
+        }
+
+        fn model(&self) -> &str {
+            "scripted"
+        }
+
+        fn supports_schema(&self) -> bool {
+            true
+        }
+
+        async fn chat(&self, request: &ChatRequest) -> Result<Completion, LlmError> {
+            self.requests.lock().push(request.clone());
+            let mut replies = self.replies.lock();
+            if replies.is_empty() {
+                return Err(LlmError::InvalidResponse("no reply scripted".to_string()));
+            }
+            Ok(Completion { content: replies.remove(0).to_string(), usage: None })
+        }
+
+        async fn chat_stream(&self, request: &ChatRequest, _: &mut OnDelta<'_>) -> Result<Completion, LlmError> {
+            self.chat(request).await
+        }
+    }
+
+    /// 第一次输出无效时带着错误修复重试一次
+    /// Invalid first output is retried once with the error in the repair prompt
+    #[tokio::test]
+    async fn repairs_invalid_output_once() {
+        let pool = db::test_pool().await;
+        let provider = ScriptedProvider::new(vec![
+            r#"{"name": "Hulu", "price": 7.99, "currency": "USD", "frequency": 7, "next_payment": "2026-11-20"}"#,
+            r#"{"name": "Hulu", "price": 7.99, "currency": "USD", "frequency": 1, "next_payment": "2026-11-20"}"#,
+        ]);
+        let result = parse_with(&pool, 1, Some(&provider), "Hulu 7.99 USD, repair test").await.unwrap();
+        assert_eq!((result.name.as_str(), result.frequency, result.source), ("Hulu", 1, "llm"));
+
+        let requests = provider.requests.lock();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].schema.is_some());
+        let repair = &requests[1].messages;
+        assert_eq!(repair.len(), 4);
+        assert_eq!(repair[2].role, "assistant");
+        assert_eq!(
+            repair[3].content,
+            REPAIR_PROMPT.replace("{error}", "frequency 7 is not one of -1, 0, 1, 3, 12")
+        );
+    }
+
+    /// 修复后仍然无效时返回错误，`parse` 回退到离线规则解析
+    /// Output still invalid after the repair is an error, and `parse` falls back to the offline rules
+    #[tokio::test]
+    async fn falls_back_to_rules_after_failed_repair() {
+        let pool = db::test_pool().await;
+        let invalid = r#"{"name": "Disney+", "price": 8.99, "currency": "USD", "next_payment": "sometime"}"#;
+
+        let provider = ScriptedProvider::new(vec![invalid, invalid]);
+        let prompt = "Disney+ 8.99 USD monthly, failed repair test".to_string();
+        let error = parse_with_llm(&pool, 1, &provider, "system".to_string(), prompt).await.unwrap_err();
+        assert_eq!(error, "invalid output after repair: next_payment 'sometime' is not a date in YYYY-MM-DD format");
+        assert_eq!(provider.requests.lock().len(), 2);
+
+        let provider = ScriptedProvider::new(vec![invalid, invalid]);
+        let result = parse_with(&pool, 1, Some(&provider), "Disney+ 8.99 USD monthly, fallback test").await.unwrap();
+        assert_eq!(result.source, "rules");
+        assert_eq!(result.price, Some(8.99));
+        assert_eq!(provider.requests.lock().len(), 2);
+    }
+}
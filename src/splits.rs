@@ -0,0 +1,615 @@
+//! 费用分摊模块
+//! Cost splitting module
+//!
+//! 为家庭共享的订阅 (如 Spotify Family、YouTube Premium Family) 定义分摊方式，
+//! 按账单周期 (自然月) 生成每位成员的应付金额报表，并支持标记结清。
+//! Defines how shared household subscriptions (e.g. Spotify Family, YouTube Premium Family) are
+//! split, produces a per-member balance report for each billing period (calendar month), and
+//! supports marking shares as settled.
+//!
+//! 订阅拥有者视为付款人，其他成员欠付款人各自的份额。
+//! The subscription owner is treated as the payer; every other member owes the payer their share.
+
+use crate::auth::{AuthUser, Role};
+use crate::db::DbPool;
+use crate::households::{self, VISIBLE_TO_USER};
+use crate::models::{SetSplit, SettleSplit, SplitMember, Subscription};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tracing::info;
+
+/// 金额比较容差
+/// Tolerance for amount comparisons
+const EPSILON: f64 = 0.01;
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// 没有辅币单位的货币，金额取整
+/// Currencies without minor units, whose amounts are whole numbers
+const ZERO_DECIMAL_CURRENCIES: &[&str] = &["CLP", "ISK", "JPY", "KRW", "VND"];
+
+/// 每个货币单位包含的最小单位数 (多数货币为 100)
+/// Minor units per unit of the currency (100 for most currencies)
+fn minor_units(currency: &str) -> f64 {
+    if ZERO_DECIMAL_CURRENCIES.contains(&currency.to_ascii_uppercase().as_str()) {
+        1.0
+    } else {
+        100.0
+    }
+}
+
+/// 按货币的最小单位取整
+/// Round to the currency's minor unit
+fn round_amount(v: f64, currency: &str) -> f64 {
+    let unit = minor_units(currency);
+    (v * unit).round() / unit
+}
+
+/// 把各成员的金额取整到货币的最小单位，并按最大余数法分配舍入差额，使各份之和等于 (取整后的) 总额
+/// Round each member's amount to the currency's minor unit, handing out the rounding difference
+/// by largest remainder so that the shares add up to the (rounded) total
+fn round_shares(amounts: Vec<(i64, f64)>, currency: &str) -> Vec<(i64, f64)> {
+    let unit = minor_units(currency);
+    let total = (amounts.iter().map(|(_, a)| a).sum::<f64>() * unit).round() as i64;
+    let mut shares: Vec<(i64, i64, f64)> = amounts
+        .iter()
+        .map(|(user_id, amount)| {
+            let scaled = amount * unit;
+            (*user_id, scaled.floor() as i64, scaled - scaled.floor())
+        })
+        .collect();
+    let mut missing = total - shares.iter().map(|(_, minor, _)| minor).sum::<i64>();
+    // 稳定排序：余数相同时按成员顺序 / Stable sort: equal remainders keep the member order
+    let mut order: Vec<usize> = (0..shares.len()).collect();
+    order.sort_by(|a, b| shares[*b].2.total_cmp(&shares[*a].2));
+    for i in order {
+        if missing <= 0 {
+            break;
+        }
+        shares[i].1 += 1;
+        missing -= 1;
+    }
+    shares.into_iter().map(|(user_id, minor, _)| (user_id, minor as f64 / unit)).collect()
+}
+
+/// 当前账单周期 (YYYY-MM)
+/// Current billing period (YYYY-MM)
+fn current_period() -> String {
+    chrono::Utc::now().format("%Y-%m").to_string()
+}
+
+/// 校验账单周期格式 (YYYY-MM)
+/// Validate the billing period format (YYYY-MM)
+fn parse_period(period: Option<String>) -> Result<String, (StatusCode, String)> {
+    let period = period.unwrap_or_else(current_period);
+    chrono::NaiveDate::parse_from_str(&format!("{}-01", period), "%Y-%m-%d")
+        .map(|_| period)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid period, expected YYYY-MM".to_string()))
+}
+
+/// 计算每位成员每期 (每次付款) 应承担的金额
+/// Compute each member's amount per payment
+///
+/// `fixed` 模式下未分配的余额由拥有者承担。
+/// In `fixed` mode, any unassigned remainder is borne by the owner.
+fn member_amounts(method: &str, price: f64, owner_id: i64, members: &[SplitMember]) -> Vec<(i64, f64)> {
+    match method {
+        "equal" => {
+            let each = price / members.len().max(1) as f64;
+            members.iter().map(|m| (m.user_id, each)).collect()
+        }
+        "percentage" => members
+            .iter()
+            .map(|m| (m.user_id, price * m.share.unwrap_or(0.0) / 100.0))
+            .collect(),
+        _ => {
+            let mut amounts: Vec<(i64, f64)> = members.iter().map(|m| (m.user_id, m.share.unwrap_or(0.0))).collect();
+            let remainder = price - amounts.iter().map(|(_, a)| a).sum::<f64>();
+            if remainder > EPSILON {
+                match amounts.iter_mut().find(|(id, _)| *id == owner_id) {
+                    Some((_, a)) => *a += remainder,
+                    None => amounts.push((owner_id, remainder)),
+                }
+            }
+            amounts
+        }
+    }
+}
+
+/// 每位成员的月均份额 (已按货币取整，合计等于订阅的月均费用)
+/// Each member's monthly share (rounded for the currency, adding up to the subscription's
+/// monthly cost)
+fn monthly_shares(method: &str, sub: &Subscription, members: &[SplitMember]) -> Vec<(i64, f64)> {
+    // 每期金额按订阅的月均费用折算
+    // Per-payment amounts are scaled to the subscription's monthly cost
+    let monthly_ratio = if sub.price > 0.0 { sub.monthly_cost() / sub.price } else { 0.0 };
+    let amounts = member_amounts(method, sub.price, sub.owner_id, members)
+        .into_iter()
+        .map(|(user_id, amount)| (user_id, amount * monthly_ratio))
+        .collect();
+    round_shares(amounts, &sub.currency)
+}
+
+/// 读取拥有者可编辑的订阅
+/// Load a subscription the user owns
+async fn owned_subscription(pool: &DbPool, id: i64, user_id: i64) -> Result<Subscription, (StatusCode, String)> {
+    sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ? AND owner_id = ?")
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(db_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Subscription not found".to_string()))
+}
+
+async fn split_members(pool: &DbPool, subscription_id: i64) -> Result<Vec<SplitMember>, sqlx::Error> {
+    sqlx::query_as::<_, SplitMember>(
+        "SELECT user_id, share FROM subscription_split_members WHERE subscription_id = ? ORDER BY user_id",
+    )
+    .bind(subscription_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// 获取订阅的分摊配置 (GET /api/subscriptions/:id/split)
+/// Get a subscription's split definition
+pub async fn get_split(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let sub = sqlx::query_as::<_, Subscription>(&format!("SELECT * FROM subscriptions WHERE id = ? AND {}", VISIBLE_TO_USER))
+        .bind(id)
+        .bind(user.user_id)
+        .bind(user.user_id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Subscription not found".to_string()))?;
+
+    let method: Option<(String,)> = sqlx::query_as("SELECT method FROM subscription_splits WHERE subscription_id = ?")
+        .bind(id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?;
+    let Some((method,)) = method else {
+        return Err((StatusCode::NOT_FOUND, "No split defined".to_string()));
+    };
+
+    let members = split_members(&pool, id).await.map_err(db_error)?;
+    let amounts: Vec<serde_json::Value> = round_shares(member_amounts(&method, sub.price, sub.owner_id, &members), &sub.currency)
+        .into_iter()
+        .map(|(user_id, amount)| serde_json::json!({ "user_id": user_id, "amount_per_payment": amount }))
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "subscription_id": id,
+        "method": method,
+        "members": members,
+        "amounts": amounts,
+    })))
+}
+
+/// 设置订阅的分摊配置 (PUT /api/subscriptions/:id/split)
+/// Set a subscription's split definition
+///
+/// 仅拥有者可设置；成员必须是拥有者本人或订阅共享家庭组的成员。
+/// `percentage` 份额之和须为 100，`fixed` 金额之和不能超过订阅价格。
+/// Only the owner can set it; members must be the owner or members of the household the
+/// subscription is shared with. `percentage` shares must sum to 100 and `fixed` amounts may not
+/// exceed the subscription price.
+pub async fn set_split(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+    Json(payload): Json<SetSplit>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+
+    let sub = owned_subscription(&pool, id, user.user_id).await?;
+    let bad_request = |msg: &str| Err((StatusCode::BAD_REQUEST, msg.to_string()));
+
+    // 1. 数据验证
+    //    Data Validation
+    let method = payload.method.trim().to_ascii_lowercase();
+    if !["equal", "percentage", "fixed"].contains(&method.as_str()) {
+        return bad_request("Invalid split method");
+    }
+    if payload.members.is_empty() {
+        return bad_request("At least one member is required");
+    }
+    let mut seen = HashSet::new();
+    for member in &payload.members {
+        if !seen.insert(member.user_id) {
+            return bad_request("Duplicate member");
+        }
+        if method != "equal" && member.share.is_none_or(|s| s < 0.0) {
+            return bad_request("Each member needs a non-negative share");
+        }
+        if member.user_id == sub.owner_id {
+            continue;
+        }
+        let allowed = match sub.household_id {
+            Some(hid) => households::is_member(&pool, hid, member.user_id).await.map_err(db_error)?,
+            None => false,
+        };
+        if !allowed {
+            return bad_request("Members must belong to the household the subscription is shared with");
+        }
+    }
+    let total: f64 = payload.members.iter().filter_map(|m| m.share).sum();
+    if method == "percentage" && (total - 100.0).abs() > EPSILON {
+        return bad_request("Percentages must sum to 100");
+    }
+    if method == "fixed" && total > sub.price + EPSILON {
+        return bad_request("Fixed amounts exceed the subscription price");
+    }
+
+    // 2. 覆盖写入
+    //    Replace the stored definition
+    let mut tx = pool.begin().await.map_err(db_error)?;
+    sqlx::query(
+        r#"
+        INSERT INTO subscription_splits (subscription_id, method, updated_at) VALUES (?, ?, CURRENT_TIMESTAMP)
+        ON CONFLICT(subscription_id) DO UPDATE SET method = excluded.method, updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(id)
+    .bind(&method)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+    sqlx::query("DELETE FROM subscription_split_members WHERE subscription_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    for member in &payload.members {
+        sqlx::query("INSERT INTO subscription_split_members (subscription_id, user_id, share) VALUES (?, ?, ?)")
+            .bind(id)
+            .bind(member.user_id)
+            .bind(if method == "equal" { None } else { member.share })
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+    }
+    tx.commit().await.map_err(db_error)?;
+
+    info!("User {} set {} split on subscription {}", user.user_id, method, id);
+    Ok(Json(serde_json::json!({ "status": "saved", "method": method })))
+}
+
+/// 删除订阅的分摊配置 (DELETE /api/subscriptions/:id/split)
+/// Delete a subscription's split definition
+pub async fn delete_split(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+
+    owned_subscription(&pool, id, user.user_id).await?;
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+    sqlx::query("DELETE FROM subscription_split_members WHERE subscription_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    sqlx::query("DELETE FROM subscription_splits WHERE subscription_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(Json(serde_json::json!({ "status": "deleted" })))
+}
+
+#[derive(Deserialize)]
+pub struct ReportQuery {
+    period: Option<String>,
+}
+
+/// 报表中某位成员在某订阅上的份额
+/// A member's share of one subscription in the report
+#[derive(Serialize)]
+struct ShareLine {
+    user_id: i64,
+    username: String,
+    monthly_amount: f64,
+    is_payer: bool,
+    settled: bool,
+}
+
+/// 报表中每位成员的汇总 (按币种)
+/// Per-member summary in the report (per currency)
+#[derive(Serialize, Default)]
+struct MemberBalance {
+    user_id: i64,
+    username: String,
+    effective_monthly_cost: BTreeMap<String, f64>,
+    outstanding: BTreeMap<String, f64>,
+    receivable: BTreeMap<String, f64>,
+}
+
+/// 分摊报表 (GET /api/splits/report?period=YYYY-MM)
+/// Split report
+///
+/// 对当前用户可见、已定义分摊的订阅，按账单周期列出每位成员的月均份额、
+/// 是否已结清，以及每位成员的实际月度成本、未结清欠款与应收款。
+/// For the split subscriptions visible to the current user, lists each member's monthly share
+/// for the billing period and whether it is settled, plus every member's effective monthly cost,
+/// outstanding debt and receivables.
+pub async fn report(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<ReportQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let period = parse_period(params.period)?;
+
+    let subs = sqlx::query_as::<_, Subscription>(&format!(
+        "SELECT * FROM subscriptions WHERE id IN (SELECT subscription_id FROM subscription_splits) AND {} ORDER BY name",
+        VISIBLE_TO_USER
+    ))
+    .bind(user.user_id)
+    .bind(user.user_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let usernames: HashMap<i64, String> = sqlx::query_as::<_, (i64, String)>("SELECT id, username FROM users")
+        .fetch_all(&pool)
+        .await
+        .map_err(db_error)?
+        .into_iter()
+        .collect();
+    let username = |id: i64| usernames.get(&id).cloned().unwrap_or_else(|| format!("user-{}", id));
+
+    let mut lines = Vec::new();
+    let mut balances: BTreeMap<i64, MemberBalance> = BTreeMap::new();
+
+    for sub in &subs {
+        let (method,): (String,) = sqlx::query_as("SELECT method FROM subscription_splits WHERE subscription_id = ?")
+            .bind(sub.id)
+            .fetch_one(&pool)
+            .await
+            .map_err(db_error)?;
+        let members = split_members(&pool, sub.id).await.map_err(db_error)?;
+        let settled: HashSet<i64> = sqlx::query_as::<_, (i64,)>(
+            "SELECT user_id FROM split_settlements WHERE subscription_id = ? AND period = ?",
+        )
+        .bind(sub.id)
+        .bind(&period)
+        .fetch_all(&pool)
+        .await
+        .map_err(db_error)?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+
+        let mut shares = Vec::new();
+        for (member_id, monthly) in monthly_shares(&method, sub, &members) {
+            let is_payer = member_id == sub.owner_id;
+            let is_settled = is_payer || settled.contains(&member_id);
+
+            let balance = balances.entry(member_id).or_insert_with(|| MemberBalance {
+                user_id: member_id,
+                username: username(member_id),
+                ..Default::default()
+            });
+            *balance.effective_monthly_cost.entry(sub.currency.clone()).or_default() += monthly;
+            if !is_settled {
+                *balance.outstanding.entry(sub.currency.clone()).or_default() += monthly;
+                let payer = balances.entry(sub.owner_id).or_insert_with(|| MemberBalance {
+                    user_id: sub.owner_id,
+                    username: username(sub.owner_id),
+                    ..Default::default()
+                });
+                *payer.receivable.entry(sub.currency.clone()).or_default() += monthly;
+            }
+
+            shares.push(ShareLine {
+                user_id: member_id,
+                username: username(member_id),
+                monthly_amount: monthly,
+                is_payer,
+                settled: is_settled,
+            });
+        }
+
+        lines.push(serde_json::json!({
+            "subscription_id": sub.id,
+            "name": sub.name,
+            "currency": sub.currency,
+            "monthly_cost": round_amount(sub.monthly_cost(), &sub.currency),
+            "method": method,
+            "payer_id": sub.owner_id,
+            "shares": shares,
+        }));
+    }
+
+    for balance in balances.values_mut() {
+        for map in [&mut balance.effective_monthly_cost, &mut balance.outstanding, &mut balance.receivable] {
+            map.iter_mut().for_each(|(currency, v)| *v = round_amount(*v, currency));
+        }
+    }
+
+    Ok(Json(serde_json::json!({
+        "period": period,
+        "subscriptions": lines,
+        "members": balances.into_values().collect::<Vec<_>>(),
+    })))
+}
+
+/// 标记结清 (POST /api/splits/settle)
+/// Mark as settled
+///
+/// 由付款人 (订阅拥有者) 确认某位成员已付清指定账单周期的份额。
+/// The payer (subscription owner) confirms that a member has paid their share for the period.
+pub async fn settle(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<SettleSplit>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    user.require_role(Role::Editor)?;
+
+    let period = parse_period(payload.period)?;
+    let sub = owned_subscription(&pool, payload.subscription_id, user.user_id).await?;
+    if payload.user_id == sub.owner_id {
+        return Err((StatusCode::BAD_REQUEST, "The payer has nothing to settle".to_string()));
+    }
+
+    let method: Option<(String,)> = sqlx::query_as("SELECT method FROM subscription_splits WHERE subscription_id = ?")
+        .bind(sub.id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?;
+    let Some((method,)) = method else {
+        return Err((StatusCode::NOT_FOUND, "No split defined".to_string()));
+    };
+    let members = split_members(&pool, sub.id).await.map_err(db_error)?;
+    let Some((_, amount)) = monthly_shares(&method, &sub, &members).into_iter().find(|(id, _)| *id == payload.user_id) else {
+        return Err((StatusCode::NOT_FOUND, "Member is not part of this split".to_string()));
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO split_settlements (subscription_id, user_id, period, amount) VALUES (?, ?, ?, ?)
+        ON CONFLICT(subscription_id, user_id, period) DO NOTHING
+        "#,
+    )
+    .bind(sub.id)
+    .bind(payload.user_id)
+    .bind(&period)
+    .bind(amount)
+    .execute(&pool)
+    .await
+    .map_err(db_error)?;
+
+    info!("Subscription {} share of user {} settled for {}", sub.id, payload.user_id, period);
+    Ok(Json(serde_json::json!({ "status": "settled", "period": period, "amount": amount })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::db;
+    use axum::http::Uri;
+
+    fn member(user_id: i64, share: Option<f64>) -> SplitMember {
+        SplitMember { user_id, share }
+    }
+
+    fn total(shares: &[(i64, f64)]) -> f64 {
+        shares.iter().map(|(_, a)| a).sum()
+    }
+
+    /// 无法均分的金额：零头按最大余数分配，合计等于价格
+    /// Amounts that do not divide evenly: the odd cents go by largest remainder and the shares add
+    /// up to the price
+    #[test]
+    fn splits_uneven_shares() {
+        let three = [member(1, None), member(2, None), member(3, None)];
+        assert_eq!(round_shares(member_amounts("equal", 10.0, 1, &three), "USD"), vec![(1, 3.34), (2, 3.33), (3, 3.33)]);
+        assert_eq!(round_shares(member_amounts("equal", 0.02, 1, &three), "USD"), vec![(1, 0.01), (2, 0.01), (3, 0.0)]);
+
+        let percentages = [member(1, Some(33.3)), member(2, Some(33.3)), member(3, Some(33.4))];
+        let shares = round_shares(member_amounts("percentage", 9.99, 1, &percentages), "EUR");
+        assert_eq!(shares, vec![(1, 3.33), (2, 3.33), (3, 3.33)]);
+        assert!((total(&shares) - 9.99).abs() < 1e-9);
+
+        // 固定金额的余额由拥有者承担，即使拥有者不在成员中
+        // The remainder of fixed amounts is borne by the owner, even when not a member
+        let fixed = [member(2, Some(4.5)), member(3, Some(2.25))];
+        assert_eq!(round_shares(member_amounts("fixed", 9.99, 1, &fixed), "USD"), vec![(2, 4.5), (3, 2.25), (1, 3.24)]);
+    }
+
+    /// 金额按货币的最小单位取整：日元为整数，大多数货币为分
+    /// Amounts are rounded to the currency's minor unit: whole yen, cents for most currencies
+    #[test]
+    fn rounds_to_currency_units() {
+        let three = [member(1, None), member(2, None), member(3, None)];
+        assert_eq!(round_shares(member_amounts("equal", 1000.0, 1, &three), "JPY"), vec![(1, 334.0), (2, 333.0), (3, 333.0)]);
+        assert_eq!(round_shares(member_amounts("equal", 1000.0, 1, &three), "jpy"), vec![(1, 334.0), (2, 333.0), (3, 333.0)]);
+        assert_eq!(round_shares(member_amounts("equal", 1000.0, 1, &three), "CNY"), vec![(1, 333.34), (2, 333.33), (3, 333.33)]);
+        assert_eq!(round_shares(vec![(1, 0.29), (2, 0.71)], "USD"), vec![(1, 0.29), (2, 0.71)]);
+        assert_eq!(round_amount(1234.5, "KRW"), 1235.0);
+        assert_eq!(round_amount(12.345, "GBP"), 12.35);
+
+        // 年付按月折算后再取整，十二个月的合计仍为一个整数金额
+        // Yearly prices are scaled to a month before rounding
+        let sub: Subscription = serde_json::from_value(serde_json::json!({
+            "id": 1, "name": "Family", "price": 100.0, "currency": "USD", "frequency": 12, "active": true, "owner_id": 1,
+        }))
+        .unwrap();
+        let shares = monthly_shares("equal", &sub, &three);
+        assert_eq!(shares, vec![(1, 2.78), (2, 2.78), (3, 2.77)]);
+        assert!((total(&shares) - 8.33).abs() < 1e-9);
+    }
+
+    #[test]
+    fn handles_no_members() {
+        assert!(member_amounts("equal", 10.0, 1, &[]).is_empty());
+        assert!(member_amounts("percentage", 10.0, 1, &[]).is_empty());
+        assert_eq!(member_amounts("fixed", 10.0, 1, &[]), vec![(1, 10.0)]);
+        assert!(round_shares(Vec::new(), "USD").is_empty());
+    }
+
+    /// 共享给没有其他成员的家庭组时，只能由拥有者独自承担
+    /// With a household that has no other members, only the owner can take part
+    #[tokio::test]
+    async fn splits_in_household_without_members() {
+        let pool = db::test_pool().await;
+        let owner = AuthUser { user_id: 1, role: Role::Admin, scopes: vec![Scope::Admin] };
+        let other = sqlx::query("INSERT INTO users (username) VALUES ('other')").execute(&pool).await.unwrap().last_insert_rowid();
+        let household = sqlx::query("INSERT INTO households (name, owner_id) VALUES ('Empty', 1)")
+            .execute(&pool)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+        let id = sqlx::query(
+            "INSERT INTO subscriptions (name, price, currency, frequency, active, owner_id, household_id) \
+             VALUES ('Family', 1000, 'JPY', 1, 1, 1, ?)",
+        )
+        .bind(household)
+        .execute(&pool)
+        .await
+        .unwrap()
+        .last_insert_rowid();
+        let split = |members: serde_json::Value| {
+            set_split(
+                State(pool.clone()),
+                Extension(owner.clone()),
+                Path(id),
+                Json(serde_json::from_value(serde_json::json!({ "method": "equal", "members": members })).unwrap()),
+            )
+        };
+
+        let err = split(serde_json::json!([{ "user_id": 1 }, { "user_id": other }])).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        assert_eq!(split(serde_json::json!([])).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+        let Json(saved) = split(serde_json::json!([{ "user_id": 1 }])).await.unwrap();
+        assert_eq!(saved["status"], "saved");
+
+        let query = Query::try_from_uri(&Uri::from_static("/api/splits/report?period=2026-10")).unwrap();
+        let Json(report) = report(State(pool.clone()), Extension(owner.clone()), query).await.unwrap();
+        let shares = &report["subscriptions"][0]["shares"];
+        assert_eq!(shares.as_array().unwrap().len(), 1);
+        assert_eq!(shares[0]["monthly_amount"], 1000.0);
+        assert_eq!(shares[0]["is_payer"], true);
+        assert_eq!(report["members"][0]["outstanding"], serde_json::json!({}));
+
+        let settle_other = SettleSplit { subscription_id: id, user_id: other, period: Some("2026-10".to_string()) };
+        let err = settle(State(pool), Extension(owner), Json(settle_other)).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,129 @@
+//! 用户管理模块 (仅管理员)
+//! User management module (admins only)
+
+use crate::auth::{AuthUser, Role};
+use crate::db::DbPool;
+use crate::models::{UpdateUserRole, User};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use tracing::info;
+
+fn db_error(e: sqlx::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// 列出所有用户 (GET /api/users)
+/// List all users
+pub async fn list_users(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<Vec<User>>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+
+    let users = sqlx::query_as::<_, User>(
+        "SELECT id, username, email, display_name, role, created_at FROM users ORDER BY id",
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(users))
+}
+
+/// 修改用户角色 (PUT /api/users/:id/role)
+/// Change a user's role
+///
+/// 不允许降级最后一位管理员，避免系统失去管理入口。
+/// The last remaining admin cannot be demoted, so the system never loses its administrator.
+pub async fn update_role(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UpdateUserRole>,
+) -> Result<Json<User>, (StatusCode, String)> {
+    user.require_role(Role::Admin)?;
+
+    let Some(role) = Role::parse(&payload.role) else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid role".to_string()));
+    };
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+    let current: Option<(String,)> = sqlx::query_as("SELECT role FROM users WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let Some((current,)) = current else {
+        return Err((StatusCode::NOT_FOUND, "User not found".to_string()));
+    };
+    if current == Role::Admin.as_str() && role != Role::Admin {
+        let (admins,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users WHERE role = 'admin'")
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        if admins <= 1 {
+            return Err((StatusCode::BAD_REQUEST, "Cannot demote the last admin".to_string()));
+        }
+    }
+
+    sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+        .bind(role.as_str())
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let updated = sqlx::query_as::<_, User>(
+        "SELECT id, username, email, display_name, role, created_at FROM users WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error)?;
+    tx.commit().await.map_err(db_error)?;
+
+    info!("User {} changed role of user {} to {}", user.user_id, id, role.as_str());
+    Ok(Json(updated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::{db, icon_cache, mail, prompts, resolver, search_cache};
+    use axum::extract::Query;
+    use axum::http::Uri;
+
+    /// 系统管理接口 (用户、提示词、缓存、解析器状态、邮件轮询) 仅限管理员
+    /// System management endpoints (users, prompts, caches, resolver status, mail polling) are
+    /// admin-only
+    #[tokio::test]
+    async fn management_requires_admin() {
+        let pool = db::test_pool().await;
+        let editor = AuthUser { user_id: 1, role: Role::Editor, scopes: vec![Scope::Read, Scope::Write] };
+        let uri = Uri::from_static("/?domain=example.com");
+        let role: UpdateUserRole = serde_json::from_value(serde_json::json!({ "role": "viewer" })).unwrap();
+
+        let statuses = [
+            list_users(State(pool.clone()), Extension(editor.clone())).await.err(),
+            update_role(State(pool.clone()), Extension(editor.clone()), Path(1), Json(role)).await.err(),
+            prompts::list_versions(State(pool.clone()), Extension(editor.clone())).await.err(),
+            search_cache::purge_cache(State(pool.clone()), Extension(editor.clone()), Query::try_from_uri(&uri).unwrap())
+                .await
+                .err(),
+            icon_cache::purge_cache(State(pool.clone()), Extension(editor.clone()), Query::try_from_uri(&uri).unwrap())
+                .await
+                .err(),
+            resolver::resolver_status(Extension(editor.clone())).await.err(),
+            mail::poll_now(State(pool.clone()), Extension(editor)).await.err(),
+        ];
+        for (i, status) in statuses.into_iter().enumerate() {
+            assert_eq!(status.map(|e| e.0), Some(StatusCode::FORBIDDEN), "endpoint {}", i);
+        }
+
+        let admin = AuthUser { user_id: 1, role: Role::Admin, scopes: vec![Scope::Admin] };
+        assert!(list_users(State(pool.clone()), Extension(admin)).await.is_ok());
+    }
+}
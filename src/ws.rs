@@ -0,0 +1,528 @@
+//! WebSocket 实时接口模块
+//! WebSocket realtime API module
+//!
+//! `GET /api/ws` 与 SSE 共用事件总线 (`events` 模块) 与认证方式 (会话 Cookie 或 Bearer 令牌)，
+//! 供原生客户端双向同步。一个连接上可以建立多个事件通道，每个通道按事件类型、分类或订阅 id
+//! 过滤；客户端也可以通过连接提交修改，每条消息的 `id` 会在对应的 `ack` 中原样返回。
+//! 消息均为 JSON 文本帧。
+//! `GET /api/ws` shares the event bus (the `events` module) and authentication (session cookie
+//! or Bearer token) with SSE, for bidirectional sync in native clients. A connection can open
+//! several event channels, each filtered by event type, category or subscription id; clients can
+//! also send changes over the connection, and each message's `id` is echoed in its `ack`.
+//! All messages are JSON text frames.
+//!
+//! 连接建立后凭据仍可能被吊销或过期，因此每条客户端消息与每次 Ping 前都会重新校验，失效时
+//! 关闭连接。
+//! Credentials may be revoked or expire after the connection is opened, so they are checked
+//! again before every client message and every ping, and the connection is closed once they are
+//! no longer valid.
+
+use crate::auth::{self, AuthUser};
+use crate::db::DbPool;
+use crate::events::{self, Event, Feed, ResyncReason};
+use crate::handlers::{self, PauseRequest};
+use crate::models::CreateSubscription;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::header::{AUTHORIZATION, COOKIE, HOST, ORIGIN};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+/// 每个连接最多的事件通道数 / Maximum number of event channels per connection
+const MAX_CHANNELS: usize = 16;
+
+/// 待发送消息队列的容量；客户端读取过慢时通道会积压并收到 `resync`
+/// Capacity of the outgoing message queue; when the client reads too slowly its channels lag
+/// behind and receive `resync`
+const OUTBOX_CAPACITY: usize = 64;
+
+/// 单条客户端消息的大小上限 / Size limit of a single client message
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// 保持连接的 Ping 间隔 / Ping interval keeping the connection alive
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 事件通道的过滤条件；各条件为空表示不限制，多个条件同时生效
+/// Filter of an event channel; an empty condition does not restrict, and all conditions apply
+#[derive(Debug, Default, Deserialize)]
+struct Filter {
+    /// 事件类型，如 `subscription.updated` / Event types, such as `subscription.updated`
+    #[serde(default)]
+    types: Vec<String>,
+    /// 订阅分类 (不区分大小写) / Subscription categories (case-insensitive)
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    subscription_ids: Vec<i64>,
+}
+
+impl Filter {
+    fn matches(&self, event: &Event) -> bool {
+        let subject = event.subject.as_ref();
+        (self.types.is_empty() || self.types.iter().any(|t| t == event.kind.name()))
+            && self.matches_subscription(event)
+            && (self.categories.is_empty() || subject.is_some_and(|s| self.has_category(&s.categories)))
+    }
+
+    /// 事件使订阅离开了按分类过滤的通道 (分类被修改)；不受事件类型条件限制
+    /// Whether the event moved the subscription out of a category-filtered channel (its category
+    /// changed); the event type condition does not apply
+    fn left(&self, event: &Event) -> bool {
+        !self.categories.is_empty()
+            && self.matches_subscription(event)
+            && event.subject.as_ref().is_some_and(|s| {
+                !self.has_category(&s.categories) && self.has_category(&s.left_categories)
+            })
+    }
+
+    fn matches_subscription(&self, event: &Event) -> bool {
+        self.subscription_ids.is_empty()
+            || event.subject.as_ref().is_some_and(|s| self.subscription_ids.contains(&s.subscription_id))
+    }
+
+    fn has_category(&self, categories: &[String]) -> bool {
+        categories.iter().any(|c| self.categories.iter().any(|f| f.trim().eq_ignore_ascii_case(c)))
+    }
+}
+
+/// 客户端消息 (`type` 字段区分)
+/// Client message (distinguished by the `type` field)
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    /// 建立事件通道，通道以该消息的 `id` 标识；`last_event_id` 用于补收错过的事件
+    /// Open an event channel identified by this message's `id`; `last_event_id` replays missed
+    /// events
+    Subscribe {
+        #[serde(default)]
+        filter: Filter,
+        last_event_id: Option<u64>,
+    },
+    Unsubscribe {
+        channel: Value,
+    },
+    Create {
+        data: CreateSubscription,
+    },
+    Update {
+        subscription_id: i64,
+        data: CreateSubscription,
+    },
+    Delete {
+        subscription_id: i64,
+    },
+    Pause {
+        subscription_id: i64,
+        #[serde(default)]
+        until: Option<String>,
+    },
+    Resume {
+        subscription_id: i64,
+    },
+}
+
+/// 建立 WebSocket 连接 (GET /api/ws)
+/// Open a WebSocket connection
+///
+/// 认证由 `/api` 的认证中间件完成。带有 `Origin` 头时必须与 `Host` 一致，防止其他网站借用户的
+/// 会话 Cookie 建立连接 (WebSocket 握手不受 CORS 限制)。
+/// Authentication is done by the `/api` middleware. An `Origin` header, when present, must match
+/// `Host`, so other sites cannot open a connection with the user's session cookie (WebSocket
+/// handshakes are not subject to CORS).
+pub async fn connect(
+    State(pool): State<DbPool>,
+    Extension(user): Extension<AuthUser>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if !same_origin(&headers) {
+        return (StatusCode::FORBIDDEN, "Cross-origin WebSocket connections are not allowed").into_response();
+    }
+    // 只保留凭据相关的请求头，用于之后重新校验
+    // Keep only the credential headers, for checking them again later
+    let mut credentials = HeaderMap::new();
+    for name in [AUTHORIZATION, COOKIE] {
+        if let Some(value) = headers.get(&name) {
+            credentials.insert(name, value.clone());
+        }
+    }
+    ws.max_message_size(MAX_MESSAGE_BYTES).on_upgrade(move |socket| run(socket, pool, user, credentials))
+}
+
+fn same_origin(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(ORIGIN).and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+    let host = headers.get(HOST).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    reqwest::Url::parse(origin).is_ok_and(|url| {
+        let origin_host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        origin_host.eq_ignore_ascii_case(host)
+    })
+}
+
+struct Connection {
+    pool: DbPool,
+    user: AuthUser,
+    credentials: HeaderMap,
+    /// 凭据已失效，发送当前回复后关闭连接
+    /// The credentials are no longer valid; the connection closes after the current reply
+    revoked: bool,
+    outbox: mpsc::Sender<String>,
+    channels: HashMap<String, JoinHandle<()>>,
+}
+
+async fn run(mut socket: WebSocket, pool: DbPool, user: AuthUser, credentials: HeaderMap) {
+    info!("WebSocket connected for user {}", user.user_id);
+    let (outbox, mut pending) = mpsc::channel(OUTBOX_CAPACITY);
+    let mut conn = Connection { pool, user, credentials, revoked: false, outbox, channels: HashMap::new() };
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.tick().await;
+
+    loop {
+        // 回复在处理完消息后立即发送，因此订阅的 ack 先于该通道的事件到达
+        // Replies are sent right after a message is handled, so a subscribe ack arrives before
+        // the channel's events
+        let outgoing = tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => conn.handle(&text).await,
+                Some(Ok(Message::Binary(_))) => {
+                    ack(Value::Null, Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, "Only text messages are supported".to_string())))
+                }
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            },
+            Some(text) = pending.recv() => Message::Text(text),
+            _ = ping.tick() => match conn.reauthenticate().await {
+                Ok(()) => Message::Ping(Vec::new()),
+                Err(_) if conn.revoked => revoked(),
+                Err(_) => continue,
+            },
+        };
+        // 凭据失效时先发送当前回复，再关闭连接
+        // With invalid credentials the current reply is sent first, then the connection closes
+        let close = conn.revoked && !matches!(outgoing, Message::Close(_));
+        if socket.send(outgoing).await.is_err() || conn.revoked {
+            if close {
+                let _ = socket.send(revoked()).await;
+            }
+            break;
+        }
+    }
+
+    for task in conn.channels.values() {
+        task.abort();
+    }
+    info!("WebSocket closed for user {}", conn.user.user_id);
+}
+
+fn revoked() -> Message {
+    Message::Close(Some(CloseFrame { code: close_code::POLICY, reason: "Credentials are no longer valid".into() }))
+}
+
+/// 回复客户端消息
+/// Reply to a client message
+fn ack(id: Value, result: Result<Value, (StatusCode, String)>) -> Message {
+    let body = match result {
+        Ok(result) => json!({ "type": "ack", "id": id, "ok": true, "result": result }),
+        Err((status, error)) => {
+            json!({ "type": "ack", "id": id, "ok": false, "status": status.as_u16(), "error": error })
+        }
+    };
+    Message::Text(body.to_string())
+}
+
+fn to_value<T: Serialize>(Json(value): Json<T>) -> Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+impl Connection {
+    /// 重新校验凭据 (令牌或会话可能已被吊销或过期，角色可能已变化)；失效时标记连接待关闭
+    /// Check the credentials again (the token or session may have been revoked or expired, and
+    /// the role may have changed); once invalid the connection is marked for closing
+    async fn reauthenticate(&mut self) -> Result<(), (StatusCode, String)> {
+        let result = match auth::resolve(&self.pool, &self.credentials).await {
+            Ok(user) if user.user_id == self.user.user_id => {
+                self.user = user;
+                return Ok(());
+            }
+            // 会话失效后可能回退为默认本地用户，同样视为失效
+            // An expired session may fall back to the default local user, which counts as invalid too
+            Ok(_) => (StatusCode::UNAUTHORIZED, "Credentials are no longer valid".to_string()),
+            Err(e) if e.0 == StatusCode::INTERNAL_SERVER_ERROR => return Err(e),
+            Err(e) => e,
+        };
+        info!("Closing WebSocket of user {}: {}", self.user.user_id, result.1);
+        self.revoked = true;
+        Err(result)
+    }
+
+    async fn handle(&mut self, text: &str) -> Message {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return ack(Value::Null, Err((StatusCode::BAD_REQUEST, e.to_string()))),
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        if let Err(e) = self.reauthenticate().await {
+            return ack(id, Err(e));
+        }
+        let result = match serde_json::from_value::<Command>(request) {
+            Ok(command) => self.execute(&id, command).await,
+            Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
+        };
+        ack(id, result)
+    }
+
+    /// 修改通过 REST 接口的处理函数执行，权限检查与事件发布与 HTTP 请求一致
+    /// Changes run through the REST handlers, so permission checks and published events match
+    /// HTTP requests
+    async fn execute(&mut self, id: &Value, command: Command) -> Result<Value, (StatusCode, String)> {
+        let state = State(self.pool.clone());
+        let user = Extension(self.user.clone());
+        let result = match command {
+            Command::Subscribe { filter, last_event_id } => return self.subscribe(id, filter, last_event_id),
+            Command::Unsubscribe { channel } => {
+                let task = self
+                    .channels
+                    .remove(&channel.to_string())
+                    .ok_or((StatusCode::NOT_FOUND, "Channel not found".to_string()))?;
+                task.abort();
+                json!({ "channel": channel })
+            }
+            Command::Create { data } => to_value(handlers::create_subscription(state, user, Json(data)).await?),
+            Command::Update { subscription_id, data } => {
+                to_value(handlers::update_subscription(state, user, Path(subscription_id), Json(data)).await?)
+            }
+            Command::Delete { subscription_id } => {
+                to_value(handlers::delete_subscription(state, user, Path(subscription_id)).await?)
+            }
+            Command::Pause { subscription_id, until } => to_value(
+                handlers::pause_subscription(state, user, Path(subscription_id), Json(PauseRequest { until })).await?,
+            ),
+            Command::Resume { subscription_id } => {
+                to_value(handlers::resume_subscription(state, user, Path(subscription_id)).await?)
+            }
+        };
+        Ok(result)
+    }
+
+    fn subscribe(&mut self, id: &Value, filter: Filter, last_event_id: Option<u64>) -> Result<Value, (StatusCode, String)> {
+        if id.is_null() {
+            return Err((StatusCode::BAD_REQUEST, "subscribe requires an id".to_string()));
+        }
+        let key = id.to_string();
+        if self.channels.contains_key(&key) {
+            return Err((StatusCode::CONFLICT, "Channel already exists".to_string()));
+        }
+        if self.channels.len() >= MAX_CHANNELS {
+            return Err((StatusCode::TOO_MANY_REQUESTS, format!("At most {} channels per connection", MAX_CHANNELS)));
+        }
+        let channel = Channel {
+            id: id.clone(),
+            filter,
+            pool: self.pool.clone(),
+            user_id: self.user.user_id,
+            outbox: self.outbox.clone(),
+        };
+        self.channels.insert(key, tokio::spawn(channel.forward(events::subscribe(last_event_id))));
+        Ok(json!({ "channel": id }))
+    }
+}
+
+/// 事件通道：把总线上可见且符合过滤条件的事件转发给连接
+/// Event channel: forwards the bus events that are visible and match the filter to the connection
+struct Channel {
+    id: Value,
+    filter: Filter,
+    pool: DbPool,
+    user_id: i64,
+    outbox: mpsc::Sender<String>,
+}
+
+impl Channel {
+    async fn forward(self, feed: Feed) {
+        let Feed { replay, resync, mut receiver } = feed;
+        if let Some(reason) = resync {
+            if !self.resync(reason).await {
+                return;
+            }
+        }
+        for event in replay {
+            if !self.deliver(&event).await {
+                return;
+            }
+        }
+        loop {
+            let delivered = match receiver.recv().await {
+                Ok(event) => self.deliver(&event).await,
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("WebSocket channel of user {} lagged, skipped {} events", self.user_id, skipped);
+                    self.resync(ResyncReason::Lagged).await
+                }
+                Err(RecvError::Closed) => return,
+            };
+            if !delivered {
+                return;
+            }
+        }
+    }
+
+    /// 发送事件，或在订阅离开过滤范围时发送 `left`；连接已关闭时返回 `false`
+    /// Send an event, or `left` when the subscription moved out of the filter; returns `false`
+    /// once the connection is closed
+    async fn deliver(&self, event: &Event) -> bool {
+        let message = if self.filter.matches(event) {
+            json!({ "type": "event", "channel": self.id, "event": event })
+        } else if self.filter.left(event) {
+            let subscription_id = event.subject.as_ref().map(|s| s.subscription_id);
+            json!({ "type": "left", "channel": self.id, "subscription_id": subscription_id, "event_id": event.id })
+        } else {
+            return true;
+        };
+        if !event.visible_to(&self.pool, self.user_id).await {
+            return true;
+        }
+        self.outbox.send(message.to_string()).await.is_ok()
+    }
+
+    async fn resync(&self, reason: ResyncReason) -> bool {
+        let message = json!({ "type": events::RESYNC, "channel": self.id, "reason": reason });
+        self.outbox.send(message.to_string()).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{Role, Scope};
+    use crate::db;
+    use crate::events::{Audience, EventKind, Subject};
+
+    fn event(kind: EventKind, subscription_id: i64, categories: &[&str], left: &[&str]) -> Event {
+        Event {
+            id: 1,
+            kind,
+            at: String::new(),
+            data: Value::Null,
+            audience: Audience::Subscription { owner_id: 1, household_id: None },
+            subject: Some(Subject {
+                subscription_id,
+                categories: categories.iter().map(|c| c.to_string()).collect(),
+                left_categories: left.iter().map(|c| c.to_string()).collect(),
+            }),
+        }
+    }
+
+    fn filter(value: Value) -> Filter {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn filters_events() {
+        let updated = event(EventKind::SubscriptionUpdated, 3, &["video"], &[]);
+        let cases = [
+            (json!({}), true),
+            (json!({ "types": ["subscription.updated"] }), true),
+            (json!({ "types": ["subscription.deleted"] }), false),
+            (json!({ "categories": [" VIDEO "] }), true),
+            (json!({ "categories": ["music"] }), false),
+            (json!({ "subscription_ids": [3, 4] }), true),
+            (json!({ "subscription_ids": [4] }), false),
+            (json!({ "types": ["subscription.updated"], "categories": ["video"], "subscription_ids": [4] }), false),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(filter(value.clone()).matches(&updated), expected, "{}", value);
+        }
+
+        // 没有涉及订阅的事件只能匹配不按订阅或分类过滤的通道
+        // An event without a subject only matches channels not filtering by subscription or category
+        let mut unrelated = updated.clone();
+        unrelated.subject = None;
+        assert!(filter(json!({ "types": ["subscription.updated"] })).matches(&unrelated));
+        assert!(!filter(json!({ "categories": ["video"] })).matches(&unrelated));
+        assert!(!filter(json!({ "subscription_ids": [3] })).matches(&unrelated));
+    }
+
+    /// 分类由 video 改为 music：按 video 过滤的通道收到 `left`，按 music 过滤的通道收到事件
+    /// Category changed from video to music: channels filtering on video get `left`, those on
+    /// music get the event
+    #[test]
+    fn detects_subscriptions_leaving_a_filter() {
+        let moved = event(EventKind::SubscriptionUpdated, 3, &["music"], &["video"]);
+        let video = filter(json!({ "categories": ["video"], "types": ["subscription.deleted"] }));
+        assert!(!video.matches(&moved) && video.left(&moved));
+        let music = filter(json!({ "categories": ["music"] }));
+        assert!(music.matches(&moved) && !music.left(&moved));
+        assert!(!filter(json!({})).left(&moved));
+        assert!(!filter(json!({ "categories": ["video"], "subscription_ids": [4] })).left(&moved));
+    }
+
+    #[test]
+    fn checks_origin() {
+        let headers = |origin: Option<&str>, host: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(HOST, host.parse().unwrap());
+            if let Some(origin) = origin {
+                headers.insert(ORIGIN, origin.parse().unwrap());
+            }
+            headers
+        };
+        let cases = [
+            (None, "wallet.local", true),
+            (Some("https://wallet.local"), "wallet.local", true),
+            (Some("http://Wallet.Local:8081"), "wallet.local:8081", true),
+            (Some("https://wallet.local"), "wallet.local:8081", false),
+            (Some("https://evil.example"), "wallet.local", false),
+            (Some("https://wallet.local.evil.example"), "wallet.local", false),
+            (Some("null"), "wallet.local", false),
+        ];
+        for (origin, host, expected) in cases {
+            assert_eq!(same_origin(&headers(origin, host)), expected, "{:?} {}", origin, host);
+        }
+    }
+
+    /// 修改分类发布的事件记录原分类
+    /// The event published for a category change records the previous category
+    #[tokio::test]
+    async fn category_change_records_previous_category() {
+        let pool = db::test_pool().await;
+        let user = AuthUser { user_id: 1, role: Role::Admin, scopes: vec![Scope::Admin] };
+        let payload = |category: &str| {
+            serde_json::from_value::<CreateSubscription>(json!({
+                "name": "Acme Stream", "price": 9.0, "currency": "USD", "frequency": 1,
+                "next_payment": "2026-11-01", "category": category
+            }))
+            .unwrap()
+        };
+        let state = State(pool.clone());
+        let Json(sub) = handlers::create_subscription(state, Extension(user.clone()), Json(payload("video"))).await.unwrap();
+
+        let mut receiver = events::receiver();
+        let state = State(pool.clone());
+        let Json(updated) =
+            handlers::update_subscription(state, Extension(user), Path(sub.id), Json(payload("music"))).await.unwrap();
+        assert_eq!(updated.category.as_deref(), Some("music"));
+        let event = loop {
+            let event = receiver.recv().await.unwrap();
+            let about_sub = event.subject.as_ref().is_some_and(|s| s.subscription_id == sub.id);
+            if event.kind == EventKind::SubscriptionUpdated && about_sub {
+                break event;
+            }
+        };
+        let subject = event.subject.as_ref().unwrap();
+        assert_eq!(subject.categories, vec!["music".to_string()]);
+        assert_eq!(subject.left_categories, vec!["video".to_string()]);
+        assert!(filter(json!({ "categories": ["video"] })).left(&event));
+    }
+}